@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, post, put, web};
+use bitcoin::{Amount, Txid};
+use shared::SignPsbtResp;
+use shared::admin::{
+    BroadcastResp, DepositsResp, OutstandingDeposit, PresignedResp, SessionInfo, SessionState,
+    SessionsResp,
+};
+
+use subtle::ConstantTimeEq;
+
+use crate::AppState;
+use crate::archive::{self, Archive};
+use crate::liability;
+use crate::policy::Policy;
+
+/// Number of finished sessions kept for the admin API.
+const HISTORY_LEN: usize = 1000;
+
+/// The `/psbt` sessions being served, and the most recently finished ones.
+#[derive(Default)]
+pub struct Registry {
+    inner: Mutex<Sessions>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    next_id: u64,
+    active: BTreeMap<u64, SessionInfo>,
+    history: VecDeque<SessionInfo>,
+}
+
+impl Registry {
+    /// Registers a new session for a request from `peer`, returning its id.
+    pub fn begin(&self, peer: Option<String>) -> u64 {
+        let mut sessions = self.inner.lock().unwrap();
+        sessions.next_id += 1;
+        let id = sessions.next_id;
+        sessions.active.insert(
+            id,
+            SessionInfo {
+                id,
+                peer,
+                started_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                deposit_txid: None,
+                deposit_sat: None,
+                state: SessionState::Pending,
+            },
+        );
+        id
+    }
+
+    pub fn set_deposit(&self, id: u64, deposit_txid: Txid, value: Amount) {
+        if let Some(session) = self.inner.lock().unwrap().active.get_mut(&id) {
+            session.deposit_txid = Some(deposit_txid);
+            session.deposit_sat = Some(value.to_sat());
+        }
+    }
+
+    /// Moves session `id` to signing, unless it was revoked.
+    pub fn start_signing(&self, id: u64) -> Result<(), String> {
+        let mut sessions = self.inner.lock().unwrap();
+        let session = sessions.active.get_mut(&id).ok_or("unknown session")?;
+        if session.state == SessionState::Revoked {
+            return Err(format!("session {} was revoked", id));
+        }
+        session.state = SessionState::Signing;
+        Ok(())
+    }
+
+    /// Moves session `id` to the history, completed if `error` is none. A revoked session stays
+    /// revoked.
+    pub fn finish(&self, id: u64, error: Option<String>) {
+        let mut sessions = self.inner.lock().unwrap();
+        let Some(mut session) = sessions.active.remove(&id) else {
+            return;
+        };
+        if session.state != SessionState::Revoked {
+            session.state = match error {
+                None => SessionState::Completed,
+                Some(reason) => SessionState::Failed { reason },
+            };
+        }
+        sessions.history.push_back(session);
+        if sessions.history.len() > HISTORY_LEN {
+            sessions.history.pop_front();
+        }
+    }
+
+    /// Number of sessions in flight.
+    pub fn active(&self) -> usize {
+        self.inner.lock().unwrap().active.len()
+    }
+
+    /// Revokes pending session `id`, returning it.
+    fn revoke(&self, id: u64) -> Result<SessionInfo, String> {
+        let mut sessions = self.inner.lock().unwrap();
+        let session = sessions
+            .active
+            .get_mut(&id)
+            .ok_or(format!("no active session {}", id))?;
+        if session.state != SessionState::Pending {
+            return Err(format!("session {} is {:?}", id, session.state));
+        }
+        session.state = SessionState::Revoked;
+        Ok(session.clone())
+    }
+}
+
+/// Whether `req` carries the admin token as bearer token. The tokens are compared in constant
+/// time, so the time to reject one does not tell how much of it was right.
+fn authorized(req: &HttpRequest, token: &str) -> bool {
+    req.headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|t| bool::from(t.as_bytes().ct_eq(token.as_bytes())))
+}
+
+fn check_auth(data: &AppState, req: &HttpRequest) -> actix_web::Result<()> {
+    match &data.admin_token {
+        Some(token) if authorized(req, token) => Ok(()),
+        _ => Err(actix_web::error::ErrorUnauthorized("invalid admin token")),
+    }
+}
+
+#[get("/admin/sessions")]
+async fn sessions(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    check_auth(&data, &req)?;
+    let sessions = data.registry.inner.lock().unwrap();
+    Ok(web::Json(SessionsResp {
+        active: sessions.active.values().cloned().collect(),
+        history: sessions.history.iter().cloned().collect(),
+    }))
+}
+
+#[delete("/admin/sessions/{id}")]
+async fn revoke(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<u64>,
+) -> actix_web::Result<impl Responder> {
+    check_auth(&data, &req)?;
+    let session = data
+        .registry
+        .revoke(*id)
+        .map_err(actix_web::error::ErrorConflict)?;
+    println!("admin revoked session {}", session.id);
+    Ok(web::Json(session))
+}
+
+#[get("/admin/deposits")]
+async fn deposits(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    check_auth(&data, &req)?;
+    let deposits: Vec<OutstandingDeposit> = match &data.liability {
+        None => vec![],
+        Some(liability) => liability
+            .deposits()
+            .map_err(liability::file_error)?
+            .into_iter()
+            .map(|d| OutstandingDeposit {
+                deposit_txid: d.deposit_txid,
+                value_sat: d.value_sat,
+                created_at: d.created_at,
+            })
+            .collect(),
+    };
+    Ok(web::Json(DepositsResp {
+        total_sat: deposits.iter().map(|d| d.value_sat).sum(),
+        deposits,
+        max_sat: data.cfg.liability.as_ref().map(|l| l.max_sat),
+    }))
+}
+
+#[get("/admin/policy")]
+async fn get_policy(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    check_auth(&data, &req)?;
+    Ok(web::Json(data.policy.read().unwrap().clone()))
+}
+
+/// Replaces the policy until the signer is restarted, after which the one in the config applies
+/// again.
+#[put("/admin/policy")]
+async fn set_policy(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    policy: web::Json<Policy>,
+) -> actix_web::Result<impl Responder> {
+    check_auth(&data, &req)?;
+    println!("admin set policy: {:?}", policy);
+    *data.policy.write().unwrap() = policy.into_inner();
+    Ok(HttpResponse::NoContent())
+}
+
+fn archive(data: &AppState) -> actix_web::Result<&Archive> {
+    data.archive.as_ref().ok_or(actix_web::error::ErrorNotFound(
+        "presigned spends are not kept",
+    ))
+}
+
+#[get("/admin/presigned")]
+async fn presigned(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    check_auth(&data, &req)?;
+    let deposit_txids = archive(&data)?
+        .deposits()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(web::Json(PresignedResp { deposit_txids }))
+}
+
+#[get("/admin/presigned/{deposit_txid}")]
+async fn presigned_spend(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    deposit_txid: web::Path<Txid>,
+) -> actix_web::Result<impl Responder> {
+    check_auth(&data, &req)?;
+    Ok(web::Json(load_presigned(&data, &deposit_txid)?))
+}
+
+pub fn load_presigned(data: &AppState, deposit_txid: &Txid) -> actix_web::Result<SignPsbtResp> {
+    archive(data)?
+        .load(deposit_txid)
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .ok_or(actix_web::error::ErrorNotFound(format!(
+            "no presigned spend for deposit {}",
+            deposit_txid
+        )))
+}
+
+/// Broadcasts the presigned spend of a deposit again, for a depositor that is unable to.
+#[post("/admin/presigned/{deposit_txid}/broadcast")]
+async fn rebroadcast(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    deposit_txid: web::Path<Txid>,
+) -> actix_web::Result<impl Responder> {
+    check_auth(&data, &req)?;
+    let url = data
+        .cfg
+        .esplora_url
+        .as_ref()
+        .ok_or(actix_web::error::ErrorServiceUnavailable(
+            "no Esplora API configured to broadcast with",
+        ))?;
+    let resp = load_presigned(&data, &deposit_txid)?;
+    let tx = archive::final_spend(&resp).ok_or(actix_web::error::ErrorConflict(
+        "the presigned spend is not final, only the depositor can complete it",
+    ))?;
+    let txid = archive::broadcast(url, &tx)
+        .await
+        .map_err(actix_web::error::ErrorBadGateway)?;
+    println!(
+        "admin rebroadcast spend {} of deposit {}",
+        txid, deposit_txid
+    );
+    Ok(web::Json(BroadcastResp { txid }))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(sessions)
+        .service(revoke)
+        .service(deposits)
+        .service(get_policy)
+        .service(set_policy)
+        .service(presigned)
+        .service(presigned_spend)
+        .service(rebroadcast);
+}
@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bitcoin::{Transaction, Txid, consensus};
+use shared::SignPsbtResp;
+
+/// Copies of the presigned transactions handed to depositors, one JSON file per deposit named
+/// after its txid. They hold nothing secret, so they can be served again to a depositor that lost
+/// its copy.
+pub struct Archive {
+    dir: PathBuf,
+}
+
+impl Archive {
+    pub fn open(dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        Ok(Archive {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn path(&self, deposit_txid: &Txid) -> PathBuf {
+        self.dir.join(format!("{}.json", deposit_txid))
+    }
+
+    /// Keeps a copy of `resp`.
+    pub fn store(&self, resp: &SignPsbtResp) -> Result<(), Box<dyn std::error::Error>> {
+        let deposit_txid = resp.deposit_psbt.unsigned_tx.compute_txid();
+        fs::write(
+            self.path(&deposit_txid),
+            serde_json::to_string_pretty(resp)?,
+        )?;
+        Ok(())
+    }
+
+    /// The presigned transactions for deposit `deposit_txid`, if any were handed out.
+    pub fn load(
+        &self,
+        deposit_txid: &Txid,
+    ) -> Result<Option<SignPsbtResp>, Box<dyn std::error::Error>> {
+        match fs::read_to_string(self.path(deposit_txid)) {
+            Ok(data) => Ok(Some(serde_json::from_str(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Txids of the archived deposits.
+    pub fn deposits(&self) -> Result<Vec<Txid>, Box<dyn std::error::Error>> {
+        let mut txids = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let Some(txid) = name.to_str().and_then(|n| n.strip_suffix(".json")) else {
+                continue;
+            };
+            if let Ok(txid) = Txid::from_str(txid) {
+                txids.push(txid);
+            }
+        }
+        txids.sort();
+        Ok(txids)
+    }
+}
+
+/// The presigned spend of `resp`, if it is final. An adaptor signed spend is only completed by
+/// the depositor.
+pub fn final_spend(resp: &SignPsbtResp) -> Option<Transaction> {
+    if resp.adaptor_sig.is_some() {
+        return None;
+    }
+    resp.spend_psbt.clone().extract_tx().ok()
+}
+
+/// Broadcasts `tx` through the Esplora API at `url`.
+pub async fn broadcast(url: &str, tx: &Transaction) -> Result<Txid, Box<dyn std::error::Error>> {
+    let resp = reqwest::Client::new()
+        .post(format!("{}/tx", url.trim_end_matches('/')))
+        .body(consensus::encode::serialize_hex(tx))
+        .send()
+        .await?;
+    let status = resp.status();
+    let body = resp.text().await?;
+    if !status.is_success() {
+        return Err(format!("broadcast rejected: {}", body.trim()).into());
+    }
+    Ok(Txid::from_str(body.trim())?)
+}
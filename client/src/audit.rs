@@ -0,0 +1,164 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::{Txid, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Hash the first entry of a log is chained to.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Something the signer did in a session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The signers were asked for an ephemeral key for a deposit.
+    SessionStarted {
+        /// Hex encoded SHA-256 of the request as received.
+        request_hash: String,
+        deposit_txid: Txid,
+        ephemeral_pubkey: XOnlyPublicKey,
+    },
+    /// The signers signed with the ephemeral key, after which they deleted it.
+    KeyDeleted {
+        deposit_txid: Txid,
+        ephemeral_pubkey: XOnlyPublicKey,
+    },
+    /// The presigned transactions were handed back to the depositor.
+    Presigned {
+        deposit_txid: Txid,
+        presigned_txid: Txid,
+        /// CETs, vault spends and the rollover spend, if any.
+        #[serde(default)]
+        other_txids: Vec<Txid>,
+    },
+    /// The signers were asked for an ephemeral key for a blind session, whose deposit the
+    /// signer does not learn.
+    BlindSessionStarted {
+        request_hash: String,
+        ephemeral_pubkey: XOnlyPublicKey,
+    },
+    /// The signers signed the template of a blind session, after which they deleted the key.
+    BlindSigned {
+        ephemeral_pubkey: XOnlyPublicKey,
+        /// Hex encoded sighash signed.
+        sighash: String,
+    },
+}
+
+/// A line of the audit log. The hash commits to the rest of the entry, including the hash of the
+/// previous entry, so no entry can be changed or removed without breaking the chain.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Entry {
+    pub seq: u64,
+    /// Unix time the entry was written.
+    pub time: u64,
+    #[serde(flatten)]
+    pub event: Event,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl Entry {
+    fn compute_hash(seq: u64, time: u64, event: &Event, prev_hash: &str) -> String {
+        let body = serde_json::json!({
+            "seq": seq,
+            "time": time,
+            "event": event,
+            "prev_hash": prev_hash,
+        });
+        hex::encode(Sha256::digest(body.to_string().as_bytes()))
+    }
+}
+
+/// Hex encoded SHA-256 of `req`, as recorded in the log.
+pub fn request_hash<T: Serialize>(req: &T) -> String {
+    let data = serde_json::to_vec(req).unwrap();
+    hex::encode(Sha256::digest(&data))
+}
+
+/// An audit log file, appended to one JSON entry per line.
+pub struct AuditLog {
+    path: PathBuf,
+    /// Sequence number and hash of the last entry.
+    tail: Mutex<(u64, String)>,
+}
+
+impl AuditLog {
+    /// Opens the log at `path`, verifying the entries already in it.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let tail = match path.exists() {
+            true => verify(path)?,
+            false => (0, GENESIS.to_string()),
+        };
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            tail: Mutex::new(tail),
+        })
+    }
+
+    /// Appends `event` to the log.
+    pub fn record(&self, event: Event) -> Result<(), Box<dyn std::error::Error>> {
+        let mut tail = self.tail.lock().unwrap();
+        let seq = tail.0 + 1;
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let hash = Entry::compute_hash(seq, time, &event, &tail.1);
+        let entry = Entry {
+            seq,
+            time,
+            event,
+            prev_hash: tail.1.clone(),
+            hash: hash.clone(),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.sync_data()?;
+        *tail = (seq, hash);
+        Ok(())
+    }
+
+    /// Syncs the log to disk.
+    pub fn flush(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let _tail = self.tail.lock().unwrap();
+        if self.path.exists() {
+            fs::File::open(&self.path)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+/// Verifies the hash chain of the log at `path`, returning the sequence number and hash of its
+/// last entry.
+pub fn verify(path: &Path) -> Result<(u64, String), Box<dyn std::error::Error>> {
+    let data = fs::read_to_string(path)?;
+    let mut tail = (0, GENESIS.to_string());
+    for (i, line) in data.lines().enumerate() {
+        let entry: Entry = serde_json::from_str(line)
+            .map_err(|e| format!("line {}: invalid entry: {}", i + 1, e))?;
+        if entry.seq != tail.0 + 1 {
+            return Err(format!(
+                "line {}: expected entry {}, found {}",
+                i + 1,
+                tail.0 + 1,
+                entry.seq
+            )
+            .into());
+        }
+        if entry.prev_hash != tail.1 {
+            return Err(format!("line {}: not chained to the previous entry", i + 1).into());
+        }
+        let hash = Entry::compute_hash(entry.seq, entry.time, &entry.event, &entry.prev_hash);
+        if entry.hash != hash {
+            return Err(format!("line {}: hash does not match the entry", i + 1).into());
+        }
+        tail = (entry.seq, entry.hash);
+    }
+    Ok(tail)
+}
@@ -0,0 +1,204 @@
+//! Command line client for the signer service's admin API.
+//!
+//! The token the API is authenticated with is read from EPHEMERAL_SIGN_ADMIN_TOKEN.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use bitcoin::Txid;
+use clap::{Parser, Subcommand};
+use shared::SignPsbtResp;
+use shared::admin::{
+    ADMIN_TOKEN_ENV, BroadcastResp, DepositsResp, PresignedResp, SessionInfo, SessionState,
+    SessionsResp,
+};
+
+#[derive(Debug, Parser)]
+#[command(verbatim_doc_comment)]
+struct Cli {
+    /// Address the admin API listens on, as given to the signer with --admin-listen.
+    #[arg(long)]
+    admin_url: SocketAddr,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List the active sessions and the most recently finished ones.
+    Sessions {
+        /// Only list the active sessions.
+        #[arg(long)]
+        active: bool,
+    },
+
+    /// Revoke a session that has not been signed yet.
+    Revoke { id: u64 },
+
+    /// List the deposits counting against the liability ceiling.
+    Deposits,
+
+    /// Print the policy in effect, as JSON.
+    Policy,
+
+    /// Replace the policy until the signer is restarted.
+    SetPolicy {
+        /// JSON file with the new policy, in the format of the `policy` field of the config.
+        file: PathBuf,
+    },
+
+    /// List the deposits the signer kept the presigned spends of, or print the presigned
+    /// transactions of one of them as JSON.
+    Presigned { deposit_txid: Option<Txid> },
+
+    /// Broadcast the presigned spend of a deposit again.
+    Rebroadcast { deposit_txid: Txid },
+}
+
+fn print_session(s: &SessionInfo) {
+    let state = match &s.state {
+        SessionState::Failed { reason } => format!("failed: {}", reason),
+        state => format!("{:?}", state).to_lowercase(),
+    };
+    println!(
+        "{:>6}  {}  {}  {}  {}  {}",
+        s.id,
+        s.started_at,
+        s.peer.as_deref().unwrap_or("-"),
+        s.deposit_txid
+            .map_or("-".to_string(), |txid| txid.to_string()),
+        s.deposit_sat
+            .map_or("-".to_string(), |sat| format!("{} sat", sat)),
+        state
+    );
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let token =
+        std::env::var(ADMIN_TOKEN_ENV).map_err(|_| format!("{} must be set", ADMIN_TOKEN_ENV))?;
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("http://{}/admin/{}", cli.admin_url, path);
+
+    match &cli.command {
+        Command::Sessions { active } => {
+            let resp: SessionsResp = client
+                .get(url("sessions"))
+                .bearer_auth(&token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            println!("Active sessions:");
+            resp.active.iter().for_each(print_session);
+            if !active {
+                println!("Finished sessions:");
+                resp.history.iter().for_each(print_session);
+            }
+        }
+        Command::Revoke { id } => {
+            let resp = client
+                .delete(url(&format!("sessions/{}", id)))
+                .bearer_auth(&token)
+                .send()
+                .await?;
+            if !resp.status().is_success() {
+                return Err(
+                    format!("unable to revoke session {}: {}", id, resp.text().await?).into(),
+                );
+            }
+            println!("Revoked session {}", id);
+        }
+        Command::Deposits => {
+            let resp: DepositsResp = client
+                .get(url("deposits"))
+                .bearer_auth(&token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            for d in &resp.deposits {
+                println!("{}  {} sat  {}", d.deposit_txid, d.value_sat, d.created_at);
+            }
+            match resp.max_sat {
+                None => println!("Liability tracking is disabled"),
+                Some(max) => println!(
+                    "Total: {} sat of {} sat in {} deposits",
+                    resp.total_sat,
+                    max,
+                    resp.deposits.len()
+                ),
+            }
+        }
+        Command::Policy => {
+            let policy: serde_json::Value = client
+                .get(url("policy"))
+                .bearer_auth(&token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&policy)?);
+        }
+        Command::SetPolicy { file } => {
+            let policy: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+            let resp = client
+                .put(url("policy"))
+                .bearer_auth(&token)
+                .json(&policy)
+                .send()
+                .await?;
+            if !resp.status().is_success() {
+                return Err(format!("unable to set policy: {}", resp.text().await?).into());
+            }
+            println!("Policy updated");
+        }
+        Command::Presigned { deposit_txid: None } => {
+            let resp: PresignedResp = client
+                .get(url("presigned"))
+                .bearer_auth(&token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            for txid in &resp.deposit_txids {
+                println!("{}", txid);
+            }
+        }
+        Command::Presigned {
+            deposit_txid: Some(txid),
+        } => {
+            let resp = client
+                .get(url(&format!("presigned/{}", txid)))
+                .bearer_auth(&token)
+                .send()
+                .await?;
+            if !resp.status().is_success() {
+                return Err(
+                    format!("no presigned spend for {}: {}", txid, resp.text().await?).into(),
+                );
+            }
+            let resp: SignPsbtResp = resp.json().await?;
+            println!("{}", serde_json::to_string_pretty(&resp)?);
+        }
+        Command::Rebroadcast { deposit_txid } => {
+            let resp = client
+                .post(url(&format!("presigned/{}/broadcast", deposit_txid)))
+                .bearer_auth(&token)
+                .send()
+                .await?;
+            if !resp.status().is_success() {
+                return Err(format!("unable to rebroadcast: {}", resp.text().await?).into());
+            }
+            let resp: BroadcastResp = resp.json().await?;
+            println!("Broadcast spend {}", resp.txid);
+        }
+    }
+    Ok(())
+}
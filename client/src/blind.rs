@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use actix_web::{HttpRequest, Responder, post, web};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Amount, OutPoint, Psbt, Sequence, TxOut, absolute};
+use musig2::secp::MaybePoint;
+use shared::blind::{BlindInitReq, BlindInitResp, BlindSignReq, BlindSignResp, template_sighash};
+use shared::{PROTOCOL_VERSIONS, PolicyRule, PolicyViolation, SignPsbtError};
+
+use crate::error::{self, PsbtError};
+use crate::{
+    AppState, EphemeralKey, SignTarget, SpendSig, audit, build_spend_psbt, fee, init_ephemeral_key,
+    parse_fallback, ratelimit, record, reject, signing_error, spend_error,
+};
+
+pub fn default_ttl() -> u64 {
+    600
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// A blind session waiting for the depositor to commit to its deposit outpoint.
+struct Pending {
+    key: EphemeralKey,
+    template: Psbt,
+    expires_at: u64,
+}
+
+/// The blind sessions started and not yet signed or expired.
+#[derive(Default)]
+pub struct BlindSessions {
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl BlindSessions {
+    fn insert(&self, session_id: String, session: Pending) {
+        let now = now();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, s| s.expires_at > now);
+        pending.insert(session_id, session);
+    }
+
+    /// Takes session `session_id` out, so its key signs at most once.
+    fn take(&self, session_id: &str) -> Option<Pending> {
+        let session = self.pending.lock().unwrap().remove(session_id)?;
+        (session.expires_at > now()).then_some(session)
+    }
+}
+
+/// Starts a blind session, returning the ephemeral key and the spend the signers will sign once
+/// the depositor commits to the outpoint funding it.
+#[utoipa::path(
+    request_body = BlindInitReq,
+    responses(
+        (status = 200, description = "The deposit key and the spend template",
+            body = BlindInitResp),
+        (status = "4XX", description = "The request was refused", body = SignPsbtError),
+        (status = "5XX", description = "The signers failed to start", body = SignPsbtError)
+    )
+)]
+#[post("/blind/init")]
+pub async fn init(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<BlindInitReq>,
+) -> actix_web::Result<impl Responder> {
+    handle_init(&data, &http_req, req.into_inner())
+        .await
+        .map(web::Json)
+        .map_err(error::structured)
+}
+
+async fn handle_init(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    req: BlindInitReq,
+) -> actix_web::Result<BlindInitResp> {
+    if data.draining.load(Ordering::SeqCst) {
+        return Err(PsbtError(SignPsbtError::Unavailable {
+            message: "signer is shutting down".to_string(),
+        })
+        .into());
+    }
+
+    let cfg = &data.cfg;
+    let policy = data.policy.read().unwrap().clone();
+
+    let api_key = http_req
+        .headers()
+        .get(ratelimit::API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    data.rate_limiter
+        .check(
+            &cfg.rate_limit,
+            http_req.peer_addr().map(|a| a.ip()),
+            api_key,
+        )
+        .map_err(|limited| {
+            data.metrics.policy_rejection(&PolicyRule::RateLimit);
+            ratelimit::rejection(limited)
+        })?;
+
+    if req.network != cfg.network {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "request is for {}, but this signer is on {}",
+            req.network, cfg.network
+        )));
+    }
+    if !PROTOCOL_VERSIONS.contains(&req.version) {
+        return Err(PsbtError(SignPsbtError::Unsupported {
+            message: format!("request is in protocol version {}", req.version),
+            versions: PROTOCOL_VERSIONS.to_vec(),
+            features: vec![],
+        })
+        .into());
+    }
+
+    if let (Some(quota), Some(peer)) = (&policy.quota, http_req.peer_addr()) {
+        data.quotas
+            .take(peer.ip(), quota)
+            .map_err(|v| reject(data, v))?;
+    }
+    policy
+        .check_blind_request(&req)
+        .map_err(|v| reject(data, v))?;
+    // The liability is counted by deposit txid, which a blind session keeps from the signer.
+    if data.liability.is_some() {
+        return Err(reject(
+            data,
+            PolicyViolation {
+                rule: PolicyRule::Liability,
+                message: "blind sessions are not served with a liability ceiling".to_string(),
+            },
+        ));
+    }
+    let fallback_script = parse_fallback(&req.fallback_addr, cfg.network)?;
+    policy
+        .check_fallback(&fallback_script)
+        .map_err(|v| reject(data, v))?;
+
+    let fee_rule = match fee::FeeRule::resolve(&cfg.fee).await {
+        Ok(r) => r,
+        Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
+    };
+
+    let secp = Secp256k1::new();
+    let key = init_ephemeral_key(cfg, &secp, vec![], None).await?;
+
+    // The template spends a null outpoint in place of the deposit's. Only the prevouts hash the
+    // depositor commits to the deposit outpoint with goes into the sighash.
+    let deposit_output = TxOut {
+        value: Amount::from_sat(req.amount_sat).unwrap(),
+        script_pubkey: key.script_pubkey(),
+    };
+    let (template, _, _) = build_spend_psbt(
+        OutPoint::null(),
+        &deposit_output,
+        None,
+        fallback_script,
+        absolute::LockTime::ZERO,
+        Sequence::ENABLE_RBF_NO_LOCKTIME,
+        &fee_rule,
+    )
+    .map_err(spend_error)?;
+    let spend_fee = fee_rule.fee(&template.unsigned_tx, deposit_output.value);
+    policy
+        .check_spend_fee(Amount::from_sat(spend_fee.fee_sat).unwrap())
+        .map_err(|v| reject(data, v))?;
+
+    record(
+        data,
+        audit::Event::BlindSessionStarted {
+            request_hash: audit::request_hash(&req),
+            ephemeral_pubkey: key.internal_key,
+        },
+    )?;
+
+    let session_id = hex::encode(rand::random::<[u8; 32]>());
+    let expires_at = now() + cfg.blind_session_ttl_secs;
+    let resp = BlindInitResp {
+        session_id: session_id.clone(),
+        internal_key: key.internal_key.to_string(),
+        template: template.clone(),
+        spend_fee,
+        expires_at,
+    };
+    data.blind_sessions.insert(
+        session_id,
+        Pending {
+            key,
+            template,
+            expires_at,
+        },
+    );
+    Ok(resp)
+}
+
+/// Signs the template of a blind session spending the outpoint committed to, after which the
+/// signers delete the key.
+#[utoipa::path(
+    request_body = BlindSignReq,
+    responses(
+        (status = 200, description = "The signature of the template", body = BlindSignResp),
+        (status = "4XX", description = "The request was refused", body = SignPsbtError),
+        (status = "5XX", description = "The signers failed to sign", body = SignPsbtError)
+    )
+)]
+#[post("/blind/sign")]
+pub async fn sign(
+    data: web::Data<AppState>,
+    req: web::Json<BlindSignReq>,
+) -> actix_web::Result<impl Responder> {
+    data.metrics.session_started();
+    let res = handle_sign(&data, req.into_inner())
+        .await
+        .map_err(error::structured);
+    match &res {
+        Ok(_) => data.metrics.session_completed(),
+        Err(e) => data
+            .metrics
+            .session_failed(e.as_response_error().status_code()),
+    }
+    res.map(web::Json)
+}
+
+async fn handle_sign(
+    data: &web::Data<AppState>,
+    req: BlindSignReq,
+) -> actix_web::Result<BlindSignResp> {
+    let prevouts_hash: [u8; 32] = hex::decode(&req.prevouts_hash)
+        .ok()
+        .and_then(|h| h.try_into().ok())
+        .ok_or_else(|| {
+            actix_web::error::ErrorBadRequest("prevouts hash must be 32 hex encoded bytes")
+        })?;
+    let session = data.blind_sessions.take(&req.session_id).ok_or_else(|| {
+        actix_web::error::ErrorNotFound(format!(
+            "unknown or expired blind session {}",
+            req.session_id
+        ))
+    })?;
+
+    let sighash = template_sighash(&session.template, prevouts_hash)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    let target = SignTarget {
+        message: sighash.to_vec(),
+        adaptor_point: MaybePoint::Infinity,
+    };
+
+    let ephemeral_pubkey = session.key.internal_key;
+    let started = Instant::now();
+    let (sig, enclave_attestations) = session.key.sign(&target).await.map_err(signing_error)?;
+    data.metrics.signed(started.elapsed());
+    record(
+        data,
+        audit::Event::BlindSigned {
+            ephemeral_pubkey,
+            sighash: hex::encode(sighash),
+        },
+    )?;
+
+    match sig {
+        SpendSig::Final(signature) => Ok(BlindSignResp {
+            signature: hex::encode(signature),
+            enclave_attestations,
+        }),
+        SpendSig::Adaptor(_) => unreachable!("blind sessions are never adaptor signed"),
+    }
+}
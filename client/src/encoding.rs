@@ -0,0 +1,66 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::body::BoxBody;
+use actix_web::dev::Payload;
+use actix_web::http::header::{ACCEPT, CONTENT_TYPE};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, Responder, web};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use shared::encoding;
+
+/// Largest body taken by `Encoded`, the limit `web::Json` has.
+pub const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// A protocol message, taken as CBOR if the request's content type says so and JSON otherwise,
+/// and responded as CBOR if the request accepts it. Error responses are always JSON.
+#[derive(Debug)]
+pub struct Encoded<T>(pub T);
+
+impl<T> std::ops::Deref for Encoded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+fn header<'a>(req: &'a HttpRequest, name: actix_web::http::header::HeaderName) -> &'a str {
+    req.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Encoded<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let cbor = encoding::is_cbor(header(req, CONTENT_TYPE));
+        let body = web::Bytes::from_request(req, payload);
+        Box::pin(async move {
+            let body = body.await?;
+            let value = if cbor {
+                encoding::from_cbor(&body).map_err(actix_web::error::ErrorBadRequest)?
+            } else {
+                serde_json::from_slice(&body).map_err(actix_web::error::ErrorBadRequest)?
+            };
+            Ok(Encoded(value))
+        })
+    }
+}
+
+impl<T: Serialize> Responder for Encoded<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        if !encoding::accepts_cbor(header(req, ACCEPT)) {
+            return HttpResponse::Ok().json(&self.0);
+        }
+        match encoding::to_cbor(&self.0) {
+            Ok(body) => HttpResponse::Ok().content_type(encoding::CBOR).body(body),
+            Err(e) => actix_web::error::ErrorInternalServerError(e.to_string()).error_response(),
+        }
+    }
+}
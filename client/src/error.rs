@@ -0,0 +1,48 @@
+use std::fmt;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use shared::SignPsbtError;
+
+/// An error responded with its status code and itself as JSON body.
+#[derive(Debug)]
+pub struct PsbtError(pub SignPsbtError);
+
+impl fmt::Display for PsbtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ResponseError for PsbtError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.0.status()).unwrap()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let SignPsbtError::RateLimited {
+            retry_after_secs, ..
+        } = &self.0
+        {
+            response.insert_header(("Retry-After", retry_after_secs.to_string()));
+        }
+        response.json(&self.0)
+    }
+}
+
+/// Turns `e` into a structured error, keeping its status code, unless it is one already.
+pub fn structured(e: actix_web::Error) -> actix_web::Error {
+    if e.as_error::<PsbtError>().is_some() {
+        return e;
+    }
+    let message = e.to_string();
+    let status = e.as_response_error().status_code();
+    let e = match status.as_u16() {
+        400 | 404 | 413 | 415 => SignPsbtError::InvalidRequest { message },
+        409 => SignPsbtError::Revoked { message },
+        502..=504 => SignPsbtError::Unavailable { message },
+        _ => SignPsbtError::Internal { message },
+    };
+    PsbtError(e).into()
+}
@@ -0,0 +1,172 @@
+use bitcoin::taproot::ControlBlock;
+use bitcoin::{Amount, Script, Transaction, Witness};
+use serde::{Deserialize, Serialize};
+use shared::{FeeTerms, QuotedRate, SpendFee};
+
+/// Weight a presigned spend gains when signed: the segwit marker and flag, and a witness with a
+/// single 64 byte signature.
+const KEY_SPEND_WITNESS_WEIGHT: u64 = 68;
+
+/// How the fee of the presigned spends is chosen, set in the `fee` field of the config.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FeeConfig {
+    #[serde(flatten)]
+    pub mode: FeeMode,
+    /// Cap on the fee, as a percentage of the value spent.
+    #[serde(default)]
+    pub max_percent: Option<f64>,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        FeeConfig {
+            mode: FeeMode::Fixed { sat: 500 },
+            max_percent: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum FeeMode {
+    /// The same fee for every spend.
+    Fixed { sat: u64 },
+    /// A fixed feerate, in sat/vB.
+    Feerate { sat_per_vb: u64 },
+    /// The feerate the Esplora API at `esplora_url` estimates for confirmation within
+    /// `target_blocks`, at signing time.
+    Estimate {
+        target_blocks: u32,
+        esplora_url: String,
+    },
+}
+
+/// The fee rule for the spends of a single request, with any estimate already fetched.
+pub struct FeeRule {
+    mode: Rule,
+    max_percent: Option<f64>,
+}
+
+enum Rule {
+    Fixed(u64),
+    Feerate { sat_per_vb: f64, source: String },
+}
+
+impl FeeRule {
+    /// Resolves `cfg` for a request being signed now.
+    pub async fn resolve(cfg: &FeeConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mode = match &cfg.mode {
+            FeeMode::Fixed { sat } => Rule::Fixed(*sat),
+            FeeMode::Feerate { sat_per_vb } => Rule::Feerate {
+                sat_per_vb: *sat_per_vb as f64,
+                source: "configured feerate".to_string(),
+            },
+            FeeMode::Estimate {
+                target_blocks,
+                esplora_url,
+            } => Rule::Feerate {
+                sat_per_vb: estimate(esplora_url, *target_blocks).await?,
+                source: format!("estimate for confirmation within {} blocks", target_blocks),
+            },
+        };
+        Ok(FeeRule {
+            mode,
+            max_percent: cfg.max_percent,
+        })
+    }
+
+    /// The rule as quoted to depositors.
+    pub fn terms(&self) -> FeeTerms {
+        let rate = match &self.mode {
+            Rule::Fixed(sat) => QuotedRate::Fixed { sat: *sat },
+            Rule::Feerate { sat_per_vb, .. } => QuotedRate::Feerate {
+                sat_per_vb: *sat_per_vb,
+            },
+        };
+        FeeTerms {
+            rate,
+            max_percent: self.max_percent,
+        }
+    }
+
+    /// The rule a quote with `terms` was issued on.
+    pub fn quoted(terms: &FeeTerms) -> Self {
+        let mode = match &terms.rate {
+            QuotedRate::Fixed { sat } => Rule::Fixed(*sat),
+            QuotedRate::Feerate { sat_per_vb } => Rule::Feerate {
+                sat_per_vb: *sat_per_vb,
+                source: "quoted feerate".to_string(),
+            },
+        };
+        FeeRule {
+            mode,
+            max_percent: terms.max_percent,
+        }
+    }
+
+    /// Fee of `tx`, an unsigned key spend of an output worth `input_value`, and why it was
+    /// chosen.
+    pub fn fee(&self, tx: &Transaction, input_value: Amount) -> SpendFee {
+        self.fee_for_weight(tx.weight().to_wu() + KEY_SPEND_WITNESS_WEIGHT, input_value)
+    }
+
+    /// Like `fee`, but for a spend through the single signature leaf `script`, which adds the
+    /// script and its control block to the witness.
+    pub fn script_spend_fee(
+        &self,
+        tx: &Transaction,
+        input_value: Amount,
+        script: &Script,
+        control_block: &ControlBlock,
+    ) -> SpendFee {
+        let mut witness = Witness::new();
+        witness.push([0; 64]);
+        witness.push(script.as_bytes());
+        witness.push(control_block.serialize());
+        // The segwit marker and flag, and the witness.
+        let weight = tx.weight().to_wu() + 2 + witness.size() as u64;
+        self.fee_for_weight(weight, input_value)
+    }
+
+    fn fee_for_weight(&self, weight: u64, input_value: Amount) -> SpendFee {
+        let vsize = weight.div_ceil(4);
+        let (mut fee, mut rationale) = match &self.mode {
+            Rule::Fixed(sat) => (*sat, format!("fixed fee of {} sat", sat)),
+            Rule::Feerate { sat_per_vb, source } => (
+                (sat_per_vb * vsize as f64).ceil() as u64,
+                format!("{:.1} sat/vB {} for {} vB", sat_per_vb, source, vsize),
+            ),
+        };
+
+        if let Some(percent) = self.max_percent {
+            let cap = (input_value.to_sat() as f64 * percent / 100.0) as u64;
+            if fee > cap {
+                rationale = format!("{}, capped at {}% of {}", rationale, percent, input_value);
+                fee = cap;
+            }
+        }
+
+        SpendFee {
+            fee_sat: fee,
+            rationale,
+        }
+    }
+}
+
+/// Feerate in sat/vB the Esplora API at `url` estimates for confirmation within `target_blocks`.
+/// Esplora only estimates some targets, so the closest one not above it is used.
+async fn estimate(url: &str, target_blocks: u32) -> Result<f64, Box<dyn std::error::Error>> {
+    let estimates: std::collections::HashMap<String, f64> =
+        reqwest::get(format!("{}/fee-estimates", url.trim_end_matches('/')))
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+    estimates
+        .iter()
+        .filter_map(|(target, rate)| Some((target.parse::<u32>().ok()?, *rate)))
+        .filter(|(target, _)| *target <= target_blocks)
+        .max_by_key(|(target, _)| *target)
+        .map(|(_, rate)| rate)
+        .ok_or_else(|| format!("no fee estimate for {} blocks", target_blocks).into())
+}
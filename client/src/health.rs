@@ -0,0 +1,31 @@
+use bitcoin::BlockHash;
+use serde::Deserialize;
+use shared::ChainTip;
+
+#[derive(Deserialize)]
+struct Block {
+    height: u32,
+    timestamp: u64,
+}
+
+/// Chain tip according to the Esplora API at `url`.
+pub async fn chain_tip(url: &str) -> Result<ChainTip, Box<dyn std::error::Error>> {
+    let url = url.trim_end_matches('/');
+    let hash: BlockHash = reqwest::get(format!("{}/blocks/tip/hash", url))
+        .await?
+        .error_for_status()?
+        .text()
+        .await?
+        .trim()
+        .parse()?;
+    let block: Block = reqwest::get(format!("{}/block/{}", url, hash))
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(ChainTip {
+        height: block.height,
+        hash,
+        time: block.timestamp,
+    })
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use shared::{SignPsbtError, SignPsbtResp};
+
+/// Longest idempotency key taken.
+const MAX_KEY_LEN: usize = 128;
+
+pub fn default_ttl() -> u64 {
+    86400
+}
+
+enum State {
+    /// The request is being signed.
+    InFlight,
+    Done(SignPsbtResp),
+}
+
+struct Entry {
+    /// Hash of the request the key was first used for.
+    request_hash: String,
+    state: State,
+    at: Instant,
+}
+
+/// The responses to requests carrying an idempotency key, replayed to retries of them so a
+/// retry does not cost the signers another key. They are kept in memory for the configured
+/// time.
+#[derive(Default)]
+pub struct Replays {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl Replays {
+    /// Starts serving the request with key `key` and hash `request_hash`, returning the
+    /// response to replay if it was served already. A request with the key must not be in
+    /// flight, and the key not have been used for another request.
+    pub fn begin(
+        &self,
+        key: &str,
+        request_hash: String,
+        ttl: Duration,
+    ) -> Result<Option<SignPsbtResp>, SignPsbtError> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Err(SignPsbtError::InvalidRequest {
+                message: format!(
+                    "idempotency key must be between 1 and {} bytes",
+                    MAX_KEY_LEN
+                ),
+            });
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, e| matches!(e.state, State::InFlight) || e.at.elapsed() < ttl);
+        let Some(entry) = entries.get(key) else {
+            entries.insert(
+                key.to_string(),
+                Entry {
+                    request_hash,
+                    state: State::InFlight,
+                    at: Instant::now(),
+                },
+            );
+            return Ok(None);
+        };
+        if entry.request_hash != request_hash {
+            return Err(SignPsbtError::InvalidRequest {
+                message: format!("idempotency key {} was used for another request", key),
+            });
+        }
+        match &entry.state {
+            State::InFlight => Err(SignPsbtError::Unavailable {
+                message: format!(
+                    "the request with idempotency key {} is being signed, retry later",
+                    key
+                ),
+            }),
+            State::Done(resp) => Ok(Some(resp.clone())),
+        }
+    }
+
+    /// Finishes the request with key `key`, keeping its response for replay. A failed request
+    /// handed nothing out, so a retry of it is served anew.
+    pub fn finish(&self, key: &str, resp: Option<&SignPsbtResp>) {
+        let mut entries = self.entries.lock().unwrap();
+        match resp {
+            Some(resp) => {
+                if let Some(entry) = entries.get_mut(key) {
+                    entry.state = State::Done(resp.clone());
+                    entry.at = Instant::now();
+                }
+            }
+            None => {
+                entries.remove(key);
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::vendor_defined::VendorDefinedMechanism;
+use cryptoki::mechanism::{Mechanism, MechanismType};
+use cryptoki::object::{Attribute, AttributeType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use musig2::secp::{Point, Scalar};
+use shared::receipt::Receipt;
+
+/// Environment variable holding the user PIN of the HSM token.
+pub const PKCS11_PIN_ENV: &str = "EPHEMERAL_SIGN_PKCS11_PIN";
+
+/// Options for keeping the operator's identity key in an HSM.
+#[derive(Debug, clap::Args)]
+pub struct Pkcs11Args {
+    /// PKCS#11 module of the HSM holding the operator's identity key, to sign the receipts in the
+    /// HSM rather than with a key loaded from --identity-key-file. The user PIN is taken from
+    /// the EPHEMERAL_SIGN_PKCS11_PIN environment variable.
+    #[arg(long, conflicts_with = "identity_key_file")]
+    pub pkcs11_module: Option<PathBuf>,
+
+    /// Label of the token holding the identity key. Defaults to the first token found.
+    #[arg(long, requires = "pkcs11_module")]
+    pub pkcs11_token: Option<String>,
+
+    /// Label of the identity key pair on the token.
+    #[arg(long, default_value = "ephemeral-sign-identity")]
+    pub pkcs11_key_label: String,
+
+    /// Hex encoded vendor defined mechanism the HSM makes BIP-340 signatures over secp256k1
+    /// with, as PKCS#11 defines none.
+    #[arg(long, requires = "pkcs11_module")]
+    pub pkcs11_mechanism: Option<String>,
+}
+
+/// The operator's identity key the receipts are signed with.
+pub enum IdentityKey {
+    /// Loaded into memory from --identity-key-file.
+    Local(Scalar),
+    /// Kept in an HSM, of which only a handle is in memory.
+    Hsm(HsmKey),
+}
+
+impl IdentityKey {
+    /// Loads the identity key configured by `file` or `pkcs11`, if any.
+    pub fn load(
+        file: Option<&PathBuf>,
+        pkcs11: &Pkcs11Args,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if let Some(path) = file {
+            let key = std::fs::read_to_string(path)?;
+            return Ok(Some(IdentityKey::Local(Scalar::from_hex(key.trim())?)));
+        }
+        match &pkcs11.pkcs11_module {
+            None => Ok(None),
+            Some(module) => Ok(Some(IdentityKey::Hsm(HsmKey::open(module, pkcs11)?))),
+        }
+    }
+
+    pub fn pubkey(&self) -> Point {
+        match self {
+            IdentityKey::Local(key) => key.base_point_mul(),
+            IdentityKey::Hsm(key) => key.pubkey,
+        }
+    }
+
+    /// Signs `receipt` with the key, checking the signature of an HSM before handing it out.
+    pub fn sign_receipt(&self, receipt: &mut Receipt) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            IdentityKey::Local(key) => receipt.sign(*key, &rand::random()),
+            IdentityKey::Hsm(key) => {
+                receipt.sign_with(key.pubkey, |message| key.sign(message))?;
+                receipt.verify()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Handle to an identity key pair on a PKCS#11 token, signing in a logged in session.
+pub struct HsmKey {
+    /// A session must not be used by several threads at once.
+    session: Mutex<Session>,
+    key: ObjectHandle,
+    mechanism: MechanismType,
+    pubkey: Point,
+}
+
+impl HsmKey {
+    fn open(module: &PathBuf, args: &Pkcs11Args) -> Result<Self, Box<dyn std::error::Error>> {
+        let mechanism = args
+            .pkcs11_mechanism
+            .as_deref()
+            .ok_or("--pkcs11-mechanism is required with --pkcs11-module")?;
+        let mechanism = u64::from_str_radix(mechanism.trim_start_matches("0x"), 16)?;
+        let mechanism = MechanismType::new_vendor_defined(mechanism as _)?;
+
+        let pkcs11 = Pkcs11::new(module)?;
+        pkcs11.initialize(CInitializeArgs::OsThreads)?;
+        let slots = pkcs11.get_slots_with_token()?;
+        let slot = match &args.pkcs11_token {
+            None => slots.first().copied().ok_or("no PKCS#11 token found")?,
+            Some(label) => slots
+                .into_iter()
+                .find(|slot| {
+                    pkcs11
+                        .get_token_info(*slot)
+                        .is_ok_and(|info| info.label() == label)
+                })
+                .ok_or(format!("no PKCS#11 token labeled {}", label))?,
+        };
+
+        let session = pkcs11.open_ro_session(slot)?;
+        let pin = std::env::var(PKCS11_PIN_ENV)
+            .map_err(|_| format!("{} not set for the PKCS#11 token", PKCS11_PIN_ENV))?;
+        session.login(UserType::User, Some(&AuthPin::new(pin.into())))?;
+
+        let find = |class| -> Result<ObjectHandle, Box<dyn std::error::Error>> {
+            let template = [
+                Attribute::Class(class),
+                Attribute::Label(args.pkcs11_key_label.as_bytes().to_vec()),
+            ];
+            let found = session.find_objects(&template)?;
+            found.into_iter().next().ok_or_else(|| {
+                format!("no key {} on the PKCS#11 token", args.pkcs11_key_label).into()
+            })
+        };
+        let key = find(ObjectClass::PRIVATE_KEY)?;
+        let public = find(ObjectClass::PUBLIC_KEY)?;
+
+        let attributes = session.get_attributes(public, &[AttributeType::EcPoint])?;
+        let Some(Attribute::EcPoint(point)) = attributes.into_iter().next() else {
+            return Err("identity key on the PKCS#11 token is not an EC key".into());
+        };
+        // The point is DER encoded as an octet string.
+        let point = match point.as_slice() {
+            [0x04, len, rest @ ..] if *len as usize == rest.len() => rest,
+            point => point,
+        };
+        let pubkey = Point::from_slice(point)?;
+
+        Ok(HsmKey {
+            session: Mutex::new(session),
+            key,
+            mechanism,
+            pubkey,
+        })
+    }
+
+    fn sign(&self, message: [u8; 32]) -> Result<[u8; 64], Box<dyn std::error::Error>> {
+        let mechanism =
+            Mechanism::VendorDefined(VendorDefinedMechanism::new::<()>(self.mechanism, None));
+        let session = self.session.lock().unwrap();
+        let signature = session.sign(&mechanism, self.key, &message)?;
+        Ok(signature
+            .try_into()
+            .map_err(|_| "HSM made a signature that is not BIP-340")?)
+    }
+}
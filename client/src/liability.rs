@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::{Amount, Txid};
+use serde::{Deserialize, Serialize};
+use shared::{PolicyRule, PolicyViolation};
+
+/// Bound on the total value of deposits the signers presigned for, set in the `liability` field
+/// of the config.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LiabilityConfig {
+    /// Ceiling on the value of all tracked deposits, in sats.
+    pub max_sat: u64,
+    /// File the tracked deposits are kept in across restarts.
+    #[serde(default = "default_file")]
+    pub file: PathBuf,
+    /// Esplora API to check the deposits with. A deposit stops counting once its output is spent
+    /// by a confirmed transaction. Without it deposits are tracked until removed from the file.
+    #[serde(default)]
+    pub esplora_url: Option<String>,
+}
+
+fn default_file() -> PathBuf {
+    PathBuf::from("liability.json")
+}
+
+/// A deposit the signers presigned a spend for.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Deposit {
+    pub deposit_txid: Txid,
+    pub value_sat: u64,
+    /// Unix time the spend was presigned at.
+    pub created_at: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct Deposits {
+    deposits: Vec<Deposit>,
+}
+
+fn load(path: &Path) -> io::Result<Deposits> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Deposits::default()),
+        Err(e) => return Err(with_path(path, e)),
+    };
+    serde_json::from_str(&data).map_err(|e| with_path(path, io::Error::other(e)))
+}
+
+fn store(path: &Path, deposits: &Deposits) -> io::Result<()> {
+    let data = serde_json::to_string_pretty(deposits).unwrap();
+    fs::write(path, data).map_err(|e| with_path(path, e))
+}
+
+fn with_path(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!("liability file {}: {}", path.display(), e),
+    )
+}
+
+/// Turns a failure to read or write the liability file into an error response.
+pub fn file_error(e: io::Error) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e.to_string())
+}
+
+/// The deposits counting against the liability ceiling.
+pub struct Liability {
+    cfg: LiabilityConfig,
+    /// Number of live reservations of each deposit that counts until its sessions commit or
+    /// fail. The lock also serializes access to the file.
+    pending: Mutex<HashMap<Txid, usize>>,
+}
+
+impl Liability {
+    pub fn new(cfg: LiabilityConfig) -> Self {
+        Liability {
+            cfg,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Drops the deposits whose outputs are spent by a confirmed transaction, if an Esplora API
+    /// is configured. Deposits that cannot be checked keep counting.
+    pub async fn refresh(&self) -> io::Result<()> {
+        let Some(url) = &self.cfg.esplora_url else {
+            return Ok(());
+        };
+        let tracked = {
+            let _guard = self.pending.lock().unwrap();
+            load(&self.cfg.file)?.deposits
+        };
+
+        let mut settled = vec![];
+        for deposit in &tracked {
+            match spent_confirmed(url, &deposit.deposit_txid).await {
+                Ok(true) => settled.push(deposit.deposit_txid),
+                Ok(false) => {}
+                Err(e) => println!("unable to check deposit {}: {}", deposit.deposit_txid, e),
+            }
+        }
+        if settled.is_empty() {
+            return Ok(());
+        }
+
+        let _guard = self.pending.lock().unwrap();
+        let mut deposits = load(&self.cfg.file)?;
+        deposits
+            .deposits
+            .retain(|d| !settled.contains(&d.deposit_txid));
+        store(&self.cfg.file, &deposits)?;
+        println!("released liability for {} settled deposits", settled.len());
+        Ok(())
+    }
+
+    /// Number and total value in sats of the tracked deposits.
+    pub fn outstanding(&self) -> io::Result<(usize, u64)> {
+        let _guard = self.pending.lock().unwrap();
+        let deposits = load(&self.cfg.file)?.deposits;
+        Ok((deposits.len(), deposits.iter().map(|d| d.value_sat).sum()))
+    }
+
+    /// The tracked deposits.
+    pub fn deposits(&self) -> io::Result<Vec<Deposit>> {
+        let _guard = self.pending.lock().unwrap();
+        Ok(load(&self.cfg.file)?.deposits)
+    }
+
+    /// Starts counting deposit `deposit_txid` worth `value`, unless it would take the total
+    /// above the ceiling. The deposit stops counting again once the returned reservation, and
+    /// those of any retries of its session running at the same time, are all dropped without one
+    /// being committed. A deposit counting already is not counted twice.
+    pub fn reserve(
+        &self,
+        deposit_txid: Txid,
+        value: Amount,
+    ) -> io::Result<Result<Reservation<'_>, PolicyViolation>> {
+        let mut pending = self.pending.lock().unwrap();
+        let mut deposits = load(&self.cfg.file)?;
+        let reservation = |committed| Reservation {
+            liability: self,
+            deposit_txid,
+            committed,
+        };
+        if deposits
+            .deposits
+            .iter()
+            .any(|d| d.deposit_txid == deposit_txid)
+        {
+            // Committed deposits, and those from before a restart, keep counting whatever
+            // becomes of this reservation.
+            return Ok(Ok(match pending.get_mut(&deposit_txid) {
+                Some(count) => {
+                    *count += 1;
+                    reservation(false)
+                }
+                None => reservation(true),
+            }));
+        }
+
+        let total: u64 = deposits.deposits.iter().map(|d| d.value_sat).sum();
+        if total + value.to_sat() > self.cfg.max_sat {
+            return Ok(Err(PolicyViolation {
+                rule: PolicyRule::Liability,
+                message: format!(
+                    "deposit of {} would take the signer's liability to {} sat, above its \
+                     ceiling of {} sat",
+                    value,
+                    total + value.to_sat(),
+                    self.cfg.max_sat
+                ),
+            }));
+        }
+
+        deposits.deposits.push(Deposit {
+            deposit_txid,
+            value_sat: value.to_sat(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        });
+        store(&self.cfg.file, &deposits)?;
+        pending.insert(deposit_txid, 1);
+        Ok(Ok(reservation(false)))
+    }
+}
+
+/// A deposit counted against the ceiling by [`Liability::reserve`]. Dropping it, on any of the
+/// ways a session can fail, stops counting the deposit unless it is committed once its spend is
+/// presigned.
+pub struct Reservation<'a> {
+    liability: &'a Liability,
+    deposit_txid: Txid,
+    committed: bool,
+}
+
+impl Reservation<'_> {
+    /// Keeps counting the deposit until its output is spent, as a spend of it is presigned.
+    pub fn commit(mut self) {
+        self.liability
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&self.deposit_txid);
+        self.committed = true;
+    }
+
+    /// Stops counting the deposit, unless another session of it is still running or committed.
+    fn release(&self) -> io::Result<()> {
+        let mut pending = self.liability.pending.lock().unwrap();
+        let Some(count) = pending.get_mut(&self.deposit_txid) else {
+            return Ok(());
+        };
+        *count -= 1;
+        if *count > 0 {
+            return Ok(());
+        }
+        pending.remove(&self.deposit_txid);
+
+        let mut deposits = load(&self.liability.cfg.file)?;
+        deposits
+            .deposits
+            .retain(|d| d.deposit_txid != self.deposit_txid);
+        store(&self.liability.cfg.file, &deposits)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        if let Err(e) = self.release() {
+            println!(
+                "unable to release liability for deposit {}: {}",
+                self.deposit_txid, e
+            );
+        }
+    }
+}
+
+/// Whether output 0 of `txid` is spent by a confirmed transaction, according to the Esplora API
+/// at `url`.
+async fn spent_confirmed(url: &str, txid: &Txid) -> Result<bool, Box<dyn std::error::Error>> {
+    let outspend: serde_json::Value = reqwest::get(format!(
+        "{}/tx/{}/outspend/0",
+        url.trim_end_matches('/'),
+        txid
+    ))
+    .await?
+    .error_for_status()?
+    .json()
+    .await?;
+    Ok(outspend["spent"].as_bool() == Some(true)
+        && outspend["status"]["confirmed"].as_bool() == Some(true))
+}
@@ -1,31 +1,73 @@
 use actix_web::middleware::Logger;
-use actix_web::{App, HttpServer, Responder, Result, post, web};
-use bitcoin::KnownHrp::Mainnet;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, Result, get, post, web};
+use bitcoin::address::script_pubkey::ScriptBufExt;
 use bitcoin::consensus_validation::TransactionExt;
 use bitcoin::hashes::Hash;
 use bitcoin::psbt::Input;
-use bitcoin::secp256k1::Secp256k1;
+use bitcoin::script::ScriptExt;
+use bitcoin::secp256k1::{Secp256k1, Verification};
 use bitcoin::sighash::SighashCache;
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapLeafHash, TaprootSpendInfo};
 use bitcoin::{
-    Address, Amount, Network, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
-    Witness, absolute, consensus, taproot, transaction,
+    Address, Amount, Network, OutPoint, Psbt, ScriptBuf, Sequence, TapSighashType, Transaction,
+    TxIn, TxOut, Txid, Witness, XOnlyPublicKey, absolute, consensus, taproot, transaction,
 };
 use clap::Parser;
 use hex::ToHex;
 use musig2::secp::{G, MaybePoint, MaybeScalar, Point, Scalar};
 use musig2::{
-    AggNonce, KeyAggContext, PartialSignature, PubNonce, SecNonce, compute_challenge_hash_tweak,
-    verify_partial_challenge,
+    AdaptorSignature, AggNonce, KeyAggContext, PartialSignature, PubNonce, SecNonce,
+    compute_challenge_hash_tweak, errors::VerifyError, verify_partial_challenge,
 };
 use rand::Rng;
 use secp256k1::{PublicKey, SecretKey, schnorr};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use shared::{InitResp, SignPsbtReq, SignPsbtResp, SignReq, SignResp};
+use shared::bip322::{self, SignedMessage};
+use shared::receipt::Receipt;
+use shared::render;
+use shared::script::{checksig_script, deposit_spend_info, unvault_script};
+use shared::silent_payment::{self, EcdhShare, SilentPaymentAddress};
+use shared::tee::EnclaveAttestation;
+use shared::{
+    Cet, DepositDescriptor, FallbackError, HealthResp, InitResp, InvalidFallback,
+    PROTOCOL_VERSIONS, Quote, SessionExpired, SignChallenge, SignPsbtError, SignPsbtReq,
+    SignPsbtResp, SignReq, SignResp, SpendSighash, VaultSpends, anchor_output, attestation_point,
+    script_paths, split_fallback,
+};
 use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::OpenApi;
+
+use encoding::Encoded;
+use error::PsbtError;
+use identity::IdentityKey;
+
+mod admin;
+mod archive;
+mod audit;
+mod blind;
+mod encoding;
+mod error;
+mod fee;
+mod health;
+mod idempotency;
+mod identity;
+mod liability;
+mod metrics;
+mod openapi;
+mod policy;
+mod quote;
+mod ratelimit;
+mod rpc;
+mod shutdown;
+mod validate;
+mod watchtower;
 
 #[derive(Debug, Parser)]
 #[command(verbatim_doc_comment)]
@@ -33,8 +75,8 @@ struct Args {
     #[arg(long)]
     cfg: Option<String>,
 
-    #[arg(long)]
-    listen: SocketAddr,
+    #[arg(long, required_unless_present = "verify_audit_log")]
+    listen: Option<SocketAddr>,
 
     #[arg(long)]
     server: bool,
@@ -42,17 +84,97 @@ struct Args {
     /// Network to use.
     #[arg(long, default_value_t = Network::Signet)]
     network: Network,
+
+    /// Verify the hash chain of the given audit log and exit.
+    #[arg(long)]
+    verify_audit_log: Option<PathBuf>,
+
+    /// Serve the admin API on this address, authenticated with the token in
+    /// EPHEMERAL_SIGN_ADMIN_TOKEN.
+    #[arg(long)]
+    admin_listen: Option<SocketAddr>,
+
+    /// Seconds to wait for the signing sessions in flight to finish on SIGTERM, before exiting
+    /// anyway.
+    #[arg(long, default_value_t = 60)]
+    shutdown_timeout: u64,
+
+    /// File with the hex encoded secret identity key of the operator, to sign a receipt for
+    /// every presigned spend with.
+    #[arg(long)]
+    identity_key_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    pkcs11: identity::Pkcs11Args,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 struct Config {
     pub signers: Vec<String>,
+    /// Peers each signer replicates its sessions to, by the signer's address. Signing a session
+    /// fails over to them if its signer cannot be reached.
+    #[serde(default)]
+    pub signer_replicas: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub policy: policy::Policy,
+    #[serde(default)]
+    pub liability: Option<liability::LiabilityConfig>,
+    #[serde(default)]
+    pub fee: fee::FeeConfig,
+    #[serde(default)]
+    pub rate_limit: ratelimit::RateLimitConfig,
+    /// File to append the audit log of signing sessions to.
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+    /// Esplora API the signer follows the chain with, to report its tip on `/health`.
+    #[serde(default)]
+    pub esplora_url: Option<String>,
+    /// Seconds a quote from `/quote` is honoured for.
+    #[serde(default = "quote::default_ttl")]
+    pub quote_ttl_secs: u64,
+    /// Directory to keep a copy of every presigned spend in, so it can be served again on
+    /// `/presigned/{deposit_txid}` or rebroadcast through the admin API.
+    #[serde(default)]
+    pub archive_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub watchtower: Option<watchtower::WatchtowerConfig>,
+    /// Seconds the response to a request with an idempotency key is replayed to its retries
+    /// for.
+    #[serde(default = "idempotency::default_ttl")]
+    pub idempotency_ttl_secs: u64,
+    /// Seconds a blind session started on `/blind/init` can be signed for.
+    #[serde(default = "blind::default_ttl")]
+    pub blind_session_ttl_secs: u64,
+    /// Network the signer serves, from --network.
+    #[serde(skip, default = "default_network")]
+    pub network: Network,
+}
+
+fn default_network() -> Network {
+    Network::Signet
 }
 
 // This struct represents state
 struct AppState {
     sessions: Mutex<HashMap<String, SessionData>>,
     cfg: Config,
+    /// Starts out as the policy of the config, and can be replaced through the admin API.
+    policy: RwLock<policy::Policy>,
+    quotas: policy::Quotas,
+    liability: Option<liability::Liability>,
+    rate_limiter: ratelimit::RateLimiter,
+    audit: Option<audit::AuditLog>,
+    archive: Option<archive::Archive>,
+    metrics: metrics::Metrics,
+    quotes: quote::Quotes,
+    replays: idempotency::Replays,
+    blind_sessions: blind::BlindSessions,
+    registry: admin::Registry,
+    admin_token: Option<String>,
+    /// The operator's identity key receipts are signed with.
+    identity_key: Option<IdentityKey>,
+    /// Set once shutting down, after which new `/psbt` requests are refused.
+    draining: AtomicBool,
 }
 
 #[derive(Clone, Debug)]
@@ -66,7 +188,14 @@ struct SessionData {
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let args = Args::parse();
-    let cfg: Config = serde_json::from_str(&args.cfg.unwrap()).unwrap();
+    if let Some(path) = &args.verify_audit_log {
+        let (seq, hash) = audit::verify(path).expect("valid audit log");
+        println!("audit log ok: {} entries, last hash {}", seq, hash);
+        return Ok(());
+    }
+
+    let mut cfg: Config = serde_json::from_str(&args.cfg.unwrap()).unwrap();
+    cfg.network = args.network;
     println!("config: {:?}", cfg);
 
     if !args.server {
@@ -76,91 +205,136 @@ async fn main() -> std::io::Result<()> {
 
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    let bind = args.listen;
+    let bind = args.listen.expect("--listen is required for the server");
     println!("listening on {}", bind);
 
+    let admin_token = args.admin_listen.map(|_| {
+        std::env::var(shared::admin::ADMIN_TOKEN_ENV)
+            .expect("EPHEMERAL_SIGN_ADMIN_TOKEN set for the admin API")
+    });
+
+    let identity_key = IdentityKey::load(args.identity_key_file.as_ref(), &args.pkcs11)
+        .expect("valid identity key");
+    if let Some(key) = &identity_key {
+        println!(
+            "signing receipts with identity key {}",
+            hex::encode(key.pubkey().serialize_xonly())
+        );
+    }
+
     let app_state = web::Data::new(AppState {
         sessions: Mutex::new(HashMap::new()),
+        policy: RwLock::new(cfg.policy.clone()),
+        liability: cfg.liability.clone().map(liability::Liability::new),
+        audit: cfg
+            .audit_log
+            .as_deref()
+            .map(|path| audit::AuditLog::open(path).expect("valid audit log")),
+        archive: cfg
+            .archive_dir
+            .as_deref()
+            .map(|dir| archive::Archive::open(dir).expect("able to open archive")),
         cfg: cfg,
+        quotas: policy::Quotas::default(),
+        rate_limiter: ratelimit::RateLimiter::default(),
+        metrics: metrics::Metrics::default(),
+        quotes: quote::Quotes::default(),
+        replays: idempotency::Replays::default(),
+        blind_sessions: blind::BlindSessions::default(),
+        registry: admin::Registry::default(),
+        admin_token,
+        identity_key,
+        draining: AtomicBool::new(false),
     });
-    HttpServer::new(move || {
+
+    // The admin API is served on its own address, so it need not be exposed with `/psbt`.
+    let admin_server = match args.admin_listen {
+        None => None,
+        Some(admin_bind) => {
+            println!("admin API listening on {}", admin_bind);
+            let app_state = app_state.clone();
+            Some(
+                HttpServer::new(move || {
+                    App::new()
+                        .wrap(Logger::default())
+                        .app_data(app_state.clone())
+                        .configure(admin::configure)
+                })
+                .disable_signals()
+                .bind(admin_bind)?
+                .run(),
+            )
+        }
+    };
+
+    let data = app_state.clone();
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
             .app_data(app_state.clone())
+            .app_data(web::PayloadConfig::new(encoding::MAX_BODY_BYTES))
             .service(sign_psbt)
+            .service(blind::init)
+            .service(blind::sign)
+            .service(metrics)
+            .service(health)
+            .service(get_quote)
+            .service(presigned)
+            .service(api_spec)
+            .service(rpc::rpc)
     })
+    // Signals are handled below, so the sessions in flight are finished before stopping.
+    .disable_signals()
     .bind(bind)?
-    .run()
-    .await
+    .run();
+
+    if let Some(watchtower) = data.cfg.watchtower.clone() {
+        assert!(
+            data.archive.is_some(),
+            "the watchtower needs archive_dir to keep the spends in"
+        );
+        let url = data
+            .cfg
+            .esplora_url
+            .clone()
+            .expect("the watchtower needs esplora_url to watch the deposits with");
+        actix_web::rt::spawn(watchtower::run(data.clone(), watchtower, url));
+    }
+
+    let mut handles = vec![server.handle()];
+    handles.extend(admin_server.as_ref().map(|s| s.handle()));
+    actix_web::rt::spawn(shutdown::drain_on_signal(
+        data.clone(),
+        handles,
+        Duration::from_secs(args.shutdown_timeout),
+    ));
+
+    let res = match admin_server {
+        None => server.await,
+        Some(admin_server) => tokio::try_join!(server, admin_server).map(|_| ()),
+    };
+    shutdown::finish(&data);
+    res
 }
 
 async fn run_example(cfg: Config) -> Result<(), Box<dyn std::error::Error>> {
-    let secp = Secp256k1::new();
     let message = "hello interwebz!";
 
-    let sessions = init_signer_sessions(&cfg).await?;
+    let sessions = init_signer_sessions(&cfg, None).await?;
     let num_signers = sessions.len();
     println!("num signers: {}", num_signers);
 
-    let (pubkeys, public_nonces, key_agg_ctx, aggregated_nonce) = aggregate_pubs(&sessions);
+    let (pubkeys, public_nonces, key_agg_ctx) = aggregate_pubs(&sessions);
+    let key_agg_ctx = key_agg_ctx.with_unspendable_taproot_tweak().unwrap();
 
     let untweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey_untweaked();
     println!("untweaked agg pubkey X: {}", untweaked_aggregated_pubkey);
-    let tweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
-
-    let blinding_factors = gen_blinding_factors(num_signers);
-
-    let aas: MaybeScalar = blinding_factors.iter().map(|(a, b)| *a).sum();
-    let bbs: MaybePoint = blinding_factors
-        .iter()
-        .enumerate()
-        .map(|(i, (a, b))| {
-            let pubkey: Point = pubkeys[i].into();
-            let c = key_agg_ctx.key_coefficient(pubkey).unwrap();
-            let bc = *b * c;
-            bc * pubkey
-        })
-        .sum();
-
-    let b: MaybeScalar = aggregated_nonce.nonce_coefficient(tweaked_aggregated_pubkey, &message);
-    let agg_nonce: MaybePoint = aggregated_nonce.final_nonce(b);
-    let sign_nonce = agg_nonce + aas * G + bbs;
-
-    let adaptor_point = MaybePoint::Infinity;
-    let adapted_nonce = sign_nonce + adaptor_point;
-
-    let nonce_x_bytes = adapted_nonce.serialize_xonly();
-    let e: MaybeScalar =
-        compute_challenge_hash_tweak(&nonce_x_bytes, &tweaked_aggregated_pubkey.into(), &message);
-
-    let partial_signatures = request_partial_sigs(
-        sessions,
-        &key_agg_ctx,
-        tweaked_aggregated_pubkey,
-        &blinding_factors,
-        sign_nonce,
-        b,
-        e,
-    )
-    .await?;
-
-    verify_partial_sigs(
-        &public_nonces,
-        &key_agg_ctx,
-        tweaked_aggregated_pubkey,
-        &blinding_factors,
-        sign_nonce,
-        b,
-        e,
-        &partial_signatures,
-    );
-
-    let unblinded_sigs = unblind_partial_sigs(blinding_factors, sign_nonce, partial_signatures);
 
-    let final_signature = aggregate_partial_sigs(message, &key_agg_ctx, sign_nonce, unblinded_sigs);
-
-    musig2::verify_single(tweaked_aggregated_pubkey, &final_signature, message)
-        .expect("aggregated signature must be valid");
+    let target = SignTarget {
+        message: message.as_bytes().to_vec(),
+        adaptor_point: MaybePoint::Infinity,
+    };
+    sign_message(sessions, &pubkeys, &public_nonces, &key_agg_ctx, &target).await?;
 
     Ok(())
 }
@@ -210,42 +384,437 @@ fn dummy_unspent_transaction_outputs() -> Vec<(OutPoint, TxOut)> {
     vec![(out_point_1, utxo_1), (out_point_2, utxo_2)]
 }
 
+#[utoipa::path(responses(
+    (status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain")
+))]
+#[get("/metrics")]
+async fn metrics(data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    let liability = data
+        .liability
+        .as_ref()
+        .map(|l| l.outstanding())
+        .transpose()
+        .map_err(liability::file_error)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(data.metrics.render(liability)))
+}
+
+#[utoipa::path(responses((status = 200, body = HealthResp)))]
+#[get("/health")]
+async fn health(data: web::Data<AppState>) -> impl Responder {
+    web::Json(health_resp(&data).await)
+}
+
+async fn health_resp(data: &AppState) -> HealthResp {
+    let cfg = &data.cfg;
+
+    let tip = match &cfg.esplora_url {
+        None => None,
+        Some(url) => match health::chain_tip(url).await {
+            Ok(tip) => Some(tip),
+            Err(e) => {
+                println!("unable to fetch chain tip: {}", e);
+                None
+            }
+        },
+    };
+
+    HealthResp {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        network: cfg.network,
+        tip,
+        policy: policy_summary(data),
+    }
+}
+
+/// The presigned transactions handed out for a deposit, for a depositor that lost its copy.
+#[utoipa::path(
+    params(("deposit_txid" = String, Path, description = "Txid of the deposit")),
+    responses(
+        (status = 200, content((SignPsbtResp = "application/json"), (SignPsbtResp = "application/cbor"))),
+        (status = 404, description = "No presigned spends are kept for the deposit")
+    )
+)]
+#[get("/presigned/{deposit_txid}")]
+async fn presigned(
+    data: web::Data<AppState>,
+    deposit_txid: web::Path<Txid>,
+) -> actix_web::Result<impl Responder> {
+    Ok(Encoded(admin::load_presigned(&data, &deposit_txid)?))
+}
+
+#[utoipa::path(responses(
+    (status = 200, body = Quote),
+    (status = 500, description = "No fee estimate to quote on")
+))]
+#[get("/quote")]
+async fn get_quote(data: web::Data<AppState>) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(issue_quote(&data).await?))
+}
+
+/// Issues a quote of the current terms.
+async fn issue_quote(data: &AppState) -> actix_web::Result<Quote> {
+    let cfg = &data.cfg;
+
+    // Estimates are fetched now and fixed in the quote.
+    let fee_rule = match fee::FeeRule::resolve(&cfg.fee).await {
+        Ok(r) => r,
+        Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
+    };
+    let expires_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + cfg.quote_ttl_secs;
+
+    let quote = Quote {
+        quote_id: hex::encode(rand::random::<[u8; 16]>()),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_versions: PROTOCOL_VERSIONS.to_vec(),
+        network: cfg.network,
+        fee: fee_rule.terms(),
+        policy: policy_summary(data),
+        features: quote::FEATURES.to_vec(),
+        expires_at,
+    };
+    data.quotes.issue(quote.clone());
+    Ok(quote)
+}
+
+/// OpenAPI description of this API.
+#[get("/openapi.json")]
+async fn api_spec() -> impl Responder {
+    web::Json(openapi::ApiDoc::openapi())
+}
+
+#[utoipa::path(
+    request_body(content((SignPsbtReq = "application/json"), (SignPsbtReq = "application/cbor"))),
+    responses(
+        (status = 200, description = "The signed deposit and its presigned spends",
+            content((SignPsbtResp = "application/json"), (SignPsbtResp = "application/cbor"))),
+        (status = "4XX", description = "The request was refused", body = SignPsbtError),
+        (status = "5XX", description = "The signers failed to sign", body = SignPsbtError)
+    )
+)]
 #[post("/psbt")]
 async fn sign_psbt(
     data: web::Data<AppState>,
-    //id: web::Path<String>,
-    req: web::Json<SignPsbtReq>,
+    http_req: HttpRequest,
+    req: Encoded<SignPsbtReq>,
 ) -> actix_web::Result<impl Responder> {
+    sign(data, http_req, req.0).await.map(Encoded)
+}
+
+/// Serves signing request `req`, keeping track of it as a session.
+async fn sign(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: SignPsbtReq,
+) -> actix_web::Result<SignPsbtResp> {
+    if data.draining.load(Ordering::SeqCst) {
+        return Err(PsbtError(SignPsbtError::Unavailable {
+            message: "signer is shutting down".to_string(),
+        })
+        .into());
+    }
+
+    // A retry of a request that was signed already gets the same response, not a new key.
+    let idempotency_key = req.idempotency_key.clone();
+    if let Some(key) = &idempotency_key {
+        let ttl = Duration::from_secs(data.cfg.idempotency_ttl_secs);
+        let replay = data
+            .replays
+            .begin(key, audit::request_hash(&req), ttl)
+            .map_err(PsbtError)?;
+        if let Some(resp) = replay {
+            println!("replaying response to idempotency key {}", key);
+            return Ok(resp);
+        }
+    }
+
+    data.metrics.session_started();
+    let session_id = data
+        .registry
+        .begin(http_req.peer_addr().map(|a| a.to_string()));
+    let res = handle_sign_psbt(data.clone(), http_req, req, session_id)
+        .await
+        .map_err(error::structured);
+    match &res {
+        Ok(_) => {
+            data.metrics.session_completed();
+            data.registry.finish(session_id, None);
+        }
+        Err(e) => {
+            data.metrics
+                .session_failed(e.as_response_error().status_code());
+            data.registry.finish(session_id, Some(e.to_string()));
+        }
+    }
+    if let Some(key) = &idempotency_key {
+        data.replays.finish(key, res.as_ref().ok());
+    }
+    res
+}
+
+async fn handle_sign_psbt(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    //id: web::Path<String>,
+    req: SignPsbtReq,
+    session_id: u64,
+) -> actix_web::Result<SignPsbtResp> {
     println!("req: {:?}", req);
 
     let secp = Secp256k1::new();
 
     let cfg = data.cfg.clone();
-    let args = Args::parse();
+    let policy = data.policy.read().unwrap().clone();
+
+    // Every request costs the signers a key and signatures, so limit them before anything else.
+    let api_key = http_req
+        .headers()
+        .get(ratelimit::API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    data.rate_limiter
+        .check(
+            &cfg.rate_limit,
+            http_req.peer_addr().map(|a| a.ip()),
+            api_key,
+        )
+        .map_err(|limited| {
+            data.metrics
+                .policy_rejection(&shared::PolicyRule::RateLimit);
+            ratelimit::rejection(limited)
+        })?;
+
+    if req.network != cfg.network {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "request is for {}, but this signer is on {}",
+            req.network, cfg.network
+        )));
+    }
 
-    let sessions = init_signer_sessions(&cfg).await?;
-    let num_signers = sessions.len();
+    let capabilities = quote::negotiate(&req).map_err(PsbtError)?;
 
-    let (pubkeys, public_nonces, key_agg_ctx, aggregated_nonce) = aggregate_pubs(&sessions);
+    // Clients are told apart by their address, counted against the quota even if rejected for
+    // other reasons.
+    if let (Some(quota), Some(peer)) = (&policy.quota, http_req.peer_addr()) {
+        data.quotas
+            .take(peer.ip(), quota)
+            .map_err(|v| reject(&data, v))?;
+    }
+    policy.check_request(&req).map_err(|v| reject(&data, v))?;
+    validate::deposit_psbt(&req.psbt).map_err(|e| {
+        PsbtError(SignPsbtError::InvalidPsbt {
+            message: e.to_string(),
+        })
+    })?;
+
+    // Feerate estimates are fetched once, so all spends of the request pay by the same rule. A
+    // request made on a quote pays by the quoted rule, even if the config changed since.
+    let fee_rule = match &req.quote {
+        Some(quote) => {
+            data.quotes
+                .check(quote, &policy_summary(&data))
+                .map_err(|v| reject(&data, v))?;
+            fee::FeeRule::quoted(&quote.fee)
+        }
+        None => match fee::FeeRule::resolve(&cfg.fee).await {
+            Ok(r) => r,
+            Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
+        },
+    };
 
-    let untweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey_untweaked();
-    println!("untweaked agg pubkey X: {}", untweaked_aggregated_pubkey);
-    let tweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
+    // Each transaction we sign needs a key of its own, as a key signs a single message.
+    let num_outcomes = req
+        .oracle_event
+        .as_ref()
+        .map(|ev| ev.outcomes.len())
+        .unwrap_or(0);
+    let num_vault_spends = if req.vault.is_some() { 2 } else { 0 };
+    let modes = [
+        num_outcomes > 0,
+        num_vault_spends > 0,
+        req.rollover,
+        req.inheritance.is_some(),
+    ];
+    if modes.iter().filter(|m| **m).count() > 1 {
+        return Err(actix_web::error::ErrorBadRequest(
+            "only one of vault, rollover, inheritance and oracle event can be requested",
+        ));
+    }
 
-    let pk = bitcoin::secp256k1::PublicKey::from_slice(&untweaked_aggregated_pubkey.serialize())
-        .unwrap();
-    let (xpub, _) = pk.x_only_public_key();
-    println!("agg pubkey: {} x-only:{}", pk, xpub);
+    // The depositor completes an adaptor signature into a 64 byte one.
+    if req.sighash_type != SpendSighash::Default && req.adaptor_point.is_some() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "a sighash type cannot be chosen with an adaptor point",
+        ));
+    }
+
+    let leaves = match req.deposit_leaves() {
+        Ok(l) => l,
+        Err(e) => return Err(actix_web::error::ErrorBadRequest(e)),
+    };
+
+    // A silent payment output is derived from the inputs of the spend, which only works for the
+    // spend of the deposit itself.
+    let silent_payment = match SilentPaymentAddress::is_silent_payment(&req.fallback_addr) {
+        false => None,
+        true => {
+            if num_vault_spends > 0 || req.rollover {
+                return Err(actix_web::error::ErrorBadRequest(
+                    "silent payment fallback cannot be combined with vault or rollover",
+                ));
+            }
+            match SilentPaymentAddress::parse(&req.fallback_addr, cfg.network) {
+                Ok(a) => Some(a),
+                Err(e) => {
+                    return Err(invalid_fallback(
+                        &req.fallback_addr,
+                        FallbackError::Malformed {
+                            message: e.to_string(),
+                        },
+                    ));
+                }
+            }
+        }
+    };
+
+    // Any other fallback address is checked before a signer is contacted, so a typo in it costs
+    // no key.
+    let fallback_script = match &silent_payment {
+        Some(_) => None,
+        None => {
+            let script = parse_fallback(&req.fallback_addr, cfg.network)?;
+            policy
+                .check_fallback(&script)
+                .map_err(|v| reject(&data, v))?;
+            Some(script)
+        }
+    };
+
+    // The spend outputs and fallback shares are checked like the fallback address. They are only
+    // paid by a spend straight to the fallback address, which is not derived from the spend's
+    // outputs, as is the anchor.
+    if (!req.spend_outputs.is_empty() || !req.fallback_shares.is_empty() || req.anchor)
+        && (num_outcomes > 0 || num_vault_spends > 0 || req.rollover || silent_payment.is_some())
+    {
+        return Err(actix_web::error::ErrorBadRequest(
+            "spend outputs, fallback shares and anchors cannot be combined with an oracle event, \
+             vault, rollover or silent payment fallback",
+        ));
+    }
+    // Inputs added to the spend would change the silent payment output it must pay.
+    if req.sighash_type == SpendSighash::AllAnyoneCanPay && silent_payment.is_some() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "ANYONECANPAY cannot be combined with a silent payment fallback",
+        ));
+    }
+    let mut spend_outputs = split_fallback(
+        req.psbt.unsigned_tx.output[0].value,
+        &req.fallback_shares,
+        cfg.network,
+    )
+    .map_err(|e| actix_web::error::ErrorBadRequest(format!("invalid fallback shares: {}", e)))?;
+    for share in &spend_outputs {
+        policy
+            .check_fallback(&share.script_pubkey)
+            .map_err(|v| reject(&data, v))?;
+    }
+    for output in &req.spend_outputs {
+        let txout = output.tx_out(cfg.network).map_err(|e| {
+            actix_web::error::ErrorBadRequest(format!(
+                "invalid spend output {}: {}",
+                output.address, e
+            ))
+        })?;
+        if txout.value < txout.script_pubkey.minimal_non_dust() {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "spend output of {} to {} is dust",
+                txout.value, output.address
+            )));
+        }
+        policy
+            .check_fallback(&txout.script_pubkey)
+            .map_err(|v| reject(&data, v))?;
+        spend_outputs.push(txout);
+    }
+    if req.anchor {
+        spend_outputs.push(anchor_output());
+    }
+
+    // The outcomes are checked before a signer is contacted too.
+    let mut outcomes = vec![];
+    if let Some(event) = &req.oracle_event {
+        for outcome in &event.outcomes {
+            let payout_script_pubkey = match Address::from_str(&outcome.payout_addr) {
+                Ok(a) => match a.require_network(cfg.network) {
+                    Ok(a) => a.script_pubkey(),
+                    Err(e) => return Err(actix_web::error::ErrorBadRequest(e)),
+                },
+                Err(e) => return Err(actix_web::error::ErrorBadRequest(e)),
+            };
+
+            let attestation_point = match attestation_point(
+                &event.oracle_pubkey,
+                &event.oracle_nonce,
+                &outcome.outcome,
+            ) {
+                Ok(p) => p,
+                Err(e) => return Err(actix_web::error::ErrorBadRequest(e)),
+            };
+            outcomes.push((
+                outcome.outcome.clone(),
+                payout_script_pubkey,
+                attestation_point,
+            ));
+        }
+    }
+
+    let clawback_script_pubkey = match &req.vault {
+        None => None,
+        Some(vault) => match Address::from_str(&vault.clawback_addr) {
+            Ok(a) => match a.require_network(cfg.network) {
+                Ok(a) => Some(a.script_pubkey()),
+                Err(e) => return Err(actix_web::error::ErrorBadRequest(e)),
+            },
+            Err(e) => return Err(actix_web::error::ErrorBadRequest(e)),
+        },
+    };
 
-    let tap = Address::p2tr(&secp, xpub, None, Mainnet);
-    let sp = tap.script_pubkey();
+    // Each CET is signed by a key of its own, through a leaf of the deposit output after the
+    // requested ones, so the keys are set up before the deposit key.
+    let mut cet_keys = vec![];
+    for _ in &outcomes {
+        cet_keys.push(init_leaf_key(&cfg).await?);
+    }
+    let mut key_leaves = leaves.clone();
+    key_leaves.extend(cet_keys.iter().map(LeafKey::script));
+
+    let ephemeral = init_ephemeral_key(
+        &cfg,
+        &secp,
+        key_leaves.clone(),
+        silent_payment.as_ref().map(|a| a.scan_key),
+    )
+    .await?;
+    let xpub = ephemeral.internal_key;
+    let sp = ephemeral.script_pubkey();
+    let script_paths = script_paths(&ephemeral.spend_info, &key_leaves);
+    let descriptor = DepositDescriptor::new(&ephemeral.spend_info);
 
     let mut deposit_psbt = req.psbt.clone();
     if let Some(output) = deposit_psbt.unsigned_tx.output.get_mut(0) {
         output.script_pubkey = sp.clone();
     }
 
-    println!("deposit: {:?}", deposit_psbt);
+    // Let the depositor verify the taptweak.
+    if let Some(output) = deposit_psbt.outputs.get_mut(0) {
+        output.tap_internal_key = Some(xpub);
+    }
+
+    print!("{}", render::psbt("Deposit", &deposit_psbt, cfg.network));
 
     let body_json = serde_json::to_string(&deposit_psbt).unwrap();
     println!("body_json: {}", body_json);
@@ -254,116 +823,712 @@ async fn sign_psbt(
     // Future: add some sort of miniscript config for the spending transaction?
     let utxos: Vec<TxOut> = deposit_psbt.unsigned_tx.output.to_vec();
 
-    println!(
-        "deposit transaction Details: {:#?}",
-        deposit_psbt.unsigned_tx
-    );
-
     let deposit_tx = deposit_psbt.unsigned_tx.clone();
     let txid = deposit_tx.compute_txid();
+
+    // Count the deposit against the liability ceiling before anything is signed for it. The
+    // signer sessions of a rejected deposit are never asked to sign, and the deposit stops
+    // counting if the session fails before it is.
+    let reservation = match &data.liability {
+        None => None,
+        Some(liability) => {
+            liability.refresh().await.map_err(liability::file_error)?;
+            let reservation = liability
+                .reserve(txid, utxos[0].value)
+                .map_err(liability::file_error)?
+                .map_err(|v| reject(&data, v))?;
+            Some(reservation)
+        }
+    };
+    data.registry.set_deposit(session_id, txid, utxos[0].value);
+    record(
+        &data,
+        audit::Event::SessionStarted {
+            request_hash: audit::request_hash(&req),
+            deposit_txid: txid,
+            ephemeral_pubkey: xpub,
+        },
+    )?;
     let op = OutPoint::from_str(format!("{}:0", txid).as_str()).unwrap();
 
+    let ecdh_shares: Vec<EcdhShare> = ephemeral
+        .sessions
+        .iter()
+        .filter_map(|s| s.init_resp.ecdh_share.clone())
+        .collect();
+    let spend_script_pubkey = match &silent_payment {
+        None => fallback_script.clone().unwrap(),
+        Some(addr) => {
+            match silent_payment::output_script(addr, &ephemeral.key_agg_ctx, &ecdh_shares, op) {
+                Ok(s) => s,
+                Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
+            }
+        }
+    };
+
+    policy
+        .check_fallback(&spend_script_pubkey)
+        .map_err(|v| reject(&data, v))?;
+
+    // Make sure the fallback address really is the output of the policy the depositor gave us.
+    if let Some(policy) = &req.decaying_multisig {
+        let spend_info = match policy.spend_info(&secp) {
+            Ok(s) => s,
+            Err(e) => return Err(actix_web::error::ErrorBadRequest(e)),
+        };
+        if ScriptBuf::new_p2tr_tweaked(spend_info.output_key()) != spend_script_pubkey {
+            return Err(actix_web::error::ErrorBadRequest(
+                "fallback address does not match the decaying multisig policy",
+            ));
+        }
+    }
+
+    // When settling on an oracle event, the fallback spend is the refund and must not be valid
+    // before the event can have been attested to. An inheritance spend must similarly not be
+    // valid before the depositor's deadline. Otherwise the depositor can ask for its own.
+    let lock_height = match (&req.oracle_event, &req.inheritance) {
+        (Some(ev), _) => Some(ev.refund_locktime),
+        (_, Some(inheritance)) => Some(inheritance.lock_time),
+        (None, None) => None,
+    };
+    let requested_lock_time = req.spend_timelock.as_ref().and_then(|t| t.lock_time());
+    if lock_height.is_some() && requested_lock_time.is_some() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "a spend locktime cannot be combined with an oracle event or inheritance",
+        ));
+    }
+    let lock_time = match lock_height {
+        None => requested_lock_time.unwrap_or(absolute::LockTime::ZERO),
+        Some(height) => match absolute::LockTime::from_height(height) {
+            Ok(l) => l,
+            Err(e) => return Err(actix_web::error::ErrorBadRequest(e)),
+        },
+    };
+    let sequence = req
+        .spend_timelock
+        .as_ref()
+        .map_or(Sequence::ENABLE_RBF_NO_LOCKTIME, |t| t.sequence());
+
+    println!(
+        "prevout: {}",
+        hex::encode(consensus::encode::serialize(&utxos[0]))
+    );
+
+    // In rollover mode the presigned spend pays into a fresh deposit output, locked to a new
+    // ephemeral key with its own presigned spend to the fallback address.
+    let rollover = match req.rollover {
+        false => None,
+        true => Some(init_ephemeral_key(&cfg, &secp, leaves, None).await?),
+    };
+
+    // In vault mode the presigned spend is the unvault, paying into the output of a key of its
+    // own, which signs the clawback. The final spend is signed by another key, through the only
+    // leaf of that output, which enforces the vault delay.
+    let vault_keys = match &req.vault {
+        None => None,
+        Some(vault) => {
+            let final_key = init_leaf_key(&cfg).await?;
+            let leaf = unvault_script(final_key.key, vault.delay);
+            let clawback_key = init_ephemeral_key(&cfg, &secp, vec![leaf], None).await?;
+            Some((final_key, clawback_key))
+        }
+    };
+
+    let first_script_pubkey = match (&vault_keys, &rollover) {
+        (Some((_, clawback_key)), _) => clawback_key.script_pubkey(),
+        (_, Some(rollover)) => rollover.script_pubkey(),
+        (None, None) => spend_script_pubkey.clone(),
+    };
+
+    let (mut spend_psbt, mut message, mut sighash_type) = build_split_spend_psbt(
+        op,
+        &utxos[0],
+        None,
+        first_script_pubkey,
+        &spend_outputs,
+        lock_time,
+        sequence,
+        &fee_rule,
+    )
+    .map_err(spend_error)?;
+    if req.sighash_type != SpendSighash::Default {
+        sighash_type = req.sighash_type.tap_sighash_type();
+        message = with_sighash_type(&mut spend_psbt, sighash_type);
+    }
+
+    let spend_fee = fee_rule.fee(&spend_psbt.unsigned_tx, utxos[0].value);
+    println!(
+        "spend fee: {} sat, {}",
+        spend_fee.fee_sat, spend_fee.rationale
+    );
+    policy
+        .check_spend_fee(Amount::from_sat(spend_fee.fee_sat).unwrap())
+        .map_err(|v| reject(&data, v))?;
+    // Only a height locktime the policy limits needs the chain tip.
+    let tip_height = match &cfg.esplora_url {
+        Some(url) if policy.max_lock_delay_secs.is_some() && lock_time.is_block_height() => {
+            health::chain_tip(url).await.ok().map(|tip| tip.height)
+        }
+        _ => None,
+    };
+    policy
+        .check_spend_timelock(&spend_psbt.unsigned_tx, tip_height)
+        .map_err(|v| reject(&data, v))?;
+
+    // In adaptor mode the spend is signed with an adaptor signature encrypted to the requested
+    // point.
+    let adaptor_point = match &req.adaptor_point {
+        None => MaybePoint::Infinity,
+        Some(p) => match MaybePoint::from_hex(p) {
+            Ok(p) => p,
+            Err(e) => return Err(actix_web::error::ErrorBadRequest(e)),
+        },
+    };
+
+    let spend_target = SignTarget {
+        message,
+        adaptor_point,
+    };
+
+    // Build a CET for each outcome, spending the deposit through the leaf of its key, with the
+    // adaptor signature encrypted to the point the oracle's attestation to that outcome will
+    // reveal the discrete log of.
+    let mut cets = vec![];
+    for ((outcome, payout_script_pubkey, attestation_point), key) in
+        outcomes.into_iter().zip(cet_keys)
+    {
+        let leaf = LeafSpend::new(&ephemeral.spend_info, key.script());
+        let (cet_psbt, cet_message, _) = build_spend_psbt(
+            op,
+            &utxos[0],
+            Some(&leaf),
+            payout_script_pubkey,
+            absolute::LockTime::ZERO,
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            &fee_rule,
+        )
+        .map_err(spend_error)?;
+
+        let target = SignTarget {
+            message: cet_message,
+            adaptor_point: attestation_point,
+        };
+        cets.push((outcome, cet_psbt, key, target));
+    }
+
+    // The final spend can only be mined once the unvault has been confirmed for the vault delay,
+    // as the leaf it is signed through checks the relative timelock BIP 68 enforces. The clawback
+    // has no such delay.
+    let vault_legs = match (&req.vault, vault_keys, clawback_script_pubkey) {
+        (Some(vault), Some((final_key, clawback_key)), Some(clawback_script_pubkey)) => {
+            let unvault_tx = &spend_psbt.unsigned_tx;
+            let unvault_op = OutPoint {
+                txid: unvault_tx.compute_txid(),
+                vout: 0,
+            };
+            let unvault_output = unvault_tx.output[0].clone();
+
+            let final_leaf = LeafSpend::new(
+                &clawback_key.spend_info,
+                unvault_script(final_key.key, vault.delay),
+            );
+            let (final_psbt, final_message, final_sighash_type) = build_spend_psbt(
+                unvault_op,
+                &unvault_output,
+                Some(&final_leaf),
+                spend_script_pubkey.clone(),
+                absolute::LockTime::ZERO,
+                Sequence::from_height(vault.delay),
+                &fee_rule,
+            )
+            .map_err(spend_error)?;
+            let (clawback_psbt, clawback_message, clawback_sighash_type) = build_spend_psbt(
+                unvault_op,
+                &unvault_output,
+                None,
+                clawback_script_pubkey,
+                absolute::LockTime::ZERO,
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+                &fee_rule,
+            )
+            .map_err(spend_error)?;
+
+            Some(VaultLegs {
+                final_key,
+                final_leaf,
+                final_psbt,
+                final_message,
+                final_sighash_type,
+                clawback_key,
+                clawback_psbt,
+                clawback_message,
+                clawback_sighash_type,
+            })
+        }
+        _ => None,
+    };
+
+    // Let the depositor verify the taptweak of the new deposit or the unvault output.
+    let next_internal_key = match (&vault_legs, &rollover) {
+        (Some(legs), _) => Some(legs.clawback_key.internal_key),
+        (_, Some(rollover)) => Some(rollover.internal_key),
+        (None, None) => None,
+    };
+    if let (Some(internal_key), Some(output)) = (next_internal_key, spend_psbt.outputs.get_mut(0)) {
+        output.tap_internal_key = Some(internal_key);
+    }
+
+    // Once the signers sign the key is deleted, so this is the last chance to revoke the session.
+    data.registry
+        .start_signing(session_id)
+        .map_err(actix_web::error::ErrorConflict)?;
+    let started = Instant::now();
+    // If the signers fail nothing was presigned for the deposit, so dropping the reservation
+    // stops counting it against the ceiling and the deposit can be retried.
+    let (spend_sig, enclave_attestations) =
+        ephemeral.sign(&spend_target).await.map_err(signing_error)?;
+    let mut cet_sigs = vec![];
+    for (outcome, psbt, key, target) in cets {
+        let leaf_key = key.key;
+        let (sig, _) = key.sign(&target).await.map_err(signing_error)?;
+        cet_sigs.push((outcome, psbt, leaf_key, sig));
+    }
+    let vault = match vault_legs {
+        None => None,
+        Some(legs) => Some(legs.sign().await.map_err(signing_error)?),
+    };
+    if let Some(reservation) = reservation {
+        reservation.commit();
+    }
+    data.metrics.signed(started.elapsed());
+    record(
+        &data,
+        audit::Event::KeyDeleted {
+            deposit_txid: txid,
+            ephemeral_pubkey: xpub,
+        },
+    )?;
+
+    let cets: Vec<Cet> = cet_sigs
+        .into_iter()
+        .map(|(outcome, psbt, leaf_key, sig)| match sig {
+            SpendSig::Adaptor(adaptor_sig) => Cet {
+                outcome,
+                psbt,
+                adaptor_sig: adaptor_sig.to_string(),
+                leaf_key: leaf_key.to_string(),
+            },
+            SpendSig::Final(_) => unreachable!("CETs are always adaptor signed"),
+        })
+        .collect();
+
+    let adaptor_sig = match spend_sig {
+        SpendSig::Adaptor(adaptor_sig) => {
+            // The spend can only be finalized by whoever knows the adaptor secret, so we hand
+            // back the unsigned spend together with the adaptor signature.
+            println!("adaptor sig: {}", adaptor_sig);
+            Some(adaptor_sig.to_string())
+        }
+        SpendSig::Final(final_signature) => {
+            finalize_spend_psbt(&mut spend_psbt, final_signature, sighash_type);
+
+            let spend_tx = spend_psbt.clone().extract_tx().unwrap();
+
+            let serialized_signed_tx = consensus::encode::serialize_hex(&spend_tx);
+            let serialized_funding_tx = consensus::encode::serialize_hex(&deposit_tx);
+            let prevouts = [Some(utxos[0].clone())];
+            print!(
+                "{}",
+                render::transaction("Presigned spend", &spend_tx, &prevouts, cfg.network)
+            );
+            // check with:
+            // bitcoin-cli decoderawtransaction <RAW_TX> true
+            println!("Raw deposit Transaction: {}", serialized_funding_tx);
+            println!("Raw spending Transaction: {}", serialized_signed_tx);
+
+            spend_tx
+                .verify(|op| {
+                    println!("fetchin op {}", op);
+                    Some(utxos[0].clone())
+                })
+                .unwrap();
+            println!("Presigned spend verified");
+            None
+        }
+    };
+
+    // Sign the spend of the new deposit output to the fallback address with the new key.
+    let rollover_spend_psbt = match rollover {
+        None => None,
+        Some(rollover) => {
+            let rollover_tx = &spend_psbt.unsigned_tx;
+            let rollover_op = OutPoint {
+                txid: rollover_tx.compute_txid(),
+                vout: 0,
+            };
+            let (mut psbt, message, sighash_type) = build_spend_psbt(
+                rollover_op,
+                &rollover_tx.output[0],
+                None,
+                spend_script_pubkey.clone(),
+                absolute::LockTime::ZERO,
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+                &fee_rule,
+            )
+            .map_err(spend_error)?;
+
+            let target = SignTarget {
+                message,
+                adaptor_point: MaybePoint::Infinity,
+            };
+            let started = Instant::now();
+            let (rollover_sig, _) = rollover.sign(&target).await.map_err(signing_error)?;
+            data.metrics.signed(started.elapsed());
+            record(
+                &data,
+                audit::Event::KeyDeleted {
+                    deposit_txid: txid,
+                    ephemeral_pubkey: rollover.internal_key,
+                },
+            )?;
+            match rollover_sig {
+                SpendSig::Final(final_signature) => {
+                    finalize_spend_psbt(&mut psbt, final_signature, sighash_type)
+                }
+                SpendSig::Adaptor(_) => unreachable!("rollover spend is never adaptor signed"),
+            }
+            Some(psbt)
+        }
+    };
+
+    let other_txids = cets
+        .iter()
+        .map(|cet| &cet.psbt)
+        .chain(vault.iter().flat_map(|v| [&v.final_psbt, &v.clawback_psbt]))
+        .chain(rollover_spend_psbt.iter())
+        .map(|psbt| psbt.unsigned_tx.compute_txid())
+        .collect();
+    record(
+        &data,
+        audit::Event::Presigned {
+            deposit_txid: txid,
+            presigned_txid: spend_psbt.unsigned_tx.compute_txid(),
+            other_txids,
+        },
+    )?;
+
+    let mut resp = SignPsbtResp {
+        deposit_psbt: deposit_psbt,
+        spend_psbt: spend_psbt,
+        network: cfg.network,
+        adaptor_sig,
+        cets,
+        vault,
+        rollover_spend_psbt,
+        script_paths,
+        descriptor: Some(descriptor),
+        ecdh_shares,
+        spend_fee: Some(spend_fee),
+        version: req.version,
+        capabilities,
+        receipt: None,
+        fallback_attestation: None,
+    };
+    // The spend is signed already, so failing to sign the receipt must not keep it from the
+    // depositor.
+    if let Some(identity_key) = &data.identity_key {
+        match receipt(&resp, &req, identity_key, enclave_attestations) {
+            Ok(receipt) => resp.receipt = Some(receipt),
+            Err(e) => println!("unable to sign receipt for {}: {}", txid, e),
+        }
+    }
+    // The key of an HSM cannot be taptweaked, so only a local key attests the fallback.
+    if let Some(IdentityKey::Local(identity_key)) = &data.identity_key {
+        resp.fallback_attestation = Some(fallback_attestation(&resp, &req, *identity_key));
+    }
+
+    // The spend is signed already, so failing to keep a copy must not keep it from the depositor.
+    if let Some(archive) = &data.archive {
+        if let Err(e) = archive.store(&resp) {
+            println!("unable to archive presigned spend of {}: {}", txid, e);
+        }
+    }
+    Ok(resp)
+}
+
+/// Receipt for `resp` to `req`, signed with the operator's identity key, carrying the signers'
+/// enclave attestations of deleting the key.
+fn receipt(
+    resp: &SignPsbtResp,
+    req: &SignPsbtReq,
+    identity_key: &IdentityKey,
+    enclave_attestations: Vec<EnclaveAttestation>,
+) -> Result<Receipt, Box<dyn std::error::Error>> {
+    let mut receipt = Receipt {
+        deposit_txid: resp.deposit_psbt.unsigned_tx.compute_txid(),
+        spend_txid: resp.spend_psbt.unsigned_tx.compute_txid(),
+        ephemeral_pubkey: resp
+            .descriptor
+            .as_ref()
+            .map(|d| d.internal_key.clone())
+            .unwrap_or_default(),
+        network: resp.network,
+        deposit_sat: resp.deposit_psbt.unsigned_tx.output[0].value.to_sat(),
+        spend_fee_sat: resp.spend_fee.as_ref().map_or(0, |f| f.fee_sat),
+        quote_id: req.quote.as_ref().map(|q| q.quote_id.clone()),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        operator_key: String::new(),
+        enclave_attestations,
+        signature: String::new(),
+    };
+    identity_key.sign_receipt(&mut receipt)?;
+    Ok(receipt)
+}
+
+/// BIP-322 attestation of the fallback commitment of `resp` to `req`, by the P2TR address of the
+/// operator's identity key.
+fn fallback_attestation(
+    resp: &SignPsbtResp,
+    req: &SignPsbtReq,
+    identity_key: Scalar,
+) -> SignedMessage {
+    let secp = Secp256k1::new();
+    let keypair = bitcoin::key::Keypair::from_seckey_slice(&secp, &identity_key.serialize())
+        .expect("valid identity key");
+    let message = bip322::fallback_commitment(
+        resp.deposit_psbt.unsigned_tx.compute_txid(),
+        resp.spend_psbt.unsigned_tx.compute_txid(),
+        &req.fallback_addr,
+    );
+    SignedMessage::sign(&secp, &keypair, resp.network, message, &rand::random())
+}
+
+/// Error response for a request with fallback address `addr` that failed with `error`.
+fn invalid_fallback(addr: &str, error: FallbackError) -> actix_web::Error {
+    PsbtError(SignPsbtError::InvalidFallback(InvalidFallback {
+        fallback_addr: addr.to_string(),
+        error,
+    }))
+    .into()
+}
+
+/// Output script paying fallback address `addr`, which must be for `network`.
+fn parse_fallback(addr: &str, network: Network) -> actix_web::Result<ScriptBuf> {
+    let unchecked = Address::from_str(addr).map_err(|e| {
+        invalid_fallback(
+            addr,
+            FallbackError::Malformed {
+                message: e.to_string(),
+            },
+        )
+    })?;
+    let addr = unchecked
+        .require_network(network)
+        .map_err(|_| invalid_fallback(addr, FallbackError::WrongNetwork { expected: network }))?;
+    Ok(addr.script_pubkey())
+}
+
+/// The current policy as reported to depositors.
+fn policy_summary(data: &AppState) -> shared::PolicySummary {
+    data.policy
+        .read()
+        .unwrap()
+        .summary(data.cfg.liability.as_ref().map(|l| l.max_sat))
+}
+
+/// Turns a failure to sign with the signers into an error response, telling the depositor to
+/// retry if the signers expired the session.
+fn signing_error(e: Box<dyn std::error::Error>) -> actix_web::Error {
+    match e.downcast::<SessionExpired>() {
+        Ok(expired) => PsbtError(SignPsbtError::Expired(*expired)).into(),
+        Err(e) => e.into(),
+    }
+}
+
+/// Counts `violation` before turning it into its error response.
+fn reject(data: &AppState, violation: shared::PolicyViolation) -> actix_web::Error {
+    data.metrics.policy_rejection(&violation.rule);
+    policy::rejection(violation)
+}
+
+/// Appends `event` to the audit log, if one is configured.
+fn record(data: &AppState, event: audit::Event) -> actix_web::Result<()> {
+    if let Some(audit) = &data.audit {
+        audit
+            .record(event)
+            .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// A script path of the output a presigned transaction spends, for a key of its own to sign.
+struct LeafSpend {
+    script: ScriptBuf,
+    control_block: ControlBlock,
+}
+
+impl LeafSpend {
+    /// The leaf `script` of the output of `spend_info`.
+    fn new(spend_info: &TaprootSpendInfo, script: ScriptBuf) -> Self {
+        let control_block = spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .expect("leaf in tree");
+        LeafSpend {
+            script,
+            control_block,
+        }
+    }
+
+    fn leaf_hash(&self) -> TapLeafHash {
+        TapLeafHash::from_script(&self.script, LeafVersion::TapScript)
+    }
+}
+
+/// Builds a transaction spending the output `op` (of value `prevout`) in full, minus the fee
+/// chosen by `fee_rule`, to `script_pubkey`. The output is spent by the ephemeral key it is
+/// locked to, or through `leaf` if given. Returns the PSBT together with its taproot sighash, or
+/// why the output does not cover the fee with more than dust left.
+fn build_spend_psbt(
+    op: OutPoint,
+    prevout: &TxOut,
+    leaf: Option<&LeafSpend>,
+    script_pubkey: ScriptBuf,
+    lock_time: absolute::LockTime,
+    sequence: Sequence,
+    fee_rule: &fee::FeeRule,
+) -> Result<(Psbt, Vec<u8>, TapSighashType), String> {
+    build_split_spend_psbt(
+        op,
+        prevout,
+        leaf,
+        script_pubkey,
+        &[],
+        lock_time,
+        sequence,
+        fee_rule,
+    )
+}
+
+/// Error response for a spend the output it spends does not cover.
+fn spend_error(message: String) -> actix_web::Error {
+    PsbtError(SignPsbtError::InvalidRequest { message }).into()
+}
+
+/// Like `build_spend_psbt`, but also paying `outputs` after the output to `script_pubkey`, which
+/// gets what is left. Fails if the deposit does not cover them with more than dust left.
+fn build_split_spend_psbt(
+    op: OutPoint,
+    prevout: &TxOut,
+    leaf: Option<&LeafSpend>,
+    script_pubkey: ScriptBuf,
+    outputs: &[TxOut],
+    lock_time: absolute::LockTime,
+    sequence: Sequence,
+    fee_rule: &fee::FeeRule,
+) -> Result<(Psbt, Vec<u8>, TapSighashType), String> {
     let spend_input = TxIn {
         previous_output: op,
         script_sig: ScriptBuf::default(),
-        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        sequence,
         witness: Witness::default(),
     };
 
-    let spend_script_pubkey = Address::from_str(&req.fallback_addr)
-        .unwrap()
-        .require_network(args.network)
-        .unwrap()
-        .script_pubkey();
-
-    let spend_out_amt = utxos[0].value - Amount::from_sat(500).unwrap(); // subtract static fee
     let spend_output = TxOut {
-        value: spend_out_amt.unwrap(),
-        script_pubkey: spend_script_pubkey.clone(),
+        value: prevout.value,
+        script_pubkey,
     };
 
-    let spending_tx = Transaction {
-        version: transaction::Version::TWO,  // Post BIP 68.
-        lock_time: absolute::LockTime::ZERO, // Ignore the locktime.
-        input: vec![spend_input],            // Input is 0-indexed.
-        output: vec![spend_output],          // Outputs, order does not matter.
+    let mut spending_tx = Transaction {
+        version: transaction::Version::TWO, // Post BIP 68.
+        lock_time,
+        input: vec![spend_input], // Input is 0-indexed.
+        output: [spend_output]
+            .into_iter()
+            .chain(outputs.iter().cloned())
+            .collect(),
     };
-    println!(
-        "prevout: {}",
-        hex::encode(consensus::encode::serialize(&utxos[0]))
-    );
+
+    // The fee depends on the size of the spend, which the output value does not change.
+    let fee = match leaf {
+        None => fee_rule.fee(&spending_tx, prevout.value),
+        Some(leaf) => fee_rule.script_spend_fee(
+            &spending_tx,
+            prevout.value,
+            &leaf.script,
+            &leaf.control_block,
+        ),
+    };
+    let paid_sat: u64 = outputs.iter().map(|o| o.value.to_sat()).sum();
+    let spend_out_amt = prevout
+        .value
+        .to_sat()
+        .checked_sub(fee.fee_sat + paid_sat)
+        .map(|sat| Amount::from_sat(sat).unwrap())
+        .ok_or_else(|| {
+            format!(
+                "deposit of {} does not cover {} sat to the spend outputs and a fee of {} sat",
+                prevout.value, paid_sat, fee.fee_sat
+            )
+        })?;
+    // A dust output would keep the spend from relaying.
+    let dust = spending_tx.output[0].script_pubkey.minimal_non_dust();
+    if spend_out_amt < dust {
+        return Err(format!(
+            "only {} is left of the deposit of {} after {} sat to the spend outputs and a fee of \
+             {} sat, below the dust limit of {}",
+            spend_out_amt, prevout.value, paid_sat, fee.fee_sat, dust
+        ));
+    }
+    spending_tx.output[0].value = spend_out_amt;
 
     let mut spend_psbt =
         Psbt::from_unsigned_tx(spending_tx.clone()).expect("Could not create PSBT");
     spend_psbt.inputs = vec![Input {
-        witness_utxo: Some(utxos[0].clone()),
+        witness_utxo: Some(prevout.clone()),
         //tap_key_origins: origins[0].clone(),
         //tap_internal_key: Some(pk_input_1),
         //sighash_type: Some(ty),
         ..Default::default()
     }];
+    // Let whoever finalizes the spend build the script path witness.
+    if let Some(leaf) = leaf {
+        let input = &mut spend_psbt.inputs[0];
+        input.tap_scripts.insert(
+            leaf.control_block.clone(),
+            (leaf.script.clone(), LeafVersion::TapScript),
+        );
+        input.tap_internal_key = Some(leaf.control_block.internal_key);
+    }
 
     let mut cache = SighashCache::new(&spending_tx);
-    let (msg, sighash_type) = spend_psbt.sighash_taproot(0, &mut cache, None).unwrap();
-    let message = msg.as_ref();
+    let leaf_hash = leaf.map(LeafSpend::leaf_hash);
+    let (msg, sighash_type) = spend_psbt
+        .sighash_taproot(0, &mut cache, leaf_hash)
+        .unwrap();
 
     println!("msg: {:?}", msg);
     println!("sighash_type: {:?}", sighash_type);
 
-    let blinding_factors = gen_blinding_factors(num_signers);
-
-    let aas: MaybeScalar = blinding_factors.iter().map(|(a, b)| *a).sum();
-    let bbs: MaybePoint = blinding_factors
-        .iter()
-        .enumerate()
-        .map(|(i, (a, b))| {
-            let pubkey: Point = pubkeys[i].into();
-            let c = key_agg_ctx.key_coefficient(pubkey).unwrap();
-            let bc = *b * c;
-            bc * pubkey
-        })
-        .sum();
-
-    let b: MaybeScalar = aggregated_nonce.nonce_coefficient(tweaked_aggregated_pubkey, &message);
-    let agg_nonce: MaybePoint = aggregated_nonce.final_nonce(b);
-    let sign_nonce = agg_nonce + aas * G + bbs;
-
-    let adaptor_point = MaybePoint::Infinity;
-    let adapted_nonce = sign_nonce + adaptor_point;
-
-    let nonce_x_bytes = adapted_nonce.serialize_xonly();
-    let e: MaybeScalar =
-        compute_challenge_hash_tweak(&nonce_x_bytes, &tweaked_aggregated_pubkey.into(), &message);
-
-    let partial_signatures = request_partial_sigs(
-        sessions,
-        &key_agg_ctx,
-        tweaked_aggregated_pubkey,
-        &blinding_factors,
-        sign_nonce,
-        b,
-        e,
-    )
-    .await?;
-
-    verify_partial_sigs(
-        &public_nonces,
-        &key_agg_ctx,
-        tweaked_aggregated_pubkey,
-        &blinding_factors,
-        sign_nonce,
-        b,
-        e,
-        &partial_signatures,
-    );
-
-    let unblinded_sigs = unblind_partial_sigs(blinding_factors, sign_nonce, partial_signatures);
-
-    let final_signature = aggregate_partial_sigs(message, &key_agg_ctx, sign_nonce, unblinded_sigs);
+    Ok((spend_psbt, msg.as_ref().to_vec(), sighash_type))
+}
 
-    musig2::verify_single(tweaked_aggregated_pubkey, &final_signature, message)
-        .expect("aggregated signature must be valid");
+/// Has the spend built by `build_spend_psbt` sign with `sighash_type`, recording it in the
+/// PSBT, and returns its sighash.
+fn with_sighash_type(spend_psbt: &mut Psbt, sighash_type: TapSighashType) -> Vec<u8> {
+    spend_psbt.inputs[0].sighash_type = Some(sighash_type.into());
+    let mut cache = SighashCache::new(&spend_psbt.unsigned_tx);
+    let (msg, _) = spend_psbt.sighash_taproot(0, &mut cache, None).unwrap();
+    msg.as_ref().to_vec()
+}
 
+fn finalize_spend_psbt(
+    spend_psbt: &mut Psbt,
+    final_signature: [u8; 64],
+    sighash_type: TapSighashType,
+) {
     let signature = schnorr::Signature::from_slice(&final_signature).unwrap();
 
     let signature = taproot::Signature {
@@ -382,60 +1547,281 @@ async fn sign_psbt(
         script_witness.push(input.tap_key_sig.unwrap().to_vec());
         input.final_script_witness = Some(script_witness);
 
-        // Clear all the data fields as per the spec.
+        // Clear all the data fields as per the spec, but the sighash type, which records what
+        // the signature commits to.
         input.partial_sigs = BTreeMap::new();
-        input.sighash_type = None;
         input.redeem_script = None;
         input.witness_script = None;
         input.bip32_derivation = BTreeMap::new();
     });
+}
+
+/// Like `finalize_spend_psbt`, for a spend through `leaf` signed by the key in it.
+fn finalize_leaf_spend_psbt(
+    spend_psbt: &mut Psbt,
+    leaf: &LeafSpend,
+    final_signature: [u8; 64],
+    sighash_type: TapSighashType,
+) {
+    let signature = schnorr::Signature::from_slice(&final_signature).unwrap();
+    let signature = taproot::Signature {
+        signature,
+        sighash_type,
+    };
+
+    let input = &mut spend_psbt.inputs[0];
+    let mut script_witness = Witness::new();
+    script_witness.push(signature.to_vec());
+    script_witness.push(leaf.script.as_bytes());
+    script_witness.push(leaf.control_block.serialize());
+    input.final_script_witness = Some(script_witness);
+
+    // Clear all the data fields as per the spec, but the sighash type.
+    input.tap_scripts = BTreeMap::new();
+    input.tap_internal_key = None;
+}
 
-    let spend_tx = spend_psbt.clone().extract_tx().unwrap();
+/// The spends of the unvault output of a vault, each signed by a key of its own.
+struct VaultLegs {
+    final_key: LeafKey,
+    final_leaf: LeafSpend,
+    final_psbt: Psbt,
+    final_message: Vec<u8>,
+    final_sighash_type: TapSighashType,
+    /// The internal key of the unvault output.
+    clawback_key: EphemeralKey,
+    clawback_psbt: Psbt,
+    clawback_message: Vec<u8>,
+    clawback_sighash_type: TapSighashType,
+}
 
-    let serialized_signed_tx = consensus::encode::serialize_hex(&spend_tx);
-    let serialized_funding_tx = consensus::encode::serialize_hex(&deposit_tx);
-    println!("Transaction Details: {:#?}", spend_tx);
-    // check with:
-    // bitcoin-cli decoderawtransaction <RAW_TX> true
-    println!("Raw deposit Transaction: {}", serialized_funding_tx);
-    println!("Raw spending Transaction: {}", serialized_signed_tx);
+impl VaultLegs {
+    /// Signs and finalizes both spends.
+    async fn sign(self) -> Result<VaultSpends, Box<dyn std::error::Error>> {
+        let final_key = self.final_key.key;
+        let target = SignTarget {
+            message: self.final_message,
+            adaptor_point: MaybePoint::Infinity,
+        };
+        let mut final_psbt = self.final_psbt;
+        match self.final_key.sign(&target).await? {
+            (SpendSig::Final(sig), _) => finalize_leaf_spend_psbt(
+                &mut final_psbt,
+                &self.final_leaf,
+                sig,
+                self.final_sighash_type,
+            ),
+            (SpendSig::Adaptor(_), _) => unreachable!("vault spends are never adaptor signed"),
+        }
+
+        let target = SignTarget {
+            message: self.clawback_message,
+            adaptor_point: MaybePoint::Infinity,
+        };
+        let mut clawback_psbt = self.clawback_psbt;
+        match self.clawback_key.sign(&target).await? {
+            (SpendSig::Final(sig), _) => {
+                finalize_spend_psbt(&mut clawback_psbt, sig, self.clawback_sighash_type)
+            }
+            (SpendSig::Adaptor(_), _) => unreachable!("vault spends are never adaptor signed"),
+        }
 
-    let res = spend_tx
-        .verify(|op| {
-            println!("fetchin op {}", op);
-            Some(utxos[0].clone())
+        Ok(VaultSpends {
+            final_psbt,
+            clawback_psbt,
+            final_key: final_key.to_string(),
         })
+    }
+}
+
+/// An ephemeral key aggregated from fresh sessions with all signers, and the taproot output it
+/// is the internal key of.
+struct EphemeralKey {
+    sessions: Vec<SigningSession>,
+    pubkeys: Vec<PublicKey>,
+    public_nonces: Vec<PubNonce>,
+    /// Tweaked for the taproot output.
+    key_agg_ctx: KeyAggContext,
+    internal_key: XOnlyPublicKey,
+    spend_info: TaprootSpendInfo,
+}
+
+impl EphemeralKey {
+    fn script_pubkey(&self) -> ScriptBuf {
+        ScriptBuf::new_p2tr_tweaked(self.spend_info.output_key())
+    }
+
+    /// Signs the target, the only message the key ever signs, after which the signers delete
+    /// the key, returning their enclave attestations of this if they all run in enclaves.
+    async fn sign(
+        self,
+        target: &SignTarget,
+    ) -> Result<(SpendSig, Vec<EnclaveAttestation>), Box<dyn std::error::Error>> {
+        sign_message(
+            self.sessions,
+            &self.pubkeys,
+            &self.public_nonces,
+            &self.key_agg_ctx,
+            target,
+        )
+        .await
+    }
+}
+
+/// A key aggregated from fresh sessions with all signers, signing a single presigned
+/// transaction through its own leaf of the output the transaction spends, such as its
+/// `checksig_script`.
+struct LeafKey {
+    sessions: Vec<SigningSession>,
+    pubkeys: Vec<PublicKey>,
+    public_nonces: Vec<PubNonce>,
+    /// Untweaked, as script path signatures are made with the key in the leaf.
+    key_agg_ctx: KeyAggContext,
+    key: XOnlyPublicKey,
+}
+
+impl LeafKey {
+    fn script(&self) -> ScriptBuf {
+        checksig_script(self.key)
+    }
+
+    /// Signs the target like `EphemeralKey::sign`.
+    async fn sign(
+        self,
+        target: &SignTarget,
+    ) -> Result<(SpendSig, Vec<EnclaveAttestation>), Box<dyn std::error::Error>> {
+        sign_message(
+            self.sessions,
+            &self.pubkeys,
+            &self.public_nonces,
+            &self.key_agg_ctx,
+            target,
+        )
+        .await
+    }
+}
+
+/// Starts sessions with all signers for a leaf key.
+async fn init_leaf_key(cfg: &Config) -> Result<LeafKey, Box<dyn std::error::Error>> {
+    let sessions = init_signer_sessions(cfg, None).await?;
+    let (pubkeys, public_nonces, key_agg_ctx) = aggregate_pubs(&sessions);
+    let key: Point = key_agg_ctx.aggregated_pubkey();
+    let key = XOnlyPublicKey::from_slice(&key.serialize_xonly())?;
+    println!("leaf key: {}", key);
+
+    Ok(LeafKey {
+        sessions,
+        pubkeys,
+        public_nonces,
+        key_agg_ctx,
+        key,
+    })
+}
+
+/// Starts sessions with all signers, each able to sign a single message, and aggregates their
+/// keys into the internal key of an output committing to `leaves`. If a scan key is given, the
+/// signers also return their ECDH shares with it.
+async fn init_ephemeral_key<C: Verification>(
+    cfg: &Config,
+    secp: &Secp256k1<C>,
+    leaves: Vec<ScriptBuf>,
+    scan_key: Option<Point>,
+) -> Result<EphemeralKey, Box<dyn std::error::Error>> {
+    let sessions = init_signer_sessions(cfg, scan_key).await?;
+
+    let (pubkeys, public_nonces, key_agg_ctx) = aggregate_pubs(&sessions);
+
+    let untweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey_untweaked();
+    println!("untweaked agg pubkey X: {}", untweaked_aggregated_pubkey);
+
+    let pk = bitcoin::secp256k1::PublicKey::from_slice(&untweaked_aggregated_pubkey.serialize())
         .unwrap();
-    println!("Transaction Result: {:#?}", res);
+    let (xpub, _) = pk.x_only_public_key();
+    println!("agg pubkey: {} x-only:{}", pk, xpub);
 
-    let resp = SignPsbtResp {
-        deposit_psbt: deposit_psbt,
-        spend_psbt: spend_psbt,
+    // The ephemeral key is the internal key of the output, committing to any script paths the
+    // depositor asked for. We must sign for the output key with the same tweak.
+    let spend_info = deposit_spend_info(secp, xpub, leaves);
+    let key_agg_ctx = match spend_info.merkle_root() {
+        None => key_agg_ctx.with_unspendable_taproot_tweak().unwrap(),
+        Some(root) => key_agg_ctx
+            .with_taproot_tweak(&root.to_byte_array())
+            .unwrap(),
     };
-    Ok(web::Json(resp))
+    let tweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
+    println!("taptweaked agg pubkey X: {}", tweaked_aggregated_pubkey);
+
+    Ok(EphemeralKey {
+        sessions,
+        pubkeys,
+        public_nonces,
+        key_agg_ctx,
+        internal_key: xpub,
+        spend_info,
+    })
 }
 
 struct SigningSession {
     signer: String,
+    /// Peers of the signer able to sign the session in its place.
+    replicas: Vec<String>,
     session_id: String,
     init_resp: InitResp,
 }
 
+/// A message to be signed by the ephemeral key, optionally encrypted to an adaptor point.
+struct SignTarget {
+    message: Vec<u8>,
+    adaptor_point: MaybePoint,
+}
+
+/// The signature produced for a SignTarget.
+enum SpendSig {
+    Final([u8; 64]),
+    Adaptor(AdaptorSignature),
+}
+
+/// A blinded aggregate of the signers' nonces, and the coefficient they are aggregated with.
+struct BlindedNonce {
+    blinding_factors: Vec<(Scalar, Scalar)>,
+    sign_nonce: MaybePoint,
+    b: MaybeScalar,
+}
+
+/// The blinded nonce and challenge used to sign a single SignTarget.
+struct BlindedChallenge {
+    blinding_factors: Vec<(Scalar, Scalar)>,
+    sign_nonce: MaybePoint,
+    adapted_nonce: MaybePoint,
+    b: MaybeScalar,
+    e: MaybeScalar,
+}
+
+/// Starts a session with each signer, handing out a key and a single nonce to sign with. A key
+/// never signs more than one message, as signing several blinded challenges with it would let
+/// us combine the signatures into a forgery (the ROS attack).
 async fn init_signer_sessions(
     cfg: &Config,
+    scan_key: Option<Point>,
 ) -> Result<Vec<SigningSession>, Box<dyn std::error::Error>> {
     let mut sessions = vec![];
 
     for s in &cfg.signers {
         let id = hex::encode(rand::thread_rng().random::<[u8; 32]>());
-        let resp = reqwest::get(format!("http://{s}/init/{id}"))
-            .await?
-            .json::<InitResp>()
-            .await?;
+        let mut url = format!("http://{s}/init/{id}");
+        if let Some(scan_key) = scan_key {
+            url.push_str(&format!("?scan_key={}", scan_key));
+        }
+        let resp = reqwest::get(url).await?.json::<InitResp>().await?;
         println!("{resp:#?}");
 
+        if scan_key.is_some() && resp.ecdh_share.as_ref().map(|e| &e.pubkey) != Some(&resp.pubkey) {
+            return Err(format!("signer {} returned no ECDH share for its key", s).into());
+        }
+
         let session = SigningSession {
             signer: s.into(),
+            replicas: cfg.signer_replicas.get(s).cloned().unwrap_or_default(),
             session_id: id.clone(),
             init_resp: resp.clone(),
         };
@@ -446,20 +1832,215 @@ async fn init_signer_sessions(
     Ok(sessions)
 }
 
+/// Signs the target with the key in a single round with the signers. Returns the signature
+/// along with the signers' enclave attestations of deleting their keys, if all of them run in
+/// enclaves.
+async fn sign_message(
+    sessions: Vec<SigningSession>,
+    pubkeys: &Vec<PublicKey>,
+    public_nonces: &Vec<PubNonce>,
+    key_agg_ctx: &KeyAggContext,
+    target: &SignTarget,
+) -> Result<(SpendSig, Vec<EnclaveAttestation>), Box<dyn std::error::Error>> {
+    let tweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
+
+    let challenge = blind_challenge(pubkeys, public_nonces, key_agg_ctx, target);
+
+    let signers: Vec<String> = sessions.iter().map(|s| s.signer.clone()).collect();
+    let (partials, attestations) =
+        request_partial_sigs(sessions, key_agg_ctx, tweaked_aggregated_pubkey, &challenge).await?;
+
+    let sig = aggregate_sigs(
+        &signers,
+        public_nonces,
+        key_agg_ctx,
+        challenge,
+        partials,
+        target,
+    )?;
+    Ok((sig, attestations))
+}
+
+/// Verifies the signers' partial signatures of the challenge, and aggregates them into a
+/// signature of the target.
+fn aggregate_sigs(
+    signers: &[String],
+    public_nonces: &Vec<PubNonce>,
+    key_agg_ctx: &KeyAggContext,
+    challenge: BlindedChallenge,
+    partials: Vec<MaybeScalar>,
+    target: &SignTarget,
+) -> Result<SpendSig, Box<dyn std::error::Error>> {
+    let tweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
+
+    verify_partial_sigs(
+        signers,
+        public_nonces,
+        key_agg_ctx,
+        tweaked_aggregated_pubkey,
+        &challenge.blinding_factors,
+        challenge.adapted_nonce,
+        challenge.b,
+        challenge.e,
+        &partials,
+    )?;
+
+    let unblinded_sigs = unblind_partial_sigs(
+        challenge.blinding_factors,
+        challenge.adapted_nonce,
+        partials,
+    );
+
+    let sig = if target.adaptor_point.is_infinity() {
+        let final_signature = aggregate_partial_sigs(
+            &target.message,
+            key_agg_ctx,
+            challenge.sign_nonce,
+            unblinded_sigs,
+        )?;
+
+        musig2::verify_single(tweaked_aggregated_pubkey, &final_signature, &target.message)?;
+
+        SpendSig::Final(final_signature)
+    } else {
+        let adaptor_sig = aggregate_partial_adaptor_sigs(
+            key_agg_ctx,
+            challenge.sign_nonce,
+            challenge.adapted_nonce,
+            challenge.e,
+            unblinded_sigs,
+        )?;
+
+        musig2::adaptor::verify_single(
+            tweaked_aggregated_pubkey,
+            &adaptor_sig,
+            &target.message,
+            target.adaptor_point,
+        )?;
+
+        SpendSig::Adaptor(adaptor_sig)
+    };
+
+    Ok(sig)
+}
+
+fn blind_challenge(
+    pubkeys: &Vec<PublicKey>,
+    public_nonces: &Vec<PubNonce>,
+    key_agg_ctx: &KeyAggContext,
+    target: &SignTarget,
+) -> BlindedChallenge {
+    let tweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
+    let nonce = blind_nonce(
+        pubkeys,
+        public_nonces,
+        key_agg_ctx,
+        tweaked_aggregated_pubkey,
+        &target.message,
+    );
+    let sign_nonce = nonce.sign_nonce;
+
+    // In adaptor mode the challenge commits to the nonce adapted with the adaptor point, and the
+    // signers must use the parity of the adapted nonce.
+    let adapted_nonce = sign_nonce + target.adaptor_point;
+
+    let nonce_x_bytes = adapted_nonce.serialize_xonly();
+    let e: MaybeScalar = compute_challenge_hash_tweak(
+        &nonce_x_bytes,
+        &tweaked_aggregated_pubkey.into(),
+        &target.message,
+    );
+
+    BlindedChallenge {
+        blinding_factors: nonce.blinding_factors,
+        sign_nonce,
+        adapted_nonce,
+        b: nonce.b,
+        e,
+    }
+}
+
+/// Aggregates `public_nonces` with a coefficient bound to `aggregated_pubkey` and `message`,
+/// and blinds the result with fresh blinding factors.
+fn blind_nonce(
+    pubkeys: &Vec<PublicKey>,
+    public_nonces: &Vec<PubNonce>,
+    key_agg_ctx: &KeyAggContext,
+    aggregated_pubkey: Point,
+    message: &[u8],
+) -> BlindedNonce {
+    let blinding_factors = gen_blinding_factors(pubkeys.len());
+
+    let aas: MaybeScalar = blinding_factors.iter().map(|(a, b)| *a).sum();
+    let bbs: MaybePoint = blinding_factors
+        .iter()
+        .enumerate()
+        .map(|(i, (a, b))| {
+            let pubkey: Point = pubkeys[i].into();
+            let c = key_agg_ctx.key_coefficient(pubkey).unwrap();
+            let bc = *b * c;
+            bc * pubkey
+        })
+        .sum();
+
+    // We manually aggregate the nonces together and then construct our partial signature.
+    let aggregated_nonce: AggNonce = public_nonces.iter().sum();
+
+    let b: MaybeScalar = aggregated_nonce.nonce_coefficient(aggregated_pubkey, message);
+    let agg_nonce: MaybePoint = aggregated_nonce.final_nonce(b);
+    let sign_nonce = agg_nonce + aas * G + bbs;
+
+    BlindedNonce {
+        blinding_factors,
+        sign_nonce,
+        b,
+    }
+}
+
 fn aggregate_partial_sigs(
     message: impl AsRef<[u8]>,
     key_agg_ctx: &KeyAggContext,
     sign_nonce: MaybePoint,
     unblinded_sigs: Vec<PartialSignature>,
-) -> [u8; 64] {
-    let final_signature: [u8; 64] = musig2::aggregate_partial_signatures_final_nonce(
+) -> Result<[u8; 64], VerifyError> {
+    musig2::aggregate_partial_signatures_final_nonce(
         &key_agg_ctx,
-        sign_nonce.try_into().unwrap(),
+        sign_nonce
+            .try_into()
+            .map_err(|_| VerifyError::BadSignature)?,
         unblinded_sigs,
         message,
     )
-    .expect("error aggregating signatures");
-    final_signature
+}
+
+/// Aggregates the unblinded partial signatures into an adaptor signature over `sign_nonce`, as
+/// `musig2::adaptor::aggregate_partial_adaptor_signatures` does for an unblinded nonce. Fails if
+/// the signature would not be valid once adapted.
+fn aggregate_partial_adaptor_sigs(
+    key_agg_ctx: &KeyAggContext,
+    sign_nonce: MaybePoint,
+    adapted_nonce: MaybePoint,
+    e: MaybeScalar,
+    unblinded_sigs: Vec<PartialSignature>,
+) -> Result<AdaptorSignature, VerifyError> {
+    let aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
+    let tweak: MaybeScalar = key_agg_ctx.tweak_sum().unwrap_or(MaybeScalar::Zero);
+
+    // Same as the regular aggregation, but the nonce is left unadapted.
+    let s: MaybeScalar = unblinded_sigs.into_iter().sum::<MaybeScalar>()
+        + (e * tweak).negate_if(aggregated_pubkey.parity());
+
+    // The signers signed with the parity of the adapted nonce, so `s` is over the nonce negated
+    // if the adapted nonce is odd. Adapting negates the secret to match.
+    let effective_nonce = match adapted_nonce.has_even_y() {
+        true => sign_nonce,
+        false => -sign_nonce,
+    };
+    if s * G != effective_nonce + e * aggregated_pubkey.to_even_y() {
+        return Err(VerifyError::BadSignature);
+    }
+
+    Ok(AdaptorSignature::new(sign_nonce, s))
 }
 
 fn unblind_partial_sigs(
@@ -481,7 +2062,10 @@ fn unblind_partial_sigs(
     unblinded_sigs
 }
 
+/// Verifies each signer's partial signature, blaming the first of `signers` to send an invalid
+/// one.
 fn verify_partial_sigs(
+    signers: &[String],
     public_nonces: &Vec<PubNonce>,
     key_agg_ctx: &KeyAggContext,
     aggregated_pubkey: Point,
@@ -490,7 +2074,7 @@ fn verify_partial_sigs(
     b: MaybeScalar,
     e: MaybeScalar,
     partial_signatures: &Vec<MaybeScalar>,
-) {
+) -> Result<(), String> {
     let challenge_parity = aggregated_pubkey.parity() ^ key_agg_ctx.parity_acc();
     let nonce_parity = sign_nonce.parity();
 
@@ -518,64 +2102,118 @@ fn verify_partial_sigs(
             b,
             ep,
         )
-        .expect("received invalid signature from a peer");
+        .map_err(|e| {
+            format!(
+                "signer {} sent an invalid partial signature: {}",
+                signers[i], e
+            )
+        })?;
     }
+    Ok(())
 }
 
 async fn request_partial_sigs(
     sessions: Vec<SigningSession>,
     key_agg_ctx: &KeyAggContext,
     aggregated_pubkey: Point,
-    blinding_factors: &Vec<(Scalar, Scalar)>,
-    sign_nonce: MaybePoint,
-    b: MaybeScalar,
-    e: MaybeScalar,
-) -> Result<Vec<MaybeScalar>, Box<dyn std::error::Error>> {
-    let challenge_parity = aggregated_pubkey.parity() ^ key_agg_ctx.parity_acc();
-    let nonce_parity = sign_nonce.parity();
-
+    challenge: &BlindedChallenge,
+) -> Result<(Vec<MaybeScalar>, Vec<EnclaveAttestation>), Box<dyn std::error::Error>> {
     let mut partial_signatures = vec![];
+    let mut attestations = vec![];
     for (i, session) in sessions.iter().enumerate() {
-        let their_pubkey: PublicKey = key_agg_ctx.get_pubkey(i).unwrap();
-        let key_coeff = key_agg_ctx.key_coefficient(their_pubkey).unwrap();
-
-        let even_parity = bool::from(!challenge_parity);
-        let ep = if sign_nonce.has_even_y() ^ even_parity {
-            e - blinding_factors[i].1
-        } else {
-            e + blinding_factors[i].1
-        };
+        let sign_challenge = signer_challenge(key_agg_ctx, aggregated_pubkey, challenge, i);
 
         let signer = session.signer.clone();
         let id = session.session_id.clone();
         let client = reqwest::Client::new();
-        let url = format!("http://{signer}/sign/{id}");
-        println!("url: {}", url);
 
         let body = SignReq {
             session_id: id.clone(),
-            challenge_parity: challenge_parity.unwrap_u8(),
-            nonce_parity: nonce_parity.unwrap_u8(),
-            b: b.encode_hex(), // TODO: blind it?
-            key_coeff: key_coeff.encode_hex(),
-            e: hex::encode(ep),
+            challenge: sign_challenge,
+            state: session.init_resp.state.clone(),
         };
         let body_json = serde_json::to_string(&body).unwrap();
         println!("body_json: {}", body_json);
-        let resp = client
-            .post(url)
-            .json(&body)
-            .send()
-            .await?;
+        let resp = send_sign_req(&client, &signer, &session.replicas, &body).await?;
         println!("{resp:#?}");
-        let j = resp.json::<SignResp>()
-            .await?;
+        if resp.status() == reqwest::StatusCode::GONE {
+            return Err(resp.json::<SessionExpired>().await?.into());
+        }
+        let j = resp.json::<SignResp>().await?;
         println!("{j:#?}");
 
-        let p = PartialSignature::from_hex(&j.sig).unwrap();
-        partial_signatures.push(p);
+        partial_signatures.push(PartialSignature::from_hex(&j.sig)?);
+
+        match j.attestation {
+            Some(a) if a.session_id == id && a.pubkey == session.init_resp.pubkey => {
+                attestations.push(a)
+            }
+            Some(_) => {
+                return Err(format!("signer {} attested another session", signer).into());
+            }
+            None => println!("signer {} attested no key deletion", signer),
+        }
+    }
+
+    // The deletion is only evidenced if every signer's key is attested.
+    if attestations.len() != sessions.len() {
+        attestations.clear();
     }
-    Ok(partial_signatures)
+    Ok((partial_signatures, attestations))
+}
+
+/// The challenge signer `i` signs, with its blinding factor applied.
+fn signer_challenge(
+    key_agg_ctx: &KeyAggContext,
+    aggregated_pubkey: Point,
+    challenge: &BlindedChallenge,
+    i: usize,
+) -> SignChallenge {
+    let challenge_parity = aggregated_pubkey.parity() ^ key_agg_ctx.parity_acc();
+    let even_parity = bool::from(!challenge_parity);
+
+    let their_pubkey: PublicKey = key_agg_ctx.get_pubkey(i).unwrap();
+    let key_coeff = key_agg_ctx.key_coefficient(their_pubkey).unwrap();
+
+    let c = challenge;
+    let ep = if c.adapted_nonce.has_even_y() ^ even_parity {
+        c.e - c.blinding_factors[i].1
+    } else {
+        c.e + c.blinding_factors[i].1
+    };
+
+    SignChallenge {
+        challenge_parity: challenge_parity.unwrap_u8(),
+        nonce_parity: c.adapted_nonce.parity().unwrap_u8(),
+        b: c.b.encode_hex(), // TODO: blind it?
+        key_coeff: key_coeff.encode_hex(),
+        e: hex::encode(ep),
+    }
+}
+
+/// Sends `req` to `signer`, failing over to its replicas in turn while the request fails. A
+/// session is never signed twice, so a replica refuses it if the signer signed after all.
+async fn send_sign_req(
+    client: &reqwest::Client,
+    signer: &str,
+    replicas: &[String],
+    req: &SignReq,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let url = format!("http://{signer}/sign/{}", req.session_id);
+    println!("url: {}", url);
+    let mut res = client.post(url).json(req).send().await;
+    for replica in replicas {
+        let Err(e) = &res else {
+            break;
+        };
+        println!(
+            "signer {} failed: {}, failing over to {}",
+            signer, e, replica
+        );
+        let url = format!("http://{replica}/sign/{}", req.session_id);
+        res = client.post(url).json(req).send().await;
+    }
+    res
 }
 
 fn gen_blinding_factors(num_signers: usize) -> Vec<(Scalar, Scalar)> {
@@ -606,7 +2244,7 @@ fn gen_blinding_factors(num_signers: usize) -> Vec<(Scalar, Scalar)> {
 
 fn aggregate_pubs(
     sessions: &Vec<SigningSession>,
-) -> (Vec<PublicKey>, Vec<PubNonce>, KeyAggContext, AggNonce) {
+) -> (Vec<PublicKey>, Vec<PubNonce>, KeyAggContext) {
     let (pubkeys, public_nonces): (Vec<PublicKey>, Vec<PubNonce>) = sessions
         .iter()
         .map(|session| {
@@ -618,14 +2256,128 @@ fn aggregate_pubs(
         })
         .collect();
 
-    let mut key_agg_ctx = KeyAggContext::new(pubkeys.clone()).unwrap();
+    // The key aggregation context is returned untweaked, as the taproot tweak depends on the
+    // script tree of the output being signed for.
+    let key_agg_ctx = KeyAggContext::new(pubkeys.clone()).unwrap();
     let untweaked_aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
     println!("untweaked agg pubkey X: {}", untweaked_aggregated_pubkey);
-    key_agg_ctx = key_agg_ctx.with_unspendable_taproot_tweak().unwrap();
-    let aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
-    println!("taptweaked agg pubkey X: {}", aggregated_pubkey);
 
-    // We manually aggregate the nonces together and then construct our partial signature.
-    let aggregated_nonce: AggNonce = public_nonces.iter().sum();
-    (pubkeys, public_nonces, key_agg_ctx, aggregated_nonce)
+    (pubkeys, public_nonces, key_agg_ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use musig2::BinaryEncoding;
+
+    /// Signs `target` as the coordinator does, with `seckeys` standing in for the signers.
+    fn sign_locally(
+        seckeys: &[SecretKey],
+        key_agg_ctx: &KeyAggContext,
+        target: &SignTarget,
+    ) -> Result<SpendSig, Box<dyn std::error::Error>> {
+        let secp = Secp256k1::new();
+        let pubkeys: Vec<PublicKey> = seckeys.iter().map(|k| k.public_key(&secp)).collect();
+        let secnonces: Vec<SecNonce> = seckeys
+            .iter()
+            .map(|_| SecNonce::build(rand::thread_rng().random::<[u8; 32]>()).build())
+            .collect();
+        let public_nonces: Vec<PubNonce> = secnonces.iter().map(|s| s.public_nonce()).collect();
+        let aggregated_pubkey: Point = key_agg_ctx.aggregated_pubkey();
+
+        let challenge = blind_challenge(&pubkeys, &public_nonces, key_agg_ctx, target);
+
+        // Sign as the signers do, from the challenge sent to them.
+        let mut partials = vec![];
+        for (i, (seckey, secnonce)) in seckeys.iter().zip(secnonces).enumerate() {
+            let c = signer_challenge(key_agg_ctx, aggregated_pubkey, &challenge, i);
+            partials.push(musig2::sign_partial_challenge(
+                MaybeScalar::from_hex(&c.b)?,
+                MaybeScalar::from_hex(&c.key_coeff)?,
+                c.challenge_parity.into(),
+                *seckey,
+                secnonce,
+                c.nonce_parity.into(),
+                MaybeScalar::from_hex(&c.e)?,
+            )?);
+        }
+
+        let signers: Vec<String> = (0..seckeys.len()).map(|i| format!("signer{i}")).collect();
+        aggregate_sigs(
+            &signers,
+            &public_nonces,
+            key_agg_ctx,
+            challenge,
+            partials,
+            target,
+        )
+    }
+
+    fn tweaked_key_agg_ctx(seckeys: &[SecretKey]) -> KeyAggContext {
+        let secp = Secp256k1::new();
+        let pubkeys: Vec<PublicKey> = seckeys.iter().map(|k| k.public_key(&secp)).collect();
+        KeyAggContext::new(pubkeys)
+            .unwrap()
+            .with_taproot_tweak(&[7u8; 32])
+            .unwrap()
+    }
+
+    fn random_keys(n: usize) -> Vec<SecretKey> {
+        (0..n)
+            .map(|_| SecretKey::from_byte_array(&rand::thread_rng().random()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn signature_verifies() {
+        let seckeys = random_keys(3);
+        let key_agg_ctx = tweaked_key_agg_ctx(&seckeys);
+        let target = SignTarget {
+            message: b"spend".to_vec(),
+            adaptor_point: MaybePoint::Infinity,
+        };
+
+        let SpendSig::Final(sig) = sign_locally(&seckeys, &key_agg_ctx, &target).unwrap() else {
+            panic!("expected a final signature");
+        };
+        let pubkey: Point = key_agg_ctx.aggregated_pubkey();
+        musig2::verify_single(pubkey, sig, &target.message).unwrap();
+    }
+
+    /// Adapting the aggregated adaptor signature with the secret must give a valid BIP-340
+    /// signature, whichever the parity of the adapted nonce.
+    #[test]
+    fn adapted_signature_verifies() {
+        let seckeys = random_keys(2);
+        let key_agg_ctx = tweaked_key_agg_ctx(&seckeys);
+        let pubkey: Point = key_agg_ctx.aggregated_pubkey();
+
+        let (mut even, mut odd) = (false, false);
+        while !(even && odd) {
+            let secret = Scalar::from_slice(&random_keys(1)[0].secret_bytes()).unwrap();
+            let target = SignTarget {
+                message: b"outcome".to_vec(),
+                adaptor_point: (secret * G).into(),
+            };
+
+            let SpendSig::Adaptor(adaptor_sig) =
+                sign_locally(&seckeys, &key_agg_ctx, &target).unwrap()
+            else {
+                panic!("expected an adaptor signature");
+            };
+            let sig: [u8; 64] = adaptor_sig.adapt(secret).unwrap();
+            musig2::verify_single(pubkey, sig, &target.message).unwrap();
+
+            // The signature is only valid once adapted with the secret.
+            let other = Scalar::from_slice(&random_keys(1)[0].secret_bytes()).unwrap();
+            let sig: [u8; 64] = adaptor_sig.adapt(other).unwrap();
+            assert!(musig2::verify_single(pubkey, sig, &target.message).is_err());
+
+            let nonce = MaybePoint::from_slice(&adaptor_sig.to_bytes()[..33]).unwrap();
+            match (nonce + target.adaptor_point).has_even_y() {
+                true => even = true,
+                false => odd = true,
+            }
+        }
+    }
 }
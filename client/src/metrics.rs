@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use shared::PolicyRule;
+
+/// Upper bounds of the signing latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Counters served in the Prometheus text format on `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    sessions_started: AtomicU64,
+    sessions_completed: AtomicU64,
+    sessions_failed: AtomicU64,
+    /// Rejections by the snake case name of the policy rule.
+    policy_rejections: Mutex<BTreeMap<String, u64>>,
+    /// Failed requests by error category.
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+    signing_latency: Mutex<Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations in each of `LATENCY_BUCKETS`, not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Error category of a failed request with status `status`.
+fn category(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 => "invalid_request",
+        403 | 429 | 503 => "policy",
+        409 => "revoked",
+        410 => "session_expired",
+        500 => "internal",
+        502 | 504 => "signer_unavailable",
+        _ => "other",
+    }
+}
+
+impl Metrics {
+    pub fn session_started(&self) {
+        self.sessions_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_completed(&self) {
+        self.sessions_completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a session that failed with an error response of status `status`.
+    pub fn session_failed(&self, status: StatusCode) {
+        self.sessions_failed.fetch_add(1, Ordering::Relaxed);
+        *self
+            .errors
+            .lock()
+            .unwrap()
+            .entry(category(status))
+            .or_default() += 1;
+    }
+
+    pub fn policy_rejection(&self, rule: &PolicyRule) {
+        let name = serde_json::to_value(rule).unwrap();
+        *self
+            .policy_rejections
+            .lock()
+            .unwrap()
+            .entry(name.as_str().unwrap().to_string())
+            .or_default() += 1;
+    }
+
+    /// Records a signing round with the signers that took `elapsed`.
+    pub fn signed(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let mut histogram = self.signing_latency.lock().unwrap();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| secs <= *le) {
+            histogram.buckets[i] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += secs;
+    }
+
+    /// Renders the metrics in the Prometheus text format. `liability` is the number and total
+    /// value in sats of the tracked deposits, if liability tracking is enabled.
+    pub fn render(&self, liability: Option<(usize, u64)>) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "sessions_started_total",
+                "Signing sessions started.",
+                &self.sessions_started,
+            ),
+            (
+                "sessions_completed_total",
+                "Signing sessions that returned the presigned transactions.",
+                &self.sessions_completed,
+            ),
+            (
+                "sessions_failed_total",
+                "Signing sessions that failed or were rejected.",
+                &self.sessions_failed,
+            ),
+        ];
+        for (name, help, value) in counters {
+            header(&mut out, name, help, "counter");
+            writeln!(
+                out,
+                "ephemeral_sign_{} {}",
+                name,
+                value.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "policy_rejections_total",
+            "Requests rejected by the signer policy, by rule.",
+            "counter",
+        );
+        for (rule, count) in self.policy_rejections.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "ephemeral_sign_policy_rejections_total{{rule=\"{}\"}} {}",
+                rule, count
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "errors_total",
+            "Failed requests, by error category.",
+            "counter",
+        );
+        for (category, count) in self.errors.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "ephemeral_sign_errors_total{{category=\"{}\"}} {}",
+                category, count
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "signing_duration_seconds",
+            "Duration of the signing rounds with the signers.",
+            "histogram",
+        );
+        let histogram = self.signing_latency.lock().unwrap();
+        let mut cumulative = 0;
+        for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            writeln!(
+                out,
+                "ephemeral_sign_signing_duration_seconds_bucket{{le=\"{}\"}} {}",
+                le, cumulative
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "ephemeral_sign_signing_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            histogram.count
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "ephemeral_sign_signing_duration_seconds_sum {}",
+            histogram.sum
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "ephemeral_sign_signing_duration_seconds_count {}",
+            histogram.count
+        )
+        .unwrap();
+
+        if let Some((deposits, value_sat)) = liability {
+            header(
+                &mut out,
+                "outstanding_deposits",
+                "Deposits counting against the liability ceiling.",
+                "gauge",
+            );
+            writeln!(out, "ephemeral_sign_outstanding_deposits {}", deposits).unwrap();
+            header(
+                &mut out,
+                "outstanding_liability_sats",
+                "Total value of the deposits counting against the liability ceiling.",
+                "gauge",
+            );
+            writeln!(
+                out,
+                "ephemeral_sign_outstanding_liability_sats {}",
+                value_sat
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP ephemeral_sign_{} {}", name, help).unwrap();
+    writeln!(out, "# TYPE ephemeral_sign_{} {}", name, kind).unwrap();
+}
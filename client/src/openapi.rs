@@ -0,0 +1,21 @@
+use utoipa::OpenApi;
+
+/// OpenAPI description of the signer service's public API, generated from the shared request and
+/// response types, so clients in other languages can be generated from it.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Ephemeral-sign signer service",
+        description = "Presigns spends of deposits to a key the signers delete after signing."
+    ),
+    paths(
+        crate::sign_psbt,
+        crate::blind::init,
+        crate::blind::sign,
+        crate::get_quote,
+        crate::health,
+        crate::metrics,
+        crate::presigned
+    )
+)]
+pub struct ApiDoc;
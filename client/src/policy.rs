@@ -0,0 +1,345 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bitcoin::{Address, Amount, Network, Script, Transaction};
+use serde::{Deserialize, Serialize};
+use shared::blind::BlindInitReq;
+use shared::{PolicyRule, PolicySummary, PolicyViolation, SignPsbtError, SignPsbtReq};
+
+use crate::error::PsbtError;
+
+/// Limits on the requests the signer serves, set in the `policy` field of the config. Every
+/// limit is optional, and nothing is restricted by default.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Policy {
+    /// Maximum value of the deposit output, in sats.
+    #[serde(default)]
+    pub max_deposit_sat: Option<u64>,
+    /// Output types the presigned spend may pay the fallback address with. Any is allowed if
+    /// empty.
+    #[serde(default)]
+    pub fallback_types: Vec<ScriptType>,
+    /// Minimum fee of the presigned spend, in sats.
+    #[serde(default)]
+    pub min_spend_fee_sat: Option<u64>,
+    /// Maximum fee of the presigned spend, in sats.
+    #[serde(default)]
+    pub max_spend_fee_sat: Option<u64>,
+    /// Networks requests may be for, in addition to matching --network. Any is allowed if
+    /// empty.
+    #[serde(default)]
+    pub networks: Vec<Network>,
+    /// Number of requests each client may make per time window.
+    #[serde(default)]
+    pub quota: Option<Quota>,
+    /// Addresses of the funders served. If set, requests must carry a BIP-322 proof of control
+    /// of one of them, as an output the deposit spends.
+    #[serde(default)]
+    pub funders: Vec<String>,
+    /// How far in the future the presigned spend's absolute locktime may be, in seconds. A
+    /// height locktime counts ten minutes a block past the tip of `esplora_url`, and is refused
+    /// if the tip is unknown.
+    #[serde(default)]
+    pub max_lock_delay_secs: Option<u64>,
+    /// Maximum relative timelock of the presigned spend, in blocks.
+    #[serde(default)]
+    pub max_relative_blocks: Option<u16>,
+    /// Whether the presigned spend must signal replaceability, so its fee can be bumped.
+    #[serde(default)]
+    pub require_rbf: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Quota {
+    pub requests: usize,
+    pub window_secs: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+}
+
+impl ScriptType {
+    pub fn of(script: &Script) -> Option<Self> {
+        match script {
+            s if s.is_p2pkh() => Some(ScriptType::P2pkh),
+            s if s.is_p2sh() => Some(ScriptType::P2sh),
+            s if s.is_p2wpkh() => Some(ScriptType::P2wpkh),
+            s if s.is_p2wsh() => Some(ScriptType::P2wsh),
+            s if s.is_p2tr() => Some(ScriptType::P2tr),
+            _ => None,
+        }
+    }
+}
+
+/// Locktimes from this value are Unix times rather than block heights.
+const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn violation(rule: PolicyRule, message: String) -> PolicyViolation {
+    PolicyViolation { rule, message }
+}
+
+impl Policy {
+    /// The limits reported on `/health`, together with the liability ceiling `max_liability_sat`.
+    pub fn summary(&self, max_liability_sat: Option<u64>) -> PolicySummary {
+        PolicySummary {
+            max_deposit_sat: self.max_deposit_sat,
+            networks: self.networks.clone(),
+            fallback_types: self
+                .fallback_types
+                .iter()
+                .map(|ty| {
+                    serde_json::to_value(ty)
+                        .unwrap()
+                        .as_str()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect(),
+            min_spend_fee_sat: self.min_spend_fee_sat,
+            max_spend_fee_sat: self.max_spend_fee_sat,
+            max_liability_sat,
+            max_lock_delay_secs: self.max_lock_delay_secs,
+            max_relative_blocks: self.max_relative_blocks,
+            require_rbf: self.require_rbf,
+        }
+    }
+
+    /// Checks what is known of `req` before any signer is contacted.
+    pub fn check_request(&self, req: &SignPsbtReq) -> Result<(), PolicyViolation> {
+        let deposit_value = req
+            .psbt
+            .unsigned_tx
+            .output
+            .first()
+            .map_or(Amount::ZERO, |o| o.value);
+        self.check_deposit(req.network, deposit_value)?;
+
+        if !self.funders.is_empty() {
+            self.check_funder(req)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the blind session `req` before any signer is contacted. Its funding is unknown,
+    /// so it cannot be from an allowlisted funder.
+    pub fn check_blind_request(&self, req: &BlindInitReq) -> Result<(), PolicyViolation> {
+        let deposit_value = Amount::from_sat(req.amount_sat).map_err(|e| {
+            violation(
+                PolicyRule::MaxDepositAmount,
+                format!("invalid deposit amount: {}", e),
+            )
+        })?;
+        self.check_deposit(req.network, deposit_value)?;
+
+        if !self.funders.is_empty() {
+            return Err(violation(
+                PolicyRule::Funder,
+                "blind sessions cannot prove their funder".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks a deposit of `deposit_value` on `network`.
+    fn check_deposit(
+        &self,
+        network: Network,
+        deposit_value: Amount,
+    ) -> Result<(), PolicyViolation> {
+        if !self.networks.is_empty() && !self.networks.contains(&network) {
+            return Err(violation(
+                PolicyRule::Network,
+                format!("requests for {} are not allowed", network),
+            ));
+        }
+
+        if let Some(max) = self.max_deposit_sat {
+            if deposit_value.to_sat() > max {
+                return Err(violation(
+                    PolicyRule::MaxDepositAmount,
+                    format!(
+                        "deposit of {} exceeds the maximum of {} sat",
+                        deposit_value, max
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the request's funding proof is valid and by an allowlisted funder.
+    fn check_funder(&self, req: &SignPsbtReq) -> Result<(), PolicyViolation> {
+        let Some(proof) = &req.funding_proof else {
+            return Err(violation(
+                PolicyRule::Funder,
+                "requests must carry a funding proof".to_string(),
+            ));
+        };
+        let script_pubkey = proof
+            .verify(&req.psbt, req.network)
+            .map_err(|e| violation(PolicyRule::Funder, format!("invalid funding proof: {}", e)))?;
+        let allowed = self.funders.iter().any(|funder| {
+            Address::from_str(funder)
+                .is_ok_and(|a| a.assume_checked().script_pubkey() == script_pubkey)
+        });
+        if !allowed {
+            return Err(violation(
+                PolicyRule::Funder,
+                format!("funder {} is not allowed", proof.proof.address),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the output script the presigned spend pays the fallback address with.
+    pub fn check_fallback(&self, script: &Script) -> Result<(), PolicyViolation> {
+        if self.fallback_types.is_empty() {
+            return Ok(());
+        }
+        match ScriptType::of(script) {
+            Some(ty) if self.fallback_types.contains(&ty) => Ok(()),
+            ty => Err(violation(
+                PolicyRule::FallbackType,
+                format!(
+                    "fallback output type {:?} is not one of {:?}",
+                    ty, self.fallback_types
+                ),
+            )),
+        }
+    }
+
+    /// Checks the fee of the presigned spend.
+    pub fn check_spend_fee(&self, fee: Amount) -> Result<(), PolicyViolation> {
+        if let Some(min) = self.min_spend_fee_sat.filter(|min| fee.to_sat() < *min) {
+            return Err(violation(
+                PolicyRule::MinSpendFee,
+                format!("spend fee {} is below the minimum of {} sat", fee, min),
+            ));
+        }
+        if let Some(max) = self.max_spend_fee_sat.filter(|max| fee.to_sat() > *max) {
+            return Err(violation(
+                PolicyRule::MaxSpendFee,
+                format!("spend fee {} exceeds the maximum of {} sat", fee, max),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the timelocks of the presigned spend `tx`, a height locktime against the chain
+    /// tip at `tip_height`.
+    pub fn check_spend_timelock(
+        &self,
+        tx: &Transaction,
+        tip_height: Option<u32>,
+    ) -> Result<(), PolicyViolation> {
+        let sequence = tx.input[0].sequence;
+        if self.require_rbf && !sequence.is_rbf() {
+            return Err(violation(
+                PolicyRule::SpendTimelock,
+                "the presigned spend must signal replaceability".to_string(),
+            ));
+        }
+        if let Some(max) = self.max_relative_blocks {
+            // Only a relative timelock in blocks can be held to a number of blocks.
+            let blocks = if !sequence.is_relative_lock_time() {
+                0
+            } else if sequence.is_height_locked() {
+                sequence.to_consensus_u32() & 0xffff
+            } else {
+                u32::MAX
+            };
+            if blocks > max as u32 {
+                return Err(violation(
+                    PolicyRule::SpendTimelock,
+                    format!(
+                        "relative timelock {} exceeds the maximum of {} blocks",
+                        sequence, max
+                    ),
+                ));
+            }
+        }
+
+        let lock_time = tx.lock_time.to_consensus_u32();
+        let Some(max) = self.max_lock_delay_secs.filter(|_| lock_time != 0) else {
+            return Ok(());
+        };
+        let delay = match lock_time {
+            t if t >= LOCK_TIME_THRESHOLD => (t as u64).saturating_sub(now()),
+            height => match tip_height {
+                Some(tip) => height.saturating_sub(tip) as u64 * 600,
+                None => {
+                    return Err(violation(
+                        PolicyRule::SpendTimelock,
+                        "the chain tip to check the locktime against is unknown".to_string(),
+                    ));
+                }
+            },
+        };
+        if delay > max {
+            return Err(violation(
+                PolicyRule::SpendTimelock,
+                format!(
+                    "locktime {} is {} seconds out, more than the maximum of {}",
+                    tx.lock_time, delay, max
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Requests made by each client within the quota window.
+#[derive(Default)]
+pub struct Quotas {
+    requests: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+}
+
+impl Quotas {
+    /// Counts a request by `client`, unless it used up `quota` already.
+    pub fn take(&self, client: IpAddr, quota: &Quota) -> Result<(), PolicyViolation> {
+        let window = Duration::from_secs(quota.window_secs);
+        let now = Instant::now();
+        let mut requests = self.requests.lock().unwrap();
+        let times = requests.entry(client).or_default();
+        while times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= window)
+        {
+            times.pop_front();
+        }
+        if times.len() >= quota.requests {
+            return Err(violation(
+                PolicyRule::Quota,
+                format!(
+                    "quota of {} requests per {} seconds used up",
+                    quota.requests, quota.window_secs
+                ),
+            ));
+        }
+        times.push_back(now);
+        Ok(())
+    }
+}
+
+/// Turns `violation` into an error response with it as JSON body, so the depositor can tell
+/// which rule its request broke.
+pub fn rejection(violation: PolicyViolation) -> actix_web::Error {
+    PsbtError(SignPsbtError::PolicyViolation(violation)).into()
+}
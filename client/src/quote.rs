@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use shared::{
+    Feature, PROTOCOL_VERSIONS, PolicyRule, PolicySummary, PolicyViolation, Quote, SignPsbtError,
+    SignPsbtReq,
+};
+
+/// Features every signer built from this tree supports.
+pub const FEATURES: [Feature; 16] = [
+    Feature::Musig2,
+    Feature::ScriptPaths,
+    Feature::AdaptorSignatures,
+    Feature::Cets,
+    Feature::Vaults,
+    Feature::Rollover,
+    Feature::Inheritance,
+    Feature::DecayingMultisig,
+    Feature::SilentPayments,
+    Feature::BinaryEncoding,
+    Feature::BlindSessions,
+    Feature::SpendOutputs,
+    Feature::WeightedFallback,
+    Feature::SpendTimelock,
+    Feature::SighashTypes,
+    Feature::Anchors,
+];
+
+pub fn default_ttl() -> u64 {
+    600
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn violation(message: String) -> PolicyViolation {
+    PolicyViolation {
+        rule: PolicyRule::QuoteTerms,
+        message,
+    }
+}
+
+/// The quotes issued and not yet expired.
+#[derive(Default)]
+pub struct Quotes {
+    issued: Mutex<HashMap<String, Quote>>,
+}
+
+impl Quotes {
+    /// Remembers `quote`, so requests made on it can be checked against it.
+    pub fn issue(&self, quote: Quote) {
+        let now = now();
+        let mut issued = self.issued.lock().unwrap();
+        issued.retain(|_, q| q.expires_at > now);
+        issued.insert(quote.quote_id.clone(), quote);
+    }
+
+    /// Checks that `quote` is one this signer issued and still honours, given its current
+    /// policy `policy`.
+    pub fn check(&self, quote: &Quote, policy: &PolicySummary) -> Result<(), PolicyViolation> {
+        let issued = self.issued.lock().unwrap();
+        match issued.get(&quote.quote_id) {
+            Some(q) if q == quote => {}
+            Some(_) => return Err(violation("quote does not match the one issued".to_string())),
+            None => return Err(violation(format!("unknown quote {}", quote.quote_id))),
+        }
+        if quote.expires_at <= now() {
+            return Err(violation(format!("quote {} expired", quote.quote_id)));
+        }
+        if quote.policy != *policy {
+            return Err(violation(
+                "the signer's policy changed since the quote, request a new one".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Negotiates the features of `req`, returning those its response may use. The request must be
+/// in a protocol version this signer speaks, and rely only on features both sides support.
+pub fn negotiate(req: &SignPsbtReq) -> Result<Vec<Feature>, SignPsbtError> {
+    let required = req.required_features();
+    let unsupported: Vec<Feature> = required
+        .iter()
+        .filter(|f| !FEATURES.contains(f))
+        .copied()
+        .collect();
+    if !PROTOCOL_VERSIONS.contains(&req.version) || !unsupported.is_empty() {
+        return Err(SignPsbtError::Unsupported {
+            message: format!(
+                "request is in protocol version {} and relies on {:?}",
+                req.version, required
+            ),
+            versions: PROTOCOL_VERSIONS.to_vec(),
+            features: unsupported,
+        });
+    }
+
+    // Version 1 requests predate capabilities, and get responses using none beyond it.
+    if req.version == 1 {
+        return Ok(vec![]);
+    }
+    let undeclared: Vec<Feature> = required
+        .iter()
+        .filter(|f| !req.capabilities.contains(f))
+        .copied()
+        .collect();
+    if !undeclared.is_empty() {
+        return Err(SignPsbtError::InvalidRequest {
+            message: format!(
+                "request relies on {:?} without listing them in its capabilities",
+                undeclared
+            ),
+        });
+    }
+    Ok(FEATURES
+        .iter()
+        .filter(|f| req.capabilities.contains(f))
+        .copied()
+        .collect())
+}
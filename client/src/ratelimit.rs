@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use shared::{PolicyRule, PolicyViolation, SignPsbtError};
+
+use crate::error::PsbtError;
+
+/// Header clients pass their API key in.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Token bucket limits on `/psbt` requests, set in the `rate_limit` field of the config.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    /// Limit for each client address.
+    #[serde(default)]
+    pub per_ip: Option<Bucket>,
+    /// Limit for each API key, for requests carrying one.
+    #[serde(default)]
+    pub per_api_key: Option<Bucket>,
+}
+
+/// A bucket holding up to `burst` requests, refilled by `per_minute` requests a minute.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Bucket {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+struct State {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Takes a token from `state`, or returns the seconds until one is available.
+    fn take(&self, state: &mut State) -> Result<(), u64> {
+        let now = Instant::now();
+        let rate = self.per_minute as f64 / 60.0;
+        let elapsed = now.duration_since(state.updated).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * rate).min(self.burst as f64);
+        state.updated = now;
+
+        if state.tokens < 1.0 {
+            return Err(((1.0 - state.tokens) / rate).ceil() as u64);
+        }
+        state.tokens -= 1.0;
+        Ok(())
+    }
+
+    fn full(&self) -> State {
+        State {
+            tokens: self.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+}
+
+/// A rejected request, with the seconds until the client may retry.
+pub struct Limited {
+    violation: PolicyViolation,
+    retry_after: u64,
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    ips: Mutex<HashMap<IpAddr, State>>,
+    api_keys: Mutex<HashMap<String, State>>,
+}
+
+impl RateLimiter {
+    /// Counts a request from `ip`, carrying `api_key` if any, against the buckets in `cfg`.
+    pub fn check(
+        &self,
+        cfg: &RateLimitConfig,
+        ip: Option<IpAddr>,
+        api_key: Option<&str>,
+    ) -> Result<(), Limited> {
+        if let (Some(bucket), Some(ip)) = (&cfg.per_ip, ip) {
+            let mut ips = self.ips.lock().unwrap();
+            let state = ips.entry(ip).or_insert_with(|| bucket.full());
+            bucket.take(state).map_err(|retry_after| {
+                limited(format!("too many requests from {}", ip), retry_after)
+            })?;
+        }
+
+        if let (Some(bucket), Some(api_key)) = (&cfg.per_api_key, api_key) {
+            let mut api_keys = self.api_keys.lock().unwrap();
+            let state = api_keys
+                .entry(api_key.to_string())
+                .or_insert_with(|| bucket.full());
+            bucket.take(state).map_err(|retry_after| {
+                limited(
+                    "too many requests for this API key".to_string(),
+                    retry_after,
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn limited(message: String, retry_after: u64) -> Limited {
+    Limited {
+        violation: PolicyViolation {
+            rule: PolicyRule::RateLimit,
+            message: format!("{}, retry in {} seconds", message, retry_after),
+        },
+        retry_after,
+    }
+}
+
+/// Turns `limited` into a 429 response telling the client when to retry.
+pub fn rejection(limited: Limited) -> actix_web::Error {
+    PsbtError(SignPsbtError::RateLimited {
+        retry_after_secs: limited.retry_after,
+        message: limited.violation.message,
+    })
+    .into()
+}
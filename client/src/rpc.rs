@@ -0,0 +1,178 @@
+use actix_web::{HttpRequest, HttpResponse, Responder, post, web};
+use bitcoin::Txid;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::AppState;
+use crate::error::{self, PsbtError};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Code of errors responded by the methods themselves, with the `SignPsbtError` as data.
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+}
+
+/// Params of `get_presigned`.
+#[derive(Deserialize)]
+struct PresignedParams {
+    deposit_txid: Txid,
+}
+
+fn response(id: Value, res: Result<Value, RpcError>) -> Response {
+    let (result, error) = match res {
+        Ok(v) => (Some(v), None),
+        Err(e) => (None, Some(e)),
+    };
+    Response {
+        jsonrpc: "2.0",
+        result,
+        error,
+        id,
+    }
+}
+
+/// The signing operations over JSON-RPC 2.0, for wallet backends that do not speak REST. The
+/// methods are `sign_psbt`, taking a `SignPsbtReq`, `get_quote`, `health` and `get_presigned`,
+/// taking a `deposit_txid`. Params are by name, or a single positional param holding them.
+/// Batches are served in order.
+#[post("/rpc")]
+pub async fn rpc(
+    data: web::Data<AppState>,
+    http_req: HttpRequest,
+    body: web::Bytes,
+) -> impl Responder {
+    let body: Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            let resp = response(Value::Null, Err(RpcError::new(PARSE_ERROR, e)));
+            return HttpResponse::Ok().json(resp);
+        }
+    };
+
+    let Value::Array(batch) = body else {
+        return match call(&data, &http_req, body).await {
+            Some(resp) => HttpResponse::Ok().json(resp),
+            None => HttpResponse::NoContent().finish(),
+        };
+    };
+    if batch.is_empty() {
+        let resp = response(
+            Value::Null,
+            Err(RpcError::new(INVALID_REQUEST, "empty batch")),
+        );
+        return HttpResponse::Ok().json(resp);
+    }
+    let mut resps = vec![];
+    for req in batch {
+        resps.extend(call(&data, &http_req, req).await);
+    }
+    if resps.is_empty() {
+        return HttpResponse::NoContent().finish();
+    }
+    HttpResponse::Ok().json(resps)
+}
+
+/// Serves a single request, on its own or in a batch, responding nothing to a notification.
+async fn call(data: &web::Data<AppState>, http_req: &HttpRequest, req: Value) -> Option<Response> {
+    // A request without an id is a notification.
+    let id = match &req {
+        Value::Object(obj) => obj.get("id").cloned(),
+        _ => Some(Value::Null),
+    };
+    let res = match serde_json::from_value::<Request>(req) {
+        Ok(req) if req.jsonrpc == "2.0" => dispatch(data, http_req, req).await,
+        Ok(_) => Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")),
+        Err(e) => Err(RpcError::new(INVALID_REQUEST, e)),
+    };
+    id.map(|id| response(id, res))
+}
+
+async fn dispatch(
+    data: &web::Data<AppState>,
+    http_req: &HttpRequest,
+    req: Request,
+) -> Result<Value, RpcError> {
+    match req.method.as_str() {
+        "sign_psbt" => {
+            let sign_req = params(req.params)?;
+            let resp = crate::sign(data.clone(), http_req.clone(), sign_req)
+                .await
+                .map_err(server_error)?;
+            Ok(serde_json::to_value(resp).unwrap())
+        }
+        "get_quote" => {
+            let quote = crate::issue_quote(data).await.map_err(server_error)?;
+            Ok(serde_json::to_value(quote).unwrap())
+        }
+        "health" => Ok(serde_json::to_value(crate::health_resp(data).await).unwrap()),
+        "get_presigned" => {
+            let PresignedParams { deposit_txid } = params(req.params)?;
+            let resp = crate::admin::load_presigned(data, &deposit_txid).map_err(server_error)?;
+            Ok(serde_json::to_value(resp).unwrap())
+        }
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("no method {}", method),
+        )),
+    }
+}
+
+/// The params of a request, by name or as a single positional param.
+fn params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, RpcError> {
+    let params = match params {
+        Some(Value::Array(mut a)) if a.len() == 1 => a.remove(0),
+        Some(v) => v,
+        None => return Err(RpcError::new(INVALID_PARAMS, "missing params")),
+    };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+/// The JSON-RPC error of a method failing with `e`, carrying the structured error `/psbt` would
+/// respond.
+fn server_error(e: actix_web::Error) -> RpcError {
+    let e = error::structured(e);
+    let e = &e.as_error::<PsbtError>().expect("structured error").0;
+    RpcError {
+        code: SERVER_ERROR,
+        message: e.to_string(),
+        data: Some(serde_json::to_value(e).unwrap()),
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use actix_web::dev::ServerHandle;
+use actix_web::rt::signal::unix::{SignalKind, signal};
+use actix_web::web;
+
+use crate::AppState;
+
+/// Waits for SIGTERM or SIGINT, then stops taking `/psbt` requests and waits up to `timeout` for
+/// the sessions in flight to finish before stopping `servers`.
+pub async fn drain_on_signal(
+    data: web::Data<AppState>,
+    servers: Vec<ServerHandle>,
+    timeout: Duration,
+) {
+    let mut term = signal(SignalKind::terminate()).expect("able to listen for SIGTERM");
+    let mut int = signal(SignalKind::interrupt()).expect("able to listen for SIGINT");
+    tokio::select! {
+        _ = term.recv() => {}
+        _ = int.recv() => {}
+    }
+
+    println!("shutting down, no longer accepting requests");
+    data.draining.store(true, Ordering::SeqCst);
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let active = data.registry.active();
+        if active == 0 {
+            break;
+        }
+        if Instant::now() >= deadline {
+            println!("{} sessions still in flight, stopping anyway", active);
+            break;
+        }
+        println!("waiting for {} sessions in flight", active);
+        actix_web::rt::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    for server in servers {
+        server.stop(true).await;
+    }
+}
+
+/// Flushes the audit log and erases the keys still held, once the servers stopped.
+pub fn finish(data: &AppState) {
+    if let Some(audit) = &data.audit {
+        if let Err(e) = audit.flush() {
+            println!("unable to flush audit log: {}", e);
+        }
+    }
+
+    let mut sessions = data.sessions.lock().unwrap();
+    for session in sessions.values_mut() {
+        session.secret_key.non_secure_erase();
+    }
+    println!("erased {} session keys", sessions.len());
+    sessions.clear();
+}
@@ -0,0 +1,271 @@
+use std::fmt;
+
+use bitcoin::script::ScriptExt;
+use bitcoin::{Amount, Psbt, TxOut};
+
+use crate::policy::ScriptType;
+
+/// Smallest deposit output worth presigning a spend for, the dust limit of a P2TR output.
+const MIN_DEPOSIT_SAT: u64 = 330;
+
+/// Feerate of the deposit above which its fee is taken to be a mistake. It is measured on the
+/// unsigned transaction, so it overestimates the actual feerate.
+const MAX_DEPOSIT_FEERATE_SAT_PER_VB: u64 = 5000;
+
+/// Why a deposit PSBT was rejected before anything was signed for it.
+#[derive(Debug)]
+pub enum PsbtError {
+    NoInputs,
+    /// The PSBT has a different number of input maps than the transaction has inputs.
+    InputCount {
+        maps: usize,
+        inputs: usize,
+    },
+    /// The input carries neither a witness nor a non-witness UTXO.
+    MissingUtxo(usize),
+    /// The non-witness UTXO of the input is not the transaction it spends from, or does not
+    /// have the output it spends.
+    WrongNonWitnessUtxo(usize),
+    /// The witness and non-witness UTXOs of the input disagree.
+    UtxoMismatch(usize),
+    NonStandardInput(usize),
+    /// The input is not a native segwit spend, so signing it would fill its `script_sig` and
+    /// change the deposit txid the spend is presigned over.
+    MalleableInput(usize),
+    NoDepositOutput,
+    DepositTooSmall(Amount),
+    NonStandardOutput(usize),
+    DustOutput(usize, Amount),
+    /// An amount above the supply of bitcoin.
+    ExcessiveValue,
+    OutputsExceedInputs {
+        inputs: Amount,
+        outputs: Amount,
+    },
+    AbsurdFee {
+        fee: Amount,
+        sat_per_vb: u64,
+    },
+}
+
+impl fmt::Display for PsbtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PsbtError::NoInputs => write!(f, "the deposit has no inputs"),
+            PsbtError::InputCount { maps, inputs } => {
+                write!(f, "the PSBT has {} input maps for {} inputs", maps, inputs)
+            }
+            PsbtError::MissingUtxo(i) => {
+                write!(f, "input {} has no witness or non-witness UTXO", i)
+            }
+            PsbtError::WrongNonWitnessUtxo(i) => write!(
+                f,
+                "the non-witness UTXO of input {} is not the output it spends",
+                i
+            ),
+            PsbtError::UtxoMismatch(i) => {
+                write!(f, "the witness and non-witness UTXOs of input {} differ", i)
+            }
+            PsbtError::NonStandardInput(i) => {
+                write!(f, "input {} spends a non-standard output script", i)
+            }
+            PsbtError::MalleableInput(i) => write!(
+                f,
+                "input {} is not a native segwit spend, signing it would change the txid",
+                i
+            ),
+            PsbtError::NoDepositOutput => write!(f, "the deposit has no outputs"),
+            PsbtError::DepositTooSmall(value) => write!(
+                f,
+                "deposit output of {} is below the minimum of {} sat",
+                value, MIN_DEPOSIT_SAT
+            ),
+            PsbtError::NonStandardOutput(i) => {
+                write!(f, "output {} has a non-standard script", i)
+            }
+            PsbtError::DustOutput(i, value) => write!(f, "output {} of {} is dust", i, value),
+            PsbtError::ExcessiveValue => write!(f, "amounts exceed the bitcoin supply"),
+            PsbtError::OutputsExceedInputs { inputs, outputs } => {
+                write!(f, "outputs of {} exceed the inputs of {}", outputs, inputs)
+            }
+            PsbtError::AbsurdFee { fee, sat_per_vb } => {
+                write!(f, "fee of {} ({} sat/vB) is absurdly high", fee, sat_per_vb)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PsbtError {}
+
+/// Checks the depositor's PSBT is a deposit worth signing for. The first output is the deposit,
+/// whose script the signer sets, and the rest are the depositor's change.
+pub fn deposit_psbt(psbt: &Psbt) -> Result<(), PsbtError> {
+    let tx = &psbt.unsigned_tx;
+    if tx.input.is_empty() {
+        return Err(PsbtError::NoInputs);
+    }
+    if psbt.inputs.len() != tx.input.len() {
+        return Err(PsbtError::InputCount {
+            maps: psbt.inputs.len(),
+            inputs: tx.input.len(),
+        });
+    }
+
+    let mut input_sat: u64 = 0;
+    for (i, (txin, input)) in tx.input.iter().zip(&psbt.inputs).enumerate() {
+        let utxo = spent_utxo(i, txin.previous_output, input)?;
+        if ScriptType::of(&utxo.script_pubkey).is_none() {
+            return Err(PsbtError::NonStandardInput(i));
+        }
+        if !utxo.script_pubkey.is_witness_program()
+            || !txin.script_sig.is_empty()
+            || input.final_script_sig.is_some()
+        {
+            return Err(PsbtError::MalleableInput(i));
+        }
+        input_sat = add(input_sat, utxo.value)?;
+    }
+
+    let deposit = tx.output.first().ok_or(PsbtError::NoDepositOutput)?;
+    if deposit.value.to_sat() < MIN_DEPOSIT_SAT {
+        return Err(PsbtError::DepositTooSmall(deposit.value));
+    }
+    let mut output_sat = add(0, deposit.value)?;
+    for (i, output) in tx.output.iter().enumerate().skip(1) {
+        if output.script_pubkey.is_op_return() {
+            output_sat = add(output_sat, output.value)?;
+            continue;
+        }
+        if ScriptType::of(&output.script_pubkey).is_none() {
+            return Err(PsbtError::NonStandardOutput(i));
+        }
+        if output.value < output.script_pubkey.minimal_non_dust() {
+            return Err(PsbtError::DustOutput(i, output.value));
+        }
+        output_sat = add(output_sat, output.value)?;
+    }
+
+    if output_sat > input_sat {
+        return Err(PsbtError::OutputsExceedInputs {
+            inputs: Amount::from_sat(input_sat).unwrap(),
+            outputs: Amount::from_sat(output_sat).unwrap(),
+        });
+    }
+    let fee = input_sat - output_sat;
+    let sat_per_vb = fee / tx.vsize() as u64;
+    if fee > deposit.value.to_sat() || sat_per_vb > MAX_DEPOSIT_FEERATE_SAT_PER_VB {
+        return Err(PsbtError::AbsurdFee {
+            fee: Amount::from_sat(fee).unwrap(),
+            sat_per_vb,
+        });
+    }
+    Ok(())
+}
+
+/// The output input `i` spends, checking its witness and non-witness UTXOs agree.
+fn spent_utxo(
+    i: usize,
+    previous_output: bitcoin::OutPoint,
+    input: &bitcoin::psbt::Input,
+) -> Result<TxOut, PsbtError> {
+    let non_witness = match &input.non_witness_utxo {
+        None => None,
+        Some(prev_tx) => {
+            if prev_tx.compute_txid() != previous_output.txid {
+                return Err(PsbtError::WrongNonWitnessUtxo(i));
+            }
+            let output = prev_tx
+                .output
+                .get(previous_output.vout as usize)
+                .ok_or(PsbtError::WrongNonWitnessUtxo(i))?;
+            Some(output.clone())
+        }
+    };
+    match (&input.witness_utxo, non_witness) {
+        (None, None) => Err(PsbtError::MissingUtxo(i)),
+        (Some(w), Some(n)) if *w != n => Err(PsbtError::UtxoMismatch(i)),
+        (Some(w), _) => Ok(w.clone()),
+        (None, Some(n)) => Ok(n),
+    }
+}
+
+/// Adds `value` to `sum` sats, failing above the bitcoin supply.
+fn add(sum: u64, value: Amount) -> Result<u64, PsbtError> {
+    sum.checked_add(value.to_sat())
+        .filter(|s| *s <= Amount::MAX_MONEY.to_sat())
+        .ok_or(PsbtError::ExcessiveValue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        OutPoint, ScriptBuf, Sequence, Transaction, TxIn, Txid, Witness, absolute, transaction,
+    };
+    use std::str::FromStr;
+
+    const P2PKH: &str = "76a914010101010101010101010101010101010101010188ac";
+    const P2SH: &str = "a914010101010101010101010101010101010101010187";
+    const P2WPKH: &str = "00140101010101010101010101010101010101010101";
+    const P2TR: &str = "51200202020202020202020202020202020202020202020202020202020202020202";
+
+    fn script(hex: &str) -> ScriptBuf {
+        ScriptBuf::from_bytes(hex::decode(hex).unwrap())
+    }
+
+    /// A deposit of 90000 sat spending a 100000 sat output with script `spent`.
+    fn deposit(spent: &str) -> Psbt {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_str(
+                        "a6b4f2b8c0bd5d91d5aa4c1a6a6d1c0f2ff0c1d8ab4e9d1b6e1f1c0a0b0c0d0e",
+                    )
+                    .unwrap(),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000).unwrap(),
+                script_pubkey: script(P2TR),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000).unwrap(),
+            script_pubkey: script(spent),
+        });
+        psbt
+    }
+
+    #[test]
+    fn segwit_inputs_accepted() {
+        deposit_psbt(&deposit(P2WPKH)).unwrap();
+        deposit_psbt(&deposit(P2TR)).unwrap();
+    }
+
+    #[test]
+    fn legacy_inputs_refused() {
+        for spent in [P2PKH, P2SH] {
+            assert!(matches!(
+                deposit_psbt(&deposit(spent)),
+                Err(PsbtError::MalleableInput(0))
+            ));
+        }
+    }
+
+    #[test]
+    fn script_sig_refused() {
+        let mut psbt = deposit(P2WPKH);
+        psbt.unsigned_tx.input[0].script_sig = script("00");
+        assert!(matches!(
+            deposit_psbt(&psbt),
+            Err(PsbtError::MalleableInput(0))
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::web;
+use bitcoin::{OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+use crate::archive;
+
+/// Broadcasting of presigned spends for depositors that went away, set in the `watchtower` field
+/// of the config. Needs `archive_dir` and `esplora_url`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WatchtowerConfig {
+    /// Seconds a deposit may sit unspent after confirming, before the signer broadcasts its
+    /// presigned spend to the fallback address.
+    pub deadline_secs: u64,
+    /// Seconds between checks of the deposits.
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_interval() -> u64 {
+    600
+}
+
+#[derive(Deserialize)]
+struct TxStatus {
+    confirmed: bool,
+    #[serde(default)]
+    block_time: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Outspend {
+    spent: bool,
+}
+
+/// Checks the archived deposits every interval, broadcasting the presigned spend of those unspent
+/// past the deadline.
+pub async fn run(data: web::Data<AppState>, cfg: WatchtowerConfig, esplora_url: String) {
+    let url = esplora_url.trim_end_matches('/').to_string();
+    // Deposits whose output is spent, which need no more checking.
+    let mut settled = HashSet::new();
+    loop {
+        actix_web::rt::time::sleep(Duration::from_secs(cfg.interval_secs)).await;
+        let Some(archive) = &data.archive else {
+            return;
+        };
+        let deposits = match archive.deposits() {
+            Ok(deposits) => deposits,
+            Err(e) => {
+                println!("watchtower unable to list deposits: {}", e);
+                continue;
+            }
+        };
+        for deposit_txid in deposits {
+            if settled.contains(&deposit_txid) {
+                continue;
+            }
+            match check(&data, &url, &cfg, deposit_txid).await {
+                Ok(true) => {
+                    settled.insert(deposit_txid);
+                }
+                Ok(false) => {}
+                Err(e) => println!("watchtower unable to check deposit {}: {}", deposit_txid, e),
+            }
+        }
+    }
+}
+
+/// Broadcasts the presigned spend of deposit `deposit_txid` if it is past the deadline, returning
+/// whether the deposit output is spent.
+async fn check(
+    data: &AppState,
+    url: &str,
+    cfg: &WatchtowerConfig,
+    deposit_txid: Txid,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let Some(resp) = data.archive.as_ref().unwrap().load(&deposit_txid)? else {
+        return Ok(false);
+    };
+    let OutPoint { txid, vout } = resp.spend_psbt.unsigned_tx.input[0].previous_output;
+
+    let outspend: Outspend = reqwest::get(format!("{}/tx/{}/outspend/{}", url, txid, vout))
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if outspend.spent {
+        return Ok(true);
+    }
+
+    let status: TxStatus = reqwest::get(format!("{}/tx/{}/status", url, txid))
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let Some(confirmed_at) = status.block_time.filter(|_| status.confirmed) else {
+        return Ok(false);
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    if now < confirmed_at + cfg.deadline_secs {
+        return Ok(false);
+    }
+
+    let Some(tx) = archive::final_spend(&resp) else {
+        // Only the depositor can complete an adaptor signed spend.
+        return Ok(true);
+    };
+    let spend_txid = archive::broadcast(url, &tx).await?;
+    println!(
+        "watchtower broadcast spend {} of deposit {}, unspent since {}",
+        spend_txid, deposit_txid, confirmed_at
+    );
+    Ok(true)
+}
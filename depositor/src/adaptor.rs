@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+
+use bitcoin::consensus_validation::TransactionExt;
+use bitcoin::sighash::SighashCache;
+use bitcoin::taproot::{self, TapLeafHash};
+use bitcoin::{Psbt, Transaction, Txid, Witness, consensus};
+use musig2::AdaptorSignature;
+use musig2::secp::{G, MaybePoint, Scalar};
+use shared::attestation_point;
+
+use crate::session;
+
+#[derive(Debug, clap::Args)]
+pub struct CompleteArgs {
+    /// ID of the session whose presigned spend to complete, the txid of its deposit.
+    id: Txid,
+
+    /// Hex encoded adaptor secret: the discrete log of the adaptor point, or for a CET the `s`
+    /// of the oracle's attestation to its outcome.
+    #[arg(long)]
+    secret: String,
+
+    /// Complete the CET for this outcome instead of the presigned spend.
+    #[arg(long)]
+    outcome: Option<String>,
+
+    /// Directory the sessions are saved in.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+}
+
+/// Completes the adaptor signature of the presigned spend, or of the CET for an outcome, of the
+/// session in `args` with the adaptor secret, and prints the transaction once it is checked to
+/// spend the deposit output.
+pub fn complete(args: CompleteArgs) {
+    let session = session::load(&session::path(&args.sessions_dir, &args.id));
+    let secret = Scalar::from_hex(&args.secret).expect("valid adaptor secret");
+
+    let (psbt, adaptor_sig, point, leaf) = match &args.outcome {
+        None => {
+            let point = session
+                .req
+                .adaptor_point
+                .as_ref()
+                .expect("presigned spend is adaptor signed");
+            let adaptor_sig = session
+                .resp
+                .adaptor_sig
+                .as_ref()
+                .expect("adaptor signature");
+            let point = MaybePoint::from_hex(point).expect("valid adaptor point");
+            (&session.resp.spend_psbt, adaptor_sig, point, false)
+        }
+        Some(outcome) => {
+            let event = session.req.oracle_event.as_ref().expect("session has CETs");
+            let cet = session
+                .resp
+                .cets
+                .iter()
+                .find(|c| &c.outcome == outcome)
+                .expect("CET for outcome");
+            let point = attestation_point(&event.oracle_pubkey, &event.oracle_nonce, outcome)
+                .expect("valid oracle announcement");
+            (&cet.psbt, &cet.adaptor_sig, point, true)
+        }
+    };
+    assert_eq!(
+        MaybePoint::Valid(secret * G),
+        point,
+        "secret is not the discrete log of the adaptor point"
+    );
+
+    let adaptor_sig = AdaptorSignature::from_hex(adaptor_sig).expect("valid adaptor signature");
+    let tx = complete_tx(psbt, &adaptor_sig, secret, leaf);
+
+    let deposit_output = &session.resp.deposit_psbt.unsigned_tx.output[0];
+    tx.verify(|_| Some(deposit_output.clone()))
+        .expect("completed transaction spends the deposit output");
+
+    println!("Completed transaction {}:", tx.compute_txid());
+    println!("{}", consensus::encode::serialize_hex(&tx));
+}
+
+/// Adapts `adaptor_sig` with `secret` into the signature of the only input of `psbt`, spending
+/// the deposit output through the key path, or through the only leaf the PSBT carries if
+/// `leaf`.
+fn complete_tx(
+    psbt: &Psbt,
+    adaptor_sig: &AdaptorSignature,
+    secret: Scalar,
+    leaf: bool,
+) -> Transaction {
+    let input = &psbt.inputs[0];
+    let script_path = match leaf {
+        true => {
+            assert_eq!(input.tap_scripts.len(), 1, "PSBT must carry a single leaf");
+            input.tap_scripts.iter().next()
+        }
+        false => None,
+    };
+
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let leaf_hash = script_path.map(|(_, (script, ver))| TapLeafHash::from_script(script, *ver));
+    let (_, sighash_type) = psbt
+        .sighash_taproot(0, &mut cache, leaf_hash)
+        .expect("sighash");
+
+    let sig: [u8; 64] = adaptor_sig.adapt(secret).expect("valid adapted nonce");
+    let mut sig = taproot::Signature::from_slice(&sig).expect("valid signature");
+    sig.sighash_type = sighash_type;
+
+    let mut witness = Witness::new();
+    witness.push(sig.to_vec());
+    if let Some((control_block, (script, _))) = script_path {
+        witness.push(script.as_bytes());
+        witness.push(control_block.serialize());
+    }
+
+    let mut tx = psbt.unsigned_tx.clone();
+    tx.input[0].witness = witness;
+    tx
+}
@@ -0,0 +1,185 @@
+use bitcoin::Txid;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+
+/// Environment variable the SMTP password is taken from, so it does not appear in the process
+/// arguments.
+const SMTP_PASSWORD_ENV: &str = "EPHEMERAL_SIGN_SMTP_PASSWORD";
+
+/// Options for the sinks watch mode events are sent to, besides stdout.
+#[derive(Debug, clap::Args)]
+pub struct AlertArgs {
+    /// POST each event as JSON to this URL.
+    #[arg(long)]
+    pub webhook_url: Option<String>,
+
+    /// Email each event through this SMTP server, using STARTTLS.
+    #[arg(long, requires_all = ["email_from", "email_to"])]
+    pub smtp_server: Option<String>,
+
+    #[arg(long, requires = "smtp_server")]
+    pub smtp_port: Option<u16>,
+
+    /// User to log in to the SMTP server as, with the password taken from the
+    /// EPHEMERAL_SIGN_SMTP_PASSWORD environment variable.
+    #[arg(long, requires = "smtp_server")]
+    pub smtp_user: Option<String>,
+
+    #[arg(long, requires = "smtp_server")]
+    pub email_from: Option<String>,
+
+    /// Address to email the events to. Can be given multiple times.
+    #[arg(long, requires = "smtp_server")]
+    pub email_to: Vec<String>,
+}
+
+/// Something watch mode reports about a deposit.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    DepositConfirmed {
+        deposit_txid: Txid,
+        height: u32,
+    },
+    /// The deposit output was spent by something other than its presigned transactions.
+    UnexpectedSpend {
+        deposit_txid: Txid,
+        spending_txid: Option<Txid>,
+    },
+    PresignedBroadcast {
+        deposit_txid: Txid,
+        presigned_txid: Txid,
+    },
+    PresignedConfirmed {
+        deposit_txid: Txid,
+        presigned_txid: Txid,
+        height: u32,
+    },
+}
+
+impl Event {
+    pub fn message(&self) -> String {
+        match self {
+            Event::DepositConfirmed {
+                deposit_txid,
+                height,
+            } => format!("Deposit {} confirmed at height {}", deposit_txid, height),
+            Event::UnexpectedSpend {
+                deposit_txid,
+                spending_txid,
+            } => format!(
+                "ALERT: deposit {} spent by unknown transaction {}! Unless you spent it through a \
+                 script path, the signer did not delete its key.",
+                deposit_txid,
+                spending_txid.map_or("(unknown)".to_string(), |txid| txid.to_string())
+            ),
+            Event::PresignedBroadcast {
+                deposit_txid,
+                presigned_txid,
+            } => format!(
+                "Broadcast presigned spend {} of deposit {}",
+                presigned_txid, deposit_txid
+            ),
+            Event::PresignedConfirmed {
+                deposit_txid,
+                presigned_txid,
+                height,
+            } => format!(
+                "Presigned spend {} of deposit {} confirmed at height {}",
+                presigned_txid, deposit_txid, height
+            ),
+        }
+    }
+}
+
+/// A destination for events.
+enum Sink {
+    Webhook(String),
+    Email {
+        transport: AsyncSmtpTransport<Tokio1Executor>,
+        from: Mailbox,
+        to: Vec<Mailbox>,
+    },
+}
+
+impl Sink {
+    async fn send(&self, event: &Event) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Sink::Webhook(url) => {
+                reqwest::Client::new()
+                    .post(url)
+                    .json(event)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Sink::Email {
+                transport,
+                from,
+                to,
+            } => {
+                let message = event.message();
+                let mut builder = Message::builder()
+                    .from(from.clone())
+                    .subject(format!("ephemeral-sign: {}", message));
+                for to in to {
+                    builder = builder.to(to.clone());
+                }
+                transport.send(builder.body(message)?).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reports events on stdout and to the configured sinks.
+pub struct Alerts {
+    sinks: Vec<Sink>,
+}
+
+impl Alerts {
+    pub fn new(args: &AlertArgs) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut sinks = vec![];
+        if let Some(url) = &args.webhook_url {
+            sinks.push(Sink::Webhook(url.clone()));
+        }
+        if let Some(server) = &args.smtp_server {
+            let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(server)?;
+            if let Some(port) = args.smtp_port {
+                builder = builder.port(port);
+            }
+            if let Some(user) = &args.smtp_user {
+                let password = std::env::var(SMTP_PASSWORD_ENV)
+                    .map_err(|_| format!("{} must be set for --smtp-user", SMTP_PASSWORD_ENV))?;
+                builder = builder.credentials(Credentials::new(user.clone(), password));
+            }
+            sinks.push(Sink::Email {
+                transport: builder.build(),
+                from: args
+                    .email_from
+                    .as_ref()
+                    .ok_or("--email-from is required")?
+                    .parse()?,
+                to: args
+                    .email_to
+                    .iter()
+                    .map(|to| to.parse())
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+        Ok(Alerts { sinks })
+    }
+
+    /// Prints `event` and sends it to every sink. A failing sink is reported but does not stop
+    /// the others.
+    pub async fn notify(&self, event: Event) {
+        println!("{}", event.message());
+        for sink in &self.sinks {
+            if let Err(e) = sink.send(&event).await {
+                println!("Unable to send alert: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use bitcoin::script::ScriptExt;
+use bitcoin::{Amount, Denomination, Transaction};
+
+/// Smallest output value, in sats, relayed for any standard output type.
+const DUST_LIMIT: u64 = 546;
+
+/// Fee, in sats, the signer takes from the deposit output for the presigned spend. The deposit
+/// must cover it and still leave a spend output above the dust limit.
+const SIGNER_SPEND_FEE: u64 = 500;
+
+/// Weight the deposit transaction gains after signing: the segwit marker and flag, and a witness
+/// with a single 64 byte signature.
+const KEY_SPEND_WITNESS_WEIGHT: u64 = 68;
+
+/// Weight of the segwit marker and flag a transaction gains with its first witness.
+const SEGWIT_MARKER_WEIGHT: u64 = 2;
+
+/// Weight of the deposit output script, which the signer fills in as a 34 byte P2TR script.
+const DEPOSIT_SCRIPT_WEIGHT: u64 = 34 * 4;
+
+/// Parses an amount given on the command line, like `0.5btc`, `150000sat`, `21_000 sats` or
+/// `50000 sat`. Underscores may separate digits, and a number without a unit is in sats.
+pub fn parse_amount(s: &str) -> Result<Amount, String> {
+    let s: String = s.chars().filter(|c| *c != '_').collect();
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let denomination = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "sat" | "sats" => Denomination::Satoshi,
+        "btc" => Denomination::Bitcoin,
+        "mbtc" => Denomination::MilliBitcoin,
+        unit => return Err(format!("unknown unit {:?}, use btc, mbtc or sat", unit)),
+    };
+    Amount::from_str_in(number, denomination).map_err(|e| format!("invalid amount {}: {}", s, e))
+}
+
+/// Checks the amounts of `tx`, spending inputs worth `input_value` whose witnesses weigh
+/// `witness_weight`, before anything is sent to the signer: the outputs must not be dust, but for
+/// an OP_RETURN, or worth more than the inputs, the deposit output must cover the fee of the
+/// presigned spend, and the fee must pay at least 1 sat/vB but not more than `max_fee`. Returns
+/// the fee.
+pub fn check_deposit(
+    tx: &Transaction,
+    input_value: Amount,
+    witness_weight: u64,
+    max_fee: Amount,
+) -> Result<Amount, Box<dyn std::error::Error>> {
+    let mut output_value = Amount::ZERO;
+    for (i, output) in tx.output.iter().enumerate() {
+        if output.value.to_sat() < DUST_LIMIT && !output.script_pubkey.is_op_return() {
+            return Err(format!("output {} of {} is dust", i, output.value).into());
+        }
+        output_value = output_value
+            .checked_add(output.value)
+            .ok_or("output amounts overflow")?;
+    }
+
+    let deposit_value = tx.output[0].value.to_sat();
+    if deposit_value < SIGNER_SPEND_FEE + DUST_LIMIT {
+        return Err(format!(
+            "deposit output must be at least {} sat to cover the presigned spend",
+            SIGNER_SPEND_FEE + DUST_LIMIT
+        )
+        .into());
+    }
+
+    let fee = input_value.checked_sub(output_value).ok_or_else(|| {
+        format!(
+            "outputs worth {} exceed the prevout amount {}",
+            output_value, input_value
+        )
+    })?;
+
+    let vsize = deposit_vsize(tx, witness_weight);
+    if fee.to_sat() < vsize {
+        return Err(format!(
+            "deposit fee {} is below 1 sat/vB for its {} vB, it will not relay",
+            fee, vsize
+        )
+        .into());
+    }
+    if fee > max_fee {
+        return Err(format!("deposit fee {} exceeds --max-deposit-fee {}", fee, max_fee).into());
+    }
+
+    Ok(fee)
+}
+
+/// Virtual size of `tx`, an unsigned deposit, once the signer fills in the deposit output script
+/// and its inputs are signed with witnesses weighing `witness_weight`.
+pub fn deposit_vsize(tx: &Transaction, witness_weight: u64) -> u64 {
+    (tx.weight().to_wu() + SEGWIT_MARKER_WEIGHT + witness_weight + DEPOSIT_SCRIPT_WEIGHT)
+        .div_ceil(4)
+}
+
+/// Fee of `tx`, an unsigned key spend of an input worth `input_value` to a single output, at
+/// `feerate` sat/vB, for replacing a transaction that paid `replaced_fee`. BIP-125 requires the
+/// replacement to pay at least the replaced fee plus 1 sat/vB for its own size. Returns an error
+/// if what is left for the output would be dust.
+pub fn replacement_fee(
+    tx: &Transaction,
+    input_value: Amount,
+    feerate: u64,
+    replaced_fee: Amount,
+) -> Result<Amount, Box<dyn std::error::Error>> {
+    let vsize = (tx.weight().to_wu() + KEY_SPEND_WITNESS_WEIGHT).div_ceil(4);
+    let fee = (feerate * vsize).max(replaced_fee.to_sat() + vsize);
+    if input_value.to_sat() < fee + DUST_LIMIT {
+        return Err(format!(
+            "replacement fee of {} sat leaves only dust of the {} input",
+            fee, input_value
+        )
+        .into());
+    }
+    Ok(Amount::from_sat(fee)?)
+}
+
+/// Fee of `tx`, an unsigned key spend of an input worth `input_value` to a single output, at
+/// `feerate` sat/vB. Returns an error if what is left for the output would be dust.
+pub fn key_spend_fee(
+    tx: &Transaction,
+    input_value: Amount,
+    feerate: u64,
+) -> Result<Amount, Box<dyn std::error::Error>> {
+    let vsize = (tx.weight().to_wu() + KEY_SPEND_WITNESS_WEIGHT).div_ceil(4);
+    let fee = feerate * vsize;
+    if input_value.to_sat() < fee + DUST_LIMIT {
+        return Err(format!(
+            "fee of {} sat leaves only dust of the {} input",
+            fee, input_value
+        )
+        .into());
+    }
+    Ok(Amount::from_sat(fee)?)
+}
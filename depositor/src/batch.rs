@@ -0,0 +1,143 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use bitcoin::{Amount, Network, OutPoint, Psbt, Transaction, TxOut};
+use shared::PROTOCOL_VERSION;
+use shared::blind::BlindInitReq;
+
+use crate::blind::{self, Started};
+use crate::enclave::EnclaveArgs;
+use crate::http::HttpArgs;
+use crate::{FeeLimits, amounts, parse_address};
+
+/// A deposit output batched into the deposit transaction after the first, given as
+/// `<amount>=<fallback address>[@<signer address>]`.
+#[derive(Debug, Clone)]
+pub struct BatchDeposit {
+    pub amount: Amount,
+    pub fallback_addr: String,
+    /// Signer to run the session with, the one of the first deposit if not given.
+    pub client_addr: Option<SocketAddr>,
+}
+
+impl FromStr for BatchDeposit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (amount, rest) = s
+            .split_once('=')
+            .ok_or("batched deposit as <amount>=<fallback address>[@<signer address>]")?;
+        let (fallback_addr, client_addr) = match rest.split_once('@') {
+            Some((addr, signer)) => (addr, Some(signer.parse().map_err(|e| format!("{}", e))?)),
+            None => (rest, None),
+        };
+        Ok(BatchDeposit {
+            amount: amounts::parse_amount(amount)?,
+            fallback_addr: fallback_addr.to_string(),
+            client_addr,
+        })
+    }
+}
+
+/// Starts a blind session for each of `deposits`, with `default_signer` unless they name
+/// another. The key of a blind session is known before the deposit's txid, so the outputs of
+/// the batched deposits can be in the deposit before the first deposit is signed for.
+pub async fn start(
+    http: &HttpArgs,
+    deposits: &[BatchDeposit],
+    default_signer: Option<SocketAddr>,
+    network: Network,
+) -> Result<Vec<Started>, Box<dyn std::error::Error>> {
+    let mut started = vec![];
+    for deposit in deposits {
+        let req = BlindInitReq {
+            network,
+            fallback_addr: parse_address(&deposit.fallback_addr, network).to_string(),
+            amount_sat: deposit.amount.to_sat(),
+            version: PROTOCOL_VERSION,
+        };
+        let client_addr = deposit
+            .client_addr
+            .or(default_signer)
+            .ok_or("batched deposit without a signer")?;
+        started.push(blind::start(http, client_addr, &req).await?);
+    }
+    Ok(started)
+}
+
+/// Has each session of `started` for `deposits` sign its spend of its deposit output in
+/// `deposit_tx`, where they follow the first deposit output in order. Returns the signed spends,
+/// each checked to pay only its fallback address within `limits`.
+pub async fn finish(
+    http: &HttpArgs,
+    deposits: &[BatchDeposit],
+    started: &[Started],
+    deposit_tx: &Transaction,
+    enclave: &EnclaveArgs,
+    limits: &FeeLimits,
+    network: Network,
+) -> Result<Vec<Psbt>, Box<dyn std::error::Error>> {
+    let txid = deposit_tx.compute_txid();
+    let mut spends = vec![];
+    for (i, (deposit, session)) in deposits.iter().zip(started).enumerate() {
+        let vout = i + 1;
+        let output = deposit_tx
+            .output
+            .get(vout)
+            .ok_or("deposit lacks a batched deposit output")?;
+        if output.script_pubkey != session.deposit_script {
+            return Err(format!("deposit output {} is not the batched deposit", vout).into());
+        }
+        let outpoint = OutPoint {
+            txid,
+            vout: vout as u32,
+        };
+        let spend = blind::finish(http, session, outpoint, enclave).await?;
+        verify_spend(deposit, &spend, output, limits, network)
+            .map_err(|e| format!("presigned spend of deposit output {}: {}", vout, e))?;
+        spends.push(spend);
+    }
+    Ok(spends)
+}
+
+/// Verifies that the signed `spend` of `deposit_output` pays all of it, less a fee within
+/// `limits`, to the fallback address of `deposit`. [`blind::finish`] checked its signature.
+fn verify_spend(
+    deposit: &BatchDeposit,
+    spend: &Psbt,
+    deposit_output: &TxOut,
+    limits: &FeeLimits,
+    network: Network,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let fallback_script = parse_address(&deposit.fallback_addr, network).script_pubkey();
+    let tx = &spend.unsigned_tx;
+    if tx.input.len() != 1 || tx.output.len() != 1 || tx.output[0].script_pubkey != fallback_script
+    {
+        return Err("spend must only pay the fallback address".into());
+    }
+    if spend.inputs[0].witness_utxo.as_ref() != Some(deposit_output) {
+        return Err("spend must commit to the deposit output".into());
+    }
+
+    let fee = deposit_output
+        .value
+        .checked_sub(tx.output[0].value)
+        .ok_or("spend pays out more than the deposit")?;
+    let vsize = spend.clone().extract_tx()?.vsize() as u64;
+    let feerate = fee.to_sat() / vsize;
+    if fee > limits.max_spend_fee {
+        return Err(format!(
+            "fee {} exceeds --max-spend-fee {}",
+            fee, limits.max_spend_fee
+        )
+        .into());
+    }
+    if feerate > limits.max_spend_feerate {
+        return Err(format!(
+            "feerate {} sat/vB exceeds --max-spend-feerate {}",
+            feerate, limits.max_spend_feerate
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::io::BufRead;
+use std::thread;
+use std::time::Duration;
+
+use crate::qr;
+
+/// UR type of a PSBT, as defined by BCR-2020-006.
+pub const PSBT_TYPE: &str = "crypto-psbt";
+
+/// UR type of arbitrary bytes, used for raw transactions.
+pub const BYTES_TYPE: &str = "bytes";
+
+/// Maximum number of bytes of the message carried by each UR part.
+const MAX_FRAGMENT_LEN: usize = 100;
+
+/// Time each frame of an animated QR sequence is shown.
+const FRAME_DURATION: Duration = Duration::from_millis(300);
+
+/// Shows `data` as an animated sequence of fountain coded UR parts in the terminal. Three times
+/// as many parts as needed are shown, so a scanner that misses some can still decode it.
+pub fn show_animated(label: &str, ur_type: &str, data: &[u8]) {
+    let message = cbor_bytes(data);
+    let mut encoder = match ur::Encoder::new(&message, MAX_FRAGMENT_LEN, ur_type) {
+        Ok(e) => e,
+        Err(e) => {
+            println!("unable to UR encode {}: {:?}", label, e);
+            return;
+        }
+    };
+
+    let num_parts = encoder.fragment_count() * 3;
+    for i in 0..num_parts {
+        let part = encoder.next_part().unwrap().to_uppercase();
+        let frame = qr::render(&part).expect("UR part fits in a QR code");
+
+        // Clear the screen before each frame.
+        print!("\x1b[2J\x1b[H");
+        println!("{} ({}/{}):\n{}", label, i + 1, num_parts, frame);
+        thread::sleep(FRAME_DURATION);
+    }
+}
+
+/// Reads UR parts, one per line, until the message can be decoded.
+pub fn read_parts(input: impl BufRead) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut decoder = ur::Decoder::default();
+    for line in input.lines() {
+        let part = line?.trim().to_lowercase();
+        if part.is_empty() {
+            continue;
+        }
+
+        let (kind, data) = ur::decode(&part).map_err(|e| format!("invalid UR: {:?}", e))?;
+        if kind == ur::ur::Kind::SinglePart {
+            return unwrap_cbor_bytes(&data);
+        }
+
+        decoder
+            .receive(&part)
+            .map_err(|e| format!("invalid UR part: {:?}", e))?;
+        if decoder.complete() {
+            let message = decoder
+                .message()
+                .map_err(|e| format!("invalid UR message: {:?}", e))?
+                .ok_or("incomplete UR message")?;
+            return unwrap_cbor_bytes(&message);
+        }
+    }
+
+    Err("input ended before the UR message was complete".into())
+}
+
+/// Encodes `data` as a CBOR byte string, the payload of both UR types we use.
+fn cbor_bytes(data: &[u8]) -> Vec<u8> {
+    let len = data.len();
+    let mut out = match len {
+        0..=23 => vec![0x40 | len as u8],
+        24..=0xff => vec![0x58, len as u8],
+        0x100..=0xffff => [&[0x59][..], &(len as u16).to_be_bytes()].concat(),
+        _ => [&[0x5a][..], &(len as u32).to_be_bytes()].concat(),
+    };
+    out.extend_from_slice(data);
+    out
+}
+
+fn unwrap_cbor_bytes(message: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (header, rest) = message.split_first().ok_or("empty UR message")?;
+    let (len, data) = match *header {
+        h @ 0x40..=0x57 => ((h - 0x40) as usize, rest),
+        0x58 if !rest.is_empty() => (rest[0] as usize, &rest[1..]),
+        0x59 if rest.len() >= 2 => (u16::from_be_bytes([rest[0], rest[1]]) as usize, &rest[2..]),
+        0x5a if rest.len() >= 4 => (
+            u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize,
+            &rest[4..],
+        ),
+        _ => return Err("UR message is not a CBOR byte string".into()),
+    };
+
+    if data.len() != len {
+        return Err("invalid CBOR byte string length".into());
+    }
+    Ok(data.to_vec())
+}
@@ -0,0 +1,188 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::sighash::SighashCache;
+use bitcoin::taproot::TaprootSpendInfo;
+use bitcoin::{OutPoint, Psbt, ScriptBuf, Witness, XOnlyPublicKey};
+use musig2::secp::Point;
+use reqwest::Response;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use shared::blind::{
+    BlindInitReq, BlindInitResp, BlindSignReq, BlindSignResp, prevouts_hash, template_sighash,
+};
+use shared::script::deposit_spend_info;
+use shared::{DepositDescriptor, SignPsbtError, SignPsbtReq, SignPsbtResp};
+
+use crate::enclave::EnclaveArgs;
+use crate::http::HttpArgs;
+
+/// A blind session started with the signer at `client_addr`, waiting for the outpoint of its
+/// deposit output.
+pub struct Started {
+    pub client_addr: SocketAddr,
+    pub init: BlindInitResp,
+    pub internal_key: XOnlyPublicKey,
+    pub spend_info: TaprootSpendInfo,
+    /// Script of the deposit output, locked to the key alone.
+    pub deposit_script: ScriptBuf,
+}
+
+/// Starts a blind session with the signer at `client_addr`, checking the template it returns
+/// spends a deposit output of the requested value locked to the session's key alone.
+pub async fn start(
+    http: &HttpArgs,
+    client_addr: SocketAddr,
+    req: &BlindInitReq,
+) -> Result<Started, Box<dyn std::error::Error>> {
+    let init: BlindInitResp = post(http, client_addr, "blind/init", req).await?;
+    println!(
+        "Blind session {} until {}, deposit key {}",
+        init.session_id, init.expires_at, init.internal_key
+    );
+
+    // The deposit output has no script paths, so only the key can spend it.
+    let secp = Secp256k1::verification_only();
+    let internal_key = XOnlyPublicKey::from_str(&init.internal_key)?;
+    let spend_info = deposit_spend_info(&secp, internal_key, vec![]);
+    let deposit_script = ScriptBuf::new_p2tr_tweaked(spend_info.output_key());
+    let deposit_output = init
+        .template
+        .inputs
+        .first()
+        .and_then(|input| input.witness_utxo.clone())
+        .ok_or("template spends no deposit output")?;
+    if deposit_output.script_pubkey != deposit_script
+        || deposit_output.value.to_sat() != req.amount_sat
+    {
+        return Err("template spends another output than the deposit".into());
+    }
+    Ok(Started {
+        client_addr,
+        init,
+        internal_key,
+        spend_info,
+        deposit_script,
+    })
+}
+
+/// Has the signer sign the template of `started` spending the deposit output at `outpoint`,
+/// returning the signed spend.
+pub async fn finish(
+    http: &HttpArgs,
+    started: &Started,
+    outpoint: OutPoint,
+    enclave: &EnclaveArgs,
+) -> Result<Psbt, Box<dyn std::error::Error>> {
+    // The signer computes the sighash from the prevouts hash alone, which must be the sighash
+    // of the template spending the deposit outpoint.
+    let mut spend_psbt = started.init.template.clone();
+    spend_psbt.unsigned_tx.input[0].previous_output = outpoint;
+    let prevouts_hash = prevouts_hash(outpoint);
+    let mut cache = SighashCache::new(&spend_psbt.unsigned_tx);
+    let (msg, _) = spend_psbt.sighash_taproot(0, &mut cache, None)?;
+    let msg: &[u8] = msg.as_ref();
+    if msg != template_sighash(&spend_psbt, prevouts_hash)? {
+        return Err("template sighash does not commit to the deposit outpoint".into());
+    }
+
+    let signed: BlindSignResp = post(
+        http,
+        started.client_addr,
+        "blind/sign",
+        &BlindSignReq {
+            session_id: started.init.session_id.clone(),
+            prevouts_hash: hex::encode(prevouts_hash),
+        },
+    )
+    .await?;
+    enclave.verify(&signed.enclave_attestations, &started.init.internal_key)?;
+
+    // Signed with the default sighash type, the witness is the bare signature.
+    let signature: [u8; 64] = hex::decode(&signed.signature)?
+        .try_into()
+        .map_err(|_| "signer returned a malformed signature")?;
+    // The deposit output is P2TR, so the witness program is the x-only output key.
+    let output_key: [u8; 32] = started.deposit_script.as_bytes()[2..34].try_into().unwrap();
+    let output_key = Point::lift_x(&output_key).map_err(|_| "invalid deposit output key")?;
+    musig2::verify_single(output_key, signature, msg)
+        .map_err(|_| "signer returned an invalid signature for the template")?;
+    let mut witness = Witness::new();
+    witness.push(signature);
+    spend_psbt.inputs[0].final_script_witness = Some(witness);
+    Ok(spend_psbt)
+}
+
+/// Serves `req` in a blind session with the signer at `client_addr`. The signer is only sent
+/// the fallback address, the value of the deposit output and the prevouts hash of the deposit
+/// outpoint, never the deposit's inputs. The deposit and its spend are assembled here from the
+/// template the signer committed to, into the response the signer would have given to `req`.
+pub async fn sign(
+    http: &HttpArgs,
+    client_addr: SocketAddr,
+    req: &SignPsbtReq,
+    enclave: &EnclaveArgs,
+) -> Result<SignPsbtResp, Box<dyn std::error::Error>> {
+    let init_req = BlindInitReq {
+        network: req.network,
+        fallback_addr: req.fallback_addr.clone(),
+        amount_sat: req.psbt.unsigned_tx.output[0].value.to_sat(),
+        version: req.version,
+    };
+    let started = start(http, client_addr, &init_req).await?;
+
+    let mut deposit_psbt = req.psbt.clone();
+    deposit_psbt.unsigned_tx.output[0].script_pubkey = started.deposit_script.clone();
+    deposit_psbt.outputs[0].tap_internal_key = Some(started.internal_key);
+    let outpoint = OutPoint {
+        txid: deposit_psbt.unsigned_tx.compute_txid(),
+        vout: 0,
+    };
+    let spend_psbt = finish(http, &started, outpoint, enclave).await?;
+
+    Ok(SignPsbtResp {
+        deposit_psbt,
+        spend_psbt,
+        network: req.network,
+        adaptor_sig: None,
+        cets: vec![],
+        vault: None,
+        rollover_spend_psbt: None,
+        script_paths: vec![],
+        descriptor: Some(DepositDescriptor::new(&started.spend_info)),
+        ecdh_shares: vec![],
+        spend_fee: Some(started.init.spend_fee),
+        version: req.version,
+        capabilities: vec![],
+        receipt: None,
+        fallback_attestation: None,
+    })
+}
+
+/// Posts `body` to `path` of the signer. Neither round of a blind session is retried, as
+/// starting one costs the signers a key and signing one deletes it.
+async fn post<T: Serialize, R: DeserializeOwned>(
+    http: &HttpArgs,
+    client_addr: SocketAddr,
+    path: &str,
+    body: &T,
+) -> Result<R, Box<dyn std::error::Error>> {
+    let client = http.client();
+    let url = format!("http://{}/{}", client_addr, path);
+    let resp = http.send(false, || client.post(&url).json(body)).await?;
+    Ok(check(resp).await?.json().await?)
+}
+
+/// Fails with the signer's `SignPsbtError` if it declined the request.
+async fn check(resp: Response) -> Result<Response, Box<dyn std::error::Error>> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = resp.text().await?;
+    Err(match serde_json::from_str::<SignPsbtError>(&body) {
+        Ok(e) => e.into(),
+        Err(_) => format!("signer responded {}: {}", status, body).into(),
+    })
+}
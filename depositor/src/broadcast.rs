@@ -0,0 +1,355 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use bitcoin::hashes::{Hash, sha256};
+use bitcoin::{Network, Transaction, Txid, consensus};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::chain::{ChainArgs, Esplora};
+use crate::locktime::{self, ChainState, Finality};
+
+/// Environment variable the bitcoind RPC password is taken from, so it does not appear in the
+/// process arguments.
+const BITCOIND_PASSWORD_ENV: &str = "EPHEMERAL_SIGN_BITCOIND_PASSWORD";
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum BackendKind {
+    Esplora,
+    Bitcoind,
+    Electrum,
+}
+
+/// Options for the backend transactions are broadcast through.
+#[derive(Debug, clap::Args)]
+pub struct BackendArgs {
+    #[arg(long, value_enum, default_value_t = BackendKind::Esplora)]
+    pub backend: BackendKind,
+
+    #[command(flatten)]
+    pub chain: ChainArgs,
+
+    /// URL of the bitcoind RPC interface. Defaults to localhost on the network's RPC port.
+    #[arg(long)]
+    pub bitcoind_url: Option<String>,
+
+    /// Cookie file to authenticate to bitcoind with.
+    #[arg(long, conflicts_with = "bitcoind_user")]
+    pub bitcoind_cookie: Option<PathBuf>,
+
+    /// RPC user to authenticate to bitcoind as, with the password taken from the
+    /// EPHEMERAL_SIGN_BITCOIND_PASSWORD environment variable.
+    #[arg(long)]
+    pub bitcoind_user: Option<String>,
+
+    /// Electrum server as <host>:<port>, over plain TCP.
+    #[arg(long)]
+    pub electrum_server: Option<String>,
+}
+
+impl BackendArgs {
+    pub fn backend(&self, network: Network) -> Result<Backend, Box<dyn std::error::Error>> {
+        match self.backend {
+            BackendKind::Esplora => Ok(Backend::Esplora(self.chain.backend(network))),
+            BackendKind::Bitcoind => {
+                let url = match &self.bitcoind_url {
+                    Some(url) => url.clone(),
+                    None => format!("http://127.0.0.1:{}", rpc_port(network)),
+                };
+                let auth = match (&self.bitcoind_cookie, &self.bitcoind_user) {
+                    (Some(cookie), _) => {
+                        let data = std::fs::read_to_string(cookie)?;
+                        let (user, password) = data
+                            .trim()
+                            .split_once(':')
+                            .ok_or("invalid bitcoind cookie file")?;
+                        Some((user.to_string(), password.to_string()))
+                    }
+                    (None, Some(user)) => {
+                        let password = std::env::var(BITCOIND_PASSWORD_ENV).map_err(|_| {
+                            format!("{} must be set for --bitcoind-user", BITCOIND_PASSWORD_ENV)
+                        })?;
+                        Some((user.clone(), password))
+                    }
+                    (None, None) => None,
+                };
+                Ok(Backend::Bitcoind(Bitcoind {
+                    url,
+                    auth,
+                    client: reqwest::Client::new(),
+                }))
+            }
+            BackendKind::Electrum => {
+                let server = self
+                    .electrum_server
+                    .clone()
+                    .ok_or("--electrum-server is required for the electrum backend")?;
+                Ok(Backend::Electrum(Electrum { server }))
+            }
+        }
+    }
+}
+
+/// The default RPC port of bitcoind on `network`.
+fn rpc_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 8332,
+        Network::Signet => 38332,
+        Network::Regtest => 18443,
+        _ => 18332,
+    }
+}
+
+/// A backend to broadcast transactions through and check they were accepted.
+pub enum Backend {
+    Esplora(Esplora),
+    Bitcoind(Bitcoind),
+    Electrum(Electrum),
+}
+
+impl Backend {
+    /// Broadcasts `tx`, returning the rejection reason of the backend if it fails.
+    pub async fn broadcast(&self, tx: &Transaction) -> Result<Txid, Box<dyn std::error::Error>> {
+        let hex = consensus::encode::serialize_hex(tx);
+        match self {
+            Backend::Esplora(esplora) => esplora.broadcast(tx).await,
+            Backend::Bitcoind(bitcoind) => {
+                let txid = bitcoind.call("sendrawtransaction", json!([hex])).await?;
+                Ok(Txid::from_str(
+                    txid.as_str().ok_or("invalid bitcoind response")?,
+                )?)
+            }
+            Backend::Electrum(electrum) => {
+                let txid = electrum
+                    .call("blockchain.transaction.broadcast", json!([hex]))
+                    .await?;
+                Ok(Txid::from_str(
+                    txid.as_str().ok_or("invalid electrum response")?,
+                )?)
+            }
+        }
+    }
+
+    /// Whether the backend knows `txid`, in its mempool or the chain.
+    pub async fn is_known(&self, txid: &Txid) -> Result<bool, Box<dyn std::error::Error>> {
+        match self {
+            Backend::Esplora(esplora) => Ok(esplora.tx_status(txid).await?.is_some()),
+            // Without -txindex bitcoind only finds transactions in its mempool, which is where
+            // a transaction we just broadcast is.
+            Backend::Bitcoind(bitcoind) => Ok(bitcoind
+                .call("getrawtransaction", json!([txid.to_string()]))
+                .await
+                .is_ok()),
+            Backend::Electrum(electrum) => Ok(electrum
+                .call("blockchain.transaction.get", json!([txid.to_string()]))
+                .await
+                .is_ok()),
+        }
+    }
+
+    /// Height and median time past of the chain tip.
+    pub async fn chain_state(&self) -> Result<ChainState, Box<dyn std::error::Error>> {
+        match self {
+            Backend::Esplora(esplora) => esplora.chain_state().await,
+            Backend::Bitcoind(bitcoind) => {
+                let info = bitcoind.call("getblockchaininfo", json!([])).await?;
+                Ok(ChainState {
+                    tip: json_u32(&info["blocks"])?,
+                    median_time: json_u32(&info["mediantime"])?,
+                })
+            }
+            Backend::Electrum(electrum) => {
+                let tip = electrum
+                    .call("blockchain.headers.subscribe", json!([]))
+                    .await?;
+                let tip = json_u32(&tip["height"])?;
+
+                // The median time past is the median timestamp of the last 11 blocks.
+                let start = tip.saturating_sub(10);
+                let headers = electrum
+                    .call("blockchain.block.headers", json!([start, tip - start + 1]))
+                    .await?;
+                let headers = hex::decode(headers["hex"].as_str().ok_or("invalid headers")?)?;
+                let mut times: Vec<u32> = headers
+                    .chunks(80)
+                    .map(|header| u32::from_le_bytes(header[68..72].try_into().unwrap()))
+                    .collect();
+                times.sort();
+                Ok(ChainState {
+                    tip,
+                    median_time: *times.get(times.len() / 2).ok_or("no headers")?,
+                })
+            }
+        }
+    }
+
+    /// Height output `vout` of `txid` confirmed at, none if it is unconfirmed.
+    pub async fn confirmation_height(
+        &self,
+        txid: &Txid,
+        vout: u32,
+    ) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+        match self {
+            Backend::Esplora(esplora) => Ok(esplora
+                .tx_status(txid)
+                .await?
+                .filter(|status| status.confirmed)
+                .and_then(|status| status.block_height)),
+            Backend::Bitcoind(bitcoind) => {
+                let txout = bitcoind
+                    .call("gettxout", json!([txid.to_string(), vout]))
+                    .await?;
+                let confirmations = match txout {
+                    Value::Null => {
+                        return Err(format!("{}:{} is spent or unknown", txid, vout).into());
+                    }
+                    txout => json_u32(&txout["confirmations"])?,
+                };
+                match confirmations {
+                    0 => Ok(None),
+                    c => Ok(Some(self.chain_state().await?.tip + 1 - c)),
+                }
+            }
+            Backend::Electrum(electrum) => {
+                // Electrum only indexes by script, so look the transaction up in the history of
+                // the output's script.
+                let tx = electrum
+                    .call("blockchain.transaction.get", json!([txid.to_string()]))
+                    .await?;
+                let tx: Transaction = consensus::encode::deserialize(&hex::decode(
+                    tx.as_str().ok_or("invalid electrum response")?,
+                )?)?;
+                let output = tx.output.get(vout as usize).ok_or("no such output")?;
+                let mut script_hash =
+                    sha256::Hash::hash(output.script_pubkey.as_bytes()).to_byte_array();
+                script_hash.reverse();
+
+                let history = electrum
+                    .call(
+                        "blockchain.scripthash.get_history",
+                        json!([hex::encode(script_hash)]),
+                    )
+                    .await?;
+                let entry = history
+                    .as_array()
+                    .ok_or("invalid electrum response")?
+                    .iter()
+                    .find(|entry| entry["tx_hash"].as_str() == Some(txid.to_string().as_str()));
+                // Unconfirmed transactions have a height of 0 or -1.
+                Ok(entry
+                    .and_then(|entry| entry["height"].as_i64())
+                    .filter(|height| *height > 0)
+                    .map(|height| height as u32))
+            }
+        }
+    }
+}
+
+/// Waits until `tx` can enter the mempool, polling `backend` every `interval`, so a presigned
+/// spend with a locktime is submitted when it becomes valid instead of rejected as non-final.
+pub async fn wait_until_final(
+    backend: &Backend,
+    tx: &Transaction,
+    interval: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let prevout = tx.input[0].previous_output;
+    let mut reported = None;
+    loop {
+        let chain = backend.chain_state().await?;
+        let input_height = backend
+            .confirmation_height(&prevout.txid, prevout.vout)
+            .await?;
+        let finality = locktime::finality(tx, &chain, input_height);
+        if finality == Finality::Final {
+            return Ok(());
+        }
+
+        if reported.as_ref() != Some(&finality) {
+            println!(
+                "Transaction {} is {}, waiting",
+                tx.compute_txid(),
+                locktime::describe(&finality, &chain)
+            );
+            reported = Some(finality);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+fn json_u32(value: &Value) -> Result<u32, Box<dyn std::error::Error>> {
+    let n = value.as_u64().ok_or("expected a number")?;
+    Ok(u32::try_from(n)?)
+}
+
+/// A bitcoind JSON-RPC interface.
+pub struct Bitcoind {
+    url: String,
+    auth: Option<(String, String)>,
+    client: reqwest::Client,
+}
+
+impl Bitcoind {
+    async fn call(&self, method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut req = self.client.post(&self.url).json(&json!({
+            "jsonrpc": "1.0",
+            "id": "ephemeral-sign",
+            "method": method,
+            "params": params,
+        }));
+        if let Some((user, password)) = &self.auth {
+            req = req.basic_auth(user, Some(password));
+        }
+
+        // Errors come with a non-success status, but still carry the reason in the body.
+        let resp = req.send().await?;
+        let status = resp.status();
+        let body: Value = resp
+            .json()
+            .await
+            .map_err(|_| format!("bitcoind responded {}", status))?;
+        match &body["error"] {
+            Value::Null => Ok(body["result"].clone()),
+            error => Err(error["message"]
+                .as_str()
+                .or(error.as_str())
+                .unwrap_or("unknown bitcoind error")
+                .to_string()
+                .into()),
+        }
+    }
+}
+
+/// An Electrum server speaking its JSON-RPC protocol over plain TCP.
+pub struct Electrum {
+    server: String,
+}
+
+impl Electrum {
+    async fn call(&self, method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut stream = TcpStream::connect(&self.server).await?;
+        let mut req = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+        req.push('\n');
+        stream.write_all(req.as_bytes()).await?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await?;
+        let body: Value = serde_json::from_str(&line)?;
+        match &body["error"] {
+            Value::Null => Ok(body["result"].clone()),
+            error => Err(error["message"]
+                .as_str()
+                .or(error.as_str())
+                .unwrap_or("unknown electrum error")
+                .to_string()
+                .into()),
+        }
+    }
+}
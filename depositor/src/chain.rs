@@ -0,0 +1,179 @@
+use std::str::FromStr;
+
+use bitcoin::{Address, Network, Transaction, Txid, consensus};
+use serde::Deserialize;
+
+use crate::locktime::ChainState;
+
+/// Options for the chain backend, an Esplora HTTP API.
+#[derive(Debug, clap::Args)]
+pub struct ChainArgs {
+    /// URL of the Esplora API to query the chain and broadcast with. Defaults to blockstream.info
+    /// for mainnet and testnet, and mempool.space for signet.
+    #[arg(long)]
+    pub esplora_url: Option<String>,
+}
+
+impl ChainArgs {
+    pub fn backend(&self, network: Network) -> Esplora {
+        let url = match (&self.esplora_url, network) {
+            (Some(url), _) => url.trim_end_matches('/').to_string(),
+            (None, Network::Bitcoin) => "https://blockstream.info/api".to_string(),
+            (None, Network::Signet) => "https://mempool.space/signet/api".to_string(),
+            (None, Network::Regtest) => panic!("--esplora-url is required on regtest"),
+            (None, _) => "https://blockstream.info/testnet/api".to_string(),
+        };
+        Esplora {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+/// Confirmation status of a transaction.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TxStatus {
+    pub confirmed: bool,
+    pub block_height: Option<u32>,
+}
+
+/// Whether an output is spent, and by which transaction.
+#[derive(Deserialize, Debug)]
+pub struct OutSpend {
+    pub spent: bool,
+    pub txid: Option<Txid>,
+    pub status: Option<TxStatus>,
+}
+
+/// An unspent output of an address.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Utxo {
+    pub txid: Txid,
+    pub vout: u32,
+    pub value: u64,
+    pub status: TxStatus,
+}
+
+pub struct Esplora {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl Esplora {
+    /// Status of `txid`, none if the backend does not know it.
+    pub async fn tx_status(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<TxStatus>, Box<dyn std::error::Error>> {
+        let resp = self
+            .client
+            .get(format!("{}/tx/{}/status", self.url, txid))
+            .send()
+            .await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(resp.error_for_status()?.json().await?))
+    }
+
+    pub async fn tx(&self, txid: &Txid) -> Result<Transaction, Box<dyn std::error::Error>> {
+        let hex = self
+            .client
+            .get(format!("{}/tx/{}/hex", self.url, txid))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(consensus::encode::deserialize(&hex::decode(hex.trim())?)?)
+    }
+
+    pub async fn outspend(
+        &self,
+        txid: &Txid,
+        vout: u32,
+    ) -> Result<OutSpend, Box<dyn std::error::Error>> {
+        Ok(self
+            .client
+            .get(format!("{}/tx/{}/outspend/{}", self.url, txid, vout))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Unspent outputs paying `address`, including unconfirmed ones.
+    pub async fn address_utxos(
+        &self,
+        address: &Address,
+    ) -> Result<Vec<Utxo>, Box<dyn std::error::Error>> {
+        Ok(self
+            .client
+            .get(format!("{}/address/{}/utxo", self.url, address))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Height of the chain tip.
+    pub async fn tip_height(&self) -> Result<u32, Box<dyn std::error::Error>> {
+        let height = self
+            .client
+            .get(format!("{}/blocks/tip/height", self.url))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(u32::from_str(height.trim())?)
+    }
+
+    /// Height and median time past of the chain tip.
+    pub async fn chain_state(&self) -> Result<ChainState, Box<dyn std::error::Error>> {
+        #[derive(Deserialize)]
+        struct Block {
+            height: u32,
+            mediantime: u32,
+        }
+
+        let hash = self
+            .client
+            .get(format!("{}/blocks/tip/hash", self.url))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let block: Block = self
+            .client
+            .get(format!("{}/block/{}", self.url, hash.trim()))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(ChainState {
+            tip: block.height,
+            median_time: block.mediantime,
+        })
+    }
+
+    /// Broadcasts `tx`, returning the rejection reason of the backend if it fails.
+    pub async fn broadcast(&self, tx: &Transaction) -> Result<Txid, Box<dyn std::error::Error>> {
+        let resp = self
+            .client
+            .post(format!("{}/tx", self.url))
+            .body(consensus::encode::serialize_hex(tx))
+            .send()
+            .await?;
+        let status = resp.status();
+        let body = resp.text().await?;
+        if !status.is_success() {
+            return Err(format!("broadcast rejected: {}", body.trim()).into());
+        }
+        Ok(Txid::from_str(body.trim())?)
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use musig2::KeyAggContext;
+use musig2::secp::Point;
+use shared::tee::EnclaveAttestation;
+
+/// Options for checking the signers' enclave attestations that they deleted the key.
+#[derive(Debug, clap::Args)]
+pub struct EnclaveArgs {
+    /// Require the receipt to carry an enclave attestation of each signer deleting its key.
+    #[arg(long)]
+    pub require_enclave: bool,
+
+    /// Hex encoded measurement the signers' enclaves must have, MRENCLAVE for SGX or PCR0 for
+    /// Nitro. Can be given multiple times. Any is accepted if not given.
+    #[arg(long)]
+    pub enclave_measurement: Vec<String>,
+
+    /// Program checking the platform's signature on an attestation, such as a DCAP quote
+    /// verifier or a Nitro attestation document verifier. It is passed the platform, sgx or
+    /// nitro, as argument and the document on stdin, and must exit successfully if it is
+    /// genuine.
+    #[arg(long)]
+    pub attestation_verifier: Option<PathBuf>,
+}
+
+impl EnclaveArgs {
+    /// Checks the signers' `attestations` attest the deletion of the keys aggregated into the
+    /// deposit's hex encoded x-only `internal_key`, by enclaves with an allowed measurement.
+    pub fn verify(
+        &self,
+        attestations: &[EnclaveAttestation],
+        internal_key: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if attestations.is_empty() {
+            if self.require_enclave {
+                return Err("signers attested no key deletion".into());
+            }
+            return Ok(());
+        }
+
+        let mut pubkeys = vec![];
+        for attestation in attestations {
+            let measurement = attestation.verify_binding()?;
+            if !self.enclave_measurement.is_empty()
+                && !self.enclave_measurement.contains(&measurement)
+            {
+                return Err(format!("enclave measurement {} is not allowed", measurement).into());
+            }
+            match &self.attestation_verifier {
+                Some(program) => verify_document(program, attestation)?,
+                None => println!(
+                    "No --attestation-verifier, the {} attestation of session {} is not checked \
+                     to be signed by the platform",
+                    attestation.platform, attestation.session_id
+                ),
+            }
+            println!(
+                "Key deletion of session {} attested by {} enclave {}",
+                attestation.session_id, attestation.platform, measurement
+            );
+            pubkeys.push(Point::from_hex(&attestation.pubkey)?);
+        }
+
+        // The attested keys must be the ones the deposit is locked to.
+        let key_agg_ctx = KeyAggContext::new(pubkeys)?;
+        let aggregated: Point = key_agg_ctx.aggregated_pubkey();
+        if hex::encode(aggregated.serialize_xonly()) != internal_key {
+            return Err("attested keys are not those of the deposit".into());
+        }
+        Ok(())
+    }
+}
+
+/// Runs `program` on the document of `attestation`, to check it is signed by the platform.
+fn verify_document(
+    program: &PathBuf,
+    attestation: &EnclaveAttestation,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new(program)
+        .arg(attestation.platform.to_string())
+        .stdin(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or("attestation verifier stdin")?
+        .write_all(&attestation.document_bytes()?)?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!(
+            "attestation verifier rejected the attestation of session {} with {}",
+            attestation.session_id, status
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::ops::Deref;
+
+use bitcoin::bip32::Xpriv;
+use bitcoin::secp256k1::{Keypair, SecretKey};
+
+/// Secret key material that can be wiped from memory.
+pub trait Erase {
+    fn erase(&mut self);
+}
+
+impl Erase for SecretKey {
+    fn erase(&mut self) {
+        self.non_secure_erase();
+    }
+}
+
+impl Erase for Keypair {
+    fn erase(&mut self) {
+        self.non_secure_erase();
+    }
+}
+
+impl Erase for Xpriv {
+    fn erase(&mut self) {
+        self.private_key.non_secure_erase();
+    }
+}
+
+/// Holds a secret and wipes it from memory when dropped. The secp256k1 key types are `Copy`, so
+/// only the copy held here is wiped, and copies should be kept as short-lived as possible.
+pub struct Erasing<T: Erase>(T);
+
+impl<T: Erase> Erasing<T> {
+    pub fn new(secret: T) -> Self {
+        Erasing(secret)
+    }
+}
+
+impl<T: Erase> Deref for Erasing<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Erase> Drop for Erasing<T> {
+    fn drop(&mut self) {
+        self.0.erase();
+    }
+}
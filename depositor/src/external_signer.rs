@@ -0,0 +1,109 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use bitcoin::bip32::KeySource;
+use bitcoin::{OutPoint, Psbt, TxOut, XOnlyPublicKey};
+
+use crate::{finalize_deposit_input, set_deposit_input};
+
+/// Like [`crate::sign_deposit`], but has the external signer `program` sign our input.
+pub fn sign_deposit(
+    program: &Path,
+    internal_key: XOnlyPublicKey,
+    key_source: Option<KeySource>,
+    deposit_psbt: &mut Psbt,
+    prevout: OutPoint,
+    deposit_prevout: &TxOut,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let our_input = set_deposit_input(
+        deposit_psbt,
+        prevout,
+        deposit_prevout,
+        internal_key,
+        key_source,
+    );
+    *deposit_psbt = sign(program, deposit_psbt, our_input)?;
+    if deposit_psbt.inputs[our_input]
+        .final_script_witness
+        .is_none()
+    {
+        finalize_deposit_input(deposit_psbt, our_input);
+    }
+    Ok(())
+}
+
+/// Pipes the base64 encoded `psbt` to the external signer `program`, and reads back the signed
+/// PSBT from its output. The program only sees the PSBT, so our keys never enter this process.
+pub fn sign(
+    program: &Path,
+    psbt: &Psbt,
+    our_input: usize,
+) -> Result<Psbt, Box<dyn std::error::Error>> {
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or("external signer stdin")?
+        .write_all(format!("{}\n", psbt).as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!("external signer failed with {}", output.status).into());
+    }
+
+    let signed = Psbt::from_str(String::from_utf8(output.stdout)?.trim())?;
+    check_signed(psbt, &signed, our_input)?;
+    Ok(signed)
+}
+
+/// Makes sure the external signer only added its signature to our input, or finalized it, and
+/// left the rest of the PSBT alone.
+fn check_signed(
+    original: &Psbt,
+    signed: &Psbt,
+    our_input: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if signed.unsigned_tx != original.unsigned_tx {
+        return Err("external signer changed the deposit transaction".into());
+    }
+    if signed.version != original.version
+        || signed.xpub != original.xpub
+        || signed.proprietary != original.proprietary
+        || signed.unknown != original.unknown
+    {
+        return Err("external signer changed the global PSBT fields".into());
+    }
+    if signed.outputs != original.outputs {
+        return Err("external signer changed the deposit outputs".into());
+    }
+
+    for (i, (input, orig)) in signed.inputs.iter().zip(original.inputs.iter()).enumerate() {
+        if i != our_input {
+            if input != orig {
+                return Err("external signer changed an input that is not ours".into());
+            }
+            continue;
+        }
+
+        // A finalizer clears the other fields, but must keep the prevout.
+        if input.final_script_witness.is_some() {
+            if input.witness_utxo != orig.witness_utxo || input.final_script_sig.is_some() {
+                return Err("external signer finalized our input unexpectedly".into());
+            }
+            continue;
+        }
+
+        let mut expected = input.clone();
+        expected.tap_key_sig = orig.tap_key_sig;
+        if expected != *orig || input.tap_key_sig.is_none() {
+            return Err("external signer must only add the key spend signature".into());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,460 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use bitcoin::address::script_pubkey::ScriptBufExt;
+use bitcoin::bip32::{DerivationPath, KeySource};
+use bitcoin::opcodes::all::OP_CHECKSIG;
+use bitcoin::psbt::Input;
+use bitcoin::script::ScriptExt;
+use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey, Signing, Verification};
+use bitcoin::sighash::SighashCache;
+use bitcoin::{
+    Amount, CompressedPublicKey, EcdsaSighashType, Network, OutPoint, PrivateKey, Psbt, PublicKey,
+    Script, ScriptBuf, Sequence, TxIn, TxOut, Witness, XOnlyPublicKey, ecdsa,
+};
+
+use crate::erase::Erasing;
+use crate::{KeyArgs, amounts, finalize_deposit_input, keys};
+
+/// Inputs the deposit is funded from: the output of our key at --prevout, and any extra inputs
+/// signed with the keyring.
+#[derive(Debug, clap::Args)]
+pub struct FundingArgs {
+    #[arg(long)]
+    pub prevout: OutPoint,
+
+    #[arg(long, value_parser = amounts::parse_amount)]
+    pub prev_amt: Amount,
+
+    /// Script type of the prevout. Segwit v0 outputs are signed with ECDSA, so only with the
+    /// private key.
+    #[arg(long, value_enum, default_value_t = FundingType::P2tr, conflicts_with_all = [
+        "pub_key", "signer_cmd", "hwi_path", "session_out",
+    ])]
+    pub prevout_type: FundingType,
+
+    /// Hex encoded transaction creating the prevout. If given, the prevout is checked to be an
+    /// output of our key of --prevout-type worth --prev-amt before building the deposit.
+    #[arg(long)]
+    pub prev_tx: Option<String>,
+
+    /// Another funding input of the deposit, as <outpoint>=<amount>@<label>, spending a key
+    /// spend output of the --keyring-key of that label. Can be given multiple times.
+    #[arg(long = "extra-input", requires = "keyring_keys", conflicts_with_all = [
+        "payjoin_endpoint", "session_out",
+    ])]
+    pub extra_inputs: Vec<ExtraInput>,
+
+    /// Key to sign the extra funding inputs with, as <label>=<key> with the key given like
+    /// --priv-key, or as an xpriv or mnemonic followed by the path to derive it at. Can be given
+    /// multiple times.
+    #[arg(long = "keyring-key")]
+    pub keyring_keys: Vec<String>,
+}
+
+impl FundingArgs {
+    /// The inputs of the deposit, --prevout first, and their total value. None if the total
+    /// overflows.
+    pub fn inputs(&self) -> Option<(Vec<TxIn>, Amount)> {
+        let outpoints =
+            std::iter::once(self.prevout).chain(self.extra_inputs.iter().map(|e| e.outpoint));
+        let inputs = outpoints
+            .map(|previous_output| TxIn {
+                previous_output,
+                script_sig: ScriptBuf::default(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::default(),
+            })
+            .collect();
+
+        let mut value = self.prev_amt;
+        for extra in &self.extra_inputs {
+            value = value.checked_add(extra.amount)?;
+        }
+        Some((inputs, value))
+    }
+
+    /// Weight the witnesses of the inputs add, the extra inputs being key spends.
+    pub fn witness_weight(&self) -> u64 {
+        self.prevout_type.witness_weight()
+            + self.extra_inputs.len() as u64 * FundingType::P2tr.witness_weight()
+    }
+}
+
+/// Script type of the output of our key the deposit is funded from.
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum FundingType {
+    /// Key spend of a taproot output.
+    #[default]
+    P2tr,
+    /// Segwit v0 output paying the hash of the key.
+    P2wpkh,
+    /// Segwit v0 output paying the hash of a script checking a signature of the key.
+    P2wsh,
+}
+
+impl FundingType {
+    /// The type of `script` if it is an output of `keypair`, none otherwise.
+    pub fn of<C: Verification>(
+        secp: &Secp256k1<C>,
+        keypair: &Keypair,
+        script: &Script,
+    ) -> Option<Self> {
+        [FundingType::P2tr, FundingType::P2wpkh, FundingType::P2wsh]
+            .into_iter()
+            .find(|ty| ty.script_pubkey(secp, keypair).as_script() == script)
+    }
+
+    /// The type of `script` by its template alone, none if it is none of the types.
+    pub fn of_output(script: &Script) -> Option<Self> {
+        if script.is_p2tr() {
+            Some(FundingType::P2tr)
+        } else if script.is_p2wpkh() {
+            Some(FundingType::P2wpkh)
+        } else if script.is_p2wsh() {
+            Some(FundingType::P2wsh)
+        } else {
+            None
+        }
+    }
+
+    /// The output script of this type paying `keypair`.
+    pub fn script_pubkey<C: Verification>(
+        self,
+        secp: &Secp256k1<C>,
+        keypair: &Keypair,
+    ) -> ScriptBuf {
+        match self {
+            FundingType::P2tr => ScriptBuf::new_p2tr(secp, keypair.x_only_public_key().0, None),
+            FundingType::P2wpkh => {
+                ScriptBuf::new_p2wpkh(&CompressedPublicKey(keypair.public_key()).wpubkey_hash())
+            }
+            FundingType::P2wsh => ScriptBuf::new_p2wsh(&witness_script(keypair).wscript_hash()),
+        }
+    }
+
+    /// Weight the witness spending an output of this type adds, without the segwit marker and
+    /// flag: the item count, and each item with its length.
+    pub fn witness_weight(self) -> u64 {
+        match self {
+            // A 64 byte Schnorr signature.
+            FundingType::P2tr => 1 + 1 + 64,
+            // An ECDSA signature of at most 72 bytes with its sighash type, and a compressed key.
+            FundingType::P2wpkh => 1 + 1 + 72 + 1 + 33,
+            // The signature, and the 35 byte witness script.
+            FundingType::P2wsh => 1 + 1 + 72 + 1 + 35,
+        }
+    }
+}
+
+/// Witness script of a P2WSH output of `keypair`, `<key> OP_CHECKSIG`.
+fn witness_script(keypair: &Keypair) -> ScriptBuf {
+    ScriptBuf::builder()
+        .push_key(&PublicKey::new(keypair.public_key()))
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Fills in the PSBT input of `deposit_psbt` spending `prevout`, a segwit v0 output of `keypair`
+/// of type `ty`, with the output it spends, its key origin and witness script. Returns the index
+/// of the input.
+pub fn set_v0_input(
+    deposit_psbt: &mut Psbt,
+    prevout: OutPoint,
+    deposit_prevout: &TxOut,
+    ty: FundingType,
+    keypair: &Keypair,
+    key_source: Option<KeySource>,
+) -> usize {
+    let our_input = deposit_psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|i| i.previous_output == prevout)
+        .expect("our input in deposit");
+    deposit_psbt.inputs[our_input] = Input {
+        witness_utxo: Some(deposit_prevout.clone()),
+        bip32_derivation: key_source
+            .map(|key_source| BTreeMap::from([(keypair.public_key(), key_source)]))
+            .unwrap_or_default(),
+        witness_script: (ty == FundingType::P2wsh).then(|| witness_script(keypair)),
+        sighash_type: Some(EcdsaSighashType::All.into()),
+        ..Default::default()
+    };
+    our_input
+}
+
+/// Signs and finalizes our input of `deposit_psbt` spending `prevout`, a segwit v0 output of
+/// `keypair` of type `ty`, with an ECDSA signature over its BIP-143 sighash.
+pub fn sign_v0<C: Signing>(
+    secp: &Secp256k1<C>,
+    keypair: &Keypair,
+    key_source: KeySource,
+    ty: FundingType,
+    deposit_psbt: &mut Psbt,
+    prevout: OutPoint,
+    deposit_prevout: &TxOut,
+) {
+    let our_input = set_v0_input(
+        deposit_psbt,
+        prevout,
+        deposit_prevout,
+        ty,
+        keypair,
+        Some(key_source),
+    );
+    let (msg, sighash_type) = {
+        let mut cache = SighashCache::new(&deposit_psbt.unsigned_tx);
+        deposit_psbt
+            .sighash_ecdsa(our_input, &mut cache)
+            .expect("deposit sighash")
+    };
+    let sk: Erasing<SecretKey> = Erasing::new(keypair.secret_key());
+    let signature = ecdsa::Signature {
+        signature: secp.sign_ecdsa(&msg, &sk),
+        sighash_type,
+    };
+
+    let pubkey = PublicKey::new(keypair.public_key());
+    let input = &mut deposit_psbt.inputs[our_input];
+    input.final_script_witness = Some(match ty {
+        FundingType::P2wpkh => Witness::p2wpkh(&signature, &pubkey.inner),
+        FundingType::P2wsh => Witness::from_slice(&[
+            signature.to_vec(),
+            input
+                .witness_script
+                .as_ref()
+                .expect("witness script")
+                .to_bytes(),
+        ]),
+        FundingType::P2tr => unreachable!("taproot inputs are signed with a Schnorr signature"),
+    });
+
+    // Clear all the data fields as per the spec.
+    input.partial_sigs = BTreeMap::new();
+    input.sighash_type = None;
+    input.witness_script = None;
+    input.bip32_derivation = BTreeMap::new();
+}
+
+/// A funding input of the deposit besides --prevout, as `<outpoint>=<amount>@<label>`, spending
+/// a key spend output of the keyring key of that label.
+#[derive(Debug, Clone)]
+pub struct ExtraInput {
+    pub outpoint: OutPoint,
+    pub amount: Amount,
+    pub label: String,
+}
+
+impl FromStr for ExtraInput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let usage = "funding input as <outpoint>=<amount>@<keyring label>";
+        let (outpoint, rest) = s.split_once('=').ok_or(usage)?;
+        let (amount, label) = rest.rsplit_once('@').ok_or(usage)?;
+        Ok(ExtraInput {
+            outpoint: outpoint.parse().map_err(|e| format!("{}", e))?,
+            amount: amounts::parse_amount(amount)?,
+            label: label.to_string(),
+        })
+    }
+}
+
+/// A key of the keyring, with its origin if derived from a master key.
+struct Key {
+    keypair: Erasing<Keypair>,
+    origin: Option<KeySource>,
+}
+
+/// Keys the funding inputs of a deposit are signed with, by label, so a deposit funded by
+/// outputs of several keys is signed in one pass.
+pub struct Keyring {
+    keys: BTreeMap<String, Key>,
+}
+
+impl Keyring {
+    /// Loads the keys given as `<label>=<key>`, where the key is given like --priv-key. An xpriv
+    /// or mnemonic is derived from at the path following it, as in `<xpriv>/86'/1'/0'/0/3`, or
+    /// otherwise like --priv-key. A `tr(<key>)` descriptor of a single key is taken as the key.
+    pub fn load<C: Signing>(
+        secp: &Secp256k1<C>,
+        specs: &[String],
+        args: &KeyArgs,
+        network: Network,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut keys = BTreeMap::new();
+        for spec in specs {
+            let (label, key) = spec.split_once('=').ok_or("keyring key as <label>=<key>")?;
+            let key = key.split_once('#').map_or(key, |(key, _checksum)| key);
+            let key = match key.strip_prefix("tr(") {
+                Some(inner) => inner.strip_suffix(')').ok_or("tr() descriptor of a key")?,
+                None => key,
+            };
+
+            let (sk, origin) = match key.split_once('/') {
+                Some((master, path)) => {
+                    let master = keys::parse_master_key(master, args, network)?;
+                    let path = DerivationPath::from_str(&format!("m/{}", path))?;
+                    let (sk, origin) = keys::derive(&master, &path)?;
+                    (sk, Some(origin))
+                }
+                None => keys::parse_priv_key(key, args, network)?,
+            };
+            let keypair = Erasing::new(Keypair::from_secret_key(secp, &sk));
+            if keys
+                .insert(label.to_string(), Key { keypair, origin })
+                .is_some()
+            {
+                return Err(format!("keyring label {} given twice", label).into());
+            }
+        }
+        Ok(Keyring { keys })
+    }
+
+    fn key(&self, label: &str) -> Result<&Key, String> {
+        self.keys
+            .get(label)
+            .ok_or_else(|| format!("no keyring key labelled {}", label))
+    }
+
+    /// The output `input` spends, a key spend of its key.
+    pub fn prevout<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        input: &ExtraInput,
+    ) -> Result<TxOut, String> {
+        let (internal_key, _) = self.key(&input.label)?.keypair.x_only_public_key();
+        Ok(TxOut {
+            value: input.amount,
+            script_pubkey: ScriptBuf::new_p2tr(secp, internal_key, None),
+        })
+    }
+
+    /// Fills in the PSBT inputs of `deposit_psbt` spending `inputs` with their UTXO, key and
+    /// origin. Every input's UTXO must be known before any input of a taproot spend is signed.
+    pub fn set_inputs<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        deposit_psbt: &mut Psbt,
+        inputs: &[ExtraInput],
+    ) -> Result<(), String> {
+        for extra in inputs {
+            let key = self.key(&extra.label)?;
+            let index = deposit_psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .position(|i| i.previous_output == extra.outpoint)
+                .ok_or_else(|| format!("deposit lacks the funding input {}", extra.outpoint))?;
+            let (internal_key, _) = key.keypair.x_only_public_key();
+            let input = &mut deposit_psbt.inputs[index];
+            input.witness_utxo = Some(self.prevout(secp, extra)?);
+            input.tap_internal_key = Some(internal_key);
+            if let Some(origin) = &key.origin {
+                input.tap_key_origins = BTreeMap::from([(internal_key, (vec![], origin.clone()))]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Signs and finalizes the inputs of `deposit_psbt` spending `inputs`, filled in with
+    /// [`Keyring::set_inputs`].
+    pub fn sign<C: Signing + Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        network: Network,
+        deposit_psbt: &mut Psbt,
+        inputs: &[ExtraInput],
+    ) -> Result<(), String> {
+        let mut key_map: HashMap<XOnlyPublicKey, PrivateKey> = HashMap::new();
+        for extra in inputs {
+            let keypair = &self.key(&extra.label)?.keypair;
+            let (xpub, _) = keypair.x_only_public_key();
+            key_map.insert(xpub, PrivateKey::new(keypair.secret_key(), network));
+        }
+        let signed = deposit_psbt.sign(&key_map, secp);
+        for key in key_map.values_mut() {
+            key.inner.non_secure_erase();
+        }
+        signed.map_err(|(_, errors)| format!("unable to sign funding inputs: {:?}", errors))?;
+
+        for extra in inputs {
+            let index = deposit_psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .position(|i| i.previous_output == extra.outpoint)
+                .expect("funding input in deposit");
+            finalize_deposit_input(deposit_psbt, index);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::consensus_validation::TransactionExt;
+    use bitcoin::locktime::absolute;
+    use bitcoin::{Transaction, Txid, transaction};
+
+    use super::*;
+    use crate::keys::raw_key_source;
+
+    /// Signs a spend of an output of type `ty` of a fresh key, checking the spend is valid and
+    /// its witness no larger than the type's estimate.
+    fn sign_and_verify(ty: FundingType) {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_secret_key(&secp, &SecretKey::new(&mut rand::thread_rng()));
+        let prevout = OutPoint {
+            txid: Txid::from_str(
+                "a6b4f2b8c0bd5d91d5aa4c1a6a6d1c0f2ff0c1d8ab4e9d1b6e1f1c0a0b0c0d0e",
+            )
+            .unwrap(),
+            vout: 0,
+        };
+        let deposit_prevout = TxOut {
+            value: Amount::from_sat(100_000).unwrap(),
+            script_pubkey: ty.script_pubkey(&secp, &keypair),
+        };
+        let unsigned_tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prevout,
+                script_sig: ScriptBuf::default(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::default(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(99_000).unwrap(),
+                script_pubkey: FundingType::P2tr.script_pubkey(&secp, &keypair),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx).expect("valid unsigned tx");
+
+        sign_v0(
+            &secp,
+            &keypair,
+            raw_key_source(&keypair),
+            ty,
+            &mut psbt,
+            prevout,
+            &deposit_prevout,
+        );
+        let tx = psbt.extract_tx().expect("valid transaction");
+        tx.verify(|_| Some(deposit_prevout.clone()))
+            .expect("valid spend");
+        assert!(ty.witness_weight() >= tx.input[0].witness.size() as u64);
+    }
+
+    #[test]
+    fn sign_p2wpkh() {
+        sign_and_verify(FundingType::P2wpkh);
+    }
+
+    #[test]
+    fn sign_p2wsh() {
+        sign_and_verify(FundingType::P2wsh);
+    }
+}
@@ -0,0 +1,302 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::{Amount, Transaction, consensus};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde::Serialize;
+
+use crate::session::Session;
+
+/// Statuses of a deposit, in the order it goes through them.
+pub const VERIFIED: &str = "verified";
+pub const SIGNED: &str = "signed";
+pub const COMPLETED: &str = "completed";
+pub const BROADCAST: &str = "broadcast";
+/// The deposit was double spent by a replacement sending its input back to us.
+pub const CANCELLED: &str = "cancelled";
+
+/// A deposit as recorded in the history database.
+#[derive(Serialize, Debug)]
+pub struct Deposit {
+    pub deposit_txid: String,
+    pub network: String,
+    pub prevout: String,
+    /// Value of our prevout, in sats.
+    pub prev_amount: u64,
+    /// Value of the deposit output, in sats.
+    pub deposit_amount: u64,
+    /// Fee of the deposit in sats, unknown if a payjoin receiver did not give its prevouts.
+    pub deposit_fee: Option<u64>,
+    pub fallback_addr: String,
+    /// Address of the signer service the deposit was made with, if known.
+    pub signer: Option<String>,
+    pub presigned_txid: String,
+    /// Hex encoded presigned spend.
+    pub presigned_tx: String,
+    pub presigned_fee: u64,
+    /// Locktime of the presigned spend, as in the transaction.
+    pub lock_time: u32,
+    pub status: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl Deposit {
+    fn from_session(session: &Session, status: &str, now: u64) -> Self {
+        let deposit_tx = &session.resp.deposit_psbt.unsigned_tx;
+        let presigned_tx: Transaction = session
+            .resp
+            .spend_psbt
+            .clone()
+            .extract_tx()
+            .expect("valid tx");
+
+        let deposit_fee = session.deposit_fee().map(|fee| fee.to_sat());
+        let deposit_amount = deposit_tx.output[0].value;
+        let presigned_fee = sum_outputs(&presigned_tx)
+            .and_then(|output| deposit_amount.checked_sub(output))
+            .unwrap_or(Amount::ZERO);
+
+        Deposit {
+            deposit_txid: session.id().to_string(),
+            network: session.network.to_string(),
+            prevout: session.prevout.to_string(),
+            prev_amount: session.deposit_prevout.value.to_sat(),
+            deposit_amount: deposit_amount.to_sat(),
+            deposit_fee,
+            fallback_addr: session.req.fallback_addr.clone(),
+            signer: session.signer.clone(),
+            presigned_txid: presigned_tx.compute_txid().to_string(),
+            presigned_tx: consensus::encode::serialize_hex(&presigned_tx),
+            presigned_fee: presigned_fee.to_sat(),
+            lock_time: presigned_tx.lock_time.to_consensus_u32(),
+            status: status.to_string(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Deposit {
+            deposit_txid: row.get("deposit_txid")?,
+            network: row.get("network")?,
+            prevout: row.get("prevout")?,
+            prev_amount: row.get("prev_amount")?,
+            deposit_amount: row.get("deposit_amount")?,
+            deposit_fee: row.get("deposit_fee")?,
+            fallback_addr: row.get("fallback_addr")?,
+            signer: row.get("signer")?,
+            presigned_txid: row.get("presigned_txid")?,
+            presigned_tx: row.get("presigned_tx")?,
+            presigned_fee: row.get("presigned_fee")?,
+            lock_time: row.get("lock_time")?,
+            status: row.get("status")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+}
+
+fn sum_outputs(tx: &Transaction) -> Option<Amount> {
+    tx.output
+        .iter()
+        .try_fold(Amount::ZERO, |sum, o| sum.checked_add(o.value))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time after the epoch")
+        .as_secs()
+}
+
+/// Local SQLite database of every deposit we made and its presigned spend.
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS deposits (
+                deposit_txid TEXT PRIMARY KEY,
+                network TEXT NOT NULL,
+                prevout TEXT NOT NULL,
+                prev_amount INTEGER NOT NULL,
+                deposit_amount INTEGER NOT NULL,
+                deposit_fee INTEGER,
+                fallback_addr TEXT NOT NULL,
+                signer TEXT,
+                presigned_txid TEXT NOT NULL,
+                presigned_tx TEXT NOT NULL,
+                presigned_fee INTEGER NOT NULL,
+                lock_time INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+        )?;
+        Ok(History { conn })
+    }
+
+    /// Records the deposit of `session` with `status`, keeping the time it was first recorded.
+    pub fn record(
+        &self,
+        session: &Session,
+        status: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let d = Deposit::from_session(session, status, now());
+        self.conn.execute(
+            "INSERT INTO deposits (
+                deposit_txid, network, prevout, prev_amount, deposit_amount, deposit_fee,
+                fallback_addr, signer, presigned_txid, presigned_tx, presigned_fee, lock_time,
+                status, created_at, updated_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+            ON CONFLICT(deposit_txid) DO UPDATE SET
+                status = excluded.status,
+                updated_at = excluded.updated_at",
+            params![
+                d.deposit_txid,
+                d.network,
+                d.prevout,
+                d.prev_amount,
+                d.deposit_amount,
+                d.deposit_fee,
+                d.fallback_addr,
+                d.signer,
+                d.presigned_txid,
+                d.presigned_tx,
+                d.presigned_fee,
+                d.lock_time,
+                d.status,
+                d.created_at,
+                d.updated_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Marks the deposit `deposit_txid` as broadcast.
+    pub fn mark_broadcast(&self, deposit_txid: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let updated = self.conn.execute(
+            "UPDATE deposits SET status = ?1, updated_at = ?2 WHERE deposit_txid = ?3",
+            params![BROADCAST, now(), deposit_txid],
+        )?;
+        if updated == 0 {
+            return Err(format!("no deposit {} in history", deposit_txid).into());
+        }
+        Ok(())
+    }
+
+    /// All deposits, oldest first.
+    pub fn list(&self) -> Result<Vec<Deposit>, Box<dyn std::error::Error>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT * FROM deposits ORDER BY created_at, deposit_txid")?;
+        let deposits = stmt
+            .query_map([], Deposit::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(deposits)
+    }
+
+    pub fn get(&self, deposit_txid: &str) -> Result<Option<Deposit>, Box<dyn std::error::Error>> {
+        let deposit = self
+            .conn
+            .query_row(
+                "SELECT * FROM deposits WHERE deposit_txid = ?1",
+                params![deposit_txid],
+                Deposit::from_row,
+            )
+            .optional()?;
+        Ok(deposit)
+    }
+}
+
+/// Opens the history at `path` and records the deposit of `session` with `status`.
+pub fn record(path: &Path, session: &Session, status: &str) {
+    History::open(path)
+        .and_then(|history| history.record(session, status))
+        .expect("able to record deposit in history");
+}
+
+/// Columns of the CSV export, one row per deposit. The raw presigned spend is left out, it is in
+/// the JSON export.
+const CSV_HEADER: &str = "deposit_txid,network,status,created_at,prevout,prev_amount,\
+    deposit_amount,deposit_fee,fallback_addr,presigned_txid,presigned_amount,presigned_fee,\
+    lock_time,signer";
+
+/// Exports `deposits` as CSV for bookkeeping, amounts in sats.
+pub fn to_csv(deposits: &[Deposit]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for d in deposits {
+        let fields = [
+            d.deposit_txid.clone(),
+            d.network.clone(),
+            d.status.clone(),
+            d.created_at.to_string(),
+            d.prevout.clone(),
+            d.prev_amount.to_string(),
+            d.deposit_amount.to_string(),
+            d.deposit_fee.map(|f| f.to_string()).unwrap_or_default(),
+            d.fallback_addr.clone(),
+            d.presigned_txid.clone(),
+            d.deposit_amount.saturating_sub(d.presigned_fee).to_string(),
+            d.presigned_fee.to_string(),
+            d.lock_time.to_string(),
+            d.signer.clone().unwrap_or_default(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quotes `field` if it contains anything CSV gives a meaning.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// Exports `deposits` as JSON, including the raw presigned spends.
+pub fn to_json(deposits: &[Deposit]) -> String {
+    serde_json::to_string_pretty(deposits).unwrap()
+}
+
+/// Prints one line per deposit in the history.
+pub fn print_list(deposits: &[Deposit]) {
+    for d in deposits {
+        println!(
+            "{} {} {} sat to {} [{}]",
+            d.created_at, d.deposit_txid, d.deposit_amount, d.fallback_addr, d.status
+        );
+    }
+}
+
+/// Prints everything recorded about a deposit.
+pub fn print_deposit(d: &Deposit) {
+    println!("deposit txid:     {}", d.deposit_txid);
+    println!("network:          {}", d.network);
+    println!("prevout:          {} ({} sat)", d.prevout, d.prev_amount);
+    println!("deposit amount:   {} sat", d.deposit_amount);
+    match d.deposit_fee {
+        Some(fee) => println!("deposit fee:      {} sat", fee),
+        None => println!("deposit fee:      unknown"),
+    }
+    println!("fallback address: {}", d.fallback_addr);
+    println!(
+        "signer:           {}",
+        d.signer.as_deref().unwrap_or("unknown")
+    );
+    println!("presigned txid:   {}", d.presigned_txid);
+    println!("presigned fee:    {} sat", d.presigned_fee);
+    println!("lock time:        {}", d.lock_time);
+    println!("status:           {}", d.status);
+    println!("created at:       {}", d.created_at);
+    println!("updated at:       {}", d.updated_at);
+    println!("presigned tx:     {}", d.presigned_tx);
+}
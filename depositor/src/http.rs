@@ -0,0 +1,122 @@
+use std::fmt;
+use std::time::Duration;
+
+use reqwest::{RequestBuilder, Response, StatusCode};
+
+/// Options for the HTTP client the depositor talks to the signer with.
+#[derive(Debug, clap::Args)]
+pub struct HttpArgs {
+    /// Seconds to wait for a connection to the signer.
+    #[arg(long, default_value_t = 10)]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for the signer to respond to a request. Signing a deposit takes the
+    /// signer a round trip to each of its signers.
+    #[arg(long, default_value_t = 120)]
+    pub request_timeout: u64,
+
+    /// Times to retry a request to the signer that failed in a way that is safe to retry.
+    #[arg(long, default_value_t = 3)]
+    pub retries: u32,
+
+    /// Milliseconds to wait before the first retry, doubling with each retry after it.
+    #[arg(long, default_value_t = 1000)]
+    pub retry_backoff_ms: u64,
+}
+
+/// How a request to the signer failed to get a response.
+#[derive(Debug)]
+pub enum Failure {
+    /// The request never reached the signer, so nothing was signed for it.
+    NotSent(reqwest::Error),
+    /// The request was sent but its response was lost, so the signer may have acted on it.
+    ResponseLost(reqwest::Error),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::NotSent(e) => write!(f, "the signer never got the request: {}", e),
+            Failure::ResponseLost(e) => write!(
+                f,
+                "the signer's response was lost, it may have signed the request: {}",
+                e
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+impl From<reqwest::Error> for Failure {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() || e.is_builder() {
+            Failure::NotSent(e)
+        } else {
+            Failure::ResponseLost(e)
+        }
+    }
+}
+
+/// Statuses of responses the signer may respond differently to later, those of its retryable
+/// errors.
+fn retryable(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 410 | 429 | 502 | 503 | 504)
+}
+
+impl HttpArgs {
+    pub fn client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(self.connect_timeout))
+            .timeout(Duration::from_secs(self.request_timeout))
+            .build()
+            .expect("valid HTTP client")
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.retry_backoff_ms.saturating_mul(1 << attempt.min(16)))
+    }
+
+    /// Sends the request made by `request`, retrying with backoff if it failed in a way that is
+    /// safe to retry. A request whose response was lost is only sent again if it is
+    /// `idempotent`, so the signer does not act on it twice. A retryable error response is
+    /// returned once the retries run out.
+    pub async fn send(
+        &self,
+        idempotent: bool,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Result<Response, Failure> {
+        let mut attempt = 0;
+        loop {
+            let failure = match request().send().await {
+                Ok(resp) if retryable(resp.status()) && attempt < self.retries => {
+                    // The signer tells how long to wait when rate limiting.
+                    let wait = resp
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse().ok())
+                        .map(Duration::from_secs)
+                        .unwrap_or(self.backoff(attempt));
+                    println!("Signer responded {}, retrying in {:?}", resp.status(), wait);
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                    continue;
+                }
+                Ok(resp) => return Ok(resp),
+                Err(e) => Failure::from(e),
+            };
+            let safe = match &failure {
+                Failure::NotSent(_) => true,
+                Failure::ResponseLost(_) => idempotent,
+            };
+            if !safe || attempt >= self.retries {
+                return Err(failure);
+            }
+            let wait = self.backoff(attempt);
+            println!("Request failed, {}. Retrying in {:?}", failure, wait);
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+}
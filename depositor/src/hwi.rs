@@ -0,0 +1,158 @@
+use std::process::Command;
+use std::str::FromStr;
+
+use bitcoin::bip32::{DerivationPath, Fingerprint, KeySource, Xpub};
+use bitcoin::{Address, Network, OutPoint, Psbt, TxOut, XOnlyPublicKey};
+use serde_json::Value;
+
+use crate::{finalize_deposit_input, set_deposit_input};
+
+/// A hardware wallet reached through the HWI command line tool, holding the deposit key at
+/// `path`.
+pub struct Device {
+    pub fingerprint: Fingerprint,
+    pub path: DerivationPath,
+    network: Network,
+    xpub: Xpub,
+}
+
+impl Device {
+    /// Opens the connected device with the given fingerprint, or the first one found, and fetches
+    /// the key at `path`.
+    pub fn open(
+        fingerprint: Option<Fingerprint>,
+        path: DerivationPath,
+        network: Network,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let devices = run(&["enumerate"])?;
+        let fingerprint = devices
+            .as_array()
+            .ok_or("unexpected HWI enumerate output")?
+            .iter()
+            .filter_map(|d| d["fingerprint"].as_str())
+            .filter_map(|fp| Fingerprint::from_str(fp).ok())
+            .find(|fp| fingerprint.is_none_or(|f| f == *fp))
+            .ok_or("no matching hardware wallet connected")?;
+
+        let resp = run(&[
+            "--fingerprint",
+            &fingerprint.to_string(),
+            "--chain",
+            chain(network),
+            "getxpub",
+            &path.to_string(),
+        ])?;
+        let xpub = resp["xpub"].as_str().ok_or("no xpub from device")?;
+        let xpub = Xpub::from_str(xpub)?;
+
+        Ok(Device {
+            fingerprint,
+            path,
+            network,
+            xpub,
+        })
+    }
+
+    /// The key the deposit input is locked to.
+    pub fn internal_key(&self) -> XOnlyPublicKey {
+        self.xpub.to_x_only_pub()
+    }
+
+    pub fn key_source(&self) -> KeySource {
+        (self.fingerprint, self.path.clone())
+    }
+
+    /// Shows the P2TR address of the key on the device, so the user can check it against the
+    /// one we print. Returns the address the device shows.
+    pub fn display_address(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let resp = self.run(&[
+            "displayaddress",
+            "--path",
+            &self.path.to_string(),
+            "--addr-type",
+            "tap",
+        ])?;
+        let addr = resp["address"].as_str().ok_or("no address from device")?;
+        Ok(addr.to_string())
+    }
+
+    /// Has the user check on the device that its key's address is `addr`, the one we print.
+    pub fn confirm_address(&self, addr: &Address) -> Result<(), Box<dyn std::error::Error>> {
+        println!("Confirm the address on the hardware wallet");
+        if self.display_address()? != addr.to_string() {
+            return Err("hardware wallet shows a different address".into());
+        }
+        Ok(())
+    }
+
+    /// Like [`crate::sign_deposit`], but has the device sign our input.
+    pub fn sign_deposit(
+        &self,
+        deposit_psbt: &mut Psbt,
+        prevout: OutPoint,
+        deposit_prevout: &TxOut,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // The device finds its key in the PSBT through the origin of the internal key.
+        let our_input = set_deposit_input(
+            deposit_psbt,
+            prevout,
+            deposit_prevout,
+            self.internal_key(),
+            Some(self.key_source()),
+        );
+        println!("Confirm the deposit on the hardware wallet");
+        *deposit_psbt = self.sign(deposit_psbt)?;
+        finalize_deposit_input(deposit_psbt, our_input);
+        Ok(())
+    }
+
+    /// Has the device sign its inputs of `psbt`, returning the PSBT with the signatures added.
+    pub fn sign(&self, psbt: &Psbt) -> Result<Psbt, Box<dyn std::error::Error>> {
+        let resp = self.run(&["signtx", &psbt.to_string()])?;
+        if resp["signed"].as_bool() != Some(true) {
+            return Err("hardware wallet did not sign the deposit".into());
+        }
+        let signed = resp["psbt"].as_str().ok_or("no PSBT from device")?;
+        Ok(Psbt::from_str(signed)?)
+    }
+
+    fn run(&self, args: &[&str]) -> Result<Value, Box<dyn std::error::Error>> {
+        let fingerprint = self.fingerprint.to_string();
+        let mut all_args = vec![
+            "--fingerprint",
+            &fingerprint,
+            "--chain",
+            chain(self.network),
+        ];
+        all_args.extend_from_slice(args);
+        run(&all_args)
+    }
+}
+
+/// The name HWI uses for `network`.
+fn chain(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "main",
+        Network::Signet => "signet",
+        Network::Regtest => "regtest",
+        _ => "test",
+    }
+}
+
+/// Runs `hwi` with the given arguments and parses its JSON output.
+fn run(args: &[&str]) -> Result<Value, Box<dyn std::error::Error>> {
+    let output = Command::new("hwi").args(args).output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "hwi failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    let resp: Value = serde_json::from_slice(&output.stdout)?;
+    if let Some(err) = resp.get("error") {
+        return Err(format!("hwi error: {}", err).into());
+    }
+    Ok(resp)
+}
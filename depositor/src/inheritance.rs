@@ -0,0 +1,67 @@
+use std::fs;
+use std::path::Path;
+
+use bitcoin::consensus;
+use bitcoin::{Transaction, Txid};
+use serde::{Deserialize, Serialize};
+
+/// The inheritance transaction currently in effect, kept so the depositor knows when the protocol
+/// must be repeated to push the date forward.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InheritanceRecord {
+    pub heir_addr: String,
+    /// Block height from which the heir can broadcast the presigned spend.
+    pub lock_time: u32,
+    pub deposit_txid: Txid,
+    /// Hex encoded presigned spend to hand to the heir.
+    pub presigned_tx: String,
+}
+
+impl InheritanceRecord {
+    pub fn new(
+        heir_addr: String,
+        lock_time: u32,
+        deposit: &Transaction,
+        presigned: &Transaction,
+    ) -> Self {
+        InheritanceRecord {
+            heir_addr,
+            lock_time,
+            deposit_txid: deposit.compute_txid(),
+            presigned_tx: consensus::encode::serialize_hex(presigned),
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Option<InheritanceRecord> {
+    let data = fs::read_to_string(path).ok()?;
+    Some(serde_json::from_str(&data).expect("valid inheritance record"))
+}
+
+pub fn store(path: &Path, record: &InheritanceRecord) {
+    let data = serde_json::to_string_pretty(record).unwrap();
+    fs::write(path, data).expect("able to write inheritance record");
+}
+
+/// Prints a warning if the active inheritance transaction is valid, or will be within
+/// `warn_blocks` blocks of `current_height`.
+pub fn warn_expiry(record: &InheritanceRecord, current_height: u32, warn_blocks: u32) {
+    if current_height >= record.lock_time {
+        println!(
+            "WARNING: inheritance tx spending deposit {} has been valid since height {}, the heir can broadcast it now",
+            record.deposit_txid, record.lock_time
+        );
+    } else if record.lock_time - current_height <= warn_blocks {
+        println!(
+            "WARNING: inheritance tx spending deposit {} becomes valid in {} blocks, repeat the protocol to push the date forward",
+            record.deposit_txid,
+            record.lock_time - current_height
+        );
+    } else {
+        println!(
+            "Inheritance tx spending deposit {} becomes valid in {} blocks",
+            record.deposit_txid,
+            record.lock_time - current_height
+        );
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bip39::Mnemonic;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource, Xpriv};
+use bitcoin::hashes::{Hash, hash160};
+use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey, Signing, Verification};
+use bitcoin::{Network, NetworkKind, OutPoint, PrivateKey, Psbt, TxOut, XOnlyPublicKey};
+use zeroize::Zeroizing;
+
+use crate::erase::Erasing;
+use crate::keystore::{self, Secret};
+use crate::{KeyArgs, external_signer, gen_keypair, hwi, sign_deposit};
+
+/// Service name our keys are stored under in the OS keychain.
+const KEYCHAIN_SERVICE: &str = "ephemeral-sign";
+
+const KEYCHAIN_PREFIX: &str = "keychain:";
+
+const KEYSTORE_PREFIX: &str = "keystore:";
+
+const MNEMONIC: &str = "mnemonic";
+
+const MNEMONIC_PREFIX: &str = "mnemonic:";
+
+/// Environment variable the private key is taken from when not given on the command line.
+const PRIV_KEY_ENV: &str = "EPHEMERAL_SIGN_PRIV_KEY";
+
+/// Options for the key the deposit input is locked to.
+#[derive(Debug, clap::Args)]
+pub struct DepositKeyArgs {
+    /// Sign the message using the given private key, hex or WIF encoded, as keychain:<label> to
+    /// load it from the OS keychain, as keystore:<label> to unlock it from the keystore, or as
+    /// mnemonic[:<words>] to derive it from a BIP-39 mnemonic. Pass "-" to read it from stdin, or
+    /// "new" to generate one at random. Without --priv-key, --pub-key or --hwi-path the key is
+    /// taken from the EPHEMERAL_SIGN_PRIV_KEY environment variable. Leave this blank if verifying
+    /// a receipt.
+    #[arg(long)]
+    pub priv_key: Option<String>,
+
+    #[command(flatten)]
+    pub key: KeyArgs,
+
+    /// X-only public key (hex) of the deposit input, to prepare a session on an online machine
+    /// without the private key, or to sign with --signer-cmd.
+    #[arg(long, conflicts_with = "priv_key")]
+    pub pub_key: Option<String>,
+
+    /// Sign the deposit by piping its PSBT (base64) to this program, which writes the signed
+    /// PSBT to its output. Only the signature of our input may be added.
+    #[arg(long, requires = "pub_key", conflicts_with = "session_out")]
+    pub signer_cmd: Option<PathBuf>,
+
+    /// Sign with the key at this derivation path on a hardware wallet connected through HWI, e.g.
+    /// m/86'/1'/0'/0/0, instead of a private key.
+    #[arg(long, conflicts_with_all = ["priv_key", "pub_key"])]
+    pub hwi_path: Option<DerivationPath>,
+
+    /// Fingerprint of the hardware wallet to use, if more than one is connected.
+    #[arg(long, requires = "hwi_path")]
+    pub hwi_fingerprint: Option<Fingerprint>,
+
+    /// Fingerprint of the master key --pub-key is derived from. Air-gapped signers like Coldcard
+    /// find their key in the PSBT by its origin.
+    #[arg(long, requires_all = ["pub_key", "key_path"])]
+    pub key_fingerprint: Option<Fingerprint>,
+
+    /// Derivation path of --pub-key from its master key, e.g. m/86'/1'/0'/0/0.
+    #[arg(long, requires = "key_fingerprint")]
+    pub key_path: Option<DerivationPath>,
+}
+
+/// The key the deposit input is locked to, and what signs our input of the deposit with it.
+pub struct DepositKey {
+    pub internal_key: XOnlyPublicKey,
+    /// Origin of the key, which signers use to find the key to sign with.
+    pub key_source: Option<KeySource>,
+    /// The private key, unless the deposit is signed on a hardware wallet, by an external signer
+    /// or offline.
+    pub keypair: Option<Erasing<Keypair>>,
+    pub device: Option<hwi::Device>,
+    signer_cmd: Option<PathBuf>,
+    /// Whether the key was generated with --priv-key new, only to be printed.
+    pub generated: bool,
+}
+
+impl DepositKey {
+    /// Loads the key of `args`: generates a new keypair, parses the given private key, opens the
+    /// hardware wallet or takes the public key. Returns none if no key is given.
+    pub fn load<C: Signing>(
+        secp: &Secp256k1<C>,
+        args: &DepositKeyArgs,
+        network: Network,
+    ) -> Option<Self> {
+        let priv_key = match (&args.pub_key, &args.hwi_path) {
+            (None, None) => priv_key_arg(args.priv_key.as_deref()),
+            _ => None,
+        };
+        let generated = priv_key.as_deref() == Some("new");
+        let (keypair, origin) = match priv_key.as_deref() {
+            None => (None, None),
+            Some("new") => (Some(Erasing::new(gen_keypair(secp))), None),
+            Some(priv_str) => {
+                let (sk, origin) =
+                    parse_priv_key(priv_str, &args.key, network).expect("valid private key");
+                let keypair = Erasing::new(Keypair::from_secret_key(secp, &sk));
+                (Some(keypair), origin)
+            }
+        };
+
+        let device = args.hwi_path.as_ref().map(|path| {
+            hwi::Device::open(args.hwi_fingerprint, path.clone(), network)
+                .expect("able to open hardware wallet")
+        });
+
+        // Only print a key we generated, a key that was passed in is already known to the user.
+        let internal_key = match (&keypair, &device, &args.pub_key) {
+            (Some(keypair), _, _) => {
+                if generated {
+                    println!("priv: {}", hex::encode(keypair.secret_key().secret_bytes()));
+                    println!(
+                        "wif: {}",
+                        PrivateKey::new(keypair.secret_key(), network).to_wif()
+                    );
+                }
+                keypair.x_only_public_key().0
+            }
+            (None, Some(device), _) => device.internal_key(),
+            (None, None, Some(pub_key)) => {
+                XOnlyPublicKey::from_str(pub_key).expect("valid public key")
+            }
+            (None, None, None) => return None,
+        };
+
+        let key_source = match (&keypair, &device, args.key_fingerprint) {
+            (Some(keypair), _, _) => Some(origin.unwrap_or_else(|| raw_key_source(keypair))),
+            (None, Some(device), _) => Some(device.key_source()),
+            (None, None, Some(fingerprint)) => Some((fingerprint, args.key_path.clone().unwrap())),
+            (None, None, None) => None,
+        };
+
+        Some(DepositKey {
+            internal_key,
+            key_source,
+            keypair,
+            device,
+            signer_cmd: args.signer_cmd.clone(),
+            generated,
+        })
+    }
+
+    /// Signs our input of `deposit_psbt`, spending `deposit_prevout` at `prevout`, with the
+    /// private key, on the hardware wallet or with the external signer.
+    pub fn sign<C: Signing + Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        network: Network,
+        deposit_psbt: &mut Psbt,
+        prevout: OutPoint,
+        deposit_prevout: &TxOut,
+    ) {
+        match (&self.keypair, &self.device) {
+            (_, Some(device)) => device
+                .sign_deposit(deposit_psbt, prevout, deposit_prevout)
+                .expect("hardware wallet signature"),
+            (Some(keypair), None) => sign_deposit(
+                secp,
+                keypair,
+                self.key_source.clone().unwrap(),
+                network,
+                deposit_psbt,
+                prevout,
+                deposit_prevout,
+            ),
+            (None, None) => external_signer::sign_deposit(
+                self.signer_cmd.as_ref().expect("priv key needed"),
+                self.internal_key,
+                self.key_source.clone(),
+                deposit_psbt,
+                prevout,
+                deposit_prevout,
+            )
+            .expect("external signer signature"),
+        }
+    }
+}
+
+/// The origin of a key used directly rather than derived, which makes it its own master key.
+pub fn raw_key_source(keypair: &Keypair) -> KeySource {
+    let hash = hash160::Hash::hash(&keypair.public_key().serialize());
+    let fingerprint: [u8; 4] = hash.to_byte_array()[..4].try_into().unwrap();
+    (Fingerprint::from(fingerprint), DerivationPath::master())
+}
+
+/// Resolves the private key argument, so it does not have to appear in the process arguments.
+/// A key given with --priv-key takes precedence, where `-` reads it from the first line of stdin.
+/// Otherwise the key is taken from the EPHEMERAL_SIGN_PRIV_KEY environment variable, if set.
+pub fn priv_key_arg(arg: Option<&str>) -> Option<Zeroizing<String>> {
+    match arg {
+        Some("-") => {
+            let mut line = Zeroizing::new(String::new());
+            std::io::stdin()
+                .read_line(&mut line)
+                .expect("private key on stdin");
+            Some(Zeroizing::new(line.trim().to_string()))
+        }
+        Some(arg) => Some(Zeroizing::new(arg.to_string())),
+        None => std::env::var(PRIV_KEY_ENV).ok().map(Zeroizing::new),
+    }
+}
+
+/// Parses a private key given on the command line. It can be hex or WIF encoded, an xpriv,
+/// `keychain:<label>` to load it from the OS keychain, `keystore:<label>` to unlock it from the
+/// keystore, or `mnemonic[:<words>]` to use a BIP-39 mnemonic, asked for if not given.
+///
+/// A key is derived from an xpriv or mnemonic at --derivation-path, or otherwise at the receive
+/// path last handed out by `keygen`, and returned with its origin.
+pub fn parse_priv_key(
+    s: &str,
+    args: &KeyArgs,
+    network: Network,
+) -> Result<(Erasing<SecretKey>, Option<KeySource>), Box<dyn std::error::Error>> {
+    match parse_secret(s, args, network)? {
+        Secret::Key(sk) => Ok((sk, None)),
+        Secret::Master(master) => {
+            let path = match &args.derivation_path {
+                Some(path) => path.clone(),
+                None => {
+                    let fingerprint = master.fingerprint(&Secp256k1::new());
+                    let next = KeyIndex::load(args).next(fingerprint);
+                    receive_path(network, next.saturating_sub(1))
+                }
+            };
+            let (sk, key_source) = derive(&master, &path)?;
+            println!("Using key at {} of {}", path, key_source.0);
+            Ok((sk, Some(key_source)))
+        }
+    }
+}
+
+/// Parses a key given on the command line like [`parse_priv_key`], but requires an extended key
+/// to derive from.
+pub fn parse_master_key(
+    s: &str,
+    args: &KeyArgs,
+    network: Network,
+) -> Result<Erasing<Xpriv>, Box<dyn std::error::Error>> {
+    match parse_secret(s, args, network)? {
+        Secret::Master(master) => Ok(master),
+        Secret::Key(_) => Err("need an xpriv or mnemonic to derive keys from".into()),
+    }
+}
+
+fn parse_secret(
+    s: &str,
+    args: &KeyArgs,
+    network: Network,
+) -> Result<Secret, Box<dyn std::error::Error>> {
+    if let Some(label) = s.strip_prefix(KEYCHAIN_PREFIX) {
+        return Ok(Secret::Key(Erasing::new(load_from_keychain(label)?)));
+    }
+    if let Some(label) = s.strip_prefix(KEYSTORE_PREFIX) {
+        let keystore = keystore::load(&args.keystore);
+        return keystore.unlock(label, &keystore::prompt_passphrase("Keystore", false));
+    }
+    if s == MNEMONIC || s.starts_with(MNEMONIC_PREFIX) {
+        let words = match s.strip_prefix(MNEMONIC_PREFIX) {
+            Some(words) => Zeroizing::new(words.to_string()),
+            None => Zeroizing::new(rpassword::prompt_password("Mnemonic: ")?),
+        };
+        let mnemonic = Mnemonic::parse(words.trim())?;
+        let master = master_from_mnemonic(&mnemonic, args, network)?;
+        return Ok(Secret::Master(master));
+    }
+    if let Ok(xpriv) = Xpriv::from_str(s) {
+        return Ok(Secret::Master(Erasing::new(xpriv)));
+    }
+    if let Ok(mut wif) = PrivateKey::from_wif(s) {
+        let sk = Erasing::new(wif.inner);
+        wif.inner.non_secure_erase();
+        if wif.network != NetworkKind::from(network) {
+            return Err("WIF private key for wrong network".into());
+        }
+        return Ok(Secret::Key(sk));
+    }
+
+    Ok(Secret::Key(Erasing::new(SecretKey::from_str(s)?)))
+}
+
+/// The master key of `mnemonic`, asking for its BIP-39 passphrase if requested.
+pub fn master_from_mnemonic(
+    mnemonic: &Mnemonic,
+    args: &KeyArgs,
+    network: Network,
+) -> Result<Erasing<Xpriv>, Box<dyn std::error::Error>> {
+    let passphrase = match args.bip39_passphrase {
+        true => Zeroizing::new(rpassword::prompt_password("BIP-39 passphrase: ")?),
+        false => Zeroizing::new(String::new()),
+    };
+    let seed = Zeroizing::new(mnemonic.to_seed(passphrase.as_str()));
+    Ok(Erasing::new(Xpriv::new_master(network, &seed[..])?))
+}
+
+/// Derives a fresh key from `master` for a new deposit, at --derivation-path if given and
+/// otherwise at the next unused BIP-86 receive path, which is then marked as used.
+pub fn allocate_key(
+    master: &Xpriv,
+    args: &KeyArgs,
+    network: Network,
+) -> Result<(Erasing<SecretKey>, KeySource), Box<dyn std::error::Error>> {
+    if let Some(path) = &args.derivation_path {
+        return derive(master, path);
+    }
+
+    let fingerprint = master.fingerprint(&Secp256k1::new());
+    let mut index = KeyIndex::load(args);
+    let next = index.next(fingerprint);
+    let key = derive(master, &receive_path(network, next))?;
+    index.next.insert(fingerprint.to_string(), next + 1);
+    index.store(args);
+    Ok(key)
+}
+
+/// Derives the key at `path` from `master`, returning it along with its origin.
+pub fn derive(
+    master: &Xpriv,
+    path: &DerivationPath,
+) -> Result<(Erasing<SecretKey>, KeySource), Box<dyn std::error::Error>> {
+    let secp = Secp256k1::new();
+    let xpriv = Erasing::new(master.derive_xpriv(&secp, path)?);
+    let sk = Erasing::new(xpriv.private_key);
+    Ok((sk, (master.fingerprint(&secp), path.clone())))
+}
+
+/// The BIP-86 path of receive address `index` of the first account.
+fn receive_path(network: Network, index: u32) -> DerivationPath {
+    let coin = match network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+    let path = DerivationPath::from_str(&format!("m/86'/{}'/0'/0", coin)).unwrap();
+    path.child(ChildNumber::from_normal_idx(index).expect("valid child index"))
+}
+
+/// The next unused receive index of each master key, so each deposit gets a fresh key.
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+struct KeyIndex {
+    next: BTreeMap<String, u32>,
+}
+
+impl KeyIndex {
+    fn load(args: &KeyArgs) -> Self {
+        match fs::read_to_string(&args.key_index_file) {
+            Ok(data) => serde_json::from_str(&data).expect("valid key index"),
+            Err(_) => KeyIndex::default(),
+        }
+    }
+
+    fn store(&self, args: &KeyArgs) {
+        let data = serde_json::to_string_pretty(self).unwrap();
+        fs::write(&args.key_index_file, data).expect("able to write key index");
+    }
+
+    fn next(&self, fingerprint: Fingerprint) -> u32 {
+        self.next
+            .get(&fingerprint.to_string())
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Stores `sk` in the OS keychain under `label`, refusing to overwrite an existing key.
+pub fn store_in_keychain(label: &str, sk: &SecretKey) -> Result<(), Box<dyn std::error::Error>> {
+    let entry = keyring::Entry::new(KEYCHAIN_SERVICE, label)?;
+    match entry.get_password() {
+        Ok(_) => return Err(format!("key '{}' already in keychain", label).into()),
+        Err(keyring::Error::NoEntry) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    entry.set_password(&Zeroizing::new(hex::encode(sk.secret_bytes())))?;
+    Ok(())
+}
+
+fn load_from_keychain(label: &str) -> Result<SecretKey, Box<dyn std::error::Error>> {
+    let entry = keyring::Entry::new(KEYCHAIN_SERVICE, label)?;
+    let sk = Zeroizing::new(entry.get_password()?);
+    Ok(SecretKey::from_str(&sk)?)
+}
@@ -0,0 +1,169 @@
+use std::fs;
+use std::path::Path;
+
+use argon2::Argon2;
+use bitcoin::XOnlyPublicKey;
+use bitcoin::bip32::Xpriv;
+use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey};
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{Key, KeyInit, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::erase::Erasing;
+
+/// On-disk keystore of deposit keys, each encrypted with a key derived from a passphrase using
+/// Argon2id, and XChaCha20-Poly1305.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Keystore {
+    pub keys: Vec<StoredKey>,
+}
+
+/// A secret kept in the keystore.
+pub enum Secret {
+    Key(Erasing<SecretKey>),
+    /// An extended key to derive deposit keys from.
+    Master(Erasing<Xpriv>),
+}
+
+impl Secret {
+    fn pubkey(&self) -> XOnlyPublicKey {
+        let secp = Secp256k1::new();
+        let keypair = match self {
+            Secret::Key(sk) => Erasing::new(Keypair::from_secret_key(&secp, sk)),
+            Secret::Master(xpriv) => Erasing::new(xpriv.to_keypair(&secp)),
+        };
+        keypair.x_only_public_key().0
+    }
+
+    fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        match self {
+            Secret::Key(sk) => Zeroizing::new(sk.secret_bytes().to_vec()),
+            Secret::Master(xpriv) => Zeroizing::new(xpriv.encode().to_vec()),
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match bytes.len() {
+            32 => Ok(Secret::Key(Erasing::new(SecretKey::from_slice(bytes)?))),
+            _ => Ok(Secret::Master(Erasing::new(Xpriv::decode(bytes)?))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoredKey {
+    pub label: String,
+    /// The public key is kept in the clear, so keys can be listed without the passphrase. For an
+    /// extended key it is the key itself, not one derived from it.
+    pub pubkey: XOnlyPublicKey,
+    /// Whether this is an extended key.
+    #[serde(default)]
+    pub master: bool,
+    /// Hex encoded salt for the passphrase.
+    salt: String,
+    /// Hex encoded nonce of the encryption.
+    nonce: String,
+    /// Hex encoded encrypted secret key.
+    ciphertext: String,
+}
+
+pub fn load(path: &Path) -> Keystore {
+    match fs::read_to_string(path) {
+        Ok(data) => serde_json::from_str(&data).expect("valid keystore"),
+        Err(_) => Keystore::default(),
+    }
+}
+
+pub fn store(path: &Path, keystore: &Keystore) {
+    let data = serde_json::to_string_pretty(keystore).unwrap();
+    fs::write(path, data).expect("able to write keystore");
+}
+
+impl Keystore {
+    /// Encrypts `secret` with `passphrase` and adds it under `label`.
+    pub fn add(
+        &mut self,
+        label: &str,
+        secret: &Secret,
+        passphrase: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.keys.iter().any(|k| k.label == label) {
+            return Err(format!("key '{}' already in keystore", label).into());
+        }
+
+        let salt: [u8; 16] = rand::random();
+        let nonce: [u8; 24] = rand::random();
+        let cipher = cipher(passphrase, &salt)?;
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), &secret.to_bytes()[..])
+            .map_err(|_| "unable to encrypt key")?;
+
+        self.keys.push(StoredKey {
+            label: label.to_string(),
+            pubkey: secret.pubkey(),
+            master: matches!(secret, Secret::Master(_)),
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        });
+        Ok(())
+    }
+
+    /// Decrypts the key stored under `label` with `passphrase`.
+    pub fn unlock(
+        &self,
+        label: &str,
+        passphrase: &str,
+    ) -> Result<Secret, Box<dyn std::error::Error>> {
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.label == label)
+            .ok_or_else(|| format!("no key '{}' in keystore", label))?;
+
+        let cipher = cipher(passphrase, &hex::decode(&key.salt)?)?;
+        let nonce = hex::decode(&key.nonce)?;
+        if nonce.len() != 24 {
+            return Err("invalid keystore nonce".into());
+        }
+        let secret = cipher
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                &hex::decode(&key.ciphertext)?[..],
+            )
+            .map_err(|_| "wrong passphrase")?;
+        let secret = Zeroizing::new(secret);
+
+        let secret = Secret::from_bytes(&secret)?;
+        if secret.pubkey() != key.pubkey {
+            return Err("keystore key does not match its public key".into());
+        }
+        Ok(secret)
+    }
+}
+
+/// Cipher keyed with `passphrase` and `salt`, shared with the recovery kit.
+pub fn cipher(
+    passphrase: &str,
+    salt: &[u8],
+) -> Result<XChaCha20Poly1305, Box<dyn std::error::Error>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key[..])
+        .map_err(|e| e.to_string())?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key[..])))
+}
+
+/// Asks for the passphrase of `what`, e.g. the keystore, on the terminal.
+pub fn prompt_passphrase(what: &str, confirm: bool) -> Zeroizing<String> {
+    let passphrase = Zeroizing::new(
+        rpassword::prompt_password(format!("{} passphrase: ", what)).expect("passphrase"),
+    );
+    if confirm {
+        let again =
+            Zeroizing::new(rpassword::prompt_password("Repeat passphrase: ").expect("passphrase"));
+        assert!(passphrase == again, "passphrases do not match");
+    }
+    passphrase
+}
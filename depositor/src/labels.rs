@@ -0,0 +1,151 @@
+use std::str::FromStr;
+
+use bitcoin::{Address, Network, Script, Transaction};
+use serde::Serialize;
+
+use crate::session::Session;
+
+/// A BIP-329 wallet label.
+#[derive(Serialize, Debug)]
+pub struct Label {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub label: String,
+    /// Only set for outputs we can spend on our own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+impl Label {
+    fn new(kind: &'static str, reference: String, label: String) -> Self {
+        Label {
+            kind,
+            reference,
+            label,
+            spendable: None,
+        }
+    }
+}
+
+/// Labels for the transactions, outputs and addresses of the deposit in `session`: the coin
+/// funding it, the deposit output and address, our change, and the presigned spend to the
+/// fallback address. Outputs of a payjoin receiver are left unlabeled.
+pub fn for_session(session: &Session) -> Vec<Label> {
+    let deposit_tx = &session.resp.deposit_psbt.unsigned_tx;
+    let deposit_txid = session.id();
+    let presigned_tx: Transaction = session
+        .resp
+        .spend_psbt
+        .clone()
+        .extract_tx()
+        .expect("valid tx");
+    let presigned_txid = presigned_tx.compute_txid();
+    let change_script = session.change_addr.as_ref().map(|addr| {
+        Address::from_str(addr)
+            .expect("valid change address")
+            .require_network(session.network)
+            .expect("change address for network")
+            .script_pubkey()
+    });
+
+    let mut labels = vec![
+        Label::new(
+            "tx",
+            deposit_txid.to_string(),
+            format!("Ephemeral deposit, presigned spend {}", presigned_txid),
+        ),
+        Label::new(
+            "output",
+            session.prevout.to_string(),
+            format!("Spent in ephemeral deposit {}", deposit_txid),
+        ),
+        Label::new(
+            "input",
+            format!("{}:{}", deposit_txid, input_index(deposit_tx, session)),
+            format!("Funding of ephemeral deposit {}", deposit_txid),
+        ),
+        Label::new(
+            "output",
+            format!("{}:0", deposit_txid),
+            format!(
+                "Ephemeral deposit output, presigned spend {}",
+                presigned_txid
+            ),
+        ),
+    ];
+    if let Some(addr) = address(&deposit_tx.output[0].script_pubkey, session.network) {
+        labels.push(Label::new(
+            "addr",
+            addr,
+            format!("Ephemeral deposit {}", deposit_txid),
+        ));
+    }
+
+    for (i, output) in deposit_tx.output.iter().enumerate().skip(1) {
+        if Some(&output.script_pubkey) != change_script.as_ref() {
+            continue;
+        }
+        labels.push(Label {
+            spendable: Some(true),
+            ..Label::new(
+                "output",
+                format!("{}:{}", deposit_txid, i),
+                format!("Change of ephemeral deposit {}", deposit_txid),
+            )
+        });
+        if let Some(addr) = address(&output.script_pubkey, session.network) {
+            labels.push(Label::new(
+                "addr",
+                addr,
+                format!("Change of ephemeral deposit {}", deposit_txid),
+            ));
+        }
+    }
+
+    labels.push(Label::new(
+        "tx",
+        presigned_txid.to_string(),
+        format!("Presigned spend of ephemeral deposit {}", deposit_txid),
+    ));
+    for (i, output) in presigned_tx.output.iter().enumerate() {
+        labels.push(Label::new(
+            "output",
+            format!("{}:{}", presigned_txid, i),
+            format!("Fallback of ephemeral deposit {}", deposit_txid),
+        ));
+        if let Some(addr) = address(&output.script_pubkey, session.network) {
+            labels.push(Label::new(
+                "addr",
+                addr,
+                format!("Fallback of ephemeral deposit {}", deposit_txid),
+            ));
+        }
+    }
+
+    labels
+}
+
+/// Index of our input in the deposit.
+fn input_index(deposit_tx: &Transaction, session: &Session) -> usize {
+    deposit_tx
+        .input
+        .iter()
+        .position(|txin| txin.previous_output == session.prevout)
+        .expect("deposit spends our prevout")
+}
+
+fn address(script: &Script, network: Network) -> Option<String> {
+    Address::from_script(script, network)
+        .ok()
+        .map(|addr| addr.to_string())
+}
+
+/// Exports `labels` as BIP-329 JSON Lines, one label per line.
+pub fn to_jsonl(labels: &[Label]) -> String {
+    labels
+        .iter()
+        .map(|label| serde_json::to_string(label).unwrap() + "\n")
+        .collect()
+}
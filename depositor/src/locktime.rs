@@ -0,0 +1,113 @@
+use bitcoin::{Transaction, absolute, transaction};
+
+/// nLockTime values below this are block heights, others Unix timestamps.
+const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// Sequence of an input that disables nLockTime, if all inputs have it.
+const SEQUENCE_FINAL: u32 = 0xffffffff;
+
+/// BIP-68 flag disabling the relative locktime of an input.
+const SEQUENCE_DISABLE_FLAG: u32 = 1 << 31;
+
+/// BIP-68 flag making a relative locktime time based, in units of 512 seconds.
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+
+const SEQUENCE_MASK: u32 = 0xffff;
+
+/// Locktime of a new transaction discouraging fee sniping, as Bitcoin Core sets it: the height
+/// of the tip, or once in ten times a random height up to 100 blocks before it, so transactions
+/// broadcast late do not stand out.
+pub fn anti_fee_sniping(tip: u32) -> absolute::LockTime {
+    let height = match rand::random::<u32>() % 10 {
+        0 => tip.saturating_sub(rand::random::<u32>() % 100),
+        _ => tip,
+    };
+    absolute::LockTime::from_height(height).expect("tip is a block height")
+}
+
+/// The chain as seen by a backend, enough to tell whether a transaction is final.
+pub struct ChainState {
+    pub tip: u32,
+    /// Median time past of the tip.
+    pub median_time: u32,
+}
+
+/// When a transaction can enter the mempool.
+#[derive(Debug, PartialEq)]
+pub enum Finality {
+    /// It can be broadcast now.
+    Final,
+    /// Once the tip reaches this height.
+    Height(u32),
+    /// Once the median time past of the tip is past this timestamp.
+    Time(u32),
+    /// Once the output it spends confirms, as it has a relative locktime.
+    InputUnconfirmed,
+}
+
+/// Whether `tx` is final on top of `chain`, given the confirmation height of the output it
+/// spends if known. Only single input transactions, like the presigned spends, are supported.
+/// Time based relative locktimes are approximated as ten minute blocks.
+pub fn finality(tx: &Transaction, chain: &ChainState, input_height: Option<u32>) -> Finality {
+    let lock_time = tx.lock_time.to_consensus_u32();
+    let all_final = tx
+        .input
+        .iter()
+        .all(|txin| txin.sequence.to_consensus_u32() == SEQUENCE_FINAL);
+    if lock_time != 0 && !all_final {
+        // A transaction is final in the next block if its locktime is below that block's height,
+        // or below the median time past of the tip by BIP-113.
+        if lock_time < LOCK_TIME_THRESHOLD && chain.tip < lock_time {
+            return Finality::Height(lock_time);
+        }
+        if lock_time >= LOCK_TIME_THRESHOLD && chain.median_time <= lock_time {
+            return Finality::Time(lock_time);
+        }
+    }
+
+    let sequence = match tx.input.first() {
+        Some(txin) => txin.sequence.to_consensus_u32(),
+        None => return Finality::Final,
+    };
+    if tx.version < transaction::Version::TWO || sequence & SEQUENCE_DISABLE_FLAG != 0 {
+        return Finality::Final;
+    }
+    let value = sequence & SEQUENCE_MASK;
+    if value == 0 {
+        return Finality::Final;
+    }
+    let Some(input_height) = input_height else {
+        return Finality::InputUnconfirmed;
+    };
+
+    // A relative locktime of n blocks lets the input be spent in the block n after it confirmed.
+    let blocks = match sequence & SEQUENCE_TYPE_FLAG {
+        0 => value,
+        _ => (value * 512).div_ceil(600),
+    };
+    let height = input_height + blocks - 1;
+    match chain.tip < height {
+        true => Finality::Height(height),
+        false => Finality::Final,
+    }
+}
+
+/// Describes when a transaction with `finality` becomes valid.
+pub fn describe(finality: &Finality, chain: &ChainState) -> String {
+    match finality {
+        Finality::Final => "valid now".to_string(),
+        Finality::Height(height) => format!(
+            "valid from height {}, {} blocks from now",
+            height,
+            height - chain.tip
+        ),
+        Finality::Time(time) => format!(
+            "valid once the median time past exceeds {}, {} seconds from now",
+            time,
+            time - chain.median_time
+        ),
+        Finality::InputUnconfirmed => {
+            "valid only some time after the output it spends confirms".to_string()
+        }
+    }
+}
@@ -1,22 +1,79 @@
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bitcoin::address::script_pubkey::ScriptBufExt;
 use bitcoin::witness::WitnessExt;
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
 
-use bitcoin::bip32::KeySource;
+use bitcoin::bip32::{DerivationPath, KeySource, Xpriv};
 use bitcoin::consensus_validation::TransactionExt;
+use bitcoin::hashes::Hash;
 use bitcoin::locktime::absolute;
 use bitcoin::psbt::Input;
-use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey, Signing};
+use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey, Signing, Verification};
+use bitcoin::sighash::SighashCache;
+use bitcoin::taproot::{self, LeafVersion, TapLeafHash, TaprootSpendInfo};
 use bitcoin::{
-    Address, Amount, Network, OutPoint, PrivateKey, Psbt, ScriptBuf, Sequence, TapSighashType,
-    Transaction, TxIn, TxOut, Witness, consensus, transaction,
+    Address, Amount, Denomination, Network, OutPoint, PrivateKey, Psbt, ScriptBuf, Sequence,
+    TapSighashType, Transaction, TxIn, TxOut, Txid, Witness, XOnlyPublicKey, consensus,
+    transaction,
 };
-use shared::{SignPsbtReq, SignPsbtResp};
+use musig2::secp::{MaybePoint, Point};
+use musig2::{AdaptorSignature, KeyAggContext};
+use shared::bip322::{self, FundingProof, SignedMessage};
+use shared::encoding;
+use shared::receipt::Receipt;
+use shared::render;
+use shared::script::{checksig_script, deposit_spend_info, op_return_script, unvault_script};
+use shared::silent_payment::{self, SilentPaymentAddress};
+use shared::{
+    Cet, DecayingMultisig, DepositDescriptor, ExpiryPath, FallbackShare, Feature, HealthResp,
+    InheritanceParams, OracleEvent, OracleOutcome, PROTOCOL_VERSION, PROTOCOL_VERSIONS, PolicyRule,
+    Quote, QuotedRate, RecoveryPath, SignPsbtError, SignPsbtReq, SignPsbtResp, SpendOutput,
+    SpendSighash, SpendTimelock, VaultParams, anchor_output, attestation_point, negotiate_version,
+    script_paths, split_fallback,
+};
+use zeroize::Zeroizing;
+
+mod adaptor;
+mod alerts;
+mod amounts;
+mod batch;
+mod bcur;
+mod blind;
+mod broadcast;
+mod chain;
+mod enclave;
+mod erase;
+mod external_signer;
+mod funding;
+mod history;
+mod http;
+mod hwi;
+mod inheritance;
+mod keys;
+mod keystore;
+mod labels;
+mod locktime;
+mod manifest;
+mod paper;
+mod payjoin;
+mod qr;
+mod recovery;
+mod rollover;
+mod session;
+mod signed;
+mod summary;
+mod watch;
+mod wizard;
+
+use erase::Erasing;
+use inheritance::InheritanceRecord;
+use session::Session;
 
 fn parse_address(addr: &str, network: Network) -> Address {
     Address::from_str(addr)
@@ -30,83 +87,2516 @@ fn gen_keypair<C: Signing>(secp: &Secp256k1<C>) -> Keypair {
     Keypair::from_secret_key(secp, &sk)
 }
 
+/// The outputs the inputs of `psbt` spend, as far as it records them.
+fn prevouts(psbt: &Psbt) -> Vec<Option<TxOut>> {
+    psbt.inputs.iter().map(|i| i.witness_utxo.clone()).collect()
+}
+
+/// BIP-21 URI paying `amount` to `addr`.
+fn bip21_uri(addr: &Address, amount: Option<Amount>, label: Option<&str>) -> String {
+    let mut params = vec![];
+    if let Some(amount) = amount {
+        params.push(format!(
+            "amount={}",
+            amount.display_in(Denomination::Bitcoin)
+        ));
+    }
+    if let Some(label) = label {
+        params.push(format!("label={}", percent_encode(label)));
+    }
+
+    match params.is_empty() {
+        true => format!("bitcoin:{}", addr),
+        false => format!("bitcoin:{}?{}", addr, params.join("&")),
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
 #[derive(Debug, Parser)]
-#[command(verbatim_doc_comment)]
-struct Args {
+#[command(
+    verbatim_doc_comment,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Option<Args>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Generate a new deposit key, and print its address and a BIP-21 URI to fund it.
+    Keygen(KeygenArgs),
+
+    /// Decode a BC-UR encoded PSBT or transaction, read from stdin one part per line.
+    UrDecode,
+
+    /// List the keys in the keystore.
+    ListKeys(ListKeysArgs),
+
+    /// Import a hex or WIF private key, xpriv or mnemonic, asked for on the terminal, into the
+    /// keystore.
+    ImportKey(ImportKeyArgs),
+
+    /// Offline step: verify a session written with --session-out and sign its deposit.
+    Sign(SignArgs),
+
+    /// Online step: finalize the deposit signed offline, and check the presigned spend against it.
+    Finalize(FinalizeArgs),
+
+    /// Continue an interrupted deposit from its saved session, signing the deposit with a private
+    /// key if that had not happened yet.
+    Resume(ResumeArgs),
+
+    /// Cancel a session whose deposit is not yet confirmed, by double spending its input back to
+    /// us at a higher feerate.
+    Cancel(CancelArgs),
+
+    /// Move the deposit of a completed session through its recovery path into a new deposit with
+    /// a fresh presigned spend, or back to us, before the session expires.
+    Rollover(RolloverArgs),
+
+    /// Sweep the fallback output, once the presigned transaction paying it confirms, to another
+    /// address. The fallback address must be a key spend of our key.
+    Sweep(SweepArgs),
+
+    /// Complete the adaptor signed presigned spend, or the CET for an outcome, with the adaptor
+    /// secret and print the transaction.
+    CompleteAdaptor(adaptor::CompleteArgs),
+
+    /// List the deposits in the history database.
+    List(ListArgs),
+
+    /// Show everything recorded about a deposit, including the raw presigned spend.
+    Show(ShowArgs),
+
+    /// Export all deposits in the history database for bookkeeping.
+    Export(ExportArgs),
+
+    /// Export BIP-329 wallet labels for the saved deposits, to import alongside their
+    /// descriptors into wallets like Sparrow or Bitcoin Core.
+    ExportLabels(ExportLabelsArgs),
+
+    /// Write a recovery kit for a saved deposit: a single versioned and checksummed file with
+    /// its presigned spend, descriptor and instructions, optionally encrypted with a passphrase.
+    RecoveryKit(RecoveryKitArgs),
+
+    /// Write a printable text backup of a saved deposit's presigned spend, as lines of hex and QR
+    /// codes with instructions, to survive the loss of every machine.
+    PaperBackup(PaperBackupArgs),
+
+    /// Check a recovery kit and print its contents, asking for its passphrase if encrypted.
+    OpenRecoveryKit(OpenRecoveryKitArgs),
+
+    /// Recover a deposit from its recovery kit: check the deposit on chain, validate the
+    /// presigned spend against it and broadcast it.
+    Recover(RecoverArgs),
+
+    /// Watch saved deposits until their outputs are spent, reporting confirmations and alerting
+    /// if one is spent by anything but its presigned transactions, on stdout and optionally by
+    /// webhook or email.
+    Watch(WatchArgs),
+
+    /// Broadcast a signed deposit, and optionally its presigned spend after it, waiting for the
+    /// backend to accept each and reporting why if it rejects them. A presigned spend with a
+    /// locktime is broadcast once it becomes valid.
+    Broadcast(BroadcastArgs),
+
+    /// Mark a deposit as broadcast in the history database.
+    MarkBroadcast(MarkBroadcastArgs),
+
+    /// Verify the receipt the signer signed for a deposit, from its session file or a file with
+    /// just the receipt.
+    VerifyReceipt(VerifyReceiptArgs),
+
+    /// Run the deposits of a JSON or CSV manifest without asking for confirmation, several
+    /// sessions at once, and write a summary of how each went as JSON.
+    Batch(manifest::BatchArgs),
+
+    /// Walk through a deposit in a terminal UI, from picking the UTXO to following the broadcast
+    /// of the deposit.
+    Wizard(wizard::WizardArgs),
+
+    /// Print a completion script for a shell, to source from its startup file, e.g.
+    /// `source <(depositor completions bash)`.
+    Completions(CompletionsArgs),
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// `SpendSighash` on the command line.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum SighashArg {
+    Default,
+    All,
+    AllAnyoneCanPay,
+}
+
+impl From<SighashArg> for SpendSighash {
+    fn from(arg: SighashArg) -> Self {
+        match arg {
+            SighashArg::Default => SpendSighash::Default,
+            SighashArg::All => SpendSighash::All,
+            SighashArg::AllAnyoneCanPay => SpendSighash::AllAnyoneCanPay,
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct SignArgs {
+    /// Session file written by the online machine.
     #[arg(long)]
-    prevout: OutPoint,
+    session: PathBuf,
+
+    #[command(flatten)]
+    sign: SessionSignArgs,
 
+    /// Write the signed deposit PSBT to this file.
     #[arg(long)]
-    prev_amt: Amount,
+    out: PathBuf,
 
+    /// Also show the signed deposit PSBT as an animated BC-UR QR code.
     #[arg(long)]
-    fallback_addr: String,
+    ur: bool,
+}
 
+/// Options for signing the deposit of a session with a private key.
+#[derive(Debug, clap::Args)]
+struct SessionSignArgs {
+    /// Private key of the deposit input, hex or WIF encoded, or as keychain:<label>,
+    /// keystore:<label> or mnemonic[:<words>]. Pass "-" to read it from stdin. Defaults to the
+    /// EPHEMERAL_SIGN_PRIV_KEY environment variable.
     #[arg(long)]
-    output_amt: Amount,
+    priv_key: Option<String>,
+
+    #[command(flatten)]
+    key: KeyArgs,
 
+    #[command(flatten)]
+    fee_limits: FeeLimits,
+
+    #[command(flatten)]
+    enclave: enclave::EnclaveArgs,
+
+    /// Sign without asking for confirmation of the deposit summary.
     #[arg(long)]
-    change_addr: Option<String>,
+    yes: bool,
+
+    /// File keeping track of the prevouts we signed deposits for.
+    #[arg(long, default_value = "signed_deposits.json")]
+    signed_file: PathBuf,
 
+    /// Sign even if a conflicting deposit spending the same prevout was signed before.
     #[arg(long)]
-    change_amt: Option<Amount>,
+    force: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct CancelArgs {
+    /// ID of the session to cancel, the txid of its deposit.
+    id: Txid,
+
+    /// Address to send the deposit input back to.
+    #[arg(long)]
+    addr: String,
+
+    /// Feerate of the replacement in sat/vB. It pays at least the fee of the deposit plus
+    /// 1 sat/vB, as BIP-125 requires.
+    #[arg(long)]
+    feerate: u64,
+
+    /// Private key of the deposit input, as for signing the deposit.
+    #[arg(long)]
+    priv_key: Option<String>,
+
+    #[command(flatten)]
+    key: KeyArgs,
+
+    /// Directory the sessions are saved in.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+
+    /// History database to record the cancellation in.
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+
+    #[command(flatten)]
+    backend: broadcast::BackendArgs,
+
+    /// Seconds to wait for the backend to accept the replacement.
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Cancel without asking for confirmation.
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct RolloverArgs {
+    /// ID of the session to roll over, the txid of its deposit.
+    id: Txid,
+
+    /// Send the deposit back to this address instead of into a new deposit.
+    #[arg(long, conflicts_with = "fallback_addr")]
+    to_addr: Option<String>,
 
+    /// Address the new presigned spend pays to. Defaults to the one of the rolled over session.
     #[arg(long)]
+    fallback_addr: Option<String>,
+
+    /// Feerate of the rollover in sat/vB.
+    #[arg(long)]
+    feerate: u64,
+
+    /// Leave the rollover's locktime at zero rather than near the chain tip.
+    #[arg(long)]
+    no_anti_fee_sniping: bool,
+
+    #[arg(long, required_unless_present = "to_addr")]
     client_url: Option<SocketAddr>,
 
-    /// Sign the message using the given private key. Pass "new" to generate one at random. Leave
-    /// this blank if verifying a receipt.
+    #[command(flatten)]
+    http: http::HttpArgs,
+
+    /// Maximum age in seconds of the signer's chain tip before it is considered lagging. Not
+    /// checked on regtest.
+    #[arg(long, default_value_t = 7200)]
+    max_signer_tip_age: u64,
+
+    /// Private key of the recovery path, as for signing the deposit.
+    #[arg(long)]
+    priv_key: Option<String>,
+
+    #[command(flatten)]
+    key: KeyArgs,
+
+    #[command(flatten)]
+    fee_limits: FeeLimits,
+
+    #[command(flatten)]
+    enclave: enclave::EnclaveArgs,
+
+    /// Maximum fee the rollover may pay.
+    #[arg(long, default_value = "50000 sat", value_parser = amounts::parse_amount)]
+    max_deposit_fee: Amount,
+
+    /// Block height the new deposit must be rolled over by in turn. Defaults to
+    /// --inheritance-height or --expiry-height.
+    #[arg(long)]
+    session_expiry: Option<u32>,
+
+    /// Block height of the expiry path of the new deposit, required if the rolled over one has
+    /// an expiry path.
+    #[arg(long)]
+    expiry_height: Option<u32>,
+
+    /// Block height the new inheritance transaction is valid from, required in inheritance mode
+    /// to push the date forward.
+    #[arg(long)]
+    inheritance_height: Option<u32>,
+
+    /// File keeping track of the active inheritance transaction.
+    #[arg(long, default_value = "inheritance.json")]
+    inheritance_file: PathBuf,
+
+    /// Directory the sessions are saved in.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+
+    /// History database to record the new deposit in.
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+
+    /// File keeping track of the prevouts we signed deposits for.
+    #[arg(long, default_value = "signed_deposits.json")]
+    signed_file: PathBuf,
+
+    /// Sign even if a conflicting rollover of the same deposit was signed before.
+    #[arg(long)]
+    force: bool,
+
+    #[command(flatten)]
+    backend: broadcast::BackendArgs,
+
+    /// Seconds to wait for the backend to accept the rollover.
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Sign without asking for confirmation.
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct SweepArgs {
+    /// ID of the session whose fallback output to sweep, the txid of its deposit.
+    id: Txid,
+
+    /// Address to sweep the fallback output to.
+    #[arg(long)]
+    addr: String,
+
+    /// Feerate of the sweep in sat/vB.
+    #[arg(long)]
+    feerate: u64,
+
+    /// Private key of the fallback address, as for signing the deposit.
     #[arg(long)]
     priv_key: Option<String>,
 
+    #[command(flatten)]
+    key: KeyArgs,
+
+    /// Directory the sessions are saved in.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+
+    #[command(flatten)]
+    backend: broadcast::BackendArgs,
+
+    /// Seconds to wait for the backend to accept the sweep.
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Sweep without asking for confirmation.
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct RecoveryKitArgs {
+    /// Txid of the deposit to write the recovery kit for.
+    deposit_txid: Txid,
+
+    /// Directory the sessions are saved in.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+
+    /// File to write the recovery kit to. Defaults to recovery-<deposit txid>.json.
+    #[arg(long)]
+    out: Option<PathBuf>,
+
+    /// Encrypt the recovery kit with a passphrase, asked for on the terminal.
+    #[arg(long)]
+    encrypt: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct PaperBackupArgs {
+    /// Txid of the deposit to write the paper backup for.
+    deposit_txid: Txid,
+
+    /// Directory the sessions are saved in.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+
+    /// File to write the paper backup to. Defaults to paper-backup-<deposit txid>.txt.
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+struct OpenRecoveryKitArgs {
+    /// Recovery kit written by the recovery-kit command.
+    file: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct RecoverArgs {
+    /// Recovery kit written by the recovery-kit command.
+    file: PathBuf,
+
+    #[command(flatten)]
+    chain: chain::ChainArgs,
+
+    /// Broadcast without asking for confirmation.
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct BroadcastArgs {
+    /// Hex encoded signed deposit transaction.
+    deposit_tx: String,
+
+    /// Hex encoded presigned spend, to broadcast once the deposit is accepted.
+    #[arg(long)]
+    presigned_tx: Option<String>,
+
+    /// The network to broadcast on.
+    #[arg(long, default_value_t = Network::Signet)]
+    network: Network,
+
+    #[command(flatten)]
+    backend: broadcast::BackendArgs,
+
+    /// Seconds to wait for the backend to accept each transaction.
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Seconds between polls of the backend while waiting for the locktime of the presigned
+    /// spend.
+    #[arg(long, default_value_t = 60)]
+    interval: u64,
+
+    /// History database to mark the deposit as broadcast in.
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct MarkBroadcastArgs {
+    /// Txid of the deposit to mark as broadcast.
+    deposit_txid: Txid,
+
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct VerifyReceiptArgs {
+    /// Session file, or file with just the receipt.
+    file: PathBuf,
+
+    /// Hex encoded x-only identity key of the operator the receipt must be signed with.
+    #[arg(long)]
+    operator_key: Option<String>,
+
+    #[command(flatten)]
+    enclave: enclave::EnclaveArgs,
+}
+
+#[derive(Debug, clap::Args)]
+struct ListArgs {
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct ShowArgs {
+    /// Txid of the deposit to show.
+    deposit_txid: Txid,
+
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct ExportArgs {
+    #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+    format: ExportFormat,
+
+    /// Write the export to this file instead of stdout.
+    #[arg(long)]
+    out: Option<PathBuf>,
+
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct ExportLabelsArgs {
+    /// Directory the sessions are saved in.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+
+    /// Write the labels to this file instead of stdout.
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+struct CompletionsArgs {
+    /// Shell to print the completion script for.
+    shell: clap_complete::Shell,
+}
+
+#[derive(Debug, clap::Args)]
+struct WatchArgs {
+    /// Txids of the deposits to watch. Defaults to all completed sessions.
+    deposit_txids: Vec<Txid>,
+
+    /// Directory the sessions are saved in.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+
+    #[command(flatten)]
+    chain: chain::ChainArgs,
+
+    /// Seconds between polls of the chain backend.
+    #[arg(long, default_value_t = 60)]
+    interval: u64,
+
+    #[command(flatten)]
+    triggers: watch::Triggers,
+
+    #[command(flatten)]
+    alerts: alerts::AlertArgs,
+}
+
+#[derive(Debug, clap::Args)]
+struct ResumeArgs {
+    /// ID of the session to resume, the txid of its deposit.
+    id: Txid,
+
+    /// Directory the sessions are saved in.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+
+    #[command(flatten)]
+    sign: SessionSignArgs,
+
+    /// File keeping track of the active inheritance transaction.
+    #[arg(long, default_value = "inheritance.json")]
+    inheritance_file: PathBuf,
+
+    /// History database to record the deposit in.
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+
+    /// Show the deposit PSBT, if it is left for a payjoin receiver to sign, as an animated BC-UR
+    /// QR code.
+    #[arg(long)]
+    ur: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct FinalizeArgs {
+    /// Session file written with --session-out.
+    #[arg(long)]
+    session: PathBuf,
+
+    /// File with the deposit PSBT signed offline.
+    #[arg(long)]
+    signed: PathBuf,
+
+    /// File keeping track of the active inheritance transaction.
+    #[arg(long, default_value = "inheritance.json")]
+    inheritance_file: PathBuf,
+
+    /// History database to record the deposit in.
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+
+    /// Show the deposit PSBT, if it is left for a payjoin receiver to sign, as an animated BC-UR
+    /// QR code.
+    #[arg(long)]
+    ur: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct KeygenArgs {
+    /// Network to use.
+    #[arg(long, default_value_t = Network::Signet)]
+    network: Network,
+
+    /// Amount to request in the funding URI.
+    #[arg(long, value_parser = amounts::parse_amount)]
+    amount: Option<Amount>,
+
+    /// Label to include in the funding URI.
+    #[arg(long)]
+    label: Option<String>,
+
+    /// Also render the address and URI as QR codes.
+    #[arg(long)]
+    qr: bool,
+
+    /// Store the private key in the OS keychain under this label instead of printing it. Use it
+    /// with --priv-key keychain:<label>.
+    #[arg(long)]
+    keychain: Option<String>,
+
+    /// Store the private key in the keystore under this label instead of printing it, encrypted
+    /// with a passphrase. Use it with --priv-key keystore:<label>.
+    #[arg(long, conflicts_with = "keychain")]
+    keystore_label: Option<String>,
+
+    /// Generate the key from a new BIP-39 mnemonic, printed for backup. Use it again with
+    /// --priv-key mnemonic.
+    #[arg(long)]
+    mnemonic: bool,
+
+    /// Number of words in the generated mnemonic.
+    #[arg(long, default_value_t = 12, requires = "mnemonic")]
+    words: usize,
+
+    /// Derive a fresh key for the next deposit from this xpriv, keystore:<label> or
+    /// mnemonic[:<words>], at the next unused receive path.
+    #[arg(long, conflicts_with = "mnemonic")]
+    from: Option<String>,
+
+    #[command(flatten)]
+    key: KeyArgs,
+}
+
+#[derive(Debug, clap::Args)]
+struct ImportKeyArgs {
+    /// Label to store the key under.
+    #[arg(long)]
+    label: String,
+
     /// Network to use.
     #[arg(long, default_value_t = Network::Signet)]
     network: Network,
+
+    #[command(flatten)]
+    key: KeyArgs,
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+#[derive(Debug, clap::Args)]
+struct ListKeysArgs {
+    /// Keystore file.
+    #[arg(long, default_value = "keystore.json")]
+    keystore: PathBuf,
+}
+
+/// Options for loading the key given with --priv-key.
+#[derive(Debug, clap::Args)]
+struct KeyArgs {
+    /// Keystore file for keystore:<label> keys.
+    #[arg(long, default_value = "keystore.json")]
+    keystore: PathBuf,
+
+    /// Derivation path of keys derived from an xpriv or mnemonic. Defaults to the BIP-86 receive
+    /// path last handed out by `keygen --from`, m/86'/0'/0'/0/<n> on mainnet and
+    /// m/86'/1'/0'/0/<n> otherwise.
+    #[arg(long)]
+    derivation_path: Option<DerivationPath>,
+
+    /// File keeping track of the receive paths handed out for each extended key.
+    #[arg(long, default_value = "key_index.json")]
+    key_index_file: PathBuf,
+
+    /// Ask for the BIP-39 passphrase of a mnemonic.
+    #[arg(long)]
+    bip39_passphrase: bool,
+}
+
+/// Limits on the fee of the presigned spend, which is picked by the signer.
+#[derive(Debug, clap::Args)]
+struct FeeLimits {
+    /// Maximum fee the presigned spend may pay.
+    #[arg(long, default_value = "10000 sat", value_parser = amounts::parse_amount)]
+    max_spend_fee: Amount,
+
+    /// Maximum feerate the presigned spend may pay, in sat/vB.
+    #[arg(long, default_value_t = 100)]
+    max_spend_feerate: u64,
+}
+
+#[derive(Debug, clap::Args)]
+struct Args {
+    #[command(flatten)]
+    funding: funding::FundingArgs,
+
+    #[command(flatten)]
+    spend: SpendArgs,
+
+    #[command(flatten)]
+    outputs: DepositOutputArgs,
+
+    /// Maximum fee the deposit transaction may pay.
+    #[arg(long, default_value = "50000 sat", value_parser = amounts::parse_amount)]
+    max_deposit_fee: Amount,
+
+    #[arg(long)]
+    client_url: Option<SocketAddr>,
+
+    #[command(flatten)]
+    http: http::HttpArgs,
+
+    /// Backend the deposit's locktime is set from the chain tip of.
+    #[command(flatten)]
+    backend: broadcast::BackendArgs,
+
+    /// Leave the deposit's locktime at zero rather than near the chain tip. Most wallets set it
+    /// near the tip to discourage fee sniping, so a zero locktime makes the deposit stand out.
+    #[arg(long)]
+    no_anti_fee_sniping: bool,
+
+    /// Maximum age in seconds of the signer's chain tip before it is considered lagging. Not
+    /// checked on regtest.
+    #[arg(long, default_value_t = 7200)]
+    max_signer_tip_age: u64,
+
+    /// Prove control of the key of --prevout to the signer with a BIP-322 signature by its
+    /// address, for signers that only serve allowlisted funders. Needs the private key.
+    #[arg(long)]
+    prove_funding: bool,
+
+    #[command(flatten)]
+    deposit_key: keys::DepositKeyArgs,
+
+    #[command(flatten)]
+    fee_limits: FeeLimits,
+
+    #[command(flatten)]
+    enclave: enclave::EnclaveArgs,
+
+    /// Also write the deposit PSBT, with our input ready for an air-gapped signer such as a
+    /// Coldcard, to this file in binary form. Pass the signed PSBT it writes back to `finalize`.
+    #[arg(long, requires = "session_out")]
+    psbt_out: Option<PathBuf>,
+
+    /// Prepare step: after verifying the signer's response, write the session to this file and
+    /// stop, leaving the deposit to be signed offline with the `sign` command.
+    #[arg(long)]
+    session_out: Option<PathBuf>,
+
+    /// Network to use.
+    #[arg(long, default_value_t = Network::Signet)]
+    network: Network,
+
+    /// Deposit in a blind session, in which the signer learns neither the deposit's inputs nor
+    /// its outpoint, only the fallback address, the deposit value and a hash of the outpoint.
+    /// The deposit output then has no script paths.
+    #[arg(long, conflicts_with_all = [
+        "adaptor_point", "oracle_pubkey", "recovery_key", "expiry_key", "vault_delay",
+        "rollover", "inheritance_height", "decaying_keys", "policy", "leaves", "prove_funding",
+        "require_enclave",
+    ])]
+    blind: bool,
+
+    #[command(flatten)]
+    oracle: OracleArgs,
+
+    #[command(flatten)]
+    paths: ScriptPathArgs,
+
+    /// Block height the deposit must be rolled over by with the rollover command. Defaults to
+    /// --inheritance-height or --expiry-height.
+    #[arg(long)]
+    session_expiry: Option<u32>,
+
+    /// Inheritance mode: the fallback address is the heir's, and the presigned spend is only
+    /// valid from this block height. Repeat the protocol before then to push the date forward.
+    #[arg(long)]
+    inheritance_height: Option<u32>,
+
+    /// File keeping track of the active inheritance transaction.
+    #[arg(long, default_value = "inheritance.json")]
+    inheritance_file: PathBuf,
+
+    /// Current block height, used to warn when the active inheritance transaction is close to
+    /// becoming valid.
+    #[arg(long)]
+    current_height: Option<u32>,
+
+    /// Number of blocks before the inheritance height to start warning.
+    #[arg(long, default_value_t = 4320)]
+    inheritance_warn_blocks: u32,
+
+    /// Write the output descriptor of the deposit to this file.
+    #[arg(long)]
+    descriptor_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    payjoin: payjoin::PayjoinArgs,
+
+    /// Also render addresses and the raw presigned transaction as QR codes.
+    #[arg(long)]
+    qr: bool,
+
+    /// Show the presigned transaction, and the deposit PSBT if it is left for a payjoin receiver
+    /// to sign, as animated BC-UR QR codes.
+    #[arg(long)]
+    ur: bool,
+
+    /// Sign without asking for confirmation of the deposit summary.
+    #[arg(long)]
+    yes: bool,
+
+    /// Run the whole protocol with the signer and verify its response, but stop before signing
+    /// the deposit. The presigned spend is useless without the deposit, so nothing is at risk.
+    #[arg(long, conflicts_with = "session_out")]
+    dry_run: bool,
+
+    /// File keeping track of the prevouts we signed deposits for.
+    #[arg(long, default_value = "signed_deposits.json")]
+    signed_file: PathBuf,
+
+    /// Sign even if a conflicting deposit spending the same prevout was signed before.
+    #[arg(long)]
+    force: bool,
+
+    /// Directory to save the session in once the signer's response is verified, so an
+    /// interrupted deposit can be continued with the resume command.
+    #[arg(long, default_value = "sessions")]
+    sessions_dir: PathBuf,
+
+    /// History database to record the deposit in.
+    #[arg(long, default_value = "history.sqlite")]
+    history_db: PathBuf,
+}
+
+/// Options for the presigned spend of the deposit.
+#[derive(Debug, clap::Args)]
+struct SpendArgs {
+    /// Address the presigned spend pays to. This can be a silent payment address, in which case
+    /// the output is derived together with the signers.
+    #[arg(long, required_unless_present = "decaying_keys")]
+    fallback_addr: Option<String>,
+
+    /// Fixed amount the presigned spend pays to an address besides the fallback address, which
+    /// gets the rest, as <address>=<amount>. Can be given multiple times.
+    #[arg(long = "spend-output", conflicts_with_all = [
+        "oracle_pubkey", "vault_delay", "rollover", "blind",
+    ])]
+    spend_outputs: Vec<String>,
+
+    /// Have the presigned spend also pay a P2A anchor output, of 240 sat, so it can be fee
+    /// bumped by CPFP.
+    #[arg(long, conflicts_with_all = ["oracle_pubkey", "vault_delay", "rollover", "blind"])]
+    anchor: bool,
+
+    /// Percent of the deposit the presigned spend pays to an address besides the fallback
+    /// address, as <address>=<percent>. Can be given multiple times, in order of priority: a
+    /// share too small to pay goes to the one before it, or to the fallback address.
+    #[arg(long = "fallback-share", conflicts_with_all = [
+        "oracle_pubkey", "vault_delay", "rollover", "blind",
+    ])]
+    fallback_shares: Vec<String>,
+
+    /// Absolute locktime of the presigned spend, a block height or from 500000000 a Unix time,
+    /// so it cannot be broadcast before then.
+    #[arg(long, conflicts_with_all = ["oracle_pubkey", "inheritance_height", "blind"])]
+    spend_lock_time: Option<u32>,
+
+    /// Number of blocks the deposit must be confirmed for before the presigned spend is valid.
+    #[arg(long, conflicts_with = "blind")]
+    spend_relative_blocks: Option<u16>,
+
+    /// Sighash type the presigned spend is signed with. all-anyone-can-pay lets inputs paying
+    /// a higher fee be added to the spend later.
+    #[arg(long, value_enum, default_value_t = SighashArg::Default, conflicts_with_all = [
+        "adaptor_point", "blind",
+    ])]
+    sighash_type: SighashArg,
+
+    /// Request an adaptor signature for the presigned spend, encrypted to this (hex encoded,
+    /// compressed) point. The spend can only be broadcast once its discrete log is revealed.
+    #[arg(long)]
+    adaptor_point: Option<String>,
+
+    /// Instead of --fallback-addr, pay the presigned spend into a multisig of these (hex encoded,
+    /// x-only) keys, initially requiring all of them.
+    #[arg(long = "decaying-key", conflicts_with = "fallback_addr")]
+    decaying_keys: Vec<String>,
+
+    /// Number of blocks after which the decaying multisig threshold decreases by one. Can be
+    /// given multiple times, in increasing order.
+    #[arg(long = "decay-delay", requires = "decaying_keys")]
+    decay_delays: Vec<u16>,
+
+    /// Presign the spend as an unvault, with a final spend to the fallback address only valid
+    /// after this many blocks, and a clawback to --clawback-addr valid before that.
+    #[arg(long, requires = "clawback_addr")]
+    vault_delay: Option<u16>,
+
+    /// Address the vault clawback pays to.
+    #[arg(long)]
+    clawback_addr: Option<String>,
+
+    /// Presign the spend into a fresh deposit output locked to a new ephemeral key, which in
+    /// turn has a presigned spend to the fallback address.
+    #[arg(long)]
+    rollover: bool,
+}
+
+/// Outputs of the deposit transaction.
+#[derive(Debug, clap::Args)]
+struct DepositOutputArgs {
+    #[arg(long, value_parser = amounts::parse_amount)]
+    output_amt: Amount,
+
+    #[arg(long, requires = "change_amt")]
+    change_addr: Option<String>,
+
+    #[arg(long, requires = "change_addr", value_parser = amounts::parse_amount)]
+    change_amt: Option<Amount>,
+
+    /// Batch another deposit output into the deposit transaction, as
+    /// <amount>=<fallback address>[@<signer address>], with the signer of --client-url unless
+    /// another is given. Each is served in a blind session, and gets a presigned spend of its
+    /// own. Can be given multiple times.
+    #[arg(long = "batch-deposit", conflicts_with = "payjoin_endpoint")]
+    batch_deposits: Vec<batch::BatchDeposit>,
+
+    /// Hex encoded data, at most 80 bytes, of an OP_RETURN output to add to the deposit, to bind
+    /// it to the deposit on-chain. Its size is paid for by the deposit fee.
+    #[arg(long, conflicts_with = "op_return_receipt")]
+    op_return: Option<String>,
+
+    /// Add an OP_RETURN output committing to the receipt in this session or receipt file, by the
+    /// hash its operator signed. A receipt covers its own deposit, so it must be of an earlier
+    /// session.
+    #[arg(long)]
+    op_return_receipt: Option<PathBuf>,
+}
+
+/// Oracle event the deposit is settled on with a CET for each outcome.
+#[derive(Debug, clap::Args)]
+struct OracleArgs {
+    /// X-only public key (hex) of an oracle to settle the deposit on. A CET is presigned for each
+    /// --outcome, and the fallback spend becomes the refund.
+    #[arg(long, requires_all = ["oracle_nonce", "event_id", "outcomes", "refund_locktime"])]
+    oracle_pubkey: Option<String>,
+
+    /// X-only nonce (hex) the oracle announced it will attest to the event with.
+    #[arg(long)]
+    oracle_nonce: Option<String>,
+
+    /// Identifier of the oracle event.
+    #[arg(long)]
+    event_id: Option<String>,
+
+    /// Outcome of the oracle event and the address it pays to, as <outcome>=<address>. Can be
+    /// given multiple times.
+    #[arg(long = "outcome")]
+    outcomes: Vec<String>,
+
+    /// Block height after which the refund to the fallback address becomes valid.
+    #[arg(long)]
+    refund_locktime: Option<u32>,
+}
+
+/// Script paths of the deposit output besides the key spend.
+#[derive(Debug, clap::Args)]
+struct ScriptPathArgs {
+    /// X-only public key (hex) that can recover the deposit through a script path, in case the
+    /// presigned spend is lost.
+    #[arg(long)]
+    recovery_key: Option<String>,
+
+    /// Number of blocks the deposit must be confirmed for before the recovery key can spend it.
+    #[arg(long, default_value_t = 144)]
+    recovery_delay: u16,
+
+    /// X-only public key (hex) the deposit returns to after --expiry-height, bounding how long
+    /// funds are at risk if the presigned spend is never broadcast.
+    #[arg(long, requires = "expiry_height")]
+    expiry_key: Option<String>,
+
+    /// Block height from which the expiry key can spend the deposit.
+    #[arg(long)]
+    expiry_height: Option<u32>,
+
+    /// Miniscript policy for the deposit output, with the ephemeral key named `ephemeral`, e.g.
+    /// `or(pk(ephemeral),and(pk(<key>),older(144)))`.
+    #[arg(long)]
+    policy: Option<String>,
+
+    /// Hex encoded tapscript leaf to commit to in the deposit output. Can be given multiple
+    /// times.
+    #[arg(long = "leaf")]
+    leaves: Vec<String>,
+}
+
+/// Lists the labels and public keys in the keystore at `path`.
+fn list_keys(path: &Path) {
+    let keystore = keystore::load(path);
+    for key in &keystore.keys {
+        match key.master {
+            true => println!("{}: {} (extended key)", key.label, key.pubkey),
+            false => println!("{}: {}", key.label, key.pubkey),
+        }
+    }
+}
+
+/// Lists the deposits in the history database at `path`.
+fn list_deposits(path: &Path) {
+    let history = history::History::open(path).expect("able to open history");
+    history::print_list(&history.list().expect("able to read history"));
+}
+
+/// Shows the deposit `deposit_txid` from the history database at `path`.
+fn show_deposit(path: &Path, deposit_txid: Txid) {
+    let history = history::History::open(path).expect("able to open history");
+    match history
+        .get(&deposit_txid.to_string())
+        .expect("able to read history")
+    {
+        Some(deposit) => history::print_deposit(&deposit),
+        None => println!("No deposit {} in history", deposit_txid),
+    }
+}
+
+/// Exports the history database at `path` in `format`, to `out` or stdout.
+fn export(path: &Path, format: ExportFormat, out: Option<&Path>) {
+    let history = history::History::open(path).expect("able to open history");
+    let deposits = history.list().expect("able to read history");
+    let data = match format {
+        ExportFormat::Csv => history::to_csv(&deposits),
+        ExportFormat::Json => history::to_json(&deposits),
+    };
+    match out {
+        Some(out) => {
+            std::fs::write(out, data).expect("able to write export");
+            println!("Exported {} deposits to {}", deposits.len(), out.display());
+        }
+        None => print!("{}", data),
+    }
+}
+
+/// Exports BIP-329 labels for the sessions saved in `dir`, to `out` or stdout.
+fn export_labels(dir: &Path, out: Option<&Path>) {
+    let sessions = session::load_all(dir);
+    let labels: Vec<labels::Label> = sessions.iter().flat_map(labels::for_session).collect();
+    let data = labels::to_jsonl(&labels);
+    match out {
+        Some(out) => {
+            std::fs::write(out, data).expect("able to write labels");
+            println!(
+                "Exported {} labels for {} deposits to {}",
+                labels.len(),
+                sessions.len(),
+                out.display()
+            );
+        }
+        None => print!("{}", data),
+    }
+}
+
+/// Writes the recovery kit of a saved deposit.
+fn recovery_kit(args: RecoveryKitArgs) {
+    let session = session::load(&session::path(&args.sessions_dir, &args.deposit_txid));
+    let kit = recovery::RecoveryKit::new(&session);
+    let passphrase = match args.encrypt {
+        true => Some(keystore::prompt_passphrase("Recovery kit", true)),
+        false => None,
+    };
+    let file = recovery::KitFile::seal(kit, passphrase.as_deref().map(|p| p.as_str()))
+        .expect("able to seal recovery kit");
+
+    let out = args
+        .out
+        .unwrap_or_else(|| PathBuf::from(format!("recovery-{}.json", args.deposit_txid)));
+    recovery::store(&out, &file);
+    println!("Wrote recovery kit to {}", out.display());
+}
+
+/// Writes the paper backup of a saved deposit.
+fn paper_backup(args: PaperBackupArgs) {
+    let session = session::load(&session::path(&args.sessions_dir, &args.deposit_txid));
+    let doc = paper::render(&recovery::RecoveryKit::new(&session));
+
+    let out = args
+        .out
+        .unwrap_or_else(|| PathBuf::from(format!("paper-backup-{}.txt", args.deposit_txid)));
+    std::fs::write(&out, doc).expect("able to write paper backup");
+    println!(
+        "Wrote paper backup to {}, print it and keep it safe",
+        out.display()
+    );
+}
+
+/// Checks the recovery kit at `path` and prints it.
+fn open_recovery_kit(path: &Path) {
+    recovery::print(&read_recovery_kit(path));
+}
+
+/// Reads the recovery kit at `path`, asking for its passphrase if encrypted.
+fn read_recovery_kit(path: &Path) -> recovery::RecoveryKit {
+    let file = recovery::load(path);
+    let passphrase = match file.is_encrypted() {
+        true => Some(keystore::prompt_passphrase("Recovery kit", false)),
+        false => None,
+    };
+    file.open(passphrase.as_deref().map(|p| p.as_str()))
+        .expect("valid recovery kit")
+}
+
+/// Recovers the deposit of the recovery kit in `args`, broadcasting its presigned spend once the
+/// deposit output is confirmed to be unspent and the spend valid against it.
+async fn recover(args: RecoverArgs) {
+    let kit = read_recovery_kit(&args.file);
+    println!("{}", kit.instructions);
+    println!();
+
+    let presigned_tx: Transaction = consensus::encode::deserialize(
+        &hex::decode(&kit.presigned_tx).expect("hex encoded presigned tx"),
+    )
+    .expect("valid presigned tx");
+    assert_eq!(
+        presigned_tx.compute_txid(),
+        kit.presigned_txid,
+        "presigned tx does not match its txid"
+    );
+    let deposit_outpoint = OutPoint {
+        txid: kit.deposit_txid,
+        vout: 0,
+    };
+    assert!(
+        presigned_tx.input.len() == 1 && presigned_tx.input[0].previous_output == deposit_outpoint,
+        "presigned tx does not spend the deposit output"
+    );
+
+    let backend = args.chain.backend(kit.network);
+    let height = match backend
+        .tx_status(&kit.deposit_txid)
+        .await
+        .expect("able to query chain backend")
+    {
+        None => {
+            println!(
+                "Deposit {} is not known to the chain backend, nothing to recover yet",
+                kit.deposit_txid
+            );
+            return;
+        }
+        Some(status) => match status.block_height {
+            Some(height) if status.confirmed => {
+                println!(
+                    "Deposit {} confirmed at height {}",
+                    kit.deposit_txid, height
+                );
+                Some(height)
+            }
+            _ => {
+                println!("Deposit {} is unconfirmed", kit.deposit_txid);
+                None
+            }
+        },
+    };
+
+    let outspend = backend
+        .outspend(&kit.deposit_txid, 0)
+        .await
+        .expect("able to query chain backend");
+    if outspend.spent {
+        match outspend.txid {
+            Some(txid) if txid == kit.presigned_txid => {
+                println!(
+                    "Deposit output already spent by the presigned spend {}",
+                    txid
+                )
+            }
+            Some(txid) => println!(
+                "WARNING: deposit output spent by {}, which is not the presigned spend",
+                txid
+            ),
+            None => println!("WARNING: deposit output spent by an unknown transaction"),
+        }
+        return;
+    }
+
+    let deposit_tx = backend
+        .tx(&kit.deposit_txid)
+        .await
+        .expect("able to fetch deposit");
+    let deposit_output = deposit_tx.output[0].clone();
+    assert_eq!(
+        deposit_output.value.to_sat(),
+        kit.deposit_amount,
+        "deposit output on chain does not match the recovery kit"
+    );
+    presigned_tx
+        .verify(|_| Some(deposit_output.clone()))
+        .expect("presigned tx valid against the deposit");
+    println!("Presigned spend {} is valid", kit.presigned_txid);
+
+    let chain = backend
+        .chain_state()
+        .await
+        .expect("able to query chain backend");
+    let finality = locktime::finality(&presigned_tx, &chain, height);
+    if finality != locktime::Finality::Final {
+        println!(
+            "The presigned spend is {}. Run recover again then, or let the watch command \
+             broadcast it with --broadcast-at-height.",
+            locktime::describe(&finality, &chain)
+        );
+        return;
+    }
+
+    if !summary::confirm("Broadcast the presigned spend?", args.yes) {
+        println!("Aborted, the presigned spend was not broadcast");
+        return;
+    }
+    match backend.broadcast(&presigned_tx).await {
+        Ok(txid) => println!("Broadcast presigned spend {}", txid),
+        Err(e) => println!("Failed to broadcast the presigned spend: {}", e),
+    }
+}
+
+/// Watches the deposits of the saved sessions in `args`.
+async fn watch(args: WatchArgs) {
+    let sessions: Vec<Session> = match args.deposit_txids.is_empty() {
+        true => session::load_all(&args.sessions_dir)
+            .into_iter()
+            .filter(|session| session.completed)
+            .collect(),
+        false => args
+            .deposit_txids
+            .iter()
+            .map(|txid| session::load(&session::path(&args.sessions_dir, txid)))
+            .collect(),
+    };
+    let Some(network) = sessions.first().map(|session| session.network) else {
+        println!("No deposits to watch");
+        return;
+    };
+    assert!(
+        sessions.iter().all(|session| session.network == network),
+        "can only watch deposits on one network at a time"
+    );
+
+    println!("Watching {} deposits", sessions.len());
+    let backend = args.chain.backend(network);
+    let alerts = alerts::Alerts::new(&args.alerts).expect("valid alert options");
+    watch::run(
+        &backend,
+        &sessions,
+        Duration::from_secs(args.interval),
+        &args.triggers,
+        &alerts,
+    )
+    .await;
+}
+
+/// Broadcasts the deposit in `args` and, once it is accepted, its presigned spend.
+async fn broadcast_txs(args: BroadcastArgs) {
+    let deposit_tx: Transaction = consensus::encode::deserialize(
+        &hex::decode(&args.deposit_tx).expect("hex encoded deposit tx"),
+    )
+    .expect("valid deposit tx");
+    let deposit_txid = deposit_tx.compute_txid();
+    let presigned_tx = args.presigned_tx.as_ref().map(|presigned_tx| {
+        let tx: Transaction = consensus::encode::deserialize(
+            &hex::decode(presigned_tx).expect("hex encoded --presigned-tx"),
+        )
+        .expect("valid --presigned-tx");
+        assert!(
+            tx.input.len() == 1
+                && tx.input[0].previous_output
+                    == OutPoint {
+                        txid: deposit_txid,
+                        vout: 0,
+                    },
+            "--presigned-tx does not spend the deposit output"
+        );
+        tx
+    });
+
+    let backend = args
+        .backend
+        .backend(args.network)
+        .expect("valid backend options");
+    let timeout = Duration::from_secs(args.timeout);
+    if !submit(&backend, "deposit", &deposit_tx, timeout).await {
+        return;
+    }
+
+    let history = history::History::open(&args.history_db).expect("able to open history");
+    let txid = deposit_txid.to_string();
+    if history.get(&txid).expect("able to read history").is_some() {
+        history
+            .mark_broadcast(&txid)
+            .expect("able to update history");
+    }
+
+    if let Some(presigned_tx) = &presigned_tx {
+        broadcast::wait_until_final(&backend, presigned_tx, Duration::from_secs(args.interval))
+            .await
+            .expect("able to query backend");
+        submit(&backend, "presigned spend", presigned_tx, timeout).await;
+    }
+}
+
+/// Broadcasts `tx` through `backend` and waits up to `timeout` for the backend to know it,
+/// returning whether it was accepted.
+async fn submit(
+    backend: &broadcast::Backend,
+    name: &str,
+    tx: &Transaction,
+    timeout: Duration,
+) -> bool {
+    let txid = match backend.broadcast(tx).await {
+        Ok(txid) => txid,
+        Err(e) => {
+            println!("The {} was rejected: {}", name, e);
+            return false;
+        }
+    };
+    println!("Broadcast {} {}, waiting for it to be accepted", name, txid);
+
+    let start = std::time::Instant::now();
+    while start.elapsed() < timeout {
+        if backend.is_known(&txid).await.unwrap_or(false) {
+            println!("The {} {} was accepted", name, txid);
+            return true;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    println!(
+        "The {} {} was not seen by the backend within {} seconds",
+        name,
+        txid,
+        timeout.as_secs()
+    );
+    false
+}
+
+/// Marks the deposit `deposit_txid` in the history database at `path` as broadcast.
+fn mark_broadcast(path: &Path, deposit_txid: Txid) {
+    let history = history::History::open(path).expect("able to open history");
+    history
+        .mark_broadcast(&deposit_txid.to_string())
+        .expect("able to update history");
+    println!("Marked deposit {} as broadcast", deposit_txid);
+}
+
+/// Reads the receipt in the session or receipt file at `path`, checking its signature, and for a
+/// session that it is for the session's transactions.
+fn read_receipt(path: &Path) -> Receipt {
+    let data = std::fs::read_to_string(path).expect("able to read receipt");
+    match serde_json::from_str::<Session>(&data) {
+        Ok(session) => {
+            let receipt = session.resp.receipt.clone().expect("session has a receipt");
+            receipt
+                .verify_for(&session.resp)
+                .expect("valid receipt for the session");
+            receipt
+        }
+        Err(_) => {
+            let receipt: Receipt = serde_json::from_str(&data).expect("session or receipt file");
+            receipt.verify().expect("valid receipt");
+            receipt
+        }
+    }
+}
+
+/// Verifies the receipt in the session or receipt file at `path`, signed with `operator_key` if
+/// given. The receipt of a session is also checked to be for its transactions, and its enclave
+/// attestations against `enclave`.
+fn verify_receipt(path: &Path, operator_key: Option<&str>, enclave: &enclave::EnclaveArgs) {
+    let receipt = read_receipt(path);
+    if let Some(operator_key) = operator_key {
+        assert_eq!(
+            receipt.operator_key, operator_key,
+            "receipt is signed with another operator key"
+        );
+    }
+    enclave
+        .verify(&receipt.enclave_attestations, &receipt.ephemeral_pubkey)
+        .expect("valid enclave attestations of the key deletion");
+
+    println!(
+        "Receipt signed by operator key {}: valid",
+        receipt.operator_key
+    );
+    println!(
+        "  Deposit: {} ({} sat)",
+        receipt.deposit_txid, receipt.deposit_sat
+    );
+    println!(
+        "  Presigned spend: {} (fee {} sat)",
+        receipt.spend_txid, receipt.spend_fee_sat
+    );
+    println!("  Ephemeral key: {}", receipt.ephemeral_pubkey);
+    println!("  Network: {}", receipt.network);
+    if let Some(quote_id) = &receipt.quote_id {
+        println!("  Quote: {}", quote_id);
+    }
+    println!("  Signed at: {}", receipt.timestamp);
+}
+
+/// Imports a key into the keystore. Mnemonics are stored as their master key.
+fn import_key(args: ImportKeyArgs) {
+    let key = Zeroizing::new(
+        rpassword::prompt_password("Key (hex, WIF, xpriv or mnemonic): ").expect("key"),
+    );
+    let key = key.trim();
+    let secret = match (Xpriv::from_str(key), PrivateKey::from_wif(key)) {
+        (Ok(xpriv), _) => keystore::Secret::Master(Erasing::new(xpriv)),
+        (_, Ok(mut wif)) => {
+            let sk = Erasing::new(wif.inner);
+            wif.inner.non_secure_erase();
+            keystore::Secret::Key(sk)
+        }
+        _ => match bip39::Mnemonic::parse(key) {
+            Ok(mnemonic) => keystore::Secret::Master(
+                keys::master_from_mnemonic(&mnemonic, &args.key, args.network).unwrap(),
+            ),
+            Err(_) => keystore::Secret::Key(Erasing::new(
+                SecretKey::from_str(key).expect("valid private key"),
+            )),
+        },
+    };
+
+    let mut keystore = keystore::load(&args.key.keystore);
+    let passphrase = keystore::prompt_passphrase("Keystore", true);
+    keystore
+        .add(&args.label, &secret, &passphrase)
+        .expect("able to add key to keystore");
+    keystore::store(&args.key.keystore, &keystore);
+    println!(
+        "Stored key in {} as keystore:{}",
+        args.key.keystore.display(),
+        args.label
+    );
+}
+
+/// Decodes a BC-UR message from stdin and prints it as a PSBT or raw transaction.
+fn ur_decode() {
+    let data = bcur::read_parts(std::io::stdin().lock()).expect("valid UR");
+    if let Ok(psbt) = Psbt::deserialize(&data) {
+        println!("PSBT: {}", psbt);
+        return;
+    }
+
+    match consensus::encode::deserialize::<Transaction>(&data) {
+        Ok(tx) => println!("Raw Transaction: {}", consensus::encode::serialize_hex(&tx)),
+        Err(_) => println!("Data: {}", hex::encode(&data)),
+    }
+}
+
+/// Generates a new deposit key, printing it along with its address and funding URI.
+fn keygen(args: KeygenArgs) {
+    let secp = Secp256k1::new();
+    let master = match (&args.from, args.mnemonic) {
+        (Some(from), _) => {
+            Some(keys::parse_master_key(from, &args.key, args.network).expect("valid extended key"))
+        }
+        (None, true) => {
+            let mnemonic = bip39::Mnemonic::generate(args.words).expect("valid word count");
+            println!("mnemonic: {}", mnemonic);
+            Some(keys::master_from_mnemonic(&mnemonic, &args.key, args.network).unwrap())
+        }
+        (None, false) => None,
+    };
+
+    let keypair = match master {
+        None => Erasing::new(gen_keypair(&secp)),
+        Some(master) => {
+            let (sk, (fingerprint, path)) =
+                keys::allocate_key(&master, &args.key, args.network).expect("able to derive key");
+            println!("fingerprint: {}", fingerprint);
+            println!("path: {}", path);
+            Erasing::new(Keypair::from_secret_key(&secp, &sk))
+        }
+    };
+
+    let (internal_key, _parity) = keypair.x_only_public_key();
+    let script_buf = ScriptBuf::new_p2tr(&secp, internal_key, None);
+    let addr = Address::from_script(script_buf.as_script(), args.network).unwrap();
+    match (&args.keychain, &args.keystore_label) {
+        (Some(label), _) => {
+            keys::store_in_keychain(label, &keypair.secret_key())
+                .expect("able to store key in keychain");
+            println!("priv: stored in keychain as keychain:{}", label);
+        }
+        (None, Some(label)) => {
+            let mut keystore = keystore::load(&args.key.keystore);
+            let passphrase = keystore::prompt_passphrase("Keystore", true);
+            keystore
+                .add(
+                    label,
+                    &keystore::Secret::Key(Erasing::new(keypair.secret_key())),
+                    &passphrase,
+                )
+                .expect("able to add key to keystore");
+            keystore::store(&args.key.keystore, &keystore);
+            println!(
+                "priv: stored in {} as keystore:{}",
+                args.key.keystore.display(),
+                label
+            );
+        }
+        (None, None) => {
+            println!("priv: {}", hex::encode(keypair.secret_key().secret_bytes()));
+            println!(
+                "wif: {}",
+                PrivateKey::new(keypair.secret_key(), args.network).to_wif()
+            );
+        }
+    }
+    println!("pub: {}", internal_key);
+    println!("address: {}", addr);
+    let uri = bip21_uri(&addr, args.amount, args.label.as_deref());
+    println!("uri: {}", uri);
+
+    if args.qr {
+        qr::print_qr("address", &addr.to_string());
+        qr::print_qr("uri", &uri);
+    }
+}
+
+/// Offline step of the air-gapped workflow: checks the signer's response in the session again, as
+/// the online machine is not trusted with the funds, and signs the deposit.
+fn sign_offline(args: SignArgs) {
+    let session = session::load(&args.session);
+    let Some(deposit_psbt) = sign_session(&session, &args.sign) else {
+        return;
+    };
+
+    std::fs::write(&args.out, deposit_psbt.to_string()).expect("able to write signed PSBT");
+    println!("Wrote signed deposit PSBT to {}", args.out.display());
+    if args.ur {
+        bcur::show_animated("deposit PSBT", bcur::PSBT_TYPE, &deposit_psbt.serialize());
+    }
+}
+
+/// Final step of the air-gapped workflow: completes the deposit signed offline and checks the
+/// presigned spend against it.
+fn finalize(args: FinalizeArgs) {
+    let session = session::load(&args.session);
+    let deposit_psbt = read_psbt(&args.signed);
+    assert_eq!(
+        deposit_psbt.unsigned_tx, session.resp.deposit_psbt.unsigned_tx,
+        "signed PSBT is not the deposit of the session"
+    );
+
+    let presigned_tx = session
+        .resp
+        .spend_psbt
+        .clone()
+        .extract_tx()
+        .expect("valid tx");
+    println!(
+        "Raw presigned Transaction: {}",
+        consensus::encode::serialize_hex(&presigned_tx)
+    );
+
+    complete_session(&session, deposit_psbt, &args.inheritance_file, args.ur);
+    history::record(&args.history_db, &session, history::COMPLETED);
+}
+
+/// Continues a saved session from where it was interrupted.
+fn resume(args: ResumeArgs) {
+    let path = session::path(&args.sessions_dir, &args.id);
+    let mut session = session::load(&path);
+    let presigned_tx = session
+        .resp
+        .spend_psbt
+        .clone()
+        .extract_tx()
+        .expect("valid tx");
+    println!(
+        "Raw presigned Transaction: {}",
+        consensus::encode::serialize_hex(&presigned_tx)
+    );
+    if session.completed {
+        println!("Session {} is already completed", args.id);
+        return;
+    }
+    if session.cancel_tx.is_some() {
+        println!("Session {} was cancelled", args.id);
+        return;
+    }
+
+    let deposit_psbt = match session.signed_psbt.clone() {
+        Some(psbt) => psbt,
+        None => {
+            let Some(psbt) = sign_session(&session, &args.sign) else {
+                return;
+            };
+            session.signed_psbt = Some(psbt.clone());
+            session::store(&path, &session);
+            history::record(&args.history_db, &session, history::SIGNED);
+            psbt
+        }
+    };
+
+    complete_session(&session, deposit_psbt, &args.inheritance_file, args.ur);
+    session.completed = true;
+    session::store(&path, &session);
+    history::record(&args.history_db, &session, history::COMPLETED);
+}
+
+/// Cancels the session in `args` by signing and broadcasting a replacement of its deposit,
+/// spending our prevout back to us.
+async fn cancel(args: CancelArgs) {
+    let path = session::path(&args.sessions_dir, &args.id);
+    let mut session = session::load(&path);
+    if let Some(tx) = &session.cancel_tx {
+        println!(
+            "Session {} was already cancelled by {}",
+            args.id,
+            tx.compute_txid()
+        );
+        return;
+    }
+
+    let backend = args
+        .backend
+        .backend(session.network)
+        .expect("valid backend options");
+    if backend.is_known(&args.id).await.unwrap_or(false) {
+        if let Some(height) = backend
+            .confirmation_height(&args.id, 0)
+            .await
+            .expect("able to query backend")
+        {
+            println!(
+                "Deposit {} confirmed at height {}, it can no longer be cancelled",
+                args.id, height
+            );
+            return;
+        }
+    }
+
+    let replaced_fee = session
+        .deposit_fee()
+        .expect("deposit fee known to replace it");
+    let addr = parse_address(&args.addr, session.network);
+    let mut cancel_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: session.prevout,
+            script_sig: ScriptBuf::default(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: session.deposit_prevout.value,
+            script_pubkey: addr.script_pubkey(),
+        }],
+    };
+    let fee = amounts::replacement_fee(
+        &cancel_tx,
+        session.deposit_prevout.value,
+        args.feerate,
+        replaced_fee,
+    )
+    .expect("sane replacement fee");
+    cancel_tx.output[0].value = session
+        .deposit_prevout
+        .value
+        .checked_sub(fee)
+        .expect("fee below input");
+
+    println!(
+        "Cancel deposit {}: send {} back to {}, paying {} in fees to replace the deposit's {}",
+        args.id, cancel_tx.output[0].value, addr, fee, replaced_fee
+    );
+    if !summary::confirm("Sign and broadcast the cancellation?", args.yes) {
+        println!("Aborted, the session was not cancelled");
+        return;
+    }
+
+    let secp = Secp256k1::new();
+    let priv_key = keys::priv_key_arg(args.priv_key.as_deref()).expect("priv key needed");
+    let (sk, origin) =
+        keys::parse_priv_key(&priv_key, &args.key, session.network).expect("valid private key");
+    let keypair = Erasing::new(Keypair::from_secret_key(&secp, &sk));
+    assert!(
+        funding::FundingType::of(&secp, &keypair, &session.deposit_prevout.script_pubkey).is_some(),
+        "private key does not match the deposit input"
+    );
+    let key_source = session
+        .key_source
+        .clone()
+        .or(origin)
+        .unwrap_or_else(|| keys::raw_key_source(&keypair));
+
+    let mut psbt = Psbt::from_unsigned_tx(cancel_tx).expect("valid unsigned tx");
+    sign_deposit(
+        &secp,
+        &keypair,
+        key_source,
+        session.network,
+        &mut psbt,
+        session.prevout,
+        &session.deposit_prevout,
+    );
+    let cancel_tx = psbt.extract_tx().expect("valid transaction");
+    cancel_tx
+        .verify(|_| Some(session.deposit_prevout.clone()))
+        .expect("valid cancellation");
+
+    // Record the conflict before broadcasting, so the session is not continued either way.
+    session.cancel_tx = Some(cancel_tx.clone());
+    session::store(&path, &session);
+    history::record(&args.history_db, &session, history::CANCELLED);
+    print!(
+        "{}",
+        render::transaction(
+            "Cancellation",
+            &cancel_tx,
+            &[Some(session.deposit_prevout.clone())],
+            session.network
+        )
+    );
+    println!(
+        "Raw cancel Transaction: {}",
+        consensus::encode::serialize_hex(&cancel_tx)
+    );
+
+    submit(
+        &backend,
+        "cancellation",
+        &cancel_tx,
+        Duration::from_secs(args.timeout),
+    )
+    .await;
+}
+
+/// Rolls the session in `args` over: spends its deposit through the recovery path into a new
+/// deposit, with a fresh presigned spend from the signer, or back to us with --to-addr. The old
+/// session is only marked as rolled over once the new presigned spend is verified, and its
+/// presigned spend remains the fallback until the rollover confirms.
+async fn rollover(args: RolloverArgs) {
+    let old_path = session::path(&args.sessions_dir, &args.id);
+    let mut old = session::load(&old_path);
+    assert!(old.completed, "session {} is not completed", args.id);
+    if let Some(txid) = old.rolled_over_to {
+        println!("Session {} was already rolled over by {}", args.id, txid);
+        return;
+    }
+    let network = old.network;
+    let spend = rollover::RecoverySpend::new(&old).expect("deposit can be rolled over");
+
+    let secp = Secp256k1::new();
+    let priv_key = keys::priv_key_arg(args.priv_key.as_deref()).expect("priv key needed");
+    let (sk, origin) =
+        keys::parse_priv_key(&priv_key, &args.key, network).expect("valid private key");
+    let keypair = Erasing::new(Keypair::from_secret_key(&secp, &sk));
+    assert_eq!(
+        keypair.x_only_public_key().0,
+        spend.key,
+        "private key does not match the recovery key"
+    );
+    let key_source = origin.unwrap_or_else(|| keys::raw_key_source(&keypair));
+
+    let backend = args
+        .backend
+        .backend(network)
+        .expect("valid backend options");
+    let chain = backend.chain_state().await.expect("able to query backend");
+    match old.expiry {
+        Some(expiry) if chain.tip >= expiry => println!(
+            "WARNING: session {} expired at height {}, the deposit may be spent by its presigned \
+             spend or expiry path before the rollover confirms",
+            args.id, expiry
+        ),
+        Some(expiry) => println!(
+            "Session {} expires at height {}, {} blocks from now",
+            args.id,
+            expiry,
+            expiry - chain.tip
+        ),
+        None => println!("Session {} has no expiry", args.id),
+    }
+
+    // The signer fills in the script of a new deposit output, a P2TR script like the one used
+    // to size the fee.
+    let output_script = match &args.to_addr {
+        Some(addr) => parse_address(addr, network).script_pubkey(),
+        None => ScriptBuf::from_bytes(vec![0; 34]),
+    };
+    let mut unsigned_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: match args.no_anti_fee_sniping {
+            true => absolute::LockTime::ZERO,
+            false => locktime::anti_fee_sniping(chain.tip),
+        },
+        input: vec![spend.txin()],
+        output: vec![TxOut {
+            value: spend.prevout.value,
+            script_pubkey: output_script,
+        }],
+    };
+    let input_height = backend
+        .confirmation_height(&spend.outpoint.txid, spend.outpoint.vout)
+        .await
+        .expect("able to query backend");
+    let finality = locktime::finality(&unsigned_tx, &chain, input_height);
+    if finality != locktime::Finality::Final {
+        println!(
+            "The recovery path of deposit {} is {}, it cannot be rolled over yet",
+            args.id,
+            locktime::describe(&finality, &chain)
+        );
+        return;
+    }
+    let fee = spend
+        .fee(&unsigned_tx, args.feerate)
+        .expect("sane rollover fee");
+    unsigned_tx.output[0].value = spend
+        .prevout
+        .value
+        .checked_sub(fee)
+        .expect("deposit covers the rollover fee");
+    if args.to_addr.is_none() {
+        unsigned_tx.output[0].script_pubkey = ScriptBuf::default();
+    }
+    amounts::check_deposit(
+        &unsigned_tx,
+        spend.prevout.value,
+        funding::FundingType::P2tr.witness_weight(),
+        args.max_deposit_fee,
+    )
+    .expect("sane rollover amounts");
+
+    let signed_tx = match &args.to_addr {
+        Some(addr) => {
+            println!(
+                "Roll deposit {} back to {}: {}, paying {} in fees",
+                args.id, addr, unsigned_tx.output[0].value, fee
+            );
+            if !summary::confirm("Sign and broadcast the rollover?", args.yes) {
+                println!("Aborted, the rollover was not signed");
+                return;
+            }
+            let mut psbt = Psbt::from_unsigned_tx(unsigned_tx).expect("valid unsigned tx");
+            sign_rollover(&secp, &keypair, key_source, network, &mut psbt, &spend);
+            let tx = psbt.extract_tx().expect("valid transaction");
+            tx.verify(|_| Some(spend.prevout.clone()))
+                .expect("valid rollover");
+            tx
+        }
+        None => {
+            let Some(session) =
+                rollover_session(&args, &old, &spend, unsigned_tx, &keypair, key_source).await
+            else {
+                return;
+            };
+            session
+                .signed_psbt
+                .expect("signed rollover")
+                .extract_tx()
+                .expect("valid transaction")
+        }
+    };
+
+    let txid = signed_tx.compute_txid();
+    old.rolled_over_to = Some(txid);
+    session::store(&old_path, &old);
+    println!(
+        "Raw rollover Transaction: {}",
+        consensus::encode::serialize_hex(&signed_tx)
+    );
+    println!(
+        "Keep the presigned spend of deposit {} until rollover {} confirms, it remains the \
+         fallback until then",
+        args.id, txid
+    );
+
+    let accepted = submit(
+        &backend,
+        "rollover",
+        &signed_tx,
+        Duration::from_secs(args.timeout),
+    )
+    .await;
+    if accepted && args.to_addr.is_none() {
+        mark_broadcast(&args.history_db, txid);
+    }
+}
+
+/// Opens a new signer session for the deposit built in `unsigned_tx`, carrying over the script
+/// paths and mode of the `old` session, and completes it by signing the recovery path `spend`.
+/// Adaptor and oracle settings are not carried over. Returns the completed session, none if
+/// signing was declined.
+async fn rollover_session(
+    args: &RolloverArgs,
+    old: &Session,
+    spend: &rollover::RecoverySpend,
+    unsigned_tx: Transaction,
+    keypair: &Keypair,
+    key_source: KeySource,
+) -> Option<Session> {
+    let secp = Secp256k1::new();
+    let network = old.network;
+    let fallback_addr = match &args.fallback_addr {
+        Some(addr) if SilentPaymentAddress::is_silent_payment(addr) => addr.clone(),
+        Some(addr) => parse_address(addr, network).to_string(),
+        None => old.req.fallback_addr.clone(),
+    };
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx).expect("valid unsigned tx");
+    psbt.inputs[0].witness_utxo = Some(spend.prevout.clone());
+    let mut req = SignPsbtReq {
+        psbt,
+        fallback_addr: fallback_addr.clone(),
+        network,
+        adaptor_point: None,
+        oracle_event: None,
+        recovery: old.req.recovery.clone(),
+        expiry: old.req.expiry.as_ref().map(|expiry| ExpiryPath {
+            expiry_key: expiry.expiry_key.clone(),
+            height: args
+                .expiry_height
+                .expect("--expiry-height for the new expiry path"),
+        }),
+        vault: old.req.vault.clone(),
+        rollover: old.req.rollover,
+        inheritance: old.req.inheritance.as_ref().map(|_| InheritanceParams {
+            lock_time: args
+                .inheritance_height
+                .expect("--inheritance-height to push the inheritance date forward"),
+        }),
+        decaying_multisig: old.req.decaying_multisig.clone(),
+        policy: old.req.policy.clone(),
+        leaves: old.req.leaves.clone(),
+        quote: None,
+        version: PROTOCOL_VERSION,
+        capabilities: CAPABILITIES.to_vec(),
+        idempotency_key: Some(hex::encode(rand::random::<[u8; 16]>())),
+        funding_proof: None,
+        spend_outputs: old.req.spend_outputs.clone(),
+        fallback_shares: old.req.fallback_shares.clone(),
+        spend_timelock: old.req.spend_timelock.clone(),
+        sighash_type: old.req.sighash_type,
+        anchor: old.req.anchor,
+    };
+    let expiry = args
+        .session_expiry
+        .or(req.inheritance.as_ref().map(|i| i.lock_time))
+        .or(req.expiry.as_ref().map(|e| e.height));
+
+    let client_url = args.client_url.expect("--client-url for the new deposit");
+    preflight(&args.http, client_url, &req, args.max_signer_tip_age)
+        .await
+        .expect("signer ready for the request");
+    fetch_quote(&args.http, client_url, &mut req)
+        .await
+        .expect("acceptable quote from the signer");
+    let resp = initiate_sign(&args.http, client_url, &req)
+        .await
+        .expect("signer accepted the request");
+    verify_response(&secp, network, &req, &resp, &args.fee_limits, &args.enclave);
+
+    let mut session = Session {
+        network,
+        prevout: spend.outpoint,
+        deposit_prevout: spend.prevout.clone(),
+        key_source: Some(key_source.clone()),
+        change_addr: None,
+        payjoin_endpoint: None,
+        signer: Some(client_url.to_string()),
+        req,
+        resp,
+        signed_psbt: None,
+        completed: false,
+        cancel_tx: None,
+        expiry,
+        rolled_over_to: None,
+        batch_spends: vec![],
+    };
+    session::save(&args.sessions_dir, &session);
+    history::record(&args.history_db, &session, history::VERIFIED);
+
+    summary::print(
+        network,
+        &session.resp.deposit_psbt,
+        spend.outpoint,
+        &spend.prevout,
+        &session.resp.spend_psbt,
+        &fallback_addr,
+    );
+    let deposit_txid = session.id();
+    let mut signed_deposits = signed::load(&args.signed_file);
+    if !check_not_signed(&signed_deposits, spend.outpoint, deposit_txid, args.force) {
+        return None;
+    }
+    if !summary::confirm("Sign the rollover?", args.yes) {
+        println!("Aborted, the rollover was not signed");
+        return None;
+    }
+
+    let mut deposit_psbt = session.resp.deposit_psbt.clone();
+    sign_rollover(
+        &secp,
+        keypair,
+        key_source,
+        network,
+        &mut deposit_psbt,
+        spend,
+    );
+    signed_deposits.add(spend.outpoint, deposit_txid);
+    signed::store(&args.signed_file, &signed_deposits);
+    session.signed_psbt = Some(deposit_psbt.clone());
+    session::save(&args.sessions_dir, &session);
+    history::record(&args.history_db, &session, history::SIGNED);
+
+    complete_session(&session, deposit_psbt, &args.inheritance_file, false);
+    session.completed = true;
+    session::save(&args.sessions_dir, &session);
+    history::record(&args.history_db, &session, history::COMPLETED);
+    Some(session)
+}
+
+/// Signs and finalizes the input of `psbt` spending the deposit through the recovery path
+/// `spend`, with `keypair` holding the recovery key.
+fn sign_rollover<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    keypair: &Keypair,
+    key_source: KeySource,
+    network: Network,
+    psbt: &mut Psbt,
+    spend: &rollover::RecoverySpend,
+) {
+    let mut key_map: HashMap<bitcoin::XOnlyPublicKey, PrivateKey> = HashMap::new();
+    key_map.insert(spend.key, PrivateKey::new(keypair.secret_key(), network));
+
+    let index = spend.set_input(psbt, key_source);
+    psbt.sign(&key_map, secp).expect("able to sign");
+    for key in key_map.values_mut() {
+        key.inner.non_secure_erase();
+    }
+    spend.finalize(psbt, index);
+}
+
+/// Sweeps the fallback output of the session in `args` to another address, once the presigned
+/// transaction paying it is confirmed.
+async fn sweep(args: SweepArgs) {
+    let session = session::load(&session::path(&args.sessions_dir, &args.id));
+    let network = session.network;
+
+    let secp = Secp256k1::new();
+    let priv_key = keys::priv_key_arg(args.priv_key.as_deref()).expect("priv key needed");
+    let (sk, origin) =
+        keys::parse_priv_key(&priv_key, &args.key, network).expect("valid private key");
+    let keypair = Erasing::new(Keypair::from_secret_key(&secp, &sk));
+    let (internal_key, _parity) = keypair.x_only_public_key();
+    let key_source = origin.unwrap_or_else(|| keys::raw_key_source(&keypair));
+
+    // The txid does not commit to the witness, so it is known even for an adaptor signed spend.
+    let fallback_tx = &session.fallback_psbt().unsigned_tx;
+    let our_script = ScriptBuf::new_p2tr(&secp, internal_key, None);
+    let vout = fallback_tx
+        .output
+        .iter()
+        .position(|output| output.script_pubkey == our_script)
+        .expect("fallback output locked to the private key");
+    let prevout = OutPoint {
+        txid: fallback_tx.compute_txid(),
+        vout: vout as u32,
+    };
+    let fallback_output = fallback_tx.output[vout].clone();
+
+    let backend = args
+        .backend
+        .backend(network)
+        .expect("valid backend options");
+    let height = backend
+        .confirmation_height(&prevout.txid, prevout.vout)
+        .await
+        .unwrap_or(None);
+    let Some(height) = height else {
+        println!(
+            "Transaction {} paying the fallback address is not confirmed, nothing to sweep yet",
+            prevout.txid
+        );
+        return;
+    };
+    println!("Fallback output {} confirmed at height {}", prevout, height);
+
+    let addr = parse_address(&args.addr, network);
+    let mut sweep_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: prevout,
+            script_sig: ScriptBuf::default(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: fallback_output.value,
+            script_pubkey: addr.script_pubkey(),
+        }],
+    };
+    let fee = amounts::key_spend_fee(&sweep_tx, fallback_output.value, args.feerate)
+        .expect("sane sweep fee");
+    sweep_tx.output[0].value = fallback_output
+        .value
+        .checked_sub(fee)
+        .expect("fee below input");
+
+    println!(
+        "Sweep {} from the fallback output to {}, paying {} in fees",
+        sweep_tx.output[0].value, addr, fee
+    );
+    if !summary::confirm("Sign and broadcast the sweep?", args.yes) {
+        println!("Aborted, the fallback output was not swept");
+        return;
+    }
+
+    let mut psbt = Psbt::from_unsigned_tx(sweep_tx).expect("valid unsigned tx");
+    sign_deposit(
+        &secp,
+        &keypair,
+        key_source,
+        network,
+        &mut psbt,
+        prevout,
+        &fallback_output,
+    );
+    let sweep_tx = psbt.extract_tx().expect("valid transaction");
+    sweep_tx
+        .verify(|_| Some(fallback_output.clone()))
+        .expect("valid sweep");
+    print!(
+        "{}",
+        render::transaction("Sweep", &sweep_tx, &[Some(fallback_output)], network)
+    );
+    println!(
+        "Raw sweep Transaction: {}",
+        consensus::encode::serialize_hex(&sweep_tx)
+    );
+
+    submit(
+        &backend,
+        "sweep",
+        &sweep_tx,
+        Duration::from_secs(args.timeout),
+    )
+    .await;
+}
+
+/// Checks the signer's response in `session` again and signs the deposit with the given private
+/// key, returning the signed PSBT unless the user aborted.
+fn sign_session(session: &Session, args: &SessionSignArgs) -> Option<Psbt> {
+    let secp = Secp256k1::new();
+    let priv_key = keys::priv_key_arg(args.priv_key.as_deref()).expect("priv key needed");
+    let (sk, origin) =
+        keys::parse_priv_key(&priv_key, &args.key, session.network).expect("valid private key");
+    let keypair = Erasing::new(Keypair::from_secret_key(&secp, &sk));
+    assert!(
+        funding::FundingType::of(&secp, &keypair, &session.deposit_prevout.script_pubkey).is_some(),
+        "private key does not match the deposit input"
+    );
+
+    verify_response(
+        &secp,
+        session.network,
+        &session.req,
+        &session.resp,
+        &args.fee_limits,
+        &args.enclave,
+    );
+    let presigned_tx = session
+        .resp
+        .spend_psbt
+        .clone()
+        .extract_tx()
+        .expect("valid tx");
+    print!(
+        "{}",
+        render::transaction(
+            "Presigned spend",
+            &presigned_tx,
+            &prevouts(&session.resp.spend_psbt),
+            session.network
+        )
+    );
+
+    let key_source = session
+        .key_source
+        .clone()
+        .or(origin)
+        .unwrap_or_else(|| keys::raw_key_source(&keypair));
+    summary::print(
+        session.network,
+        &session.resp.deposit_psbt,
+        session.prevout,
+        &session.deposit_prevout,
+        &session.resp.spend_psbt,
+        &session.req.fallback_addr,
+    );
+    let deposit_txid = session.id();
+    let mut signed_deposits = signed::load(&args.signed_file);
+    if !check_not_signed(&signed_deposits, session.prevout, deposit_txid, args.force) {
+        return None;
+    }
+    if !summary::confirm("Sign the deposit?", args.yes) {
+        println!("Aborted, the deposit was not signed");
+        return None;
+    }
+
+    let mut deposit_psbt = session.resp.deposit_psbt.clone();
+    sign_deposit(
+        &secp,
+        &keypair,
+        key_source,
+        session.network,
+        &mut deposit_psbt,
+        session.prevout,
+        &session.deposit_prevout,
+    );
+    signed_deposits.add(session.prevout, deposit_txid);
+    signed::store(&args.signed_file, &signed_deposits);
+    Some(deposit_psbt)
+}
+
+/// Completes the deposit of `session`, signed by us in `deposit_psbt`, and checks the presigned
+/// spend against it.
+fn complete_session(session: &Session, mut deposit_psbt: Psbt, inheritance_file: &Path, ur: bool) {
+    // Signers like Coldcard may leave the signed input to be finalized by us.
+    let our_input = deposit_psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|i| i.previous_output == session.prevout)
+        .expect("our input in deposit");
+    if deposit_psbt.inputs[our_input]
+        .final_script_witness
+        .is_none()
+    {
+        finalize_deposit_input(&mut deposit_psbt, our_input);
+    }
+
+    let presigned_tx = session
+        .resp
+        .spend_psbt
+        .clone()
+        .extract_tx()
+        .expect("valid tx");
+    let signed_tx = complete_deposit(
+        deposit_psbt,
+        session.network,
+        session.payjoin_endpoint.as_deref(),
+        ur,
+        &session.deposit_prevout,
+    );
+    verify_txid_binding(&presigned_tx, &signed_tx);
+
+    if let Some(inheritance) = &session.req.inheritance {
+        let prev_inheritance = inheritance::load(inheritance_file);
+        store_inheritance(
+            inheritance_file,
+            InheritanceRecord::new(
+                session.req.fallback_addr.clone(),
+                inheritance.lock_time,
+                &signed_tx,
+                &presigned_tx,
+            ),
+            prev_inheritance,
+        );
+    }
+
+    // The adaptor signed spend is not valid until completed, so there is nothing to verify yet.
+    if session.req.adaptor_point.is_none() {
+        verify_presigned(&presigned_tx, &signed_tx);
+    }
+
+    // The batched deposit outputs follow the first, each with its own presigned spend.
+    let deposit_txid = signed_tx.compute_txid();
+    for (i, spend) in session.batch_spends.iter().enumerate() {
+        let vout = i + 1;
+        let tx = spend.clone().extract_tx().expect("valid tx");
+        let deposit_op = OutPoint {
+            txid: deposit_txid,
+            vout: vout as u32,
+        };
+        assert_eq!(
+            tx.input[0].previous_output, deposit_op,
+            "presigned spend of deposit output {} does not spend it",
+            vout
+        );
+        tx.verify(|_| Some(signed_tx.output[vout].clone()))
+            .expect("valid presigned spend of the batched deposit output");
+        println!(
+            "Presigned spend of deposit output {}: {}",
+            vout,
+            tx.compute_txid()
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let args = match cli.command {
+        Some(Command::Keygen(keygen_args)) => return keygen(keygen_args),
+        Some(Command::UrDecode) => return ur_decode(),
+        Some(Command::ListKeys(list_args)) => return list_keys(&list_args.keystore),
+        Some(Command::ImportKey(import_args)) => return import_key(import_args),
+        Some(Command::Sign(sign_args)) => return sign_offline(sign_args),
+        Some(Command::Finalize(finalize_args)) => return finalize(finalize_args),
+        Some(Command::Resume(resume_args)) => return resume(resume_args),
+        Some(Command::Cancel(cancel_args)) => return cancel(cancel_args).await,
+        Some(Command::Rollover(rollover_args)) => return rollover(rollover_args).await,
+        Some(Command::Sweep(sweep_args)) => return sweep(sweep_args).await,
+        Some(Command::CompleteAdaptor(complete_args)) => return adaptor::complete(complete_args),
+        Some(Command::List(list_args)) => return list_deposits(&list_args.history_db),
+        Some(Command::Show(show_args)) => {
+            return show_deposit(&show_args.history_db, show_args.deposit_txid);
+        }
+        Some(Command::Export(export_args)) => {
+            return export(
+                &export_args.history_db,
+                export_args.format,
+                export_args.out.as_deref(),
+            );
+        }
+        Some(Command::ExportLabels(labels_args)) => {
+            return export_labels(&labels_args.sessions_dir, labels_args.out.as_deref());
+        }
+        Some(Command::RecoveryKit(kit_args)) => return recovery_kit(kit_args),
+        Some(Command::PaperBackup(paper_args)) => return paper_backup(paper_args),
+        Some(Command::OpenRecoveryKit(kit_args)) => return open_recovery_kit(&kit_args.file),
+        Some(Command::Recover(recover_args)) => return recover(recover_args).await,
+        Some(Command::Watch(watch_args)) => return watch(watch_args).await,
+        Some(Command::Batch(batch_args)) => return manifest::run(batch_args).await,
+        Some(Command::Wizard(wizard_args)) => return wizard::run(wizard_args).await,
+        Some(Command::Completions(completions_args)) => {
+            return clap_complete::generate(
+                completions_args.shell,
+                &mut Cli::command(),
+                "depositor",
+                &mut std::io::stdout(),
+            );
+        }
+        Some(Command::Broadcast(broadcast_args)) => return broadcast_txs(broadcast_args).await,
+        Some(Command::MarkBroadcast(mark_args)) => {
+            return mark_broadcast(&mark_args.history_db, mark_args.deposit_txid);
+        }
+        Some(Command::VerifyReceipt(receipt_args)) => {
+            return verify_receipt(
+                &receipt_args.file,
+                receipt_args.operator_key.as_deref(),
+                &receipt_args.enclave,
+            );
+        }
+        None => cli.args.expect("deposit arguments"),
+    };
+    deposit(args).await;
+}
+
+/// Runs the protocol for the deposit in `args`, and signs and completes the deposit. Returns
+/// the txid of the deposit if it was completed.
+async fn deposit(args: Args) -> Option<Txid> {
+    let yes = args.yes;
+    deposit_reviewed(args, |_| summary::confirm("Sign the deposit?", yes)).await
+}
+
+/// Like `deposit`, but asks `review` whether to sign the deposit once the session has the
+/// verified presigned spend.
+async fn deposit_reviewed(args: Args, review: impl FnOnce(&Session) -> bool) -> Option<Txid> {
+    let secp = Secp256k1::new();
+    let network = args.network;
+
+    // Generate a new keypair or use the given private key. When preparing a session for offline
+    // signing, only the public key is known.
+    let Some(deposit_key) = keys::DepositKey::load(&secp, &args.deposit_key, network) else {
+        println!("priv key needed");
+        return None;
+    };
+    let internal_key = deposit_key.internal_key;
+    let key_source = deposit_key.key_source.clone();
+
+    let script_buf = match &deposit_key.keypair {
+        Some(keypair) => args.funding.prevout_type.script_pubkey(&secp, keypair),
+        None => ScriptBuf::new_p2tr(&secp, internal_key, None),
+    };
+    let addr = Address::from_script(script_buf.as_script(), network).unwrap();
+    println!("pub: {}", internal_key);
+    println!("address: {}", addr);
+    if args.qr {
+        qr::print_qr("address", &addr.to_string());
+    }
 
-    let secp = Secp256k1::new();
-    let network = args.network;
+    if let Some(device) = &deposit_key.device {
+        device
+            .confirm_address(&addr)
+            .expect("hardware wallet shows the address");
+    }
 
-    // Generate a new keypair or use the given private key.
-    let (keypair, script_pub) = match args.priv_key.as_deref() {
-        Some(priv_str) => {
-            let keypair = if priv_str == "new" {
-                gen_keypair(&secp)
-            } else {
-                let sk = SecretKey::from_str(&priv_str).unwrap();
-                Keypair::from_secret_key(&secp, &sk)
-            };
+    if deposit_key.generated {
+        return None;
+    }
+    if args.deposit_key.pub_key.is_some()
+        && args.deposit_key.signer_cmd.is_none()
+        && args.session_out.is_none()
+        && !args.dry_run
+    {
+        println!("--pub-key needs --session-out, --signer-cmd or --dry-run");
+        return None;
+    }
+    let script_pub = addr.script_pubkey();
 
-            let (internal_key, _parity) = keypair.x_only_public_key();
-            let script_buf = ScriptBuf::new_p2tr(&secp, internal_key, None);
-            let addr = Address::from_script(script_buf.as_script(), network).unwrap();
-            println!("priv: {}", hex::encode(keypair.secret_key().secret_bytes()));
-            println!("pub: {}", internal_key);
-            println!("address: {}", addr);
+    let decaying_multisig = match args.spend.decaying_keys.is_empty() {
+        true => None,
+        false => Some(DecayingMultisig {
+            keys: args.spend.decaying_keys.clone(),
+            delays: args.spend.decay_delays.clone(),
+        }),
+    };
 
-            if priv_str == "new" {
-                return;
-            }
+    // A silent payment fallback address is resolved to an output script once the signer's ECDH
+    // shares are known.
+    let silent_payment = args
+        .spend
+        .fallback_addr
+        .as_deref()
+        .filter(|a| SilentPaymentAddress::is_silent_payment(a))
+        .map(|a| SilentPaymentAddress::parse(a, network).expect("valid silent payment address"));
+    assert!(
+        !args.blind || silent_payment.is_none(),
+        "a silent payment fallback is derived from the deposit's inputs, which a blind session \
+         keeps from the signer"
+    );
 
-            (keypair, addr.script_pubkey())
+    //    // Address the presigned tx will send coins to.
+    let fallback_addr = match (&decaying_multisig, &silent_payment) {
+        (None, Some(_)) => args.spend.fallback_addr.clone().unwrap(),
+        (None, None) => {
+            parse_address(args.spend.fallback_addr.as_ref().unwrap(), args.network).to_string()
         }
-        _ => {
-            println!("priv key needed");
-            return;
+        (Some(policy), _) => {
+            let spend_info = policy
+                .spend_info(&secp)
+                .expect("valid decaying multisig policy");
+            for (leaf, _) in spend_info.script_map().keys() {
+                let control_block = spend_info
+                    .control_block(&(leaf.clone(), LeafVersion::TapScript))
+                    .expect("leaf in tree");
+                println!("Fallback script path: {}", leaf.to_hex_string());
+                println!("Control block: {}", hex::encode(control_block.serialize()));
+            }
+
+            let script = ScriptBuf::new_p2tr_tweaked(spend_info.output_key());
+            Address::from_script(script.as_script(), network)
+                .unwrap()
+                .to_string()
         }
     };
+    println!("fallback address: {}", fallback_addr);
+    if args.qr {
+        qr::print_qr("fallback address", &fallback_addr);
+    }
 
-    //    // Address the presigned tx will send coins to.
-    let fallback_addr = parse_address(&args.fallback_addr, args.network);
+    let prev_inheritance = inheritance::load(&args.inheritance_file);
+    if let Some(record) = &prev_inheritance {
+        match args.current_height {
+            Some(height) => inheritance::warn_expiry(record, height, args.inheritance_warn_blocks),
+            None => println!(
+                "Active inheritance tx spending deposit {} becomes valid at height {}",
+                record.deposit_txid, record.lock_time
+            ),
+        }
+    }
 
     let deposit_prevout = TxOut {
-        value: args.prev_amt,
+        value: args.funding.prev_amt,
         script_pubkey: script_pub,
     };
+    if let Some(prev_tx) = &args.funding.prev_tx {
+        verify_prevout(prev_tx, args.funding.prevout, &deposit_prevout);
+    }
 
     let utxos: Vec<TxOut> = vec![deposit_prevout.clone()];
     println!(
@@ -114,138 +2604,1664 @@ async fn main() {
         hex::encode(consensus::encode::serialize(&utxos[0]))
     );
 
-    // Input to deposit.
-    let input = TxIn {
-        previous_output: args.prevout,
-        script_sig: ScriptBuf::default(),
-        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-        witness: Witness::default(),
-    };
+    // Any further funding inputs are signed with the keys of the keyring.
+    let keyring = funding::Keyring::load(
+        &secp,
+        &args.funding.keyring_keys,
+        &args.deposit_key.key,
+        network,
+    )
+    .expect("valid keyring keys");
+    let (inputs, input_value) = args
+        .funding
+        .inputs()
+        .expect("funding inputs not overflowing");
 
     // The output the deposit will go into. Note that the output script is not yet determined at
     // this point.
     let deposit_output = TxOut {
-        value: args.output_amt,
+        value: args.outputs.output_amt,
         script_pubkey: ScriptBuf::default(),
     };
 
     // The change output is locked to a key controlled by us.
-    let change = match args.change_addr {
+    let change = match &args.outputs.change_addr {
         None => None,
         Some(addr) => {
-            let a = parse_address(&addr, args.network);
+            let a = parse_address(addr, args.network);
             Some(TxOut {
-                value: args.change_amt.unwrap(),
+                value: args.outputs.change_amt.unwrap(),
                 script_pubkey: a.script_pubkey(),
             })
         }
     };
 
-    let outputs = match change {
-        None => vec![deposit_output],
-        Some(c) => vec![deposit_output, c],
+    // The batched deposits follow the first, each locked to the key of its blind session.
+    let batch = batch::start(
+        &args.http,
+        &args.outputs.batch_deposits,
+        args.client_url,
+        network,
+    )
+    .await
+    .expect("signers started the batched sessions");
+    let mut outputs = vec![deposit_output];
+    outputs.extend(
+        batch
+            .iter()
+            .zip(&args.outputs.batch_deposits)
+            .map(|(started, deposit)| TxOut {
+                value: deposit.amount,
+                script_pubkey: started.deposit_script.clone(),
+            }),
+    );
+    outputs.extend(change);
+
+    // The OP_RETURN output goes last, after the change a payjoin receiver takes its fee from.
+    let op_return = match (&args.outputs.op_return, &args.outputs.op_return_receipt) {
+        (Some(data), _) => Some(hex::decode(data).expect("hex encoded OP_RETURN data")),
+        (_, Some(path)) => Some(read_receipt(path).message().to_vec()),
+        (None, None) => None,
+    };
+    if let Some(data) = op_return {
+        outputs.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: op_return_script(&data).expect("valid OP_RETURN data"),
+        });
+    }
+
+    let lock_time = match args.no_anti_fee_sniping {
+        true => absolute::LockTime::ZERO,
+        false => {
+            let backend = args
+                .backend
+                .backend(args.network)
+                .expect("valid backend options");
+            let chain = backend
+                .chain_state()
+                .await
+                .expect("chain tip for the deposit's locktime");
+            locktime::anti_fee_sniping(chain.tip)
+        }
     };
 
     // The transaction we want to sign and broadcast.
     let unsigned_tx = Transaction {
-        version: transaction::Version::TWO,  // Post BIP 68.
-        lock_time: absolute::LockTime::ZERO, // Ignore the locktime.
-        input: vec![input],                  // Input is 0-indexed.
-        output: outputs,                     // Outputs, order does not matter.
+        version: transaction::Version::TWO, // Post BIP 68.
+        lock_time,
+        input: inputs,   // Inputs are 0-indexed.
+        output: outputs, // Outputs, order does not matter.
     };
+    let witness_weight = args.funding.witness_weight();
+    let deposit_fee = amounts::check_deposit(
+        &unsigned_tx,
+        input_value,
+        witness_weight,
+        args.max_deposit_fee,
+    )
+    .expect("sane deposit amounts");
+    println!("Deposit fee: {}", deposit_fee);
 
     // Now we'll start the PSBT workflow.
     // Step 1: Creator role; that creates,
     // and add inputs and outputs to the PSBT.
-    let psbt = Psbt::from_unsigned_tx(unsigned_tx).expect("Could not create PSBT");
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx).expect("Could not create PSBT");
+    // The signer checks the deposit against the outputs it spends.
+    psbt.inputs[0].witness_utxo = Some(deposit_prevout.clone());
+    keyring
+        .set_inputs(&secp, &mut psbt, &args.funding.extra_inputs)
+        .expect("keyring keys of the funding inputs");
 
-    let resp = initiate_sign(args.client_url.unwrap(), &psbt, fallback_addr.to_string())
+    // Let the payjoin receiver add its inputs and outputs before the signer sees the deposit.
+    let fee_output = args.outputs.change_addr.as_ref().map(|_| 1);
+    let psbt = payjoin::join(&args.payjoin, psbt, fee_output, network)
         .await
-        .unwrap();
-    let presigned_tx = resp.spend_psbt.extract_tx().expect("valid tx");
-    let mut deposit_psbt = resp.deposit_psbt;
+        .expect("valid payjoin proposal");
+
+    if let Some(adaptor_point) = &args.spend.adaptor_point {
+        MaybePoint::from_hex(adaptor_point).expect("valid adaptor point");
+    }
+
+    let oracle_event = args
+        .oracle
+        .oracle_pubkey
+        .as_ref()
+        .map(|oracle_pubkey| OracleEvent {
+            event_id: args.oracle.event_id.clone().unwrap(),
+            oracle_pubkey: oracle_pubkey.clone(),
+            oracle_nonce: args.oracle.oracle_nonce.clone().unwrap(),
+            outcomes: args
+                .oracle
+                .outcomes
+                .iter()
+                .map(|o| {
+                    let (outcome, addr) =
+                        o.split_once('=').expect("outcome as <outcome>=<address>");
+                    OracleOutcome {
+                        outcome: outcome.to_string(),
+                        payout_addr: parse_address(addr, network).to_string(),
+                    }
+                })
+                .collect(),
+            refund_locktime: args.oracle.refund_locktime.unwrap(),
+        });
+
+    let mut req = SignPsbtReq {
+        psbt: psbt.clone(),
+        fallback_addr: fallback_addr.clone(),
+        network,
+        adaptor_point: args.spend.adaptor_point.clone(),
+        oracle_event,
+        recovery: args.paths.recovery_key.as_ref().map(|k| RecoveryPath {
+            recovery_key: k.clone(),
+            delay: args.paths.recovery_delay,
+        }),
+        expiry: args.paths.expiry_key.as_ref().map(|k| ExpiryPath {
+            expiry_key: k.clone(),
+            height: args.paths.expiry_height.unwrap(),
+        }),
+        vault: args.spend.vault_delay.map(|delay| VaultParams {
+            delay,
+            clawback_addr: parse_address(args.spend.clawback_addr.as_ref().unwrap(), network)
+                .to_string(),
+        }),
+        rollover: args.spend.rollover,
+        inheritance: args
+            .inheritance_height
+            .map(|lock_time| InheritanceParams { lock_time }),
+        decaying_multisig,
+        policy: args.paths.policy.clone(),
+        leaves: args.paths.leaves.clone(),
+        quote: None,
+        version: PROTOCOL_VERSION,
+        capabilities: CAPABILITIES.to_vec(),
+        idempotency_key: Some(hex::encode(rand::random::<[u8; 16]>())),
+        funding_proof: None,
+        spend_outputs: args
+            .spend
+            .spend_outputs
+            .iter()
+            .map(|o| {
+                let (addr, amount) = o
+                    .split_once('=')
+                    .expect("spend output as <address>=<amount>");
+                SpendOutput {
+                    address: parse_address(addr, network).to_string(),
+                    amount_sat: amounts::parse_amount(amount)
+                        .expect("valid spend output amount")
+                        .to_sat(),
+                }
+            })
+            .collect(),
+        fallback_shares: args
+            .spend
+            .fallback_shares
+            .iter()
+            .map(|s| {
+                let (addr, percent) = s
+                    .split_once('=')
+                    .expect("fallback share as <address>=<percent>");
+                FallbackShare {
+                    address: parse_address(addr, network).to_string(),
+                    percent: percent.parse().expect("fallback share in percent"),
+                }
+            })
+            .collect(),
+        spend_timelock: (args.spend.spend_lock_time.is_some()
+            || args.spend.spend_relative_blocks.is_some())
+        .then(|| SpendTimelock {
+            lock_time: args.spend.spend_lock_time,
+            relative_blocks: args.spend.spend_relative_blocks,
+        }),
+        sighash_type: args.spend.sighash_type.into(),
+        anchor: args.spend.anchor,
+    };
+    if args.prove_funding {
+        let keypair = deposit_key
+            .keypair
+            .as_ref()
+            .expect("--prove-funding needs the private key");
+        assert_eq!(
+            args.funding.prevout_type,
+            funding::FundingType::P2tr,
+            "--prove-funding needs a P2TR prevout"
+        );
+        let message = FundingProof::message(args.funding.prevout);
+        req.funding_proof = Some(FundingProof {
+            prevout: args.funding.prevout,
+            proof: SignedMessage::sign(&secp, keypair, network, message, &rand::random()),
+        });
+    }
+
+    preflight(
+        &args.http,
+        args.client_url.unwrap(),
+        &req,
+        args.max_signer_tip_age,
+    )
+    .await
+    .expect("signer ready for the request");
+    fetch_quote(&args.http, args.client_url.unwrap(), &mut req)
+        .await
+        .expect("acceptable quote from the signer");
+    let mut resp = match args.blind {
+        false => initiate_sign(&args.http, args.client_url.unwrap(), &req)
+            .await
+            .expect("signer accepted the request"),
+        true => {
+            assert!(
+                req.quote
+                    .as_ref()
+                    .is_some_and(|q| q.features.contains(&Feature::BlindSessions)),
+                "signer does not offer blind sessions"
+            );
+            blind::sign(&args.http, args.client_url.unwrap(), &req, &args.enclave)
+                .await
+                .expect("signer signed the blind session")
+        }
+    };
+    verify_response(&secp, network, &req, &resp, &args.fee_limits, &args.enclave);
+    // The signer need not return what we know of the funding inputs, but the fee and signing the
+    // deposit depend on it.
+    keyring
+        .set_inputs(&secp, &mut resp.deposit_psbt, &args.funding.extra_inputs)
+        .expect("funding inputs in deposit");
+
+    let descriptor = resp.descriptor.as_ref().expect("verified descriptor");
+    if let Some(path) = &args.descriptor_file {
+        std::fs::write(path, &descriptor.descriptor).expect("able to write descriptor");
+        println!("Wrote deposit descriptor to {}", path.display());
+    }
+
+    let presigned_tx = resp.spend_psbt.clone().extract_tx().expect("valid tx");
     let serialized_presigned_tx = consensus::encode::serialize_hex(&presigned_tx);
-    println!("Presigned Details: {:#?}", presigned_tx);
+    print!(
+        "{}",
+        render::transaction(
+            "Presigned spend",
+            &presigned_tx,
+            &prevouts(&resp.spend_psbt),
+            network
+        )
+    );
     println!("Raw presigned Transaction: {}", serialized_presigned_tx);
+    if args.qr {
+        qr::print_qr("presigned transaction", &serialized_presigned_tx);
+    }
+    if args.ur {
+        bcur::show_animated(
+            "presigned transaction",
+            bcur::BYTES_TYPE,
+            &consensus::encode::serialize(&presigned_tx),
+        );
+    }
+
+    // Only now is the deposit's txid fixed, so the batched sessions can sign. Signing deletes
+    // their keys, so a dry run leaves them unsigned.
+    let batch_spends = match args.dry_run {
+        true => vec![],
+        false => batch::finish(
+            &args.http,
+            &args.outputs.batch_deposits,
+            &batch,
+            &resp.deposit_psbt.unsigned_tx,
+            &args.enclave,
+            &args.fee_limits,
+            network,
+        )
+        .await
+        .expect("signers signed valid spends of the batched deposits"),
+    };
+    for (i, spend) in batch_spends.iter().enumerate() {
+        let tx = spend.clone().extract_tx().expect("valid tx");
+        println!(
+            "Raw presigned transaction of deposit output {}: {}",
+            i + 1,
+            consensus::encode::serialize_hex(&tx)
+        );
+    }
+
+    let mut session = Session {
+        network,
+        prevout: args.funding.prevout,
+        deposit_prevout: deposit_prevout.clone(),
+        key_source: key_source.clone(),
+        change_addr: args.outputs.change_addr.clone(),
+        payjoin_endpoint: args.payjoin.payjoin_endpoint.clone(),
+        signer: args.client_url.map(|url| url.to_string()),
+        req,
+        resp,
+        signed_psbt: None,
+        completed: false,
+        cancel_tx: None,
+        expiry: args
+            .session_expiry
+            .or(args.inheritance_height)
+            .or(args.paths.expiry_height),
+        rolled_over_to: None,
+        batch_spends,
+    };
+    if !args.dry_run {
+        session::save(&args.sessions_dir, &session);
+        history::record(&args.history_db, &session, history::VERIFIED);
+        println!(
+            "Saved session {}, continue it with the resume command if interrupted",
+            session.id()
+        );
+    }
+
+    // Leave the signing to the offline machine.
+    if let Some(path) = &args.session_out {
+        if let Some(psbt_path) = &args.psbt_out {
+            let key_source = key_source.clone().expect("--key-fingerprint for the PSBT");
+            let mut psbt = session.resp.deposit_psbt.clone();
+            set_deposit_input(
+                &mut psbt,
+                args.funding.prevout,
+                &deposit_prevout,
+                internal_key,
+                Some(key_source),
+            );
+            std::fs::write(psbt_path, psbt.serialize()).expect("able to write PSBT");
+            println!("Wrote deposit PSBT to {}", psbt_path.display());
+        }
+
+        session::store(path, &session);
+        println!(
+            "Wrote session to {}, sign it offline with the sign command",
+            path.display()
+        );
+        return None;
+    }
+
+    // Now that we have the presigned spend, we can sign the deposit.
+    summary::print(
+        network,
+        &session.resp.deposit_psbt,
+        args.funding.prevout,
+        &deposit_prevout,
+        &session.resp.spend_psbt,
+        &fallback_addr,
+    );
+    if args.dry_run {
+        let mut psbt = session.resp.deposit_psbt.clone();
+        match (&deposit_key.keypair, args.funding.prevout_type) {
+            (Some(keypair), ty) if ty != funding::FundingType::P2tr => {
+                funding::set_v0_input(
+                    &mut psbt,
+                    args.funding.prevout,
+                    &deposit_prevout,
+                    ty,
+                    keypair,
+                    key_source,
+                );
+            }
+            _ => {
+                set_deposit_input(
+                    &mut psbt,
+                    args.funding.prevout,
+                    &deposit_prevout,
+                    internal_key,
+                    key_source,
+                );
+            }
+        }
+        println!("Unsigned deposit PSBT: {}", psbt);
+        println!("Dry run, the deposit was not signed");
+        return None;
+    }
+    let deposit_txid = session.id();
+    let mut signed_deposits = signed::load(&args.signed_file);
+    if !check_not_signed(
+        &signed_deposits,
+        args.funding.prevout,
+        deposit_txid,
+        args.force,
+    ) {
+        return None;
+    }
+    if !review(&session) {
+        println!("Aborted, the deposit was not signed");
+        return None;
+    }
+
+    let mut deposit_psbt = session.resp.deposit_psbt.clone();
+    deposit_key.sign(
+        &secp,
+        network,
+        &mut deposit_psbt,
+        args.funding.prevout,
+        &deposit_prevout,
+    );
+    keyring
+        .sign(
+            &secp,
+            network,
+            &mut deposit_psbt,
+            &args.funding.extra_inputs,
+        )
+        .expect("signed funding inputs");
+    signed_deposits.add(args.funding.prevout, deposit_txid);
+    signed::store(&args.signed_file, &signed_deposits);
+    session.signed_psbt = Some(deposit_psbt.clone());
+    session::save(&args.sessions_dir, &session);
+    history::record(&args.history_db, &session, history::SIGNED);
+
+    complete_session(&session, deposit_psbt, &args.inheritance_file, args.ur);
+    session.completed = true;
+    session::save(&args.sessions_dir, &session);
+    history::record(&args.history_db, &session, history::COMPLETED);
+    Some(deposit_txid)
+}
+
+/// Verifies that output `prevout` of the hex encoded transaction `prev_tx` is `expected`, so the
+/// deposit input really is locked to our key and our signature will be valid.
+fn verify_prevout(prev_tx: &str, prevout: OutPoint, expected: &TxOut) {
+    let tx: Transaction =
+        consensus::encode::deserialize(&hex::decode(prev_tx).expect("hex encoded --prev-tx"))
+            .expect("valid --prev-tx");
+    assert_eq!(
+        tx.compute_txid(),
+        prevout.txid,
+        "--prev-tx is not the transaction of --prevout"
+    );
+
+    let output = tx
+        .output
+        .get(prevout.vout as usize)
+        .expect("--prevout output index out of range");
+    assert_eq!(
+        output.script_pubkey, expected.script_pubkey,
+        "prevout is not an output of our key"
+    );
+    assert_eq!(
+        output.value, expected.value,
+        "prevout is worth {}, not --prev-amt",
+        output.value
+    );
+}
+
+/// Checks the signer's BIP-322 `attestation` is on the fallback commitment of `resp` to `req`,
+/// and by the operator key of the receipt, if any.
+fn verify_fallback_attestation<C: Verification>(
+    secp: &Secp256k1<C>,
+    network: Network,
+    req: &SignPsbtReq,
+    resp: &SignPsbtResp,
+    attestation: &SignedMessage,
+) {
+    let commitment = bip322::fallback_commitment(
+        resp.deposit_psbt.unsigned_tx.compute_txid(),
+        resp.spend_psbt.unsigned_tx.compute_txid(),
+        &req.fallback_addr,
+    );
+    assert_eq!(
+        attestation.message, commitment,
+        "signer attested another fallback commitment"
+    );
+    attestation
+        .verify(network)
+        .expect("valid fallback attestation from the signer");
+    if let Some(receipt) = &resp.receipt {
+        let operator_key =
+            XOnlyPublicKey::from_str(&receipt.operator_key).expect("valid operator key");
+        assert_eq!(
+            attestation.address,
+            Address::p2tr(secp, operator_key, None, network).to_string(),
+            "fallback attestation is by another key than the receipt"
+        );
+    }
+    println!("Fallback commitment attested by {}", attestation.address);
+}
+
+/// Runs all checks of the signer's response to `req` that can be done before signing the deposit,
+/// returning the output script of the fallback address.
+fn verify_response<C: Verification>(
+    secp: &Secp256k1<C>,
+    network: Network,
+    req: &SignPsbtReq,
+    resp: &SignPsbtResp,
+    fee_limits: &FeeLimits,
+    enclave: &enclave::EnclaveArgs,
+) -> ScriptBuf {
+    assert_eq!(req.network, network, "session is for another network");
+    assert_eq!(
+        resp.network, network,
+        "signer built the transactions for another network"
+    );
+    assert_eq!(
+        resp.version, req.version,
+        "signer responded in another protocol version"
+    );
+    assert!(
+        resp.capabilities
+            .iter()
+            .all(|f| req.capabilities.contains(f)),
+        "signer responded with features {:?} we do not support",
+        resp.capabilities
+    );
+    verify_deposit_tx(&req.psbt, &resp.deposit_psbt);
+    let leaves = req.deposit_leaves().expect("valid script paths");
+    let mut deposit_leaves = leaves.clone();
+    if let Some(event) = &req.oracle_event {
+        deposit_leaves.extend(cet_leaves(resp, event));
+    }
+    let spend_info = verify_deposit_taptweak(secp, &resp.deposit_psbt, deposit_leaves.clone());
+    assert_eq!(
+        resp.script_paths,
+        script_paths(&spend_info, &deposit_leaves),
+        "signer reported unexpected script paths"
+    );
+    for script_path in &resp.script_paths {
+        println!("Deposit script path: {}", script_path.script);
+        println!("Control block: {}", script_path.control_block);
+    }
+
+    let descriptor = DepositDescriptor::new(&spend_info);
+    assert_eq!(
+        resp.descriptor.as_ref(),
+        Some(&descriptor),
+        "signer reported unexpected descriptor"
+    );
+    println!("Deposit descriptor: {}", descriptor.descriptor);
+    println!("Deposit internal key: {}", descriptor.internal_key);
+    if let Some(merkle_root) = &descriptor.merkle_root {
+        println!("Deposit merkle root: {}", merkle_root);
+    }
+    println!("Deposit output key: {}", descriptor.output_key);
+
+    if let Some(receipt) = &resp.receipt {
+        receipt
+            .verify_for(resp)
+            .expect("valid receipt from the signer");
+        println!("Receipt signed by operator key {}", receipt.operator_key);
+    }
+    let attestations = resp
+        .receipt
+        .as_ref()
+        .map_or(&[][..], |r| &r.enclave_attestations);
+    enclave
+        .verify(attestations, &descriptor.internal_key)
+        .expect("valid enclave attestations of the key deletion");
+    if let Some(attestation) = &resp.fallback_attestation {
+        verify_fallback_attestation(secp, network, req, resp, attestation);
+    }
+
+    let fallback_script = match SilentPaymentAddress::is_silent_payment(&req.fallback_addr) {
+        false => parse_address(&req.fallback_addr, network).script_pubkey(),
+        true => {
+            let addr = SilentPaymentAddress::parse(&req.fallback_addr, network)
+                .expect("valid silent payment address");
+            verify_silent_payment(resp, &spend_info, &addr)
+        }
+    };
+
+    if let Some(adaptor_point) = &req.adaptor_point {
+        let adaptor_point = MaybePoint::from_hex(adaptor_point).expect("valid adaptor point");
+        let adaptor_sig = verify_adaptor_sig(
+            &resp.deposit_psbt,
+            &resp.spend_psbt,
+            resp.adaptor_sig
+                .as_deref()
+                .expect("adaptor signature in response"),
+            adaptor_point,
+        );
+        println!("Spend adaptor signature: {}", adaptor_sig);
+        println!("The presigned spend must be completed with the adaptor secret before broadcast.");
+    } else {
+        verify_spend_sig(req, &resp.deposit_psbt, &resp.spend_psbt);
+    }
+    verify_spend_fee(resp, fee_limits);
+
+    if let Some(event) = &req.oracle_event {
+        verify_cets(resp, event, &spend_info);
+    }
+
+    if let Some(vault) = &req.vault {
+        verify_vault(secp, resp, vault, &fallback_script);
+    }
+
+    // Unless unvaulting or rolling over, the presigned spend pays straight to the fallback
+    // address, and the fallback shares and spend outputs asked for.
+    let mut spend_outputs = split_fallback(
+        req.psbt.unsigned_tx.output[0].value,
+        &req.fallback_shares,
+        req.network,
+    )
+    .expect("valid fallback shares");
+    spend_outputs.extend(
+        req.spend_outputs
+            .iter()
+            .map(|o| o.tx_out(req.network).expect("valid spend output")),
+    );
+    if req.anchor {
+        spend_outputs.push(anchor_output());
+    }
+    if req.vault.is_none() && !req.rollover {
+        verify_pays_exactly(
+            &resp.spend_psbt.unsigned_tx,
+            &fallback_script,
+            &spend_outputs,
+            "presigned spend",
+        );
+    }
+
+    if let Some(inheritance) = &req.inheritance {
+        verify_inheritance(resp, inheritance, &fallback_script, &spend_outputs);
+    }
+
+    if req.rollover {
+        verify_rollover(secp, resp, leaves, &fallback_script);
+    }
+
+    fallback_script
+}
+
+/// Returns whether we may sign deposit `deposit_txid` spending `prevout`, which we may not if we
+/// already signed a conflicting deposit, unless `force` is set.
+fn check_not_signed(
+    signed_deposits: &signed::SignedDeposits,
+    prevout: OutPoint,
+    deposit_txid: Txid,
+    force: bool,
+) -> bool {
+    match (signed_deposits.check(prevout, deposit_txid), force) {
+        (Ok(()), _) => true,
+        (Err(e), true) => {
+            println!("WARNING: {}", e);
+            true
+        }
+        (Err(e), false) => {
+            println!("{}, pass --force to sign anyway", e);
+            false
+        }
+    }
+}
+
+/// Reads a PSBT from a file, either binary as written by air-gapped signers, or base64.
+fn read_psbt(path: &Path) -> Psbt {
+    let data = std::fs::read(path).expect("able to read PSBT");
+    match data.starts_with(b"psbt\xff") {
+        true => Psbt::deserialize(&data).expect("valid PSBT"),
+        false => {
+            let data = String::from_utf8(data).expect("base64 PSBT");
+            Psbt::from_str(data.trim()).expect("valid PSBT")
+        }
+    }
+}
+
+/// Signs our input of the deposit with `keypair`, spending `deposit_prevout` at `prevout`. In a
+/// payjoin the deposit has other inputs too, which we leave to the receiver.
+fn sign_deposit<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    keypair: &Keypair,
+    key_source: KeySource,
+    network: Network,
+    deposit_psbt: &mut Psbt,
+    prevout: OutPoint,
+    deposit_prevout: &TxOut,
+) {
+    // Segwit v0 outputs of our key take an ECDSA signature instead of a key spend.
+    match funding::FundingType::of(secp, keypair, &deposit_prevout.script_pubkey) {
+        Some(funding::FundingType::P2tr) | None => {}
+        Some(ty) => {
+            return funding::sign_v0(
+                secp,
+                keypair,
+                key_source,
+                ty,
+                deposit_psbt,
+                prevout,
+                deposit_prevout,
+            );
+        }
+    }
 
     let mut key_map: HashMap<bitcoin::XOnlyPublicKey, PrivateKey> = HashMap::new();
     let (xpub, _) = keypair.x_only_public_key();
-    let sk = PrivateKey::new(keypair.secret_key(), args.network);
-    key_map.insert(xpub, sk);
+    key_map.insert(xpub, PrivateKey::new(keypair.secret_key(), network));
+
+    let our_input = set_deposit_input(
+        deposit_psbt,
+        prevout,
+        deposit_prevout,
+        xpub,
+        Some(key_source),
+    );
+    deposit_psbt.sign(&key_map, secp).expect("able to sign");
+    for key in key_map.values_mut() {
+        key.inner.non_secure_erase();
+    }
+    finalize_deposit_input(deposit_psbt, our_input);
+}
 
+/// Fills in the PSBT input spending `deposit_prevout` at `prevout` with a key spend by
+/// `internal_key`, returning its index. The origin of the key, if known, is how the signer finds
+/// it.
+fn set_deposit_input(
+    deposit_psbt: &mut Psbt,
+    prevout: OutPoint,
+    deposit_prevout: &TxOut,
+    internal_key: XOnlyPublicKey,
+    key_source: Option<KeySource>,
+) -> usize {
     let mut deposit_origin_input = BTreeMap::new();
-    deposit_origin_input.insert(xpub, (vec![], KeySource::default()));
+    if let Some(key_source) = key_source {
+        deposit_origin_input.insert(internal_key, (vec![], key_source));
+    }
 
-    // Now that we have the presigned spend, we can sign the deposit.
+    let our_input = deposit_psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|i| i.previous_output == prevout)
+        .expect("our input in deposit");
     let ty = TapSighashType::All.into();
-    deposit_psbt.inputs = vec![Input {
+    deposit_psbt.inputs[our_input] = Input {
         witness_utxo: Some(deposit_prevout.clone()),
         tap_key_origins: deposit_origin_input,
-        tap_internal_key: Some(xpub),
+        tap_internal_key: Some(internal_key),
         sighash_type: Some(ty),
         ..Default::default()
-    }];
-
-    deposit_psbt.sign(&key_map, &secp).expect("able to sign");
-    deposit_psbt.inputs.iter_mut().for_each(|input| {
-        let script_witness = Witness::p2tr_key_spend(&input.tap_key_sig.unwrap());
-        input.final_script_witness = Some(script_witness);
-
-        // Clear all the data fields as per the spec.
-        input.partial_sigs = BTreeMap::new();
-        input.sighash_type = None;
-        input.redeem_script = None;
-        input.witness_script = None;
-        input.bip32_derivation = BTreeMap::new();
-    });
+    };
 
-    println!("Deposit PSBT: {:#?}", deposit_psbt);
+    our_input
+}
 
-    let signed_tx = deposit_psbt.extract_tx().expect("valid transaction");
+/// Finalizes our signed input of the deposit.
+fn finalize_deposit_input(deposit_psbt: &mut Psbt, our_input: usize) {
+    let input = &mut deposit_psbt.inputs[our_input];
+    let script_witness = Witness::p2tr_key_spend(&input.tap_key_sig.expect("deposit signature"));
+    input.final_script_witness = Some(script_witness);
 
-    let serialized_signed_tx = consensus::encode::serialize_hex(&signed_tx);
-    println!("Deposit Details: {:#?}", signed_tx);
-    // check with:
-    // bitcoin-cli decoderawtransaction <RAW_TX> true
-    println!("Raw deposit Transaction: {}", serialized_signed_tx);
+    // Clear all the data fields as per the spec.
+    input.partial_sigs = BTreeMap::new();
+    input.sighash_type = None;
+    input.redeem_script = None;
+    input.witness_script = None;
+    input.bip32_derivation = BTreeMap::new();
+}
 
-    let res = signed_tx
-        .verify(|op| {
-            println!("fetchin op {}", op);
-            Some(utxos[0].clone())
-        })
-        .unwrap();
-    println!("Transaction Result: {:#?}", res);
+/// Returns the deposit transaction the presigned spend is checked against. Unless it is left to
+/// the payjoin receiver at `payjoin_endpoint` to sign, it is extracted from the signed PSBT and
+/// verified.
+fn complete_deposit(
+    deposit_psbt: Psbt,
+    network: Network,
+    payjoin_endpoint: Option<&str>,
+    ur: bool,
+    deposit_prevout: &TxOut,
+) -> Transaction {
+    match payjoin_endpoint {
+        // The receiver's signatures do not change the txid, so the unsigned transaction is
+        // enough to check the presigned spend against.
+        Some(endpoint) => {
+            println!(
+                "Deposit PSBT for the payjoin receiver at {} to sign and broadcast: {}",
+                endpoint, deposit_psbt
+            );
+            if ur {
+                bcur::show_animated("deposit PSBT", bcur::PSBT_TYPE, &deposit_psbt.serialize());
+            }
+            deposit_psbt.unsigned_tx
+        }
+        None => {
+            let prevouts = prevouts(&deposit_psbt);
+            let signed_tx = deposit_psbt.extract_tx().expect("valid transaction");
+
+            let serialized_signed_tx = consensus::encode::serialize_hex(&signed_tx);
+            print!(
+                "{}",
+                render::transaction("Deposit", &signed_tx, &prevouts, network)
+            );
+            // check with:
+            // bitcoin-cli decoderawtransaction <RAW_TX> true
+            println!("Raw deposit Transaction: {}", serialized_signed_tx);
+
+            signed_tx
+                .verify(|op| {
+                    println!("fetchin op {}", op);
+                    let i = signed_tx
+                        .input
+                        .iter()
+                        .position(|txin| txin.previous_output == *op)?;
+                    prevouts[i]
+                        .clone()
+                        .or_else(|| Some(deposit_prevout.clone()))
+                })
+                .unwrap();
+            println!("Deposit transaction verified");
+            signed_tx
+        }
+    }
+}
+
+fn store_inheritance(path: &Path, record: InheritanceRecord, prev: Option<InheritanceRecord>) {
+    inheritance::store(path, &record);
+    println!("Stored active inheritance tx in {}", path.display());
+
+    // The previous inheritance tx is not invalidated by this one, only by spending its deposit
+    // output.
+    if let Some(prev) = prev {
+        println!(
+            "NOTE: the previous inheritance tx spending deposit {} stays valid from height {} until that deposit is spent",
+            prev.deposit_txid, prev.lock_time
+        );
+    }
+}
 
-    // TODO: verify presigned tx before signing
-    let res = presigned_tx
+/// Verifies that the presigned spend spends the deposit output of the final deposit transaction,
+/// as it can never be broadcast otherwise.
+fn verify_txid_binding(presigned_tx: &Transaction, signed_tx: &Transaction) {
+    let deposit_op = OutPoint {
+        txid: signed_tx.compute_txid(),
+        vout: 0,
+    };
+    assert!(
+        presigned_tx
+            .input
+            .iter()
+            .any(|i| i.previous_output == deposit_op),
+        "presigned spend does not spend the final deposit {}",
+        deposit_op
+    );
+}
+
+/// Verifies the presigned spend against the deposit output it spends.
+fn verify_presigned(presigned_tx: &Transaction, signed_tx: &Transaction) {
+    presigned_tx
         .verify(|op| {
             println!("fetchin op {}", op);
             Some(signed_tx.output[0].clone())
         })
         .unwrap();
-    println!("Pre-signed Transaction Result: {:#?}", res);
+    println!("Presigned spend verified");
+}
+
+/// Verifies that the deposit returned by the signer is the transaction we built, with only the
+/// deposit output script filled in.
+fn verify_deposit_tx(req_psbt: &Psbt, deposit_psbt: &Psbt) {
+    let tx = &deposit_psbt.unsigned_tx;
+    assert_eq!(
+        tx.output.len(),
+        req_psbt.unsigned_tx.output.len(),
+        "signer changed the deposit outputs"
+    );
+    assert_eq!(
+        deposit_psbt.inputs.len(),
+        tx.input.len(),
+        "deposit PSBT must have one input entry per input"
+    );
+
+    let mut expected = req_psbt.unsigned_tx.clone();
+    expected.output[0].script_pubkey = tx.output[0].script_pubkey.clone();
+    assert_eq!(
+        tx, &expected,
+        "signer returned a different deposit than we built"
+    );
+}
+
+/// Verifies that the deposit output commits to exactly the script paths we requested, using the
+/// ephemeral key the signer reported as internal key.
+fn verify_deposit_taptweak<C: Verification>(
+    secp: &Secp256k1<C>,
+    deposit_psbt: &Psbt,
+    leaves: Vec<ScriptBuf>,
+) -> TaprootSpendInfo {
+    let internal_key = deposit_psbt.outputs[0]
+        .tap_internal_key
+        .expect("internal key for deposit output");
+    let spend_info = deposit_spend_info(secp, internal_key, leaves);
+
+    let expected = ScriptBuf::new_p2tr_tweaked(spend_info.output_key());
+    assert_eq!(
+        deposit_psbt.unsigned_tx.output[0].script_pubkey, expected,
+        "deposit output must commit to the requested script paths"
+    );
+
+    spend_info
+}
+
+/// Verifies that the ECDH shares are from the signers of the deposit output key, and derives the
+/// silent payment output of the presigned spend from them.
+fn verify_silent_payment(
+    resp: &SignPsbtResp,
+    spend_info: &TaprootSpendInfo,
+    addr: &SilentPaymentAddress,
+) -> ScriptBuf {
+    let pubkeys: Vec<Point> = resp
+        .ecdh_shares
+        .iter()
+        .map(|s| Point::from_hex(&s.pubkey).expect("valid signer key"))
+        .collect();
+    let key_agg_ctx = KeyAggContext::new(pubkeys).expect("valid signer keys");
+    let key_agg_ctx = match spend_info.merkle_root() {
+        None => key_agg_ctx.with_unspendable_taproot_tweak().unwrap(),
+        Some(root) => key_agg_ctx
+            .with_taproot_tweak(&root.to_byte_array())
+            .unwrap(),
+    };
+
+    let output_key: Point = key_agg_ctx.aggregated_pubkey();
+    let deposit_script = &resp.deposit_psbt.unsigned_tx.output[0].script_pubkey;
+    assert_eq!(
+        &deposit_script.as_bytes()[2..34],
+        &output_key.serialize_xonly()[..],
+        "ECDH shares must be from the signers of the deposit"
+    );
+
+    let deposit_op = OutPoint {
+        txid: resp.deposit_psbt.unsigned_tx.compute_txid(),
+        vout: 0,
+    };
+    let script = silent_payment::output_script(addr, &key_agg_ctx, &resp.ecdh_shares, deposit_op)
+        .expect("valid ECDH shares");
+    println!("Silent payment fallback script: {}", script.to_hex_string());
+    script
+}
+
+/// Verifies that the adaptor signature for a spend of the deposit output is valid for the
+/// deposit output key, such that adapting it with the secret of `adaptor_point` yields a valid
+/// signature.
+fn verify_adaptor_sig(
+    deposit_psbt: &Psbt,
+    spend_psbt: &Psbt,
+    adaptor_sig: &str,
+    adaptor_point: MaybePoint,
+) -> AdaptorSignature {
+    let adaptor_sig = AdaptorSignature::from_hex(adaptor_sig).expect("valid adaptor signature");
+
+    // The deposit output is P2TR, so the witness program is the x-only output key.
+    let deposit_script = &deposit_psbt.unsigned_tx.output[0].script_pubkey;
+    assert!(deposit_script.is_p2tr(), "deposit output must be p2tr");
+    let output_key: [u8; 32] = deposit_script.as_bytes()[2..34].try_into().unwrap();
+    let output_key = Point::lift_x(&output_key).expect("valid output key");
+
+    let mut cache = SighashCache::new(&spend_psbt.unsigned_tx);
+    let (msg, _) = spend_psbt
+        .sighash_taproot(0, &mut cache, None)
+        .expect("spend sighash");
+
+    musig2::adaptor::verify_single(output_key, &adaptor_sig, msg.as_ref(), adaptor_point)
+        .expect("adaptor signature must be valid");
+
+    adaptor_sig
+}
+
+/// Verifies that `cet` spends the deposit output of `spend_info` through the leaf of its key,
+/// which the PSBT carries for finalizing it, and that its adaptor signature is valid for the key.
+fn verify_cet_adaptor_sig(spend_info: &TaprootSpendInfo, cet: &Cet, adaptor_point: MaybePoint) {
+    let adaptor_sig =
+        AdaptorSignature::from_hex(&cet.adaptor_sig).expect("valid adaptor signature");
+    let key = XOnlyPublicKey::from_str(&cet.leaf_key).expect("valid CET key");
+    let leaf = (checksig_script(key), LeafVersion::TapScript);
+    let control_block = spend_info
+        .control_block(&leaf)
+        .expect("CET leaf in the deposit output");
+    assert_eq!(
+        cet.psbt.inputs[0].tap_scripts.get(&control_block),
+        Some(&leaf),
+        "CET must carry the script path it spends"
+    );
+
+    let mut cache = SighashCache::new(&cet.psbt.unsigned_tx);
+    let leaf_hash = TapLeafHash::from_script(&leaf.0, leaf.1);
+    let (msg, _) = cet
+        .psbt
+        .sighash_taproot(0, &mut cache, Some(leaf_hash))
+        .expect("CET sighash");
+
+    let key = Point::lift_x(&key.serialize()).expect("valid CET key");
+    musig2::adaptor::verify_single(key, &adaptor_sig, msg.as_ref(), adaptor_point)
+        .expect("CET adaptor signature must be valid");
+}
+
+/// Verifies that `tx` has a single output, paying to `script`, so no value can go anywhere else.
+fn verify_pays_only(tx: &Transaction, script: &ScriptBuf, name: &str) {
+    verify_pays_exactly(tx, script, &[], name);
+}
+
+/// Verifies that `tx` pays to `script`, then exactly `outputs` and nothing else.
+fn verify_pays_exactly(tx: &Transaction, script: &ScriptBuf, outputs: &[TxOut], name: &str) {
+    assert_eq!(
+        tx.output.len(),
+        1 + outputs.len(),
+        "{} must have {} outputs",
+        name,
+        1 + outputs.len()
+    );
+    assert_eq!(
+        &tx.output[0].script_pubkey, script,
+        "{} pays to unexpected script",
+        name
+    );
+    assert_eq!(
+        &tx.output[1..],
+        outputs,
+        "{} pays unexpected spend outputs",
+        name
+    );
+}
+
+/// Verifies that the fee of the presigned spend, the part of the deposit output it does not pay
+/// out, stays within `limits`, and matches what the signer reported choosing.
+fn verify_spend_fee(resp: &SignPsbtResp, limits: &FeeLimits) {
+    let spend_psbt = &resp.spend_psbt;
+    let input_value = resp.deposit_psbt.unsigned_tx.output[0].value;
+    let output_value = spend_psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .try_fold(Amount::ZERO, |sum, o| sum.checked_add(o.value))
+        .expect("valid output amounts");
+    let fee = input_value
+        .checked_sub(output_value)
+        .expect("presigned spend must not pay out more than the deposit");
+
+    let vsize = spend_psbt.clone().extract_tx().expect("valid tx").vsize() as u64;
+    let feerate = fee.to_sat() / vsize;
+    println!("Presigned spend fee: {} ({} sat/vB)", fee, feerate);
+    if let Some(spend_fee) = &resp.spend_fee {
+        assert_eq!(
+            spend_fee.fee_sat,
+            fee.to_sat(),
+            "signer reported a different fee than the presigned spend pays"
+        );
+        println!("Signer's fee rationale: {}", spend_fee.rationale);
+    }
+    assert!(
+        fee <= limits.max_spend_fee,
+        "presigned spend fee {} exceeds --max-spend-fee {}",
+        fee,
+        limits.max_spend_fee
+    );
+    assert!(
+        feerate <= limits.max_spend_feerate,
+        "presigned spend feerate {} sat/vB exceeds --max-spend-feerate {}",
+        feerate,
+        limits.max_spend_feerate
+    );
+}
+
+/// Verifies that the presigned spend is a key spend of the deposit output, signed for the deposit
+/// output key, and with the locktime the signer must have used for `req`. As the deposit txid does
+/// not depend on our signature, this can be checked before we sign the deposit.
+fn verify_spend_sig(req: &SignPsbtReq, deposit_psbt: &Psbt, spend_psbt: &Psbt) {
+    let deposit_tx = &deposit_psbt.unsigned_tx;
+    let deposit_op = OutPoint {
+        txid: deposit_tx.compute_txid(),
+        vout: 0,
+    };
+
+    let tx = &spend_psbt.unsigned_tx;
+    assert_eq!(
+        tx.input.len(),
+        1,
+        "presigned spend must have a single input"
+    );
+    assert_eq!(
+        tx.input[0].previous_output, deposit_op,
+        "presigned spend must spend the deposit"
+    );
+    // The timelocks asked for must be echoed in the spend, and no others.
+    let timelock = req.spend_timelock.as_ref();
+    assert_eq!(
+        tx.input[0].sequence,
+        timelock.map_or(Sequence::ENABLE_RBF_NO_LOCKTIME, |t| t.sequence()),
+        "presigned spend has unexpected relative timelock"
+    );
+
+    let lock_height = match (&req.oracle_event, &req.inheritance) {
+        (Some(event), _) => Some(event.refund_locktime),
+        (_, Some(inheritance)) => Some(inheritance.lock_time),
+        (None, None) => None,
+    };
+    let lock_time = match lock_height {
+        None => timelock
+            .and_then(|t| t.lock_time())
+            .unwrap_or(absolute::LockTime::ZERO),
+        Some(height) => absolute::LockTime::from_height(height).expect("valid lock height"),
+    };
+    assert_eq!(
+        tx.lock_time, lock_time,
+        "presigned spend has unexpected locktime"
+    );
+
+    // The sighash commits to the spent output, so it must be the deposit output itself.
+    let input = &spend_psbt.inputs[0];
+    assert_eq!(
+        input.witness_utxo.as_ref(),
+        Some(&deposit_tx.output[0]),
+        "presigned spend must commit to the deposit output"
+    );
+    let witness = input
+        .final_script_witness
+        .as_ref()
+        .expect("presigned spend must be signed");
+    assert_eq!(witness.len(), 1, "presigned spend must be a key spend");
+    let sig = taproot::Signature::from_slice(&witness[0]).expect("valid spend signature");
+
+    // The sighash is computed with the type recorded in the PSBT, which must be the one asked
+    // for, as must the one the signature commits to.
+    let requested = req.sighash_type.tap_sighash_type();
+    let recorded = input
+        .sighash_type
+        .map(|ty| ty.taproot_hash_ty().expect("taproot type"));
+    assert_eq!(
+        recorded.unwrap_or(TapSighashType::Default),
+        requested,
+        "presigned spend records unexpected sighash type"
+    );
+    let mut cache = SighashCache::new(tx);
+    let (msg, sighash_type) = spend_psbt
+        .sighash_taproot(0, &mut cache, None)
+        .expect("spend sighash");
+    assert_eq!(
+        sig.sighash_type, sighash_type,
+        "presigned spend signed with unexpected sighash type"
+    );
+
+    // The deposit output is P2TR, so the witness program is the x-only output key.
+    let deposit_script = &deposit_tx.output[0].script_pubkey;
+    assert!(deposit_script.is_p2tr(), "deposit output must be p2tr");
+    let output_key: [u8; 32] = deposit_script.as_bytes()[2..34].try_into().unwrap();
+    let output_key = Point::lift_x(&output_key).expect("valid output key");
+
+    musig2::verify_single(output_key, sig.signature.serialize(), msg.as_ref())
+        .expect("presigned spend signature must be valid for the deposit output key");
+    println!("Presigned spend signature: valid");
+}
+
+/// The CET for `outcome`.
+fn cet_for<'a>(resp: &'a SignPsbtResp, outcome: &OracleOutcome) -> &'a Cet {
+    resp.cets
+        .iter()
+        .find(|c| c.outcome == outcome.outcome)
+        .expect("CET for outcome")
+}
+
+/// The leaves of the CET keys the deposit output commits to, in the order of the outcomes.
+fn cet_leaves(resp: &SignPsbtResp, event: &OracleEvent) -> Vec<ScriptBuf> {
+    event
+        .outcomes
+        .iter()
+        .map(|outcome| {
+            let key =
+                XOnlyPublicKey::from_str(&cet_for(resp, outcome).leaf_key).expect("valid CET key");
+            checksig_script(key)
+        })
+        .collect()
+}
+
+/// Verifies that the refund and a CET for every outcome of the oracle event were returned, each
+/// spending the deposit output through the leaf of a key of its own to the outcome's payout
+/// address, and adaptor signed to the point revealed by the oracle attesting to that outcome.
+fn verify_cets(resp: &SignPsbtResp, event: &OracleEvent, spend_info: &TaprootSpendInfo) {
+    let refund_locktime = resp.spend_psbt.unsigned_tx.lock_time;
+    assert_eq!(
+        refund_locktime,
+        absolute::LockTime::from_height(event.refund_locktime).expect("valid refund locktime"),
+        "refund must not be valid before the requested locktime"
+    );
+
+    let deposit_op = OutPoint {
+        txid: resp.deposit_psbt.unsigned_tx.compute_txid(),
+        vout: 0,
+    };
+
+    assert_eq!(resp.cets.len(), event.outcomes.len(), "one CET per outcome");
+    let mut keys = vec![];
+    for outcome in &event.outcomes {
+        let cet = cet_for(resp, outcome);
+        assert!(
+            !keys.contains(&&cet.leaf_key),
+            "each CET must be signed by a key of its own"
+        );
+        keys.push(&cet.leaf_key);
+
+        let tx = &cet.psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1, "CET must have a single input");
+        assert_eq!(
+            tx.input[0].previous_output, deposit_op,
+            "CET must spend the deposit"
+        );
+
+        let payout_script = Address::from_str(&outcome.payout_addr)
+            .unwrap()
+            .assume_checked()
+            .script_pubkey();
+        verify_pays_only(tx, &payout_script, "CET");
+
+        let point = attestation_point(&event.oracle_pubkey, &event.oracle_nonce, &outcome.outcome)
+            .expect("valid oracle announcement");
+        verify_cet_adaptor_sig(spend_info, cet, point);
+
+        println!(
+            "CET for outcome '{}': {}",
+            outcome.outcome,
+            consensus::encode::serialize_hex(tx)
+        );
+        println!("CET adaptor signature: {}", cet.adaptor_sig);
+    }
+}
+
+/// Verifies that the presigned spend only pays to the heir, and is not valid before the
+/// inheritance height.
+fn verify_inheritance(
+    resp: &SignPsbtResp,
+    params: &InheritanceParams,
+    heir_script: &ScriptBuf,
+    spend_outputs: &[TxOut],
+) {
+    let tx = &resp.spend_psbt.unsigned_tx;
+    assert_eq!(
+        tx.lock_time,
+        absolute::LockTime::from_height(params.lock_time).expect("valid inheritance height"),
+        "inheritance tx must be timelocked"
+    );
+    assert!(
+        tx.input
+            .iter()
+            .all(|i| i.sequence.enables_absolute_lock_time()),
+        "inheritance tx must enable the timelock"
+    );
+    verify_pays_exactly(tx, heir_script, spend_outputs, "inheritance tx");
+}
+
+/// Verifies that the presigned spend pays into a new deposit output, committing to the same
+/// script paths under a new ephemeral key, and that the new output has a presigned spend to the
+/// fallback address.
+fn verify_rollover<C: Verification>(
+    secp: &Secp256k1<C>,
+    resp: &SignPsbtResp,
+    leaves: Vec<ScriptBuf>,
+    fallback_script: &ScriptBuf,
+) {
+    let rollover_spend_psbt = resp
+        .rollover_spend_psbt
+        .as_ref()
+        .expect("rollover spend in response");
+
+    let rollover_tx = &resp.spend_psbt.unsigned_tx;
+    assert_eq!(
+        rollover_tx.output.len(),
+        1,
+        "rollover must have a single output"
+    );
+    let internal_key = verify_deposit_taptweak(secp, &resp.spend_psbt, leaves).internal_key();
+    assert_ne!(
+        Some(internal_key),
+        resp.deposit_psbt.outputs[0].tap_internal_key,
+        "rollover must use a new ephemeral key"
+    );
+    println!("Rollover ephemeral key: {}", internal_key);
+
+    let tx = rollover_spend_psbt.clone().extract_tx().expect("valid tx");
+    assert_eq!(tx.input.len(), 1, "rollover spend must have a single input");
+    assert_eq!(
+        tx.input[0].previous_output,
+        OutPoint {
+            txid: rollover_tx.compute_txid(),
+            vout: 0,
+        },
+        "rollover spend must spend the new deposit"
+    );
+    verify_pays_only(&tx, fallback_script, "rollover spend");
+
+    tx.verify(|_| Some(rollover_tx.output[0].clone())).unwrap();
+    println!("Rollover spend verified");
+    println!(
+        "Raw rollover spend Transaction: {}",
+        consensus::encode::serialize_hex(&tx)
+    );
+}
+
+/// Verifies that the presigned spend unvaults into an output of new keys, which only the
+/// clawback can spend right away, and the final spend to the fallback address through a leaf that
+/// enforces the vault delay. Each spend must be signed by a key of its own.
+fn verify_vault<C: Verification>(
+    secp: &Secp256k1<C>,
+    resp: &SignPsbtResp,
+    params: &VaultParams,
+    fallback_script: &ScriptBuf,
+) {
+    let vault = resp.vault.as_ref().expect("vault spends in response");
+
+    let unvault_tx = &resp.spend_psbt.unsigned_tx;
+    assert_eq!(
+        unvault_tx.output.len(),
+        1,
+        "unvault must have a single output"
+    );
+    let final_key = XOnlyPublicKey::from_str(&vault.final_key).expect("valid final spend key");
+    let final_leaf = unvault_script(final_key, params.delay);
+    let clawback_key =
+        verify_deposit_taptweak(secp, &resp.spend_psbt, vec![final_leaf.clone()]).internal_key();
+    let deposit_key = resp.deposit_psbt.outputs[0].tap_internal_key;
+    assert!(
+        Some(clawback_key) != deposit_key && Some(final_key) != deposit_key,
+        "vault spends must be signed by keys other than the deposit's"
+    );
+    assert_ne!(
+        final_key, clawback_key,
+        "final spend and clawback must be signed by keys of their own"
+    );
+    println!("Vault final spend key: {}", final_key);
+    println!("Vault clawback key: {}", clawback_key);
+
+    let unvault_op = OutPoint {
+        txid: unvault_tx.compute_txid(),
+        vout: 0,
+    };
+    let clawback_script = Address::from_str(&params.clawback_addr)
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
+
+    for (name, psbt, script) in [
+        ("final", &vault.final_psbt, fallback_script),
+        ("clawback", &vault.clawback_psbt, &clawback_script),
+    ] {
+        let tx = psbt.clone().extract_tx().expect("valid tx");
+        assert_eq!(tx.input.len(), 1, "{} spend must have a single input", name);
+        assert_eq!(
+            tx.input[0].previous_output, unvault_op,
+            "{} spend must spend the unvault",
+            name
+        );
+        verify_pays_only(&tx, script, &format!("{} spend", name));
+
+        // Check the relative timelock the vault depends on, and the final spend can only be
+        // valid through the leaf enforcing it.
+        let sequence = tx.input[0].sequence;
+        if name == "final" {
+            assert_eq!(
+                sequence,
+                Sequence::from_height(params.delay),
+                "final spend must be delayed"
+            );
+            let witness = &tx.input[0].witness;
+            assert!(
+                witness.len() == 3 && &witness[1] == final_leaf.as_bytes(),
+                "final spend must be signed through the delayed leaf"
+            );
+        } else {
+            assert!(
+                !sequence.is_relative_lock_time(),
+                "clawback must not be delayed"
+            );
+        }
+
+        tx.verify(|_| Some(unvault_tx.output[0].clone())).unwrap();
+        println!("Vault {} spend verified", name);
+        println!(
+            "Raw vault {} spend Transaction: {}",
+            name,
+            consensus::encode::serialize_hex(&tx)
+        );
+    }
 }
 
+/// Environment variable with the API key to identify to the signer with, if it requires one.
+const API_KEY_ENV: &str = "EPHEMERAL_SIGN_API_KEY";
+
+/// Checks the signer at `client_addr` is on the network of `req`, follows the chain, and allows
+/// the deposit, so a misconfigured signer is caught before the request is sent.
+async fn preflight(
+    http: &http::HttpArgs,
+    client_addr: SocketAddr,
+    req: &SignPsbtReq,
+    max_tip_age: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = http.client();
+    let url = format!("http://{}/health", client_addr);
+    let resp = http.send(true, || client.get(&url)).await?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        println!("Signer has no health endpoint, skipping the preflight check");
+        return Ok(());
+    }
+    let health = resp.error_for_status()?.json::<HealthResp>().await?;
+    println!("Signer version {} on {}", health.version, health.network);
+
+    if health.network != req.network {
+        return Err(format!(
+            "signer is on {}, but the deposit is for {}",
+            health.network, req.network
+        )
+        .into());
+    }
+    let policy = &health.policy;
+    if !policy.networks.is_empty() && !policy.networks.contains(&req.network) {
+        return Err(format!("signer policy does not allow {}", req.network).into());
+    }
+    let deposit_value = req.psbt.unsigned_tx.output[0].value;
+    if let Some(max) = policy.max_deposit_sat {
+        if deposit_value.to_sat() > max {
+            return Err(format!(
+                "deposit of {} exceeds the signer's maximum of {} sat",
+                deposit_value, max
+            )
+            .into());
+        }
+    }
+
+    if let Some(timelock) = &req.spend_timelock {
+        let blocks = timelock.relative_blocks.unwrap_or(0);
+        if policy.max_relative_blocks.is_some_and(|max| blocks > max) {
+            return Err(format!(
+                "relative timelock of {} blocks exceeds the signer's maximum",
+                blocks
+            )
+            .into());
+        }
+        // Without the signer's tip only a time locktime can be checked up front.
+        let lock_time = timelock.lock_time.unwrap_or(0) as u64;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let delay = match &health.tip {
+            _ if lock_time >= 500_000_000 => Some(lock_time.saturating_sub(now)),
+            Some(tip) => Some(lock_time.saturating_sub(tip.height as u64) * 600),
+            None => None,
+        };
+        if let (Some(delay), Some(max)) = (delay, policy.max_lock_delay_secs) {
+            if delay > max {
+                return Err(format!(
+                    "spend locktime is {} seconds out, more than the signer's maximum of {}",
+                    delay, max
+                )
+                .into());
+            }
+        }
+    }
+
+    match &health.tip {
+        None => println!("Signer does not report its chain tip"),
+        Some(tip) => {
+            println!("Signer chain tip: {} ({})", tip.height, tip.hash);
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            let age = now.saturating_sub(tip.time);
+            if req.network != Network::Regtest && age > max_tip_age {
+                return Err(format!(
+                    "signer's chain tip {} is {} minutes old, it is likely lagging",
+                    tip.height,
+                    age / 60
+                )
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Features of the protocol this depositor supports.
+const CAPABILITIES: [Feature; 16] = [
+    Feature::Musig2,
+    Feature::ScriptPaths,
+    Feature::AdaptorSignatures,
+    Feature::Cets,
+    Feature::Vaults,
+    Feature::Rollover,
+    Feature::Inheritance,
+    Feature::DecayingMultisig,
+    Feature::SilentPayments,
+    Feature::BinaryEncoding,
+    Feature::BlindSessions,
+    Feature::SpendOutputs,
+    Feature::WeightedFallback,
+    Feature::SpendTimelock,
+    Feature::SighashTypes,
+    Feature::Anchors,
+];
+
+/// Fetches the signer's terms and shows them, checking it supports what `req` asks for. The
+/// request is then made on the quote, in the newest protocol version both sides speak. A signer
+/// that does not quote its terms predates quotes, so it is spoken to in the first version.
+async fn fetch_quote(
+    http: &http::HttpArgs,
+    client_addr: SocketAddr,
+    req: &mut SignPsbtReq,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = http.client();
+    let url = format!("http://{}/quote", client_addr);
+    let resp = http.send(true, || client.get(&url)).await?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        println!("Signer does not quote its terms");
+        req.version = PROTOCOL_VERSIONS[0];
+        return Ok(());
+    }
+    let quote = resp.error_for_status()?.json::<Quote>().await?;
+
+    println!("Signer quote {}:", quote.quote_id);
+    println!("  Protocol versions: {:?}", quote.protocol_versions);
+    match &quote.fee.rate {
+        QuotedRate::Fixed { sat } => println!("  Spend fee: {} sat", sat),
+        QuotedRate::Feerate { sat_per_vb } => println!("  Spend feerate: {} sat/vB", sat_per_vb),
+    }
+    if let Some(percent) = quote.fee.max_percent {
+        println!("  Spend fee cap: {}% of the deposit", percent);
+    }
+    println!("  Policy: {:?}", quote.policy);
+    println!("  Features: {:?}", quote.features);
+    println!("  Valid until: {}", quote.expires_at);
+
+    let Some(version) = negotiate_version(&quote.protocol_versions) else {
+        return Err(format!(
+            "signer speaks none of protocol versions {:?}",
+            PROTOCOL_VERSIONS
+        )
+        .into());
+    };
+    if quote.network != req.network {
+        return Err(format!("signer quoted for {}", quote.network).into());
+    }
+    for feature in req.required_features() {
+        if !quote.features.contains(&feature) {
+            return Err(format!("signer does not support {:?}", feature).into());
+        }
+    }
+    req.version = version;
+    req.quote = Some(quote);
+    Ok(())
+}
+
+/// What the depositor can do about error `e` from the signer.
+fn advice(e: &SignPsbtError) -> String {
+    match e {
+        SignPsbtError::InvalidRequest { .. } | SignPsbtError::InvalidPsbt { .. } => {
+            "Fix the request before retrying.".to_string()
+        }
+        SignPsbtError::InvalidFallback(_) => "Check the fallback address.".to_string(),
+        SignPsbtError::PolicyViolation(v) => match v.rule {
+            PolicyRule::Quota => "Retry once the quota window has passed.".to_string(),
+            PolicyRule::Liability => {
+                "Retry once the signer's outstanding deposits have settled.".to_string()
+            }
+            PolicyRule::QuoteTerms => "Retry to sign on a fresh quote.".to_string(),
+            _ => "The signer's policy does not allow this request, see its /health.".to_string(),
+        },
+        SignPsbtError::RateLimited {
+            retry_after_secs, ..
+        } => format!("Retry in {} seconds.", retry_after_secs),
+        SignPsbtError::Expired(_) => "Nothing was signed, retry the request.".to_string(),
+        SignPsbtError::Revoked { .. } => {
+            "The signer's operator revoked the session, ask them before retrying.".to_string()
+        }
+        SignPsbtError::Unavailable { .. } => "Retry once the signer is back.".to_string(),
+        SignPsbtError::Internal { .. } => "Report this to the signer's operator.".to_string(),
+    }
+}
+
+/// Sends `body` to the signer at `client_addr`. A request the signer declines fails with its
+/// `SignPsbtError`. Failures that are safe to retry are retried, a lost response only if the
+/// request carries an idempotency key for the signer to replay its response to.
 async fn initiate_sign(
+    http: &http::HttpArgs,
     client_addr: SocketAddr,
-    psbt: &Psbt,
-    fallback_addr: String,
-) -> Result<SignPsbtResp, reqwest::Error> {
-    let client = reqwest::Client::new();
+    body: &SignPsbtReq,
+) -> Result<SignPsbtResp, Box<dyn std::error::Error>> {
+    let client = http.client();
     let url = format!("http://{}/psbt", client_addr);
     println!("url: {}", url);
 
-    let body_json = serde_json::to_string(&psbt).unwrap();
+    let body_json = serde_json::to_string(&body.psbt).unwrap();
     println!("body_json: {}", body_json);
-    let body = SignPsbtReq {
-        psbt: psbt.clone(),
-        fallback_addr: fallback_addr,
+    // Whole PSBTs are bulky in JSON, so they are sent in CBOR to a signer that takes it.
+    let cbor = body
+        .quote
+        .as_ref()
+        .is_some_and(|q| q.features.contains(&Feature::BinaryEncoding));
+    let (content_type, data) = if cbor {
+        (encoding::CBOR, encoding::to_cbor(body)?)
+    } else {
+        ("application/json", serde_json::to_vec(body)?)
+    };
+    let api_key = std::env::var(API_KEY_ENV).ok();
+    let request = || {
+        let mut req = client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .header(reqwest::header::ACCEPT, content_type)
+            .body(data.clone());
+        if let Some(api_key) = &api_key {
+            req = req.header("X-Api-Key", api_key);
+        }
+        req
     };
-    let resp = client.post(url).json(&body).send().await?;
+    let resp = http.send(body.idempotency_key.is_some(), request).await?;
     println!("{resp:#?}");
 
-    let j = resp.json::<SignPsbtResp>().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await?;
+        return Err(match serde_json::from_str::<SignPsbtError>(&body) {
+            Ok(e) => {
+                let retry = if e.retryable() {
+                    "retryable"
+                } else {
+                    "not retryable"
+                };
+                println!(
+                    "Signer declined the request ({}): {}. {}",
+                    retry,
+                    e,
+                    advice(&e)
+                );
+                e.into()
+            }
+            Err(_) => format!("signer responded {}: {}", status, body).into(),
+        });
+    }
+
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let j: SignPsbtResp = if encoding::is_cbor(&content_type) {
+        encoding::from_cbor(&resp.bytes().await?)?
+    } else {
+        resp.json().await?
+    };
     println!("{j:#?}");
 
     Ok(j)
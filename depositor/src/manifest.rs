@@ -0,0 +1,178 @@
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bitcoin::{OutPoint, Txid};
+use clap::Parser;
+use futures::FutureExt;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{Cli, deposit};
+
+#[derive(Debug, clap::Args)]
+pub struct BatchArgs {
+    /// Manifest of the deposits, a JSON array of entries, or a CSV file with a header row naming
+    /// the same fields if it has the .csv extension.
+    #[arg(long)]
+    manifest: PathBuf,
+
+    /// Number of sessions run at once.
+    #[arg(long, default_value_t = 4)]
+    parallel: usize,
+
+    /// Write the summary of the sessions to this file as JSON, rather than print it.
+    #[arg(long)]
+    summary: Option<PathBuf>,
+
+    /// Arguments shared by all deposits, as given for a single deposit, after "--". The
+    /// deposits are signed without asking for confirmation.
+    #[arg(last = true)]
+    args: Vec<String>,
+}
+
+/// A deposit of the manifest.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub prevout: OutPoint,
+    pub prev_amt_sat: u64,
+    pub output_amt_sat: u64,
+    pub fallback_addr: String,
+    pub client_url: SocketAddr,
+    #[serde(default)]
+    pub change_addr: Option<String>,
+    #[serde(default)]
+    pub change_amt_sat: Option<u64>,
+}
+
+/// How the session of a deposit of the manifest went.
+#[derive(Serialize, Debug)]
+pub struct Outcome {
+    pub prevout: OutPoint,
+    pub status: Status,
+    /// Txid of the deposit, once completed.
+    pub deposit_txid: Option<Txid>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Completed,
+    /// The session ended without completing the deposit, like a dry run does.
+    NotCompleted,
+    Failed,
+}
+
+/// Runs a deposit session for every entry of the manifest in `args`, at most `args.parallel` at
+/// a time, and writes a summary of how each went.
+pub async fn run(args: BatchArgs) {
+    let entries = load(&args.manifest).expect("valid manifest");
+    println!("Running {} deposit sessions", entries.len());
+
+    let outcomes: Vec<Outcome> = stream::iter(entries)
+        .map(|entry| run_entry(entry, &args.args))
+        .buffered(args.parallel.max(1))
+        .collect()
+        .await;
+
+    let summary = serde_json::to_string_pretty(&outcomes).unwrap();
+    match &args.summary {
+        Some(path) => {
+            std::fs::write(path, summary).expect("able to write summary");
+            println!("Wrote summary to {}", path.display());
+        }
+        None => println!("{}", summary),
+    }
+    let failed = outcomes
+        .iter()
+        .filter(|o| o.status == Status::Failed)
+        .count();
+    println!("{} of {} deposit sessions failed", failed, outcomes.len());
+}
+
+/// Runs the session of `entry`. A failing session panics like a single deposit does, which is
+/// caught here so the other sessions carry on.
+async fn run_entry(entry: Entry, common: &[String]) -> Outcome {
+    let mut argv = vec![
+        "depositor".to_string(),
+        "--yes".to_string(),
+        format!("--prevout={}", entry.prevout),
+        format!("--prev-amt={} sat", entry.prev_amt_sat),
+        format!("--output-amt={} sat", entry.output_amt_sat),
+        format!("--fallback-addr={}", entry.fallback_addr),
+        format!("--client-url={}", entry.client_url),
+    ];
+    if let (Some(addr), Some(amt)) = (&entry.change_addr, entry.change_amt_sat) {
+        argv.push(format!("--change-addr={}", addr));
+        argv.push(format!("--change-amt={} sat", amt));
+    }
+    argv.extend(common.iter().cloned());
+
+    let outcome = |status, deposit_txid, error| Outcome {
+        prevout: entry.prevout,
+        status,
+        deposit_txid,
+        error,
+    };
+    let args = match Cli::try_parse_from(argv).map(|cli| cli.args) {
+        Ok(Some(args)) => args,
+        Ok(None) => return outcome(Status::Failed, None, Some("no deposit arguments".into())),
+        Err(e) => return outcome(Status::Failed, None, Some(e.to_string())),
+    };
+    match AssertUnwindSafe(deposit(args)).catch_unwind().await {
+        Ok(Some(txid)) => outcome(Status::Completed, Some(txid), None),
+        Ok(None) => outcome(Status::NotCompleted, None, None),
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "session panicked".to_string());
+            outcome(Status::Failed, None, Some(message))
+        }
+    }
+}
+
+/// Loads the manifest at `path`, as CSV if it has the .csv extension and JSON otherwise.
+fn load(path: &Path) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let data = std::fs::read_to_string(path)?;
+    if path.extension().is_none_or(|ext| ext != "csv") {
+        return Ok(serde_json::from_str(&data)?);
+    }
+
+    let mut lines = data.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or("manifest has no header")?
+        .split(',')
+        .map(str::trim)
+        .collect();
+    let mut entries = vec![];
+    for (i, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != header.len() {
+            return Err(format!("row {} has {} fields", i + 1, fields.len()).into());
+        }
+        let field = |name: &str| {
+            header
+                .iter()
+                .position(|h| *h == name)
+                .map(|j| fields[j])
+                .filter(|f| !f.is_empty())
+        };
+        let required =
+            |name: &str| field(name).ok_or_else(|| format!("row {} lacks {}", i + 1, name));
+        entries.push(Entry {
+            prevout: OutPoint::from_str(required("prevout")?)?,
+            prev_amt_sat: required("prev_amt_sat")?.parse()?,
+            output_amt_sat: required("output_amt_sat")?.parse()?,
+            fallback_addr: required("fallback_addr")?.to_string(),
+            client_url: required("client_url")?.parse()?,
+            change_addr: field("change_addr").map(str::to_string),
+            change_amt_sat: field("change_amt_sat").map(str::parse).transpose()?,
+        });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,96 @@
+use bitcoin::hashes::{Hash, sha256};
+
+use crate::qr;
+use crate::recovery::RecoveryKit;
+
+/// Hex characters per line of the chunked presigned transaction.
+const LINE_LEN: usize = 64;
+
+/// Hex characters per group within a line, to make transcribing easier.
+const GROUP_LEN: usize = 8;
+
+/// Hex characters per QR code, small enough to scan reliably from paper.
+const QR_CHUNK_LEN: usize = 500;
+
+/// Column to wrap the instructions at.
+const WIDTH: usize = 78;
+
+/// Renders a printable backup of the presigned spend in `kit`: the details of the deposit, the
+/// instructions, the transaction as numbered lines of hex with a checksum each and as QR codes.
+pub fn render(kit: &RecoveryKit) -> String {
+    let mut doc = String::new();
+    doc.push_str("EPHEMERAL SIGN PAPER BACKUP\n");
+    doc.push_str("===========================\n\n");
+    doc.push_str(&format!("Network:          {}\n", kit.network));
+    doc.push_str(&format!(
+        "Deposit output:   {}:0 ({} sat)\n",
+        kit.deposit_txid, kit.deposit_amount
+    ));
+    doc.push_str(&format!("Fallback address: {}\n", kit.fallback_addr));
+    doc.push_str(&format!("Presigned txid:   {}\n", kit.presigned_txid));
+    doc.push_str(&format!("Lock time:        {}\n", kit.lock_time));
+    if let Some(descriptor) = &kit.descriptor {
+        doc.push_str(&format!("Descriptor:       {}\n", descriptor.descriptor));
+    }
+
+    doc.push_str("\nHOW TO RECOVER\n\n");
+    doc.push_str(&wrap(&kit.instructions));
+    doc.push('\n');
+    doc.push_str(&wrap(
+        "Scan the QR codes below in order and join their contents, or type in the lines of hex \
+         without the line numbers, spaces and checksums. The checksum in brackets is the start \
+         of the SHA-256 of the line's hex, to catch typing mistakes line by line.",
+    ));
+
+    let len = kit.presigned_tx.len() / 2;
+    doc.push_str(&format!("\nPRESIGNED TRANSACTION ({} bytes)\n\n", len));
+    for (i, line) in chunks(&kit.presigned_tx, LINE_LEN).iter().enumerate() {
+        let groups = chunks(line, GROUP_LEN);
+        doc.push_str(&format!(
+            "{:03}  {:<width$}  [{}]\n",
+            i + 1,
+            groups.join(" "),
+            checksum(line),
+            width = LINE_LEN + LINE_LEN / GROUP_LEN - 1
+        ));
+    }
+
+    let parts = chunks(&kit.presigned_tx, QR_CHUNK_LEN);
+    doc.push_str("\nQR CODES\n");
+    for (i, part) in parts.iter().enumerate() {
+        let code = qr::render_print(part).expect("chunk fits in a QR code");
+        doc.push_str(&format!("\nPart {}/{}:\n{}\n", i + 1, parts.len(), code));
+    }
+    doc
+}
+
+fn chunks(s: &str, len: usize) -> Vec<&str> {
+    s.as_bytes()
+        .chunks(len)
+        .map(|c| std::str::from_utf8(c).expect("hex is ascii"))
+        .collect()
+}
+
+/// First 4 hex characters of the SHA-256 of `line`.
+fn checksum(line: &str) -> String {
+    sha256::Hash::hash(line.as_bytes()).to_string()[..4].to_string()
+}
+
+/// Wraps `text` at `WIDTH` columns.
+fn wrap(text: &str) -> String {
+    let mut wrapped = String::new();
+    let mut line_len = 0;
+    for word in text.split_whitespace() {
+        if line_len > 0 && line_len + 1 + word.len() > WIDTH {
+            wrapped.push('\n');
+            line_len = 0;
+        } else if line_len > 0 {
+            wrapped.push(' ');
+            line_len += 1;
+        }
+        wrapped.push_str(word);
+        line_len += word.len();
+    }
+    wrapped.push('\n');
+    wrapped
+}
@@ -0,0 +1,207 @@
+//! Payjoin of the deposit with a receiver adding its own inputs and outputs. This is not BIP-78,
+//! whose sender signs the original transaction first: the deposit output script is only known
+//! once the signer has seen the final transaction, so nothing can be signed before the receiver
+//! answers. The protocol borrows the shape of BIP-78 but is its own:
+//!
+//! 1. The sender POSTs the unsigned original PSBT, base64 encoded as `text/plain`, to the
+//!    receiver's endpoint with the query parameters `v=`[`VERSION`] and
+//!    `disableoutputsubstitution=true`, and optionally `additionalfeeoutputindex` and
+//!    `maxadditionalfeecontribution` as in BIP-78.
+//! 2. The receiver answers with the proposal PSBT, adding inputs and outputs but not signing
+//!    its inputs. Each must spend a native segwit output and carry its `non_witness_utxo`, so the
+//!    script it spends is committed to by its txid rather than taken on the receiver's word.
+//!    Receivers that only speak BIP-78 reject the unknown version.
+//! 3. The sender checks the proposal as `check_proposal` does, and has the deposit output filled
+//!    in and the spend presigned by the signer. The deposit output stays first.
+//! 4. The sender signs its inputs and hands the PSBT to the receiver, which signs its own and
+//!    broadcasts the deposit. Signing segwit inputs does not change the txid the presigned spend
+//!    commits to.
+
+use std::str::FromStr;
+
+use bitcoin::script::ScriptExt;
+use bitcoin::{Amount, Network, Psbt};
+use shared::render;
+
+use crate::amounts;
+
+/// Version of the protocol sent in the `v` parameter.
+pub const VERSION: &str = "ephemeral-deposit-1";
+
+/// Options for funding the deposit in a payjoin.
+#[derive(Debug, clap::Args)]
+pub struct PayjoinArgs {
+    /// Fund the deposit in a payjoin with the receiver at this URL. This is not BIP-78: the
+    /// original PSBT is sent unsigned, and the receiver signs its inputs after us and broadcasts
+    /// the deposit.
+    #[arg(long)]
+    pub payjoin_endpoint: Option<String>,
+
+    /// Maximum amount the payjoin receiver may take from the change output for fees.
+    #[arg(long, default_value = "1000 sat", value_parser = amounts::parse_amount)]
+    pub payjoin_max_fee: Amount,
+}
+
+/// Lets the payjoin receiver of `args`, if any, add its inputs and outputs to the unsigned
+/// deposit `psbt` with [`negotiate`], and prints its proposal. Without a receiver the deposit is
+/// returned as it is.
+pub async fn join(
+    args: &PayjoinArgs,
+    psbt: Psbt,
+    fee_output: Option<usize>,
+    network: Network,
+) -> Result<Psbt, Box<dyn std::error::Error>> {
+    let Some(endpoint) = &args.payjoin_endpoint else {
+        return Ok(psbt);
+    };
+    let proposal = negotiate(endpoint, &psbt, fee_output, args.payjoin_max_fee).await?;
+    print!("{}", render::psbt("Payjoin proposal", &proposal, network));
+    Ok(proposal)
+}
+
+/// Runs a payjoin round with the receiver at `endpoint`, letting it add inputs and outputs to
+/// the unsigned deposit transaction. The receiver may take up to `max_fee_contribution` from the
+/// output at `fee_output` to pay for the extra weight.
+pub async fn negotiate(
+    endpoint: &str,
+    original: &Psbt,
+    fee_output: Option<usize>,
+    max_fee_contribution: Amount,
+) -> Result<Psbt, Box<dyn std::error::Error>> {
+    let mut url = format!("{}?v={}&disableoutputsubstitution=true", endpoint, VERSION);
+    if let Some(index) = fee_output {
+        url.push_str(&format!(
+            "&additionalfeeoutputindex={}&maxadditionalfeecontribution={}",
+            index,
+            max_fee_contribution.to_sat()
+        ));
+    }
+
+    let resp = reqwest::Client::new()
+        .post(url)
+        .header("Content-Type", "text/plain")
+        .body(original.to_string())
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let proposal = Psbt::from_str(resp.trim())?;
+
+    check_proposal(original, &proposal, fee_output, max_fee_contribution)?;
+
+    // The receiver strips the data of our inputs, so add it back.
+    let mut proposal = proposal;
+    for (i, txin) in original.unsigned_tx.input.iter().enumerate() {
+        let j = proposal
+            .unsigned_tx
+            .input
+            .iter()
+            .position(|p| p.previous_output == txin.previous_output)
+            .ok_or("payjoin proposal dropped our input")?;
+        proposal.inputs[j] = original.inputs[i].clone();
+    }
+
+    // The signer and our taproot sighash need the receiver's prevouts as witness UTXOs, which
+    // the checked transactions they spend from give.
+    for (txin, input) in proposal
+        .unsigned_tx
+        .input
+        .iter()
+        .zip(proposal.inputs.iter_mut())
+    {
+        if let (None, Some(prev_tx)) = (&input.witness_utxo, &input.non_witness_utxo) {
+            input.witness_utxo = Some(prev_tx.output[txin.previous_output.vout as usize].clone());
+        }
+    }
+
+    Ok(proposal)
+}
+
+/// Checks the receiver's proposal as a BIP-78 sender would, making sure it only adds to the
+/// original transaction and does not take more from us than allowed, and in addition that the
+/// receiver's inputs are segwit and left unsigned.
+fn check_proposal(
+    original: &Psbt,
+    proposal: &Psbt,
+    fee_output: Option<usize>,
+    max_fee_contribution: Amount,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (orig_tx, tx) = (&original.unsigned_tx, &proposal.unsigned_tx);
+    if orig_tx.version != tx.version || orig_tx.lock_time != tx.lock_time {
+        return Err("payjoin proposal changed the transaction version or locktime".into());
+    }
+
+    for txin in &orig_tx.input {
+        match tx
+            .input
+            .iter()
+            .find(|p| p.previous_output == txin.previous_output)
+        {
+            Some(p) if p.sequence == txin.sequence => {}
+            Some(_) => return Err("payjoin proposal changed the sequence of our input".into()),
+            None => return Err("payjoin proposal dropped our input".into()),
+        }
+    }
+
+    // The receiver's inputs must be native segwit so their signatures do not change the txid,
+    // which the receiver can only prove with the transactions they spend from. A `witness_utxo`
+    // alone could claim a segwit script for a legacy output. Their prevouts also let us sign
+    // ours.
+    for (txin, input) in tx.input.iter().zip(proposal.inputs.iter()) {
+        if orig_tx
+            .input
+            .iter()
+            .any(|o| o.previous_output == txin.previous_output)
+        {
+            continue;
+        }
+
+        let prev_tx = input
+            .non_witness_utxo
+            .as_ref()
+            .ok_or("payjoin receiver inputs must carry the transaction they spend from")?;
+        if prev_tx.compute_txid() != txin.previous_output.txid {
+            return Err("payjoin receiver input spends another transaction than it carries".into());
+        }
+        let utxo = prev_tx
+            .output
+            .get(txin.previous_output.vout as usize)
+            .ok_or("payjoin receiver input spends a missing output")?;
+        if !utxo.script_pubkey.is_witness_program() {
+            return Err("payjoin receiver inputs must be native segwit".into());
+        }
+        if input.witness_utxo.as_ref().is_some_and(|w| w != utxo) {
+            return Err("payjoin receiver input misstates the output it spends".into());
+        }
+        if input.final_script_witness.is_some() || !txin.script_sig.is_empty() {
+            return Err("payjoin receiver must not sign its inputs before the deposit".into());
+        }
+    }
+
+    // The deposit output must stay first, as that is where the signer expects it.
+    if tx.output.first() != orig_tx.output.first() {
+        return Err("payjoin proposal changed the deposit output".into());
+    }
+
+    for (i, txout) in orig_tx.output.iter().enumerate() {
+        let out = tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey == txout.script_pubkey)
+            .ok_or("payjoin proposal dropped one of our outputs")?;
+
+        let min_value = match fee_output {
+            Some(index) if index == i => txout
+                .value
+                .checked_sub(max_fee_contribution)
+                .unwrap_or(Amount::ZERO),
+            _ => txout.value,
+        };
+        if out.value < min_value {
+            return Err("payjoin proposal takes more from our outputs than allowed".into());
+        }
+    }
+
+    Ok(())
+}
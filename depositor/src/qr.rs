@@ -0,0 +1,26 @@
+use qrcode::QrCode;
+use qrcode::render::unicode;
+
+/// Renders `data` as a QR code for the terminal, if it fits in one.
+pub fn render(data: &str) -> Result<String, qrcode::types::QrError> {
+    let code = QrCode::new(data.as_bytes())?;
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+/// Renders `data` as a QR code for printing on paper, with dark modules printed dark.
+pub fn render_print(data: &str) -> Result<String, qrcode::types::QrError> {
+    let code = QrCode::new(data.as_bytes())?;
+    Ok(code.render::<unicode::Dense1x2>().build())
+}
+
+/// Prints `data` as a QR code to the terminal, under `label`.
+pub fn print_qr(label: &str, data: &str) {
+    match render(data) {
+        Ok(image) => println!("{}:\n{}", label, image),
+        Err(e) => println!("{} too large for a QR code: {}", label, e),
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs;
+use std::path::Path;
+
+use bitcoin::hashes::{Hash, sha256};
+use bitcoin::{Network, Transaction, Txid, absolute, consensus};
+use chacha20poly1305::XNonce;
+use chacha20poly1305::aead::Aead;
+use serde::{Deserialize, Serialize};
+use shared::{DepositDescriptor, ScriptPath};
+use zeroize::Zeroizing;
+
+use crate::keystore;
+use crate::session::Session;
+
+/// Version of the recovery kit format, bumped on incompatible changes.
+pub const VERSION: u32 = 1;
+
+/// Everything needed to recover a deposit through its presigned spend, without the depositor or
+/// the signer.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RecoveryKit {
+    pub network: Network,
+    pub deposit_txid: Txid,
+    /// Value of the deposit output, in sats.
+    pub deposit_amount: u64,
+    /// Output descriptor of the deposit, for importing it into a watch-only wallet.
+    pub descriptor: Option<DepositDescriptor>,
+    /// Script paths of the deposit output, for spending it other than through the presigned
+    /// spend.
+    pub script_paths: Vec<ScriptPath>,
+    pub fallback_addr: String,
+    pub presigned_txid: Txid,
+    /// Hex encoded presigned spend.
+    pub presigned_tx: String,
+    /// Locktime of the presigned spend, as in the transaction.
+    pub lock_time: u32,
+    pub instructions: String,
+}
+
+impl RecoveryKit {
+    pub fn new(session: &Session) -> Self {
+        let resp = &session.resp;
+        let presigned_tx: Transaction = resp.spend_psbt.clone().extract_tx().expect("valid tx");
+        let deposit_txid = session.id();
+
+        let valid = match presigned_tx.lock_time == absolute::LockTime::ZERO {
+            true => "as soon as the deposit confirms".to_string(),
+            false => format!("from {}", presigned_tx.lock_time),
+        };
+        let mut instructions = format!(
+            "The presigned spend pays the deposit output {}:0 to {}. It is valid {}: broadcast \
+             presigned_tx then, for example with `bitcoin-cli sendrawtransaction <presigned_tx>`. \
+             Import the descriptor into a watch-only wallet to follow the deposit output.",
+            deposit_txid, session.req.fallback_addr, valid
+        );
+        if resp.adaptor_sig.is_some() {
+            instructions.push_str(
+                " The presigned spend is unsigned in adaptor mode, it needs the adaptor secret \
+                 to complete its signature first.",
+            );
+        }
+        if resp.vault.is_some() {
+            instructions.push_str(
+                " In vault mode the presigned spend is the unvault, keep the session for its \
+                 final spend and clawback.",
+            );
+        }
+
+        RecoveryKit {
+            network: session.network,
+            deposit_txid,
+            deposit_amount: resp.deposit_psbt.unsigned_tx.output[0].value.to_sat(),
+            descriptor: resp.descriptor.clone(),
+            script_paths: resp.script_paths.clone(),
+            fallback_addr: session.req.fallback_addr.clone(),
+            presigned_txid: presigned_tx.compute_txid(),
+            presigned_tx: consensus::encode::serialize_hex(&presigned_tx),
+            lock_time: presigned_tx.lock_time.to_consensus_u32(),
+            instructions,
+        }
+    }
+}
+
+/// A recovery kit as stored on disk, either in the clear or encrypted like the keystore.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KitFile {
+    pub version: u32,
+    /// Hex encoded SHA-256 of the kit serialized as compact JSON, checked when opening it.
+    pub checksum: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kit: Option<RecoveryKit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<EncryptedKit>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncryptedKit {
+    /// Hex encoded salt for the passphrase.
+    salt: String,
+    /// Hex encoded nonce of the encryption.
+    nonce: String,
+    /// Hex encoded encrypted kit.
+    ciphertext: String,
+}
+
+impl KitFile {
+    /// Wraps `kit` for storing, encrypting it with `passphrase` if given.
+    pub fn seal(
+        kit: RecoveryKit,
+        passphrase: Option<&str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let data = Zeroizing::new(serde_json::to_vec(&kit)?);
+        let checksum = sha256::Hash::hash(&data).to_string();
+
+        let encrypted = match passphrase {
+            None => None,
+            Some(passphrase) => {
+                let salt: [u8; 16] = rand::random();
+                let nonce: [u8; 24] = rand::random();
+                let ciphertext = keystore::cipher(passphrase, &salt)?
+                    .encrypt(XNonce::from_slice(&nonce), &data[..])
+                    .map_err(|_| "unable to encrypt recovery kit")?;
+                Some(EncryptedKit {
+                    salt: hex::encode(salt),
+                    nonce: hex::encode(nonce),
+                    ciphertext: hex::encode(ciphertext),
+                })
+            }
+        };
+
+        Ok(KitFile {
+            version: VERSION,
+            checksum,
+            kit: encrypted.is_none().then_some(kit),
+            encrypted,
+        })
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted.is_some()
+    }
+
+    /// Returns the kit, decrypting it with `passphrase` if encrypted, after checking its version
+    /// and checksum.
+    pub fn open(self, passphrase: Option<&str>) -> Result<RecoveryKit, Box<dyn std::error::Error>> {
+        if self.version != VERSION {
+            return Err(format!("unsupported recovery kit version {}", self.version).into());
+        }
+
+        let data = match (self.kit, self.encrypted) {
+            (Some(kit), None) => Zeroizing::new(serde_json::to_vec(&kit)?),
+            (None, Some(encrypted)) => {
+                let passphrase = passphrase.ok_or("recovery kit is encrypted")?;
+                let cipher = keystore::cipher(passphrase, &hex::decode(&encrypted.salt)?)?;
+                let nonce = hex::decode(&encrypted.nonce)?;
+                if nonce.len() != 24 {
+                    return Err("invalid recovery kit nonce".into());
+                }
+                let data = cipher
+                    .decrypt(
+                        XNonce::from_slice(&nonce),
+                        &hex::decode(&encrypted.ciphertext)?[..],
+                    )
+                    .map_err(|_| "wrong passphrase")?;
+                Zeroizing::new(data)
+            }
+            _ => return Err("recovery kit must be either in the clear or encrypted".into()),
+        };
+
+        if sha256::Hash::hash(&data).to_string() != self.checksum {
+            return Err("recovery kit checksum mismatch, the file is corrupted".into());
+        }
+        Ok(serde_json::from_slice(&data)?)
+    }
+}
+
+pub fn load(path: &Path) -> KitFile {
+    let data = fs::read_to_string(path).expect("able to read recovery kit");
+    serde_json::from_str(&data).expect("valid recovery kit")
+}
+
+pub fn store(path: &Path, file: &KitFile) {
+    let data = serde_json::to_string_pretty(file).unwrap();
+    fs::write(path, data).expect("able to write recovery kit");
+}
+
+/// Prints the contents of `kit`, instructions first.
+pub fn print(kit: &RecoveryKit) {
+    println!("{}", kit.instructions);
+    println!();
+    println!("network:          {}", kit.network);
+    println!("deposit txid:     {}", kit.deposit_txid);
+    println!("deposit amount:   {} sat", kit.deposit_amount);
+    if let Some(descriptor) = &kit.descriptor {
+        println!("descriptor:       {}", descriptor.descriptor);
+    }
+    for script_path in &kit.script_paths {
+        println!(
+            "script path:      {} (control block {})",
+            script_path.script, script_path.control_block
+        );
+    }
+    println!("fallback address: {}", kit.fallback_addr);
+    println!("presigned txid:   {}", kit.presigned_txid);
+    println!("lock time:        {}", kit.lock_time);
+    println!("presigned tx:     {}", kit.presigned_tx);
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bitcoin::bip32::KeySource;
+use bitcoin::psbt::Input;
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapLeafHash};
+use bitcoin::{
+    Amount, OutPoint, Psbt, ScriptBuf, Sequence, TapSighashType, Transaction, TxIn, TxOut, Witness,
+    XOnlyPublicKey,
+};
+use shared::script;
+
+use crate::session::Session;
+
+/// The recovery script path of a deposit output, through which the depositor moves the funds
+/// into a new deposit, or back to itself, before the session expires.
+pub struct RecoverySpend {
+    pub outpoint: OutPoint,
+    pub prevout: TxOut,
+    pub key: XOnlyPublicKey,
+    pub delay: u16,
+    script: ScriptBuf,
+    control_block: ControlBlock,
+}
+
+impl RecoverySpend {
+    /// The recovery path of the deposit of `session`, which must have been made with a recovery
+    /// key, as nothing else lets the depositor spend it.
+    pub fn new(session: &Session) -> Result<Self, Box<dyn std::error::Error>> {
+        let recovery = session
+            .req
+            .recovery
+            .as_ref()
+            .ok_or("the deposit has no recovery path to roll it over through")?;
+        let key = XOnlyPublicKey::from_str(&recovery.recovery_key)?;
+        let script = script::recovery_script(key, recovery.delay);
+        let script_path = session
+            .resp
+            .script_paths
+            .iter()
+            .find(|path| path.script == hex::encode(script.as_bytes()))
+            .ok_or("recovery script path missing from the session")?;
+        let control_block = ControlBlock::decode(&hex::decode(&script_path.control_block)?)?;
+
+        Ok(RecoverySpend {
+            outpoint: OutPoint {
+                txid: session.id(),
+                vout: 0,
+            },
+            prevout: session.resp.deposit_psbt.unsigned_tx.output[0].clone(),
+            key,
+            delay: recovery.delay,
+            script,
+            control_block,
+        })
+    }
+
+    /// Input spending the deposit output, with the relative timelock of the recovery path.
+    pub fn txin(&self) -> TxIn {
+        TxIn {
+            previous_output: self.outpoint,
+            script_sig: ScriptBuf::default(),
+            sequence: Sequence::from_height(self.delay),
+            witness: Witness::default(),
+        }
+    }
+
+    /// Fee of `tx`, spending the deposit output through the recovery path, at `feerate` sat/vB.
+    pub fn fee(
+        &self,
+        tx: &Transaction,
+        feerate: u64,
+    ) -> Result<Amount, Box<dyn std::error::Error>> {
+        // The segwit marker and flag, and the witness with a SIGHASH_ALL signature.
+        let witness = self.witness(&[0; 65]);
+        let weight = tx.weight().to_wu() + 2 + witness.size() as u64;
+        Ok(Amount::from_sat(feerate * weight.div_ceil(4))?)
+    }
+
+    fn leaf_hash(&self) -> TapLeafHash {
+        TapLeafHash::from_script(&self.script, LeafVersion::TapScript)
+    }
+
+    fn witness(&self, sig: &[u8]) -> Witness {
+        let mut witness = Witness::new();
+        witness.push(sig);
+        witness.push(self.script.as_bytes());
+        witness.push(self.control_block.serialize());
+        witness
+    }
+
+    /// Sets the input of `psbt` spending the deposit output up for signing with the recovery
+    /// key, returning its index.
+    pub fn set_input(&self, psbt: &mut Psbt, key_source: KeySource) -> usize {
+        let index = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .position(|i| i.previous_output == self.outpoint)
+            .expect("deposit output spent by the PSBT");
+
+        let mut tap_scripts = BTreeMap::new();
+        tap_scripts.insert(
+            self.control_block.clone(),
+            (self.script.clone(), LeafVersion::TapScript),
+        );
+        let mut tap_key_origins = BTreeMap::new();
+        tap_key_origins.insert(self.key, (vec![self.leaf_hash()], key_source));
+        psbt.inputs[index] = Input {
+            witness_utxo: Some(self.prevout.clone()),
+            tap_scripts,
+            tap_key_origins,
+            tap_internal_key: Some(self.control_block.internal_key),
+            sighash_type: Some(TapSighashType::All.into()),
+            ..Default::default()
+        };
+        index
+    }
+
+    /// Finalizes input `index` of `psbt`, signed with the recovery key.
+    pub fn finalize(&self, psbt: &mut Psbt, index: usize) {
+        let input = &mut psbt.inputs[index];
+        let sig = input
+            .tap_script_sigs
+            .get(&(self.key, self.leaf_hash()))
+            .expect("recovery signature");
+        input.final_script_witness = Some(self.witness(&sig.to_vec()));
+
+        // Clear all the data fields as per the spec.
+        input.tap_script_sigs = BTreeMap::new();
+        input.tap_scripts = BTreeMap::new();
+        input.tap_key_origins = BTreeMap::new();
+        input.tap_internal_key = None;
+        input.sighash_type = None;
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bitcoin::bip32::KeySource;
+use bitcoin::{Amount, Network, OutPoint, Psbt, Transaction, TxOut, Txid};
+use serde::{Deserialize, Serialize};
+use shared::{SignPsbtReq, SignPsbtResp};
+
+/// Everything the online machine learned from the signer, so the deposit can be signed on an
+/// offline machine holding the private key, and finalized back online. Sessions are also saved
+/// once the signer's response is verified, so an interrupted deposit can be resumed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Session {
+    pub network: Network,
+    /// The coin funding the deposit.
+    pub prevout: OutPoint,
+    pub deposit_prevout: TxOut,
+    /// Origin of the deposit key, if known when preparing.
+    #[serde(default)]
+    pub key_source: Option<KeySource>,
+    /// Address of our change output, if any.
+    #[serde(default)]
+    pub change_addr: Option<String>,
+    /// Payjoin receiver that signs its inputs after us, if any.
+    pub payjoin_endpoint: Option<String>,
+    /// Address of the signer service, if known.
+    #[serde(default)]
+    pub signer: Option<String>,
+    pub req: SignPsbtReq,
+    pub resp: SignPsbtResp,
+    /// The deposit once signed by us.
+    #[serde(default)]
+    pub signed_psbt: Option<Psbt>,
+    /// Set once the deposit is completed and the presigned spend checked against it.
+    #[serde(default)]
+    pub completed: bool,
+    /// Replacement of the deposit sending our prevout back to us, once the session is cancelled.
+    /// It conflicts with the deposit, so the session must not be continued.
+    #[serde(default)]
+    pub cancel_tx: Option<Transaction>,
+    /// Block height the deposit must be rolled over by, as from then on its presigned spend is
+    /// valid to the heir or the expiry key can take the deposit back.
+    #[serde(default)]
+    pub expiry: Option<u32>,
+    /// Transaction the deposit was rolled over with, spending it through the recovery path. The
+    /// presigned spend remains the fallback until it confirms.
+    #[serde(default)]
+    pub rolled_over_to: Option<Txid>,
+    /// Presigned spends of the deposit outputs batched after the first, in output order.
+    #[serde(default)]
+    pub batch_spends: Vec<Psbt>,
+}
+
+impl Session {
+    /// Sessions are identified by the txid of their deposit, which our signature does not change.
+    pub fn id(&self) -> Txid {
+        self.resp.deposit_psbt.unsigned_tx.compute_txid()
+    }
+
+    /// Fee of the deposit, unknown if a payjoin receiver did not give the prevouts of its inputs.
+    pub fn deposit_fee(&self) -> Option<Amount> {
+        let psbt = &self.resp.deposit_psbt;
+        let input_value = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .zip(psbt.inputs.iter())
+            .try_fold(Amount::ZERO, |sum, (txin, input)| {
+                let value = match txin.previous_output == self.prevout {
+                    true => Some(self.deposit_prevout.value),
+                    false => input.witness_utxo.as_ref().map(|u| u.value),
+                };
+                sum.checked_add(value?)
+            })?;
+        let output_value = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .try_fold(Amount::ZERO, |sum, o| sum.checked_add(o.value))?;
+        input_value.checked_sub(output_value)
+    }
+
+    /// The presigned transaction paying the fallback address: the final spend of the unvault in
+    /// vault mode, the spend of the new deposit output when rolling over, otherwise the presigned
+    /// spend itself.
+    pub fn fallback_psbt(&self) -> &Psbt {
+        match (&self.resp.vault, &self.resp.rollover_spend_psbt) {
+            (Some(vault), _) => &vault.final_psbt,
+            (None, Some(psbt)) => psbt,
+            (None, None) => &self.resp.spend_psbt,
+        }
+    }
+
+    /// Txids of the transactions we expect to spend the deposit output: the presigned spend, any
+    /// CETs and the rollover.
+    pub fn known_spends(&self) -> Vec<Txid> {
+        let mut txids = vec![self.resp.spend_psbt.unsigned_tx.compute_txid()];
+        txids.extend(
+            self.resp
+                .cets
+                .iter()
+                .map(|cet| cet.psbt.unsigned_tx.compute_txid()),
+        );
+        txids.extend(self.rolled_over_to);
+        txids
+    }
+}
+
+/// Path of the session `id` saved in `dir`.
+pub fn path(dir: &Path, id: &Txid) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
+
+pub fn load(path: &Path) -> Session {
+    let data = fs::read_to_string(path).expect("able to read session");
+    serde_json::from_str(&data).expect("valid session")
+}
+
+/// All sessions saved in `dir`, none if it does not exist.
+pub fn load_all(dir: &Path) -> Vec<Session> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .map(|entry| entry.expect("able to read sessions directory").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    paths.iter().map(|path| load(path)).collect()
+}
+
+pub fn store(path: &Path, session: &Session) {
+    let data = serde_json::to_string_pretty(session).unwrap();
+    fs::write(path, data).expect("able to write session");
+}
+
+/// Saves `session` under its ID in `dir`.
+pub fn save(dir: &Path, session: &Session) {
+    fs::create_dir_all(dir).expect("able to create sessions directory");
+    store(&path(dir, &session.id()), session);
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::Path;
+
+use bitcoin::{OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+
+/// The prevouts we have signed a deposit for. Signing a second deposit spending the same prevout
+/// could double spend the first, leaving its presigned spend unbroadcastable.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SignedDeposits {
+    pub deposits: Vec<SignedDeposit>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignedDeposit {
+    pub prevout: OutPoint,
+    pub deposit_txid: Txid,
+}
+
+impl SignedDeposits {
+    /// Returns an error if a deposit other than `deposit_txid` was signed spending `prevout`.
+    pub fn check(&self, prevout: OutPoint, deposit_txid: Txid) -> Result<(), String> {
+        match self
+            .deposits
+            .iter()
+            .find(|d| d.prevout == prevout && d.deposit_txid != deposit_txid)
+        {
+            None => Ok(()),
+            Some(d) => Err(format!(
+                "already signed deposit {} spending {}, signing another would conflict with it",
+                d.deposit_txid, prevout
+            )),
+        }
+    }
+
+    pub fn add(&mut self, prevout: OutPoint, deposit_txid: Txid) {
+        if self
+            .deposits
+            .iter()
+            .any(|d| d.prevout == prevout && d.deposit_txid == deposit_txid)
+        {
+            return;
+        }
+        self.deposits.push(SignedDeposit {
+            prevout,
+            deposit_txid,
+        });
+    }
+}
+
+pub fn load(path: &Path) -> SignedDeposits {
+    match fs::read_to_string(path) {
+        Ok(data) => serde_json::from_str(&data).expect("valid signed deposits file"),
+        Err(_) => SignedDeposits::default(),
+    }
+}
+
+pub fn store(path: &Path, signed: &SignedDeposits) {
+    let data = serde_json::to_string_pretty(signed).unwrap();
+    fs::write(path, data).expect("able to write signed deposits file");
+}
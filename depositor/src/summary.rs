@@ -0,0 +1,123 @@
+use std::io::Write;
+
+use bitcoin::{Amount, Network, OutPoint, Psbt, TxOut, absolute};
+use shared::render::describe;
+
+/// Prints what signing the deposit commits to: where the funds come from and go, the fees of the
+/// deposit and the presigned spend, and when the presigned spend becomes valid.
+pub fn print(
+    network: Network,
+    deposit_psbt: &Psbt,
+    prevout: OutPoint,
+    deposit_prevout: &TxOut,
+    spend_psbt: &Psbt,
+    fallback_addr: &str,
+) {
+    println!();
+    println!("Deposit summary");
+    for line in lines(
+        network,
+        deposit_psbt,
+        prevout,
+        deposit_prevout,
+        spend_psbt,
+        fallback_addr,
+    ) {
+        println!("  {}", line);
+    }
+    println!();
+}
+
+/// The lines of the summary `print` prints.
+pub fn lines(
+    network: Network,
+    deposit_psbt: &Psbt,
+    prevout: OutPoint,
+    deposit_prevout: &TxOut,
+    spend_psbt: &Psbt,
+    fallback_addr: &str,
+) -> Vec<String> {
+    let deposit_tx = &deposit_psbt.unsigned_tx;
+    let mut lines = vec![];
+
+    // Inputs other than ours are from a payjoin receiver, which gave us their prevouts.
+    let mut input_value = Some(Amount::ZERO);
+    for (txin, input) in deposit_tx.input.iter().zip(deposit_psbt.inputs.iter()) {
+        let value = match txin.previous_output == prevout {
+            true => Some(deposit_prevout.value),
+            false => input.witness_utxo.as_ref().map(|u| u.value),
+        };
+        let shown = value.map_or("unknown".to_string(), |v| v.to_string());
+        lines.push(format!(
+            "input:            {} ({})",
+            txin.previous_output, shown
+        ));
+        input_value = input_value.zip(value).and_then(|(a, b)| a.checked_add(b));
+    }
+
+    for (i, output) in deposit_tx.output.iter().enumerate() {
+        let name = match i {
+            0 => "deposit output:",
+            _ => "output:",
+        };
+        lines.push(format!(
+            "{:<17} {} to {}",
+            name,
+            output.value,
+            describe(&output.script_pubkey, network)
+        ));
+    }
+    lines.push(format!(
+        "deposit fee:      {}",
+        fee(input_value, deposit_tx.output.iter())
+    ));
+
+    let spend_tx = &spend_psbt.unsigned_tx;
+    lines.push(format!("fallback address: {}", fallback_addr));
+    for output in &spend_tx.output {
+        lines.push(format!(
+            "presigned spend:  {} to {}",
+            output.value,
+            describe(&output.script_pubkey, network)
+        ));
+    }
+    lines.push(format!(
+        "presigned fee:    {}",
+        fee(
+            deposit_tx.output.first().map(|o| o.value),
+            spend_tx.output.iter()
+        )
+    ));
+    if spend_tx.lock_time == absolute::LockTime::ZERO {
+        lines.push("presigned valid:  immediately".to_string());
+    } else {
+        lines.push(format!("presigned valid:  from {}", spend_tx.lock_time));
+    }
+    lines
+}
+
+/// Asks the user to confirm `question`, e.g. signing the deposit, unless `yes` is set.
+pub fn confirm(question: &str, yes: bool) -> bool {
+    if yes {
+        return true;
+    }
+
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush().expect("able to flush stdout");
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .expect("answer on stdin");
+    matches!(line.trim(), "y" | "Y" | "yes")
+}
+
+fn fee<'a>(input_value: Option<Amount>, outputs: impl Iterator<Item = &'a TxOut>) -> String {
+    let output_value = outputs.try_fold(Amount::ZERO, |sum, o| sum.checked_add(o.value));
+    match input_value.zip(output_value) {
+        Some((input, output)) => match input.checked_sub(output) {
+            Some(fee) => fee.to_string(),
+            None => "negative".to_string(),
+        },
+        None => "unknown".to_string(),
+    }
+}
@@ -0,0 +1,253 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bitcoin::{Transaction, Txid};
+
+use crate::alerts::{Alerts, Event};
+use crate::chain::Esplora;
+use crate::locktime::{self, Finality};
+use crate::session::Session;
+
+/// Conditions on which watch mode broadcasts the presigned spend of a deposit itself, acting as
+/// its own watchtower.
+#[derive(Debug, clap::Args)]
+pub struct Triggers {
+    /// Broadcast the presigned spends once the chain reaches this height.
+    #[arg(long)]
+    pub broadcast_at_height: Option<u32>,
+
+    /// Broadcast the presigned spend of a deposit still unspent this many blocks after it
+    /// confirmed.
+    #[arg(long)]
+    pub broadcast_after_blocks: Option<u32>,
+
+    /// Broadcast the presigned spends once this file exists, to trigger it by hand.
+    #[arg(long)]
+    pub trigger_file: Option<PathBuf>,
+}
+
+impl Triggers {
+    /// The trigger that fired for a deposit with `confirmations` at height `tip`, if any.
+    fn fired(&self, tip: u32, confirmations: u32) -> Option<String> {
+        if let Some(height) = self.broadcast_at_height.filter(|height| tip >= *height) {
+            return Some(format!("height {} reached", height));
+        }
+        if let Some(blocks) = self
+            .broadcast_after_blocks
+            .filter(|blocks| confirmations > *blocks)
+        {
+            return Some(format!("unspent {} blocks after confirming", blocks));
+        }
+        if let Some(path) = self.trigger_file.as_ref().filter(|path| path.exists()) {
+            return Some(format!("trigger file {} exists", path.display()));
+        }
+        None
+    }
+}
+
+/// A deposit being watched, and what was last reported about it.
+struct Watched {
+    deposit_txid: Txid,
+    known_spends: Vec<Txid>,
+    /// The presigned spend to broadcast on a trigger, none if it is not fully signed.
+    presigned_tx: Option<Transaction>,
+    confirmations: Option<u32>,
+    broadcast: bool,
+    /// What the presigned spend was last reported waiting for.
+    waiting: Option<Finality>,
+    /// The presigned transaction seen spending the deposit, watched until it confirms.
+    spent_by: Option<Txid>,
+}
+
+/// Polls `backend` every `interval` until the deposit outputs of `sessions` are spent and the
+/// spends confirmed, reporting new confirmations and broadcasting the presigned spends when one
+/// of `triggers` fires. Events go to `alerts`; a spend by anything but a presigned transaction is
+/// an alert: unless we spent it through a script path, the signer did not delete its key.
+pub async fn run(
+    backend: &Esplora,
+    sessions: &[Session],
+    interval: Duration,
+    triggers: &Triggers,
+    alerts: &Alerts,
+) {
+    let mut watched: Vec<Watched> = sessions
+        .iter()
+        .map(|session| Watched {
+            deposit_txid: session.id(),
+            known_spends: session.known_spends(),
+            // In adaptor mode the presigned spend still needs the adaptor secret.
+            presigned_tx: match session.resp.adaptor_sig {
+                Some(_) => None,
+                None => Some(
+                    session
+                        .resp
+                        .spend_psbt
+                        .clone()
+                        .extract_tx()
+                        .expect("valid tx"),
+                ),
+            },
+            confirmations: None,
+            broadcast: false,
+            waiting: None,
+            spent_by: None,
+        })
+        .collect();
+
+    while !watched.is_empty() {
+        match backend.tip_height().await {
+            Ok(tip) => {
+                let mut still_watched = vec![];
+                for mut w in watched {
+                    match check(backend, &mut w, tip, triggers, alerts).await {
+                        Ok(true) => {}
+                        Ok(false) => still_watched.push(w),
+                        Err(e) => {
+                            println!("Unable to check deposit {}: {}", w.deposit_txid, e);
+                            still_watched.push(w);
+                        }
+                    }
+                }
+                watched = still_watched;
+            }
+            Err(e) => println!("Unable to query chain backend: {}", e),
+        }
+
+        if !watched.is_empty() {
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/// Checks the deposit in `w` against the chain at height `tip`, broadcasting its presigned spend
+/// if one of `triggers` fired. Returns whether its output is spent for good, so there is nothing
+/// left to watch.
+async fn check(
+    backend: &Esplora,
+    w: &mut Watched,
+    tip: u32,
+    triggers: &Triggers,
+    alerts: &Alerts,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let height = match backend.tx_status(&w.deposit_txid).await? {
+        Some(status) if status.confirmed => status.block_height,
+        Some(_) => None,
+        None => {
+            if w.confirmations.is_some() {
+                println!("Deposit {} dropped from the mempool", w.deposit_txid);
+                w.confirmations = None;
+            }
+            return Ok(false);
+        }
+    };
+    let confirmations = height.map_or(0, |height| (tip + 1).saturating_sub(height));
+    if w.confirmations != Some(confirmations) {
+        match (w.confirmations, height) {
+            (Some(0), Some(height)) => {
+                alerts
+                    .notify(Event::DepositConfirmed {
+                        deposit_txid: w.deposit_txid,
+                        height,
+                    })
+                    .await
+            }
+            _ => println!(
+                "Deposit {} has {} confirmations",
+                w.deposit_txid, confirmations
+            ),
+        }
+        w.confirmations = Some(confirmations);
+    }
+
+    let outspend = backend.outspend(&w.deposit_txid, 0).await?;
+    if !outspend.spent {
+        if let (false, Some(reason)) = (w.broadcast, triggers.fired(tip, confirmations)) {
+            broadcast(backend, w, &reason, height, alerts).await?;
+        }
+        return Ok(false);
+    }
+    let txid = match outspend.txid {
+        Some(txid) if w.known_spends.contains(&txid) => txid,
+        spending_txid => {
+            alerts
+                .notify(Event::UnexpectedSpend {
+                    deposit_txid: w.deposit_txid,
+                    spending_txid,
+                })
+                .await;
+            return Ok(true);
+        }
+    };
+
+    if w.spent_by != Some(txid) {
+        println!(
+            "Deposit {} spent by presigned transaction {}",
+            w.deposit_txid, txid
+        );
+        w.spent_by = Some(txid);
+    }
+    match outspend.status.and_then(|status| status.block_height) {
+        Some(height) => {
+            alerts
+                .notify(Event::PresignedConfirmed {
+                    deposit_txid: w.deposit_txid,
+                    presigned_txid: txid,
+                    height,
+                })
+                .await;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Broadcasts the presigned spend of `w`, whose deposit confirmed at `height`, because of
+/// `reason`. A spend that is not yet final and failures are retried on the next poll.
+async fn broadcast(
+    backend: &Esplora,
+    w: &mut Watched,
+    reason: &str,
+    height: Option<u32>,
+    alerts: &Alerts,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(tx) = w.presigned_tx.clone() else {
+        println!(
+            "Trigger fired for deposit {} ({}), but its presigned spend is not fully signed",
+            w.deposit_txid, reason
+        );
+        w.broadcast = true;
+        return Ok(());
+    };
+
+    let chain = backend.chain_state().await?;
+    let finality = locktime::finality(&tx, &chain, height);
+    if finality != Finality::Final {
+        if w.waiting.as_ref() != Some(&finality) {
+            println!(
+                "Presigned spend of deposit {} is {}, broadcasting it then",
+                w.deposit_txid,
+                locktime::describe(&finality, &chain)
+            );
+            w.waiting = Some(finality);
+        }
+        return Ok(());
+    }
+
+    println!(
+        "Broadcasting presigned spend of deposit {}: {}",
+        w.deposit_txid, reason
+    );
+    match backend.broadcast(&tx).await {
+        Ok(presigned_txid) => {
+            w.broadcast = true;
+            alerts
+                .notify(Event::PresignedBroadcast {
+                    deposit_txid: w.deposit_txid,
+                    presigned_txid,
+                })
+                .await;
+        }
+        Err(e) => println!("Failed to broadcast the presigned spend: {}", e),
+    }
+    Ok(())
+}
@@ -0,0 +1,528 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use bitcoin::{
+    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+    absolute, transaction,
+};
+use clap::{Parser, ValueEnum};
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListState, Paragraph, Wrap};
+
+use crate::chain::{ChainArgs, Esplora, TxStatus, Utxo};
+use crate::funding::FundingType;
+use crate::session::{self, Session};
+use crate::{Cli, amounts, history, summary};
+
+/// How often the progress screen asks the backend whether the deposit confirmed.
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, clap::Args)]
+pub struct WizardArgs {
+    /// Address of our deposit key, to pick the UTXO to deposit from.
+    #[arg(long)]
+    address: String,
+
+    /// Signer to choose from, given once for each.
+    #[arg(long = "signer", required = true)]
+    signers: Vec<SocketAddr>,
+
+    #[arg(long, default_value_t = Network::Signet)]
+    network: Network,
+
+    #[command(flatten)]
+    chain: ChainArgs,
+
+    /// Further arguments for the deposit, as given for a single deposit, after "--", like the
+    /// key to sign it with.
+    #[arg(last = true)]
+    args: Vec<String>,
+}
+
+/// What the user chose to deposit in the wizard.
+struct Choice {
+    utxo: Utxo,
+    details: Details,
+    signer: SocketAddr,
+}
+
+/// The amounts and addresses of the deposit, checked against the UTXO it spends.
+struct Details {
+    output_amt: Amount,
+    fallback_addr: String,
+    change: Option<(String, Amount)>,
+    fee: Amount,
+    vsize: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    Utxo,
+    Details,
+    Signer,
+}
+
+const FIELDS: [&str; 4] = [
+    "Deposit amount",
+    "Fallback address",
+    "Change address",
+    "Change amount",
+];
+
+/// The fields of the details step, as typed.
+#[derive(Default)]
+struct Form {
+    values: [String; 4],
+    focus: usize,
+}
+
+impl Form {
+    /// The details of a deposit spending `utxo`, an output of type `ty`, or why the fields do not
+    /// make one.
+    fn details(&self, utxo: &Utxo, ty: FundingType, network: Network) -> Result<Details, String> {
+        let [output_amt, fallback_addr, change_addr, change_amt] = &self.values;
+        let output_amt = parse_amount(output_amt, "deposit amount")?;
+        parse_address(fallback_addr, network).map_err(|e| format!("fallback address: {}", e))?;
+        let change = match (change_addr.is_empty(), change_amt.is_empty()) {
+            (true, true) => None,
+            (false, false) => {
+                let addr = parse_address(change_addr, network)
+                    .map_err(|e| format!("change address: {}", e))?;
+                Some((addr, parse_amount(change_amt, "change amount")?))
+            }
+            _ => return Err("give both a change address and amount, or neither".to_string()),
+        };
+
+        // The same transaction the deposit is built as, but for the locktime.
+        let mut output = vec![TxOut {
+            value: output_amt,
+            script_pubkey: ScriptBuf::default(),
+        }];
+        output.extend(change.as_ref().map(|(addr, value)| TxOut {
+            value: *value,
+            script_pubkey: addr.script_pubkey(),
+        }));
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: outpoint(utxo),
+                script_sig: ScriptBuf::default(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::default(),
+            }],
+            output,
+        };
+        let input_value = Amount::from_sat(utxo.value).map_err(|e| e.to_string())?;
+        let fee = amounts::check_deposit(&tx, input_value, ty.witness_weight(), Amount::MAX_MONEY)
+            .map_err(|e| e.to_string())?;
+        Ok(Details {
+            output_amt,
+            fallback_addr: fallback_addr.clone(),
+            change: change.map(|(addr, amt)| (addr.to_string(), amt)),
+            fee,
+            vsize: amounts::deposit_vsize(&tx, ty.witness_weight()),
+        })
+    }
+}
+
+/// Walks the user through a deposit in a terminal UI: picking the UTXO, entering the amounts
+/// with a preview of the fee, choosing the signer, reviewing the presigned spend and following
+/// the broadcast of the deposit. The protocol itself runs as for a single deposit, in between.
+pub async fn run(args: WizardArgs) {
+    let network = args.network;
+    let esplora = args.chain.backend(network);
+    let address = parse_address(&args.address, network).expect("valid --address");
+    let prevout_type = FundingType::of_output(&address.script_pubkey())
+        .expect("--address is a P2TR, P2WPKH or P2WSH address");
+    let utxos = esplora
+        .address_utxos(&address)
+        .await
+        .expect("UTXOs of --address");
+    if utxos.is_empty() {
+        println!("No UTXOs of {} to deposit", address);
+        return;
+    }
+
+    let mut terminal = ratatui::init();
+    let choice = choose(&mut terminal, &utxos, prevout_type, &args.signers, network);
+    ratatui::restore();
+    let Some(choice) = choice.expect("able to draw the wizard") else {
+        println!("Aborted, nothing was deposited");
+        return;
+    };
+
+    // The prevout is checked to be of our key against the transaction creating it.
+    let prev_tx = esplora
+        .tx(&choice.utxo.txid)
+        .await
+        .expect("transaction creating the UTXO");
+    let details = &choice.details;
+    let mut argv = vec![
+        "depositor".to_string(),
+        format!("--network={}", network),
+        format!("--prevout={}", outpoint(&choice.utxo)),
+        format!("--prev-amt={} sat", choice.utxo.value),
+        format!(
+            "--prevout-type={}",
+            prevout_type.to_possible_value().unwrap().get_name()
+        ),
+        format!(
+            "--prev-tx={}",
+            bitcoin::consensus::encode::serialize_hex(&prev_tx)
+        ),
+        format!("--output-amt={} sat", details.output_amt.to_sat()),
+        format!("--fallback-addr={}", details.fallback_addr),
+        format!("--client-url={}", choice.signer),
+    ];
+    if let Some((addr, amt)) = &details.change {
+        argv.push(format!("--change-addr={}", addr));
+        argv.push(format!("--change-amt={} sat", amt.to_sat()));
+    }
+    argv.extend(args.args.iter().cloned());
+    let deposit_args = match Cli::try_parse_from(argv).map(|cli| cli.args) {
+        Ok(Some(deposit_args)) => deposit_args,
+        Ok(None) => panic!("no deposit arguments"),
+        Err(e) => e.exit(),
+    };
+    assert!(
+        deposit_args.payjoin.payjoin_endpoint.is_none(),
+        "the wizard broadcasts the deposit itself, it cannot payjoin"
+    );
+    let sessions_dir = deposit_args.sessions_dir.clone();
+    let history_db = deposit_args.history_db.clone();
+
+    println!(
+        "Running the deposit protocol with the signer at {}",
+        choice.signer
+    );
+    let ask = |session: &Session| {
+        let mut terminal = ratatui::init();
+        let confirmed = review(&mut terminal, session);
+        ratatui::restore();
+        confirmed.expect("able to draw the wizard")
+    };
+    let Some(deposit_txid) = crate::deposit_reviewed(deposit_args, ask).await else {
+        return;
+    };
+
+    let session = session::load(&session::path(&sessions_dir, &deposit_txid));
+    let mut deposit_psbt = session.signed_psbt.clone().expect("signed deposit");
+    let our_input = deposit_psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|i| i.previous_output == session.prevout)
+        .expect("our input in deposit");
+    if deposit_psbt.inputs[our_input]
+        .final_script_witness
+        .is_none()
+    {
+        crate::finalize_deposit_input(&mut deposit_psbt, our_input);
+    }
+    let deposit_tx = deposit_psbt.extract_tx().expect("valid deposit");
+
+    let mut terminal = ratatui::init();
+    let res = follow_broadcast(&mut terminal, &esplora, &deposit_tx, &history_db).await;
+    ratatui::restore();
+    res.expect("able to draw the wizard");
+}
+
+/// Runs the steps up to choosing the signer. Returns none if the user quits.
+fn choose(
+    terminal: &mut DefaultTerminal,
+    utxos: &[Utxo],
+    prevout_type: FundingType,
+    signers: &[SocketAddr],
+    network: Network,
+) -> io::Result<Option<Choice>> {
+    let mut step = Step::Utxo;
+    let mut utxo_list = ListState::default().with_selected(Some(0));
+    let mut signer_list = ListState::default().with_selected(Some(0));
+    let mut form = Form::default();
+    let utxo_items: Vec<String> = utxos.iter().map(describe_utxo).collect();
+    let signer_items: Vec<String> = signers.iter().map(|s| s.to_string()).collect();
+
+    loop {
+        let utxo = &utxos[utxo_list.selected().unwrap_or(0)];
+        let details = form.details(utxo, prevout_type, network);
+        terminal.draw(|frame| match step {
+            Step::Utxo => draw_list(
+                frame,
+                "1/4 Pick the UTXO to deposit",
+                &utxo_items,
+                &mut utxo_list,
+                "up/down select, enter continue, esc quit",
+            ),
+            Step::Details => draw_form(frame, &form, utxo, &details),
+            Step::Signer => draw_list(
+                frame,
+                "3/4 Choose the signer",
+                &signer_items,
+                &mut signer_list,
+                "up/down select, enter run the protocol, esc back",
+            ),
+        })?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match (step, key.code) {
+            (Step::Utxo, KeyCode::Esc) => return Ok(None),
+            (Step::Utxo, KeyCode::Up) => utxo_list.select_previous(),
+            (Step::Utxo, KeyCode::Down) => utxo_list.select_next(),
+            (Step::Utxo, KeyCode::Enter) => step = Step::Details,
+
+            (Step::Details, KeyCode::Esc) => step = Step::Utxo,
+            (Step::Details, KeyCode::Up | KeyCode::BackTab) => {
+                form.focus = (form.focus + FIELDS.len() - 1) % FIELDS.len()
+            }
+            (Step::Details, KeyCode::Down | KeyCode::Tab) => {
+                form.focus = (form.focus + 1) % FIELDS.len()
+            }
+            (Step::Details, KeyCode::Backspace) => {
+                form.values[form.focus].pop();
+            }
+            (Step::Details, KeyCode::Char(c)) => form.values[form.focus].push(c),
+            (Step::Details, KeyCode::Enter) if details.is_ok() => step = Step::Signer,
+
+            (Step::Signer, KeyCode::Esc) => step = Step::Details,
+            (Step::Signer, KeyCode::Up) => signer_list.select_previous(),
+            (Step::Signer, KeyCode::Down) => signer_list.select_next(),
+            (Step::Signer, KeyCode::Enter) => {
+                let signer = signers[signer_list.selected().unwrap_or(0)];
+                return Ok(details.ok().map(|details| Choice {
+                    utxo: utxo.clone(),
+                    details,
+                    signer,
+                }));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Shows the summary of the presigned spend the signer returned, and asks whether to sign and
+/// broadcast the deposit.
+fn review(terminal: &mut DefaultTerminal, session: &Session) -> io::Result<bool> {
+    let lines: Vec<Line> = summary::lines(
+        session.network,
+        &session.resp.deposit_psbt,
+        session.prevout,
+        &session.deposit_prevout,
+        &session.resp.spend_psbt,
+        &session.req.fallback_addr,
+    )
+    .into_iter()
+    .map(Line::from)
+    .collect();
+
+    loop {
+        terminal.draw(|frame| {
+            let [body, help] = screen(frame.area());
+            frame.render_widget(
+                Paragraph::new(lines.clone())
+                    .wrap(Wrap { trim: false })
+                    .block(Block::bordered().title("4/4 Review the presigned spend")),
+                body,
+            );
+            frame.render_widget(
+                Line::from("y sign and broadcast the deposit, n abort"),
+                help,
+            );
+        })?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('y') => return Ok(true),
+            KeyCode::Char('n') | KeyCode::Esc => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+/// Broadcasts `deposit_tx` and shows its progress until it confirms or the user quits.
+async fn follow_broadcast(
+    terminal: &mut DefaultTerminal,
+    esplora: &Esplora,
+    deposit_tx: &Transaction,
+    history_db: &Path,
+) -> io::Result<()> {
+    let txid = deposit_tx.compute_txid();
+    let mut log = vec![format!("Broadcasting deposit {}", txid)];
+    let mut done = match esplora.broadcast(deposit_tx).await {
+        Ok(_) => {
+            log.push("The backend accepted the deposit, waiting for it to confirm".to_string());
+            let marked = history::History::open(history_db)
+                .and_then(|history| history.mark_broadcast(&txid.to_string()));
+            if let Err(e) = marked {
+                log.push(format!("Failed to mark the deposit as broadcast: {}", e));
+            }
+            false
+        }
+        Err(e) => {
+            log.push(format!("The deposit was not broadcast: {}", e));
+            true
+        }
+    };
+
+    let mut status = String::new();
+    let mut checked: Option<Instant> = None;
+    loop {
+        if !done && checked.is_none_or(|at| at.elapsed() >= STATUS_INTERVAL) {
+            checked = Some(Instant::now());
+            status = match esplora.tx_status(&txid).await {
+                Ok(Some(TxStatus {
+                    confirmed: true,
+                    block_height,
+                })) => {
+                    let height = block_height.map_or("?".to_string(), |h| h.to_string());
+                    log.push(format!("The deposit confirmed in block {}", height));
+                    done = true;
+                    String::new()
+                }
+                Ok(Some(_)) => "In the mempool, unconfirmed".to_string(),
+                Ok(None) => "Not yet seen by the backend".to_string(),
+                Err(e) => format!("Failed to query the backend: {}", e),
+            };
+        }
+
+        terminal.draw(|frame| {
+            let [body, help] = screen(frame.area());
+            let mut lines: Vec<Line> = log.iter().map(|l| Line::from(l.as_str())).collect();
+            if !status.is_empty() {
+                lines.push(Line::styled(
+                    status.as_str(),
+                    Style::new().fg(Color::Yellow),
+                ));
+            }
+            frame.render_widget(
+                Paragraph::new(lines)
+                    .wrap(Wrap { trim: false })
+                    .block(Block::bordered().title("Broadcast")),
+                body,
+            );
+            frame.render_widget(
+                Line::from("q quit, the presigned spend is saved with the session"),
+                help,
+            );
+        })?;
+
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press
+                && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Splits a screen into its body and a help line.
+fn screen(area: Rect) -> [Rect; 2] {
+    Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area)
+}
+
+fn draw_list(frame: &mut Frame, title: &str, items: &[String], state: &mut ListState, help: &str) {
+    let [body, help_area] = screen(frame.area());
+    let list = List::new(items.iter().map(String::as_str))
+        .block(Block::bordered().title(title))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(list, body, state);
+    frame.render_widget(Line::from(help), help_area);
+}
+
+fn draw_form(frame: &mut Frame, form: &Form, utxo: &Utxo, details: &Result<Details, String>) {
+    let [body, help] = screen(frame.area());
+    let [fields, preview] = Layout::vertical([
+        Constraint::Length(FIELDS.len() as u16 + 2),
+        Constraint::Min(0),
+    ])
+    .areas(body);
+
+    let lines: Vec<Line> = FIELDS
+        .iter()
+        .zip(&form.values)
+        .enumerate()
+        .map(|(i, (name, value))| match i == form.focus {
+            true => Line::styled(
+                format!("> {:<22} {}_", name, value),
+                Style::new().add_modifier(Modifier::BOLD),
+            ),
+            false => Line::from(format!("  {:<22} {}", name, value)),
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("2/4 Enter the amounts")),
+        fields,
+    );
+
+    let mut lines = vec![Line::from(describe_utxo(utxo))];
+    lines.push(match details {
+        // The deposit output script is not known yet, but it is always P2TR.
+        Ok(details) => Line::styled(
+            format!(
+                "Deposit fee: {} ({:.1} sat/vB over {} vB)",
+                details.fee,
+                details.fee.to_sat() as f64 / details.vsize as f64,
+                details.vsize
+            ),
+            Style::new().fg(Color::Green),
+        ),
+        Err(e) => Line::styled(e.clone(), Style::new().fg(Color::Red)),
+    });
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title("Preview")),
+        preview,
+    );
+    frame.render_widget(Line::from("up/down field, enter continue, esc back"), help);
+}
+
+fn describe_utxo(utxo: &Utxo) -> String {
+    let status = match (utxo.status.confirmed, utxo.status.block_height) {
+        (true, Some(height)) => format!("confirmed at {}", height),
+        (true, None) => "confirmed".to_string(),
+        (false, _) => "unconfirmed".to_string(),
+    };
+    format!("{}  {} sat  {}", outpoint(utxo), utxo.value, status)
+}
+
+fn outpoint(utxo: &Utxo) -> OutPoint {
+    OutPoint {
+        txid: utxo.txid,
+        vout: utxo.vout,
+    }
+}
+
+fn parse_amount(s: &str, name: &str) -> Result<Amount, String> {
+    amounts::parse_amount(s).map_err(|e| format!("{}: {}", name, e))
+}
+
+fn parse_address(s: &str, network: Network) -> Result<Address, String> {
+    Address::from_str(s)
+        .map_err(|e| e.to_string())?
+        .require_network(network)
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,73 @@
+use bitcoin::Txid;
+use serde::{Deserialize, Serialize};
+
+/// Environment variable holding the token the admin API is authenticated with.
+pub const ADMIN_TOKEN_ENV: &str = "EPHEMERAL_SIGN_ADMIN_TOKEN";
+
+/// A `/psbt` request the signer served or is serving.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionInfo {
+    pub id: u64,
+    /// Address of the client that made the request.
+    #[serde(default)]
+    pub peer: Option<String>,
+    /// Unix time the request was received.
+    pub started_at: u64,
+    /// Set once the deposit transaction is known.
+    #[serde(default)]
+    pub deposit_txid: Option<Txid>,
+    #[serde(default)]
+    pub deposit_sat: Option<u64>,
+    pub state: SessionState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SessionState {
+    /// Not yet signed, and can still be revoked.
+    Pending,
+    /// The signers are signing, it is too late to revoke.
+    Signing,
+    Completed,
+    Failed {
+        reason: String,
+    },
+    /// Revoked by an admin before it was signed.
+    Revoked,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionsResp {
+    pub active: Vec<SessionInfo>,
+    /// The most recently finished sessions since the signer started, newest last.
+    pub history: Vec<SessionInfo>,
+}
+
+/// A deposit counting against the signer's liability ceiling.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OutstandingDeposit {
+    pub deposit_txid: Txid,
+    pub value_sat: u64,
+    /// Unix time the spend was presigned at.
+    pub created_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DepositsResp {
+    pub deposits: Vec<OutstandingDeposit>,
+    pub total_sat: u64,
+    /// The liability ceiling, none if liability tracking is disabled.
+    #[serde(default)]
+    pub max_sat: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PresignedResp {
+    /// Deposits the signer kept the presigned spends of.
+    pub deposit_txids: Vec<Txid>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BroadcastResp {
+    pub txid: Txid,
+}
@@ -0,0 +1,291 @@
+use bitcoin::base64::Engine;
+use bitcoin::base64::engine::general_purpose::STANDARD as BASE64;
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::opcodes::all::{OP_PUSHBYTES_0, OP_RETURN};
+use bitcoin::psbt::Input;
+use bitcoin::script::Builder;
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::sighash::SighashCache;
+use bitcoin::{
+    Address, Amount, Network, OutPoint, Psbt, ScriptBuf, Sequence, TapSighashType, Transaction,
+    TxIn, TxOut, Txid, Witness, absolute, consensus, transaction,
+};
+use musig2::secp::{Point, Scalar};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use crate::silent_payment::tagged_hash;
+
+/// A BIP-322 signed message, proving control of the key of `address`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct SignedMessage {
+    pub address: String,
+    pub message: String,
+    /// Base64 encoded BIP-322 simple signature.
+    pub signature: String,
+}
+
+impl SignedMessage {
+    /// Signs `message` with the key spend of the P2TR address of `keypair`.
+    pub fn sign<C: Verification>(
+        secp: &Secp256k1<C>,
+        keypair: &Keypair,
+        network: Network,
+        message: String,
+        aux: &[u8; 32],
+    ) -> Self {
+        let (internal_key, _) = keypair.x_only_public_key();
+        let address = Address::p2tr(secp, internal_key, None, network);
+        let to_sign = to_sign(&address.script_pubkey(), &message);
+
+        let tweaked = Keypair::from(keypair.tap_tweak(secp, None));
+        let seckey = Scalar::from_slice(&tweaked.secret_bytes()).expect("valid secret key");
+        let signature: musig2::LiftedSignature = musig2::sign_solo(seckey, sighash(&to_sign), *aux);
+        let witness = Witness::from_slice(&[signature.serialize()]);
+
+        SignedMessage {
+            address: address.to_string(),
+            message,
+            signature: BASE64.encode(consensus::encode::serialize(&witness)),
+        }
+    }
+
+    /// Verifies the signature, for an address on `network`. Only key spends of P2TR addresses
+    /// are supported.
+    pub fn verify(&self, network: Network) -> Result<(), Box<dyn std::error::Error>> {
+        let script_pubkey = Address::from_str(&self.address)?
+            .require_network(network)?
+            .script_pubkey();
+        if !script_pubkey.is_p2tr() {
+            return Err("only P2TR addresses are supported".into());
+        }
+        let witness: Witness = consensus::encode::deserialize(&BASE64.decode(&self.signature)?)?;
+        if witness.len() != 1 {
+            return Err("signature is not a key spend".into());
+        }
+        // Signers like Bitcoin Core commit to SIGHASH_ALL explicitly, as in the BIP's vectors.
+        let (signature, sighash_type) = match witness[0].len() {
+            64 => (&witness[0][..], TapSighashType::Default),
+            65 if witness[0][64] == TapSighashType::All as u8 => {
+                (&witness[0][..64], TapSighashType::All)
+            }
+            _ => return Err("signature must use the default sighash type or SIGHASH_ALL".into()),
+        };
+        let signature: [u8; 64] = signature.try_into().unwrap();
+
+        let output_key: [u8; 32] = script_pubkey.as_bytes()[2..34].try_into().unwrap();
+        let output_key = Point::lift_x(&output_key)?;
+        let mut to_sign = to_sign(&script_pubkey, &self.message);
+        to_sign.inputs[0].sighash_type = Some(sighash_type.into());
+        musig2::verify_single(output_key, signature, sighash(&to_sign))
+            .map_err(|_| "invalid BIP-322 signature")?;
+        Ok(())
+    }
+}
+
+/// Proof that the depositor controls the key of one of the outputs the deposit spends, for
+/// signers that only serve allowlisted funders.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct FundingProof {
+    /// The output spent by the deposit whose address signed the proof.
+    #[schema(value_type = String)]
+    pub prevout: OutPoint,
+    pub proof: SignedMessage,
+}
+
+impl FundingProof {
+    /// The message signed for a deposit spending `prevout`, so a proof is good for no other
+    /// deposit.
+    pub fn message(prevout: OutPoint) -> String {
+        format!("ephemeral-sign funding {}", prevout)
+    }
+
+    /// Verifies the proof is by the address of its prevout, which `psbt` must spend, returning
+    /// the address's output script.
+    pub fn verify(
+        &self,
+        psbt: &Psbt,
+        network: Network,
+    ) -> Result<ScriptBuf, Box<dyn std::error::Error>> {
+        if self.proof.message != Self::message(self.prevout) {
+            return Err("funding proof is for another prevout".into());
+        }
+        let input = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .position(|i| i.previous_output == self.prevout)
+            .ok_or("funding proof is for a prevout the deposit does not spend")?;
+        let utxo = psbt.inputs[input]
+            .witness_utxo
+            .as_ref()
+            .ok_or("deposit input of the funding proof has no witness UTXO")?;
+        let script_pubkey = Address::from_str(&self.proof.address)?
+            .require_network(network)?
+            .script_pubkey();
+        if utxo.script_pubkey != script_pubkey {
+            return Err("funding proof is by another address than the prevout's".into());
+        }
+        self.proof.verify(network)?;
+        Ok(script_pubkey)
+    }
+}
+
+/// The message the signer attests its commitment with: that the presigned spend `spend_txid` of
+/// deposit `deposit_txid` pays `fallback_addr`.
+pub fn fallback_commitment(deposit_txid: Txid, spend_txid: Txid, fallback_addr: &str) -> String {
+    format!(
+        "ephemeral-sign fallback commitment\ndeposit_txid={}\nspend_txid={}\nfallback_addr={}\n",
+        deposit_txid, spend_txid, fallback_addr
+    )
+}
+
+/// The virtual transaction of BIP-322 committing to `message`, paying `script_pubkey`.
+fn to_spend(script_pubkey: &ScriptBuf, message: &str) -> Transaction {
+    let message_hash = tagged_hash("BIP0322-signed-message", &[message.as_bytes()]);
+    Transaction {
+        version: transaction::Version::maybe_non_standard(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::COINBASE_PREVOUT,
+            script_sig: Builder::new()
+                .push_opcode(OP_PUSHBYTES_0)
+                .push_slice(message_hash)
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// The virtual transaction of BIP-322 whose signature is the signature of `message`, as a PSBT
+/// carrying the output it spends.
+fn to_sign(script_pubkey: &ScriptBuf, message: &str) -> Psbt {
+    let to_spend = to_spend(script_pubkey, message);
+    let tx = Transaction {
+        version: transaction::Version::maybe_non_standard(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: to_spend.compute_txid(),
+                vout: 0,
+            },
+            script_sig: ScriptBuf::default(),
+            sequence: Sequence::ZERO,
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).expect("unsigned transaction");
+    psbt.inputs = vec![Input {
+        witness_utxo: Some(to_spend.output[0].clone()),
+        ..Default::default()
+    }];
+    psbt
+}
+
+fn sighash(to_sign: &Psbt) -> Vec<u8> {
+    let mut cache = SighashCache::new(&to_sign.unsigned_tx);
+    let (msg, _) = to_sign
+        .sighash_taproot(0, &mut cache, None)
+        .expect("to_sign sighash");
+    msg.as_ref().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::PrivateKey;
+
+    /// Key of the BIP-322 test vectors, and its P2TR key spend address.
+    const WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+    const TAPROOT_ADDRESS: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+
+    #[test]
+    fn message_hash_vectors() {
+        for (message, hash) in [
+            (
+                "",
+                "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1",
+            ),
+            (
+                "Hello World",
+                "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a",
+            ),
+        ] {
+            let got = tagged_hash("BIP0322-signed-message", &[message.as_bytes()]);
+            assert_eq!(hex::encode(got), hash);
+        }
+    }
+
+    #[test]
+    fn transaction_vectors() {
+        let script_pubkey = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l")
+            .unwrap()
+            .require_network(Network::Bitcoin)
+            .unwrap()
+            .script_pubkey();
+        for (message, to_spend_txid, to_sign_txid) in [
+            (
+                "",
+                "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7",
+                "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6",
+            ),
+            (
+                "Hello World",
+                "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b",
+                "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf",
+            ),
+        ] {
+            let to_spend = to_spend(&script_pubkey, message);
+            assert_eq!(to_spend.compute_txid().to_string(), to_spend_txid);
+            let to_sign = to_sign(&script_pubkey, message);
+            assert_eq!(to_sign.unsigned_tx.compute_txid().to_string(), to_sign_txid);
+        }
+    }
+
+    #[test]
+    fn taproot_signature_vector() {
+        let signed = SignedMessage {
+            address: TAPROOT_ADDRESS.to_string(),
+            message: "Hello World".to_string(),
+            signature: concat!(
+                "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5",
+                "EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==",
+            )
+            .to_string(),
+        };
+        signed.verify(Network::Bitcoin).unwrap();
+
+        let tampered = SignedMessage {
+            message: "Hello World!".to_string(),
+            ..signed
+        };
+        assert!(tampered.verify(Network::Bitcoin).is_err());
+    }
+
+    #[test]
+    fn sign_verifies() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let keypair = Keypair::from_secret_key(&secp, &key.inner);
+
+        let signed = SignedMessage::sign(
+            &secp,
+            &keypair,
+            Network::Bitcoin,
+            "Hello World".to_string(),
+            &[0; 32],
+        );
+        assert_eq!(signed.address, TAPROOT_ADDRESS);
+        signed.verify(Network::Bitcoin).unwrap();
+    }
+}
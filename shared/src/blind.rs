@@ -0,0 +1,108 @@
+use bitcoin::consensus::encode::serialize;
+use bitcoin::{Network, OutPoint, Psbt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::silent_payment::tagged_hash;
+use crate::tee::EnclaveAttestation;
+use crate::{SpendFee, psbt_base64};
+
+/// Request of the signer's `/blind/init` endpoint, starting a blind session. The signer only
+/// learns the fallback address and the value of the deposit output, not the deposit's funding
+/// inputs or outpoint, and the depositor assembles the deposit and its spend itself.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct BlindInitReq {
+    #[schema(value_type = String, example = "signet")]
+    pub network: Network,
+    pub fallback_addr: String,
+    /// Value of the deposit output.
+    pub amount_sat: u64,
+    /// Protocol version the request is made in, one both sides speak.
+    pub version: u32,
+}
+
+/// Response of `/blind/init`, the ephemeral key and the spend the signer commits to signing.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct BlindInitResp {
+    pub session_id: String,
+    /// Hex encoded x-only internal key of the deposit output, which has no script paths.
+    pub internal_key: String,
+    /// The presigned spend, paying the fallback address, with a null outpoint in place of the
+    /// deposit's. The witness UTXO of its input is the deposit output to fund.
+    #[serde(with = "psbt_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub template: Psbt,
+    pub spend_fee: SpendFee,
+    /// Unix time the session must be signed by.
+    pub expires_at: u64,
+}
+
+/// Request of `/blind/sign`, for signing the template of a blind session.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct BlindSignReq {
+    pub session_id: String,
+    /// Hex encoded `prevouts_hash` of the deposit outpoint. It is all the sighash needs of the
+    /// outpoint, so the signer can sign without learning it.
+    pub prevouts_hash: String,
+}
+
+/// Response of `/blind/sign`.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct BlindSignResp {
+    /// Hex encoded BIP-340 signature of the template spending the committed outpoint.
+    pub signature: String,
+    /// The signers' enclave attestations of deleting the key, if they all run in enclaves.
+    #[serde(default)]
+    pub enclave_attestations: Vec<EnclaveAttestation>,
+}
+
+/// BIP-341 `sha_prevouts` of a transaction spending only `outpoint`.
+pub fn prevouts_hash(outpoint: OutPoint) -> [u8; 32] {
+    Sha256::digest(serialize(&outpoint)).into()
+}
+
+/// BIP-341 key spend sighash, with the default sighash type, of the template's input spending
+/// the outpoint committed to by `prevouts_hash`. The sighash commits to the value and script of
+/// the deposit output as well, so the signature is invalid for any other deposit.
+pub fn template_sighash(
+    template: &Psbt,
+    prevouts_hash: [u8; 32],
+) -> Result<[u8; 32], Box<dyn std::error::Error>> {
+    let tx = &template.unsigned_tx;
+    if tx.input.len() != 1 || template.inputs.len() != 1 {
+        return Err("template must have a single input".into());
+    }
+    let prevout = template.inputs[0]
+        .witness_utxo
+        .as_ref()
+        .ok_or("template input has no witness UTXO")?;
+
+    let mut outputs = vec![];
+    for output in &tx.output {
+        outputs.extend(serialize(output));
+    }
+    let sha_amounts: [u8; 32] = Sha256::digest(prevout.value.to_sat().to_le_bytes()).into();
+    let sha_scriptpubkeys: [u8; 32] = Sha256::digest(serialize(&prevout.script_pubkey)).into();
+    let sha_sequences: [u8; 32] =
+        Sha256::digest(tx.input[0].sequence.to_consensus_u32().to_le_bytes()).into();
+    let sha_outputs: [u8; 32] = Sha256::digest(outputs).into();
+
+    Ok(tagged_hash(
+        "TapSighash",
+        &[
+            // Epoch, then SIGHASH_DEFAULT.
+            &[0x00, 0x00],
+            &tx.version.0.to_le_bytes(),
+            &tx.lock_time.to_consensus_u32().to_le_bytes(),
+            &prevouts_hash,
+            &sha_amounts,
+            &sha_scriptpubkeys,
+            &sha_sequences,
+            &sha_outputs,
+            // Key spend without annex, of input 0.
+            &[0x00],
+            &0u32.to_le_bytes(),
+        ],
+    ))
+}
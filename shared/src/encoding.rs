@@ -0,0 +1,29 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Content type of bodies in CBOR, the binary encoding of the protocol messages. They are JSON
+/// otherwise.
+pub const CBOR: &str = "application/cbor";
+
+/// Whether a body of content type `content_type` is CBOR.
+pub fn is_cbor(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|t| t.trim().eq_ignore_ascii_case(CBOR))
+}
+
+/// Whether a request with Accept header `accept` asks for a CBOR response.
+pub fn accepts_cbor(accept: &str) -> bool {
+    accept.split(',').any(is_cbor)
+}
+
+pub fn to_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut buf = vec![];
+    ciborium::into_writer(value, &mut buf)?;
+    Ok(buf)
+}
+
+pub fn from_cbor<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn std::error::Error>> {
+    Ok(ciborium::from_reader(data)?)
+}
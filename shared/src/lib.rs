@@ -1,36 +1,1042 @@
-use bitcoin::Psbt;
+use bip322::{FundingProof, SignedMessage};
+use bitcoin::script::ScriptExt;
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::taproot::{LeafVersion, TaprootSpendInfo};
+use bitcoin::{
+    Address, Amount, BlockHash, Network, Psbt, ScriptBuf, Sequence, TapSighashType, TxOut,
+    XOnlyPublicKey, absolute,
+};
+use miniscript::descriptor::checksum::desc_checksum;
+use musig2::compute_challenge_hash_tweak;
+use musig2::secp::errors::InvalidPointString;
+use musig2::secp::{MaybePoint, MaybeScalar, Point};
+use receipt::Receipt;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use silent_payment::{EcdhShare, SilentPaymentAddress};
+use std::str::FromStr;
+use tee::EnclaveAttestation;
+use utoipa::ToSchema;
+
+pub mod admin;
+pub mod bip322;
+pub mod blind;
+pub mod encoding;
+pub mod policy;
+pub mod psbt_base64;
+pub mod receipt;
+pub mod render;
+pub mod script;
+pub mod silent_payment;
+pub mod tee;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct InitResp {
     pub session_id: String,
     pub pubkey: String,
+    /// The public nonce of the single message the session signs, as a key signing several
+    /// blinded challenges is open to forgery.
     pub pubnonce: String,
+    /// ECDH share with the requested silent payment scan key, if any.
+    #[serde(default)]
+    pub ecdh_share: Option<EcdhShare>,
+    /// Sealed session state, set if the signer keeps no sessions. It must be passed back with
+    /// the sign request.
+    #[serde(default)]
+    pub state: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SignReq {
     pub session_id: String,
+    /// The challenge for the nonce handed out by the session, the only one it signs.
+    pub challenge: SignChallenge,
+    /// The state from the session's init response, if any.
+    #[serde(default)]
+    pub state: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignChallenge {
     pub challenge_parity: u8,
     pub nonce_parity: u8,
     pub key_coeff: String,
     pub b: String,
     pub e: String,
 }
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SignResp {
     pub session_id: String,
     pub sig: String,
+    /// Attestation of the signer's enclave that it deleted the session's key, if it runs in
+    /// one.
+    #[serde(default)]
+    pub attestation: Option<EnclaveAttestation>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
 pub struct SignPsbtReq {
+    #[serde(with = "psbt_base64")]
+    #[schema(value_type = String, format = Byte)]
     pub psbt: Psbt,
     pub fallback_addr: String,
+
+    /// Network of the deposit. The signer rejects requests for any other network than its own.
+    #[schema(value_type = String, example = "bitcoin")]
+    pub network: Network,
+
+    /// If set, the spend is not signed directly but an adaptor signature encrypted to this
+    /// (hex encoded, compressed) point is returned instead.
+    #[serde(default)]
+    pub adaptor_point: Option<String>,
+
+    /// If set, a CET is built for each of the event's outcomes in addition to the fallback spend,
+    /// which then acts as the refund.
+    #[serde(default)]
+    pub oracle_event: Option<OracleEvent>,
+
+    /// If set, the deposit output gets a script path letting the depositor recover the funds
+    /// after a relative timelock.
+    #[serde(default)]
+    pub recovery: Option<RecoveryPath>,
+
+    /// If set, the deposit output gets a script path returning the funds to the depositor after
+    /// an absolute block height.
+    #[serde(default)]
+    pub expiry: Option<ExpiryPath>,
+
+    /// If set, the presigned spend is an unvault into an output that can only be spent to the
+    /// fallback address after a delay, or clawed back by the depositor before that.
+    #[serde(default)]
+    pub vault: Option<VaultParams>,
+
+    /// If set, the presigned spend pays into a fresh deposit output locked to a new ephemeral
+    /// key, instead of the fallback address. This lets the depositor rotate the key by
+    /// broadcasting it.
+    #[serde(default)]
+    pub rollover: bool,
+
+    /// If set, the fallback address is the depositor's heir, and the presigned spend is not
+    /// valid before a far-future block height.
+    #[serde(default)]
+    pub inheritance: Option<InheritanceParams>,
+
+    /// If set, the fallback address is the output of this decaying multisig policy, which the
+    /// signer checks before signing.
+    #[serde(default)]
+    pub decaying_multisig: Option<DecayingMultisig>,
+
+    /// Miniscript policy for the deposit output, with `pk(ephemeral)` as a top-level
+    /// alternative. Its other alternatives become script paths in addition to the above.
+    #[serde(default)]
+    pub policy: Option<String>,
+
+    /// Hex encoded tapscript leaves to commit to in the deposit output, in addition to the above.
+    #[serde(default)]
+    pub leaves: Vec<String>,
+
+    /// Quote from the signer's `/quote` endpoint the request is made on. The signer signs on the
+    /// quoted terms, or rejects the request if it no longer honours them.
+    #[serde(default)]
+    pub quote: Option<Quote>,
+
+    /// Protocol version the request is made in, one both sides speak. Requests without one are
+    /// version 1.
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+
+    /// Features the depositor supports. The signer refuses a request relying on a feature that
+    /// is not listed here or that it does not support itself, and uses no others in its
+    /// response.
+    #[serde(default)]
+    pub capabilities: Vec<Feature>,
+
+    /// Key the depositor generates for the request, and sends again with retries of it. The
+    /// signer replays its response to a retry rather than signing with another key.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+
+    /// BIP-322 proof of control of an output the deposit spends, for signers that only serve
+    /// allowlisted funders.
+    #[serde(default)]
+    pub funding_proof: Option<FundingProof>,
+
+    /// Outputs the presigned spend pays a fixed amount to, after the fallback address, which
+    /// gets what is left of the deposit after them and the fee. Not supported with vaults,
+    /// rollover, an oracle event or a silent payment fallback.
+    #[serde(default)]
+    pub spend_outputs: Vec<SpendOutput>,
+
+    /// Shares of the deposit value the presigned spend pays to other addresses than the fallback
+    /// address, in order of priority after it, see `split_fallback`. Not supported with vaults,
+    /// rollover, an oracle event or a silent payment fallback.
+    #[serde(default)]
+    pub fallback_shares: Vec<FallbackShare>,
+
+    /// If set, the presigned spend is not valid before these timelocks, so it cannot be
+    /// broadcast early even by the depositor. The absolute locktime cannot be combined with an
+    /// oracle event or inheritance, which set their own.
+    #[serde(default)]
+    pub spend_timelock: Option<SpendTimelock>,
+
+    /// Sighash type the presigned spend is signed with. Not supported with an adaptor point.
+    #[serde(default)]
+    pub sighash_type: SpendSighash,
+
+    /// If set, the presigned spend also pays `anchor_output`, after the spend outputs, so it
+    /// can be fee bumped by CPFP when fees rise past what it pays. Not supported with vaults,
+    /// rollover, an oracle event or a silent payment fallback.
+    #[serde(default)]
+    pub anchor: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+fn legacy_version() -> u32 {
+    1
+}
+
+impl SignPsbtReq {
+    /// Features the signer must support to serve the request.
+    pub fn required_features(&self) -> Vec<Feature> {
+        let required = [
+            (true, Feature::Musig2),
+            (self.adaptor_point.is_some(), Feature::AdaptorSignatures),
+            (self.oracle_event.is_some(), Feature::Cets),
+            (self.vault.is_some(), Feature::Vaults),
+            (self.rollover, Feature::Rollover),
+            (self.inheritance.is_some(), Feature::Inheritance),
+            (self.decaying_multisig.is_some(), Feature::DecayingMultisig),
+            (!self.spend_outputs.is_empty(), Feature::SpendOutputs),
+            (self.anchor, Feature::Anchors),
+            (!self.fallback_shares.is_empty(), Feature::WeightedFallback),
+            (self.spend_timelock.is_some(), Feature::SpendTimelock),
+            (
+                self.sighash_type != SpendSighash::Default,
+                Feature::SighashTypes,
+            ),
+            (
+                self.recovery.is_some()
+                    || self.expiry.is_some()
+                    || self.policy.is_some()
+                    || !self.leaves.is_empty(),
+                Feature::ScriptPaths,
+            ),
+            (
+                SilentPaymentAddress::is_silent_payment(&self.fallback_addr),
+                Feature::SilentPayments,
+            ),
+        ];
+        required
+            .into_iter()
+            .filter(|(needed, _)| *needed)
+            .map(|(_, feature)| feature)
+            .collect()
+    }
+
+    /// Returns the tapscript leaves the depositor requested the deposit output to commit to.
+    pub fn deposit_leaves(&self) -> Result<Vec<ScriptBuf>, Box<dyn std::error::Error>> {
+        let mut leaves = vec![];
+        if let Some(recovery) = &self.recovery {
+            let key = XOnlyPublicKey::from_str(&recovery.recovery_key)?;
+            leaves.push(script::recovery_script(key, recovery.delay));
+        }
+
+        if let Some(expiry) = &self.expiry {
+            let key = XOnlyPublicKey::from_str(&expiry.expiry_key)?;
+            let height = absolute::LockTime::from_height(expiry.height)?;
+            leaves.push(script::expiry_script(key, height));
+        }
+
+        if let Some(policy) = &self.policy {
+            leaves.extend(policy::compile_deposit_policy(policy)?);
+        }
+
+        for leaf in &self.leaves {
+            leaves.push(ScriptBuf::from_bytes(hex::decode(leaf)?));
+        }
+
+        Ok(leaves)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct RecoveryPath {
+    /// Hex encoded x-only public key of the depositor.
+    pub recovery_key: String,
+    /// Number of blocks the deposit must be confirmed for before it can be recovered.
+    pub delay: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct ExpiryPath {
+    /// Hex encoded x-only public key of the depositor.
+    pub expiry_key: String,
+    /// Block height from which the depositor can spend the deposit.
+    pub height: u32,
+}
+
+/// A multisig that starts out requiring all keys, with the threshold decreasing by one after
+/// each delay.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct DecayingMultisig {
+    /// Hex encoded x-only public keys.
+    pub keys: Vec<String>,
+    /// Number of blocks the output must be confirmed for before each decrease of the threshold.
+    pub delays: Vec<u16>,
+}
+
+impl DecayingMultisig {
+    /// Validates the policy and compiles it into the taproot tree of the output.
+    pub fn spend_info<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<TaprootSpendInfo, Box<dyn std::error::Error>> {
+        if self.keys.len() < 2 || self.keys.len() > 16 {
+            return Err("decaying multisig must have between 2 and 16 keys".into());
+        }
+        if self.delays.len() >= self.keys.len() {
+            return Err("decaying multisig threshold cannot decay below 1".into());
+        }
+        if self.delays.windows(2).any(|w| w[0] >= w[1]) {
+            return Err("decaying multisig delays must be increasing".into());
+        }
+
+        let keys = self
+            .keys
+            .iter()
+            .map(|k| XOnlyPublicKey::from_str(k))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(script::decaying_multisig_spend_info(
+            secp,
+            &keys,
+            &self.delays,
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct InheritanceParams {
+    /// Block height from which the heir can broadcast the presigned spend.
+    pub lock_time: u32,
+}
+
+/// An output of the presigned spend paying a fixed amount.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct SpendOutput {
+    pub address: String,
+    pub amount_sat: u64,
+}
+
+impl SpendOutput {
+    /// The output, paying an address for `network`.
+    pub fn tx_out(&self, network: Network) -> Result<TxOut, Box<dyn std::error::Error>> {
+        let address = Address::from_str(&self.address)?.require_network(network)?;
+        Ok(TxOut {
+            value: Amount::from_sat(self.amount_sat)?,
+            script_pubkey: address.script_pubkey(),
+        })
+    }
+}
+
+/// The P2A anchor output of a presigned spend, paying its dust limit.
+pub fn anchor_output() -> TxOut {
+    let script_pubkey = script::anchor_script();
+    TxOut {
+        value: script_pubkey.minimal_non_dust(),
+        script_pubkey,
+    }
+}
+
+/// Timelocks of the presigned spend asked for by the depositor.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct SpendTimelock {
+    /// Absolute locktime in consensus encoding, a block height below 500000000 and a Unix time
+    /// from there.
+    #[serde(default)]
+    pub lock_time: Option<u32>,
+    /// Number of blocks the deposit must be confirmed for before the spend is valid.
+    #[serde(default)]
+    pub relative_blocks: Option<u16>,
+}
+
+impl SpendTimelock {
+    pub fn lock_time(&self) -> Option<absolute::LockTime> {
+        self.lock_time.map(absolute::LockTime::from_consensus)
+    }
+
+    /// Sequence of the spend's input, which signals replaceability either way.
+    pub fn sequence(&self) -> Sequence {
+        match self.relative_blocks {
+            Some(blocks) => Sequence::from_height(blocks),
+            None => Sequence::ENABLE_RBF_NO_LOCKTIME,
+        }
+    }
+}
+
+/// Sighash types the presigned spend can be signed with.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendSighash {
+    /// SIGHASH_DEFAULT, committing to the whole transaction with a 64 byte signature.
+    #[default]
+    Default,
+    All,
+    /// SIGHASH_ALL|ANYONECANPAY, so inputs paying a higher fee can be added to the spend.
+    AllAnyoneCanPay,
+}
+
+impl SpendSighash {
+    pub fn tap_sighash_type(self) -> TapSighashType {
+        match self {
+            SpendSighash::Default => TapSighashType::Default,
+            SpendSighash::All => TapSighashType::All,
+            SpendSighash::AllAnyoneCanPay => TapSighashType::AllPlusAnyoneCanPay,
+        }
+    }
+}
+
+/// A share of the deposit value paid to an address besides the fallback address.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct FallbackShare {
+    pub address: String,
+    /// Percent of the deposit value the address gets.
+    pub percent: u8,
+}
+
+/// Splits the deposit `value` over `shares` for `network`, into the outputs paying them. Each
+/// share gets its percent of `value`, rounded down. A share below the dust limit of its address
+/// is not paid, and goes to the share before it instead, or to the fallback address for the
+/// first. The fallback address gets what is left after the shares, and pays the spend's fee, so
+/// the split does not depend on the fee.
+pub fn split_fallback(
+    value: Amount,
+    shares: &[FallbackShare],
+    network: Network,
+) -> Result<Vec<TxOut>, Box<dyn std::error::Error>> {
+    let total: u32 = shares.iter().map(|s| s.percent as u32).sum();
+    if shares.iter().any(|s| s.percent == 0) || total >= 100 {
+        return Err(
+            "fallback shares must be positive and leave some to the fallback address".into(),
+        );
+    }
+    let mut outputs: Vec<TxOut> = vec![];
+    for share in shares {
+        let address = Address::from_str(&share.address)?.require_network(network)?;
+        let script_pubkey = address.script_pubkey();
+        let sat = value.to_sat() * share.percent as u64 / 100;
+        if sat >= script_pubkey.minimal_non_dust().to_sat() {
+            outputs.push(TxOut {
+                value: Amount::from_sat(sat)?,
+                script_pubkey,
+            });
+        } else if let Some(prev) = outputs.last_mut() {
+            prev.value = Amount::from_sat(prev.value.to_sat() + sat)?;
+        }
+    }
+    Ok(outputs)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct VaultParams {
+    /// Number of blocks the unvault must be confirmed for before the final spend is valid.
+    pub delay: u16,
+    /// Address the clawback spend pays to.
+    pub clawback_addr: String,
+}
+
+/// An oracle's announcement of a future event, with the payout for each of its outcomes.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct OracleEvent {
+    pub event_id: String,
+    /// Hex encoded x-only public key of the oracle.
+    pub oracle_pubkey: String,
+    /// Hex encoded x-only nonce the oracle will attest to the outcome with.
+    pub oracle_nonce: String,
+    pub outcomes: Vec<OracleOutcome>,
+    /// Block height after which the refund (fallback) spend becomes valid.
+    pub refund_locktime: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct OracleOutcome {
+    pub outcome: String,
+    pub payout_addr: String,
+}
+
+/// A contract execution transaction, spendable once the oracle attests to its outcome.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct Cet {
+    pub outcome: String,
+    #[serde(with = "psbt_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub psbt: Psbt,
+    /// Hex encoded adaptor signature, encrypted to the outcome's attestation point.
+    pub adaptor_sig: String,
+    /// Hex encoded x-only key the CET is signed with, through its `script::checksig_script`
+    /// leaf of the deposit output. The CET leaves follow the requested script paths, in the
+    /// order of the outcomes.
+    pub leaf_key: String,
+}
+
+/// Rule of the signer's policy that a request broke.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    Network,
+    MaxDepositAmount,
+    FallbackType,
+    MinSpendFee,
+    MaxSpendFee,
+    Quota,
+    /// The signer's total liability would exceed its ceiling.
+    Liability,
+    RateLimit,
+    /// The request was made on a quote the signer no longer honours.
+    QuoteTerms,
+    /// The request lacks a valid funding proof by an allowlisted funder.
+    Funder,
+    /// The presigned spend's locktime or sequence is outside what the signer allows.
+    SpendTimelock,
+}
+
+/// Body of the signer's response to a request its policy does not allow.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct PolicyViolation {
+    pub rule: PolicyRule,
+    pub message: String,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "policy violation ({:?}): {}", self.rule, self.message)
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+/// Body of the response to a request whose signing session expired before the protocol
+/// completed. The session's key is wiped without signing anything, so the request can be retried.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct SessionExpired {
+    pub session_id: String,
+    /// Seconds the session was allowed to live.
+    pub ttl_secs: u64,
+}
+
+impl std::fmt::Display for SessionExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "signing session {} expired after {} seconds, retry the request",
+            self.session_id, self.ttl_secs
+        )
+    }
+}
+
+impl std::error::Error for SessionExpired {}
+
+/// Body of the signer's response to a request whose fallback address it cannot pay. Nothing is
+/// signed for such a request.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct InvalidFallback {
+    pub fallback_addr: String,
+    #[serde(flatten)]
+    pub error: FallbackError,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum FallbackError {
+    /// Not an address or silent payment address the signer can parse.
+    Malformed { message: String },
+    /// An address for another network than the signer's.
+    WrongNetwork {
+        #[schema(value_type = String)]
+        expected: Network,
+    },
+}
+
+impl std::fmt::Display for InvalidFallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error {
+            FallbackError::Malformed { message } => {
+                write!(
+                    f,
+                    "invalid fallback address {}: {}",
+                    self.fallback_addr, message
+                )
+            }
+            FallbackError::WrongNetwork { expected } => write!(
+                f,
+                "fallback address {} is not for {}",
+                self.fallback_addr, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidFallback {}
+
+/// Body of every error response to `/psbt`. Nothing is handed out for a failed request, but the
+/// signers may have deleted keys for it, so only retry those that are retryable.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum SignPsbtError {
+    /// The request is malformed or asks for something the signer does not do.
+    InvalidRequest {
+        message: String,
+    },
+    /// The deposit PSBT is not a deposit the signer signs for.
+    InvalidPsbt {
+        message: String,
+    },
+    InvalidFallback(InvalidFallback),
+    PolicyViolation(PolicyViolation),
+    /// Too many requests, retry after the given number of seconds.
+    RateLimited {
+        retry_after_secs: u64,
+        message: String,
+    },
+    Expired(SessionExpired),
+    /// The signer's operator revoked the session before it was signed.
+    Revoked {
+        message: String,
+    },
+    /// The request needs a protocol version or features the signer does not speak.
+    Unsupported {
+        message: String,
+        /// The protocol versions the signer speaks.
+        versions: Vec<u32>,
+        /// Features the request relies on the signer does not support.
+        #[serde(default)]
+        features: Vec<Feature>,
+    },
+    /// The signer is shutting down, or could not reach its signers.
+    Unavailable {
+        message: String,
+    },
+    Internal {
+        message: String,
+    },
+}
+
+impl SignPsbtError {
+    /// HTTP status code the error is responded with.
+    pub fn status(&self) -> u16 {
+        match self {
+            SignPsbtError::InvalidRequest { .. }
+            | SignPsbtError::InvalidPsbt { .. }
+            | SignPsbtError::InvalidFallback(_)
+            | SignPsbtError::Unsupported { .. } => 400,
+            SignPsbtError::PolicyViolation(v) => match v.rule {
+                PolicyRule::Quota => 429,
+                // Not the request's fault, the signer is at capacity until deposits settle.
+                PolicyRule::Liability => 503,
+                _ => 403,
+            },
+            SignPsbtError::RateLimited { .. } => 429,
+            SignPsbtError::Expired(_) => 410,
+            SignPsbtError::Revoked { .. } => 409,
+            SignPsbtError::Unavailable { .. } => 503,
+            SignPsbtError::Internal { .. } => 500,
+        }
+    }
+
+    /// Whether the same request may succeed if made again later.
+    pub fn retryable(&self) -> bool {
+        match self {
+            SignPsbtError::PolicyViolation(v) => matches!(
+                v.rule,
+                PolicyRule::Quota | PolicyRule::Liability | PolicyRule::QuoteTerms
+            ),
+            SignPsbtError::RateLimited { .. }
+            | SignPsbtError::Expired(_)
+            | SignPsbtError::Unavailable { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for SignPsbtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignPsbtError::InvalidRequest { message } => write!(f, "invalid request: {}", message),
+            SignPsbtError::InvalidPsbt { message } => {
+                write!(f, "invalid deposit PSBT: {}", message)
+            }
+            SignPsbtError::InvalidFallback(e) => e.fmt(f),
+            SignPsbtError::PolicyViolation(v) => v.fmt(f),
+            SignPsbtError::RateLimited { message, .. } => write!(f, "rate limited: {}", message),
+            SignPsbtError::Expired(e) => e.fmt(f),
+            SignPsbtError::Revoked { message } => write!(f, "revoked: {}", message),
+            SignPsbtError::Unsupported { message, .. } => write!(f, "unsupported: {}", message),
+            SignPsbtError::Unavailable { message } => write!(f, "signer unavailable: {}", message),
+            SignPsbtError::Internal { message } => write!(f, "signer error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SignPsbtError {}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
 pub struct SignPsbtResp {
+    #[serde(with = "psbt_base64")]
+    #[schema(value_type = String, format = Byte)]
     pub deposit_psbt: Psbt,
+    #[serde(with = "psbt_base64")]
+    #[schema(value_type = String, format = Byte)]
     pub spend_psbt: Psbt,
+
+    /// Network the signer built the transactions for.
+    #[schema(value_type = String, example = "bitcoin")]
+    pub network: Network,
+
+    /// Hex encoded adaptor signature for the spend, set if an adaptor point was requested. In
+    /// that case the spend PSBT is left unfinalized.
+    #[serde(default)]
+    pub adaptor_sig: Option<String>,
+
+    /// One CET per outcome of the requested oracle event.
+    #[serde(default)]
+    pub cets: Vec<Cet>,
+
+    /// The spends of the unvault output, set if vault mode was requested. The spend PSBT is then
+    /// the unvault itself.
+    #[serde(default)]
+    pub vault: Option<VaultSpends>,
+
+    /// Spend of the new deposit output to the fallback address, set if rollover was requested.
+    #[serde(default, with = "psbt_base64::option")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub rollover_spend_psbt: Option<Psbt>,
+
+    /// Every script path of the deposit output, in the order of the requested leaves.
+    #[serde(default)]
+    pub script_paths: Vec<ScriptPath>,
+
+    /// Output descriptor of the deposit, for importing it into watch-only wallets.
+    #[serde(default)]
+    pub descriptor: Option<DepositDescriptor>,
+
+    /// Each signer's ECDH share with the scan key, in key aggregation order, set if the fallback
+    /// address is a silent payment address. The depositor can derive the fallback output from
+    /// these to check it.
+    #[serde(default)]
+    pub ecdh_shares: Vec<EcdhShare>,
+
+    /// Fee of the presigned spend as chosen by the signer's fee policy, and why.
+    #[serde(default)]
+    pub spend_fee: Option<SpendFee>,
+
+    /// Protocol version of the response, that of the request.
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+
+    /// Features the response may use, those of the request the signer supports.
+    #[serde(default)]
+    pub capabilities: Vec<Feature>,
+
+    /// Receipt for the presigned spend, signed with the operator's identity key, if the signer
+    /// has one.
+    #[serde(default)]
+    pub receipt: Option<Receipt>,
+
+    /// BIP-322 signature of the operator's identity key, by its P2TR address, on the
+    /// `bip322::fallback_commitment` of the response, so any BIP-322 verifier can check what the
+    /// signer committed to.
+    #[serde(default)]
+    pub fallback_attestation: Option<SignedMessage>,
+}
+
+/// A fee picked by the signer, with the rule it followed.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct SpendFee {
+    pub fee_sat: u64,
+    pub rationale: String,
+}
+
+/// Response of the signer's `/health` endpoint, checked by the depositor before it sends a
+/// request.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct HealthResp {
+    pub version: String,
+    #[schema(value_type = String, example = "bitcoin")]
+    pub network: Network,
+    /// Chain tip the signer sees, if it follows the chain.
+    #[serde(default)]
+    pub tip: Option<ChainTip>,
+    pub policy: PolicySummary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct ChainTip {
+    pub height: u32,
+    #[schema(value_type = String)]
+    pub hash: BlockHash,
+    /// Timestamp of the tip block.
+    pub time: u64,
+}
+
+/// Newest version of the protocol between depositor and signer this build speaks.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Versions of the protocol this build speaks. Version 2 added version and capability
+/// negotiation to requests and responses.
+pub const PROTOCOL_VERSIONS: [u32; 2] = [1, 2];
+
+/// The newest protocol version this build and a peer speaking `theirs` have in common.
+pub fn negotiate_version(theirs: &[u32]) -> Option<u32> {
+    PROTOCOL_VERSIONS
+        .iter()
+        .rev()
+        .find(|v| theirs.contains(v))
+        .copied()
+}
+
+/// Terms the signer serves requests on, from its `/quote` endpoint.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct Quote {
+    /// Identifies the quote to the signer that issued it.
+    pub quote_id: String,
+    pub version: String,
+    pub protocol_versions: Vec<u32>,
+    #[schema(value_type = String, example = "bitcoin")]
+    pub network: Network,
+    pub fee: FeeTerms,
+    pub policy: PolicySummary,
+    pub features: Vec<Feature>,
+    /// Unix time until which the signer honours the quote.
+    pub expires_at: u64,
+}
+
+/// Fee the signer's presigned spends pay on a quote.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct FeeTerms {
+    #[serde(flatten)]
+    pub rate: QuotedRate,
+    /// Cap on the fee, as a percentage of the value spent.
+    #[serde(default)]
+    pub max_percent: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum QuotedRate {
+    Fixed {
+        sat: u64,
+    },
+    /// Feerate in sat/vB, an estimate fixed when the quote was issued.
+    Feerate {
+        sat_per_vb: f64,
+    },
+}
+
+/// Something the signer supports beyond a plain key spend.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// The deposit output is an aggregate MuSig2 key of the signers.
+    Musig2,
+    /// Recovery, expiry, policy and custom leaves in the deposit output.
+    ScriptPaths,
+    AdaptorSignatures,
+    Cets,
+    Vaults,
+    Rollover,
+    Inheritance,
+    DecayingMultisig,
+    SilentPayments,
+    /// Requests and responses in a binary encoding rather than JSON.
+    BinaryEncoding,
+    /// Blind sessions on `/blind/init` and `/blind/sign`, in which the signer does not learn
+    /// the deposit's funding inputs.
+    BlindSessions,
+    /// Presigned spends paying fixed amounts to outputs besides the fallback address.
+    SpendOutputs,
+    /// Presigned spends splitting the deposit between the fallback address and weighted shares.
+    WeightedFallback,
+    /// Presigned spends timelocked as the depositor asks.
+    SpendTimelock,
+    /// Presigned spends signed with another sighash type than SIGHASH_DEFAULT.
+    SighashTypes,
+    /// A P2A anchor output on the presigned spend, for bumping its fee by CPFP.
+    Anchors,
+}
+
+/// The limits of the signer's policy a depositor can check a request against up front.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, ToSchema)]
+pub struct PolicySummary {
+    #[serde(default)]
+    pub max_deposit_sat: Option<u64>,
+    /// Networks requests may be for. Any is allowed if empty.
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub networks: Vec<Network>,
+    /// Output types the fallback address may be. Any is allowed if empty.
+    #[serde(default)]
+    pub fallback_types: Vec<String>,
+    #[serde(default)]
+    pub min_spend_fee_sat: Option<u64>,
+    #[serde(default)]
+    pub max_spend_fee_sat: Option<u64>,
+    /// Ceiling on the signer's total liability, in sats.
+    #[serde(default)]
+    pub max_liability_sat: Option<u64>,
+    /// How far in the future the presigned spend's absolute locktime may be, in seconds, at ten
+    /// minutes a block for a height.
+    #[serde(default)]
+    pub max_lock_delay_secs: Option<u64>,
+    #[serde(default)]
+    pub max_relative_blocks: Option<u16>,
+    /// Whether the presigned spend must signal replaceability.
+    #[serde(default)]
+    pub require_rbf: bool,
+}
+
+/// Output descriptor of a deposit, along with the details of its taptweak.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct DepositDescriptor {
+    /// `tr()` descriptor if the deposit has no script paths, otherwise a `rawtr()` descriptor of
+    /// the output key, since arbitrary leaves cannot be expressed as descriptors.
+    pub descriptor: String,
+    /// Hex encoded x-only internal key, i.e. the ephemeral key.
+    pub internal_key: String,
+    /// Hex encoded merkle root of the script tree, if any.
+    pub merkle_root: Option<String>,
+    /// Hex encoded x-only output key.
+    pub output_key: String,
+}
+
+impl DepositDescriptor {
+    pub fn new(spend_info: &TaprootSpendInfo) -> Self {
+        let internal_key = spend_info.internal_key().to_string();
+        let output_key = spend_info.output_key().to_string();
+        let merkle_root = spend_info.merkle_root().map(|r| r.to_string());
+
+        let desc = match merkle_root {
+            None => format!("tr({})", internal_key),
+            Some(_) => format!("rawtr({})", output_key),
+        };
+        let checksum = desc_checksum(&desc).expect("valid descriptor characters");
+
+        DepositDescriptor {
+            descriptor: format!("{}#{}", desc, checksum),
+            internal_key,
+            merkle_root,
+            output_key,
+        }
+    }
+}
+
+/// A leaf of a taproot tree, with the control block needed to spend it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct ScriptPath {
+    /// Hex encoded tapscript.
+    pub script: String,
+    /// Hex encoded control block.
+    pub control_block: String,
+}
+
+/// Lists the script paths of `spend_info` for each of `leaves`.
+pub fn script_paths(spend_info: &TaprootSpendInfo, leaves: &[ScriptBuf]) -> Vec<ScriptPath> {
+    leaves
+        .iter()
+        .map(|leaf| {
+            let control_block = spend_info
+                .control_block(&(leaf.clone(), LeafVersion::TapScript))
+                .expect("leaf in tree");
+            ScriptPath {
+                script: hex::encode(leaf.as_bytes()),
+                control_block: hex::encode(control_block.serialize()),
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct VaultSpends {
+    /// Spend of the unvault to the fallback address, valid after the vault delay, which the
+    /// unvault output enforces.
+    #[serde(with = "psbt_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub final_psbt: Psbt,
+    /// Spend of the unvault to the clawback address, valid immediately.
+    #[serde(with = "psbt_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub clawback_psbt: Psbt,
+    /// Hex encoded x-only key the final spend is signed with, through the only leaf of the
+    /// unvault output, its `script::unvault_script`. The clawback is signed with the internal key
+    /// of the unvault output, in the unvault PSBT. Each key signs only its own spend.
+    pub final_key: String,
+}
+
+/// Computes the point whose discrete log is revealed when the oracle attests to `outcome`. The
+/// attestation is a BIP-340 signature on the sha256 of the outcome using the announced nonce.
+pub fn attestation_point(
+    oracle_pubkey: &str,
+    oracle_nonce: &str,
+    outcome: &str,
+) -> Result<MaybePoint, InvalidPointString> {
+    let pubkey = Point::lift_x_hex(oracle_pubkey)?;
+    let nonce = Point::lift_x_hex(oracle_nonce)?;
+    let msg: [u8; 32] = Sha256::digest(outcome.as_bytes()).into();
+    let e: MaybeScalar = compute_challenge_hash_tweak(&nonce.serialize_xonly(), &pubkey, &msg);
+    Ok(nonce + e * pubkey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// P2WPKH address from BIP-173, dust below 294 sat.
+    const P2WPKH: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+    /// P2TR address from BIP-350, dust below 330 sat.
+    const P2TR: &str = "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0";
+
+    fn share(address: &str, percent: u8) -> FallbackShare {
+        FallbackShare {
+            address: address.to_string(),
+            percent,
+        }
+    }
+
+    fn split(value: u64, shares: &[FallbackShare]) -> Vec<u64> {
+        split_fallback(Amount::from_sat(value).unwrap(), shares, Network::Bitcoin)
+            .unwrap()
+            .iter()
+            .map(|o| o.value.to_sat())
+            .collect()
+    }
+
+    #[test]
+    fn dust_share_goes_to_previous_share() {
+        // 1% of 20000 sat is 200 sat, dust for the P2TR share.
+        assert_eq!(
+            split(20_000, &[share(P2WPKH, 50), share(P2TR, 1)]),
+            [10_200]
+        );
+    }
+
+    #[test]
+    fn dust_first_share_goes_to_fallback() {
+        // Nothing is paid for the first share, leaving its 200 sat to the fallback address.
+        assert_eq!(
+            split(20_000, &[share(P2TR, 1), share(P2WPKH, 50)]),
+            [10_000]
+        );
+    }
+
+    #[test]
+    fn shares_leave_some_to_fallback() {
+        assert_eq!(
+            split(100_000, &[share(P2WPKH, 60), share(P2TR, 39)]),
+            [60_000, 39_000]
+        );
+
+        let all = [share(P2WPKH, 60), share(P2TR, 40)];
+        assert!(
+            split_fallback(Amount::from_sat(100_000).unwrap(), &all, Network::Bitcoin).is_err()
+        );
+    }
 }
@@ -0,0 +1,52 @@
+use bitcoin::ScriptBuf;
+use miniscript::Tap;
+use miniscript::policy::Concrete;
+
+/// Name standing for the ephemeral key in a deposit policy.
+pub const EPHEMERAL_KEY: &str = "ephemeral";
+
+/// Compiles a miniscript policy for the deposit output into its script path leaves.
+///
+/// The policy must have `pk(ephemeral)` as one of its top-level alternatives, e.g.
+/// `or(pk(ephemeral),and(pk(<key>),older(144)))`. The ephemeral key becomes the internal key of
+/// the output, and each of the other alternatives is compiled into a leaf. Since the ephemeral
+/// key is deleted after signing, it cannot be used anywhere else in the policy.
+pub fn compile_deposit_policy(policy: &str) -> Result<Vec<ScriptBuf>, Box<dyn std::error::Error>> {
+    let policy: Concrete<String> = policy.parse()?;
+
+    let mut alternatives = vec![];
+    flatten_or(&policy, &mut alternatives);
+
+    let (ephemeral, rest): (Vec<_>, Vec<_>) = alternatives
+        .into_iter()
+        .partition(|p| matches!(p, Concrete::Key(k) if k == EPHEMERAL_KEY));
+    if ephemeral.len() != 1 {
+        return Err("policy must have pk(ephemeral) as exactly one top-level alternative".into());
+    }
+
+    rest.into_iter()
+        .map(
+            |alternative| -> Result<ScriptBuf, Box<dyn std::error::Error>> {
+                if alternative.keys().into_iter().any(|k| k == EPHEMERAL_KEY) {
+                    return Err("ephemeral key can only be a top-level alternative".into());
+                }
+
+                // With the ephemeral key gone, the remaining keys must be actual x-only keys.
+                let alternative: Concrete<miniscript::bitcoin::XOnlyPublicKey> =
+                    alternative.to_string().parse()?;
+                let ms = alternative.compile::<Tap>()?;
+                Ok(ScriptBuf::from_bytes(ms.encode().into_bytes()))
+            },
+        )
+        .collect()
+}
+
+/// Collects the alternatives of any top-level `or`s of the policy.
+fn flatten_or<'a>(policy: &'a Concrete<String>, alternatives: &mut Vec<&'a Concrete<String>>) {
+    match policy {
+        Concrete::Or(subs) => subs
+            .iter()
+            .for_each(|(_, sub)| flatten_or(sub, alternatives)),
+        _ => alternatives.push(policy),
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::Psbt;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Serializes a PSBT of the protocol messages as its BIP-174 base64 string in JSON and its BIP-174
+/// bytes in CBOR, for `#[serde(with = "psbt_base64")]`, so it is read by any PSBT library.
+pub fn serialize<S: Serializer>(psbt: &Psbt, s: S) -> Result<S::Ok, S::Error> {
+    if s.is_human_readable() {
+        s.serialize_str(&psbt.to_string())
+    } else {
+        s.serialize_bytes(&psbt.serialize())
+    }
+}
+
+/// Deserializes a PSBT serialized by `serialize`, or in rust-bitcoin's own serde layout as sent
+/// before.
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Psbt, D::Error> {
+    d.deserialize_any(PsbtVisitor)
+}
+
+struct PsbtVisitor;
+
+impl<'de> Visitor<'de> for PsbtVisitor {
+    type Value = Psbt;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a base64 encoded PSBT")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Psbt, E> {
+        Psbt::from_str(v).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Psbt, E> {
+        Psbt::deserialize(v).map_err(E::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Psbt, A::Error> {
+        <Psbt as Deserialize>::deserialize(de::value::MapAccessDeserializer::new(map))
+    }
+}
+
+struct Ref<'a>(&'a Psbt);
+
+impl Serialize for Ref<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        serialize(self.0, s)
+    }
+}
+
+struct Owned(Psbt);
+
+impl<'de> Deserialize<'de> for Owned {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        deserialize(d).map(Owned)
+    }
+}
+
+/// The same for optional PSBTs, with `#[serde(with = "psbt_base64::option")]`.
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(psbt: &Option<Psbt>, s: S) -> Result<S::Ok, S::Error> {
+        psbt.as_ref().map(Ref).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Psbt>, D::Error> {
+        Ok(Option::<Owned>::deserialize(d)?.map(|o| o.0))
+    }
+}
@@ -0,0 +1,137 @@
+use std::convert::Infallible;
+
+use bitcoin::{Network, Txid};
+use musig2::LiftedSignature;
+use musig2::secp::{Point, Scalar};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::SignPsbtResp;
+use crate::silent_payment::tagged_hash;
+use crate::tee::EnclaveAttestation;
+
+const RECEIPT_TAG: &str = "ephemeral-sign/receipt";
+
+/// The signer's signed statement of what it presigned a spend for, on which terms, signed with
+/// the operator's long-lived identity key so the depositor can prove it later.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct Receipt {
+    #[schema(value_type = String)]
+    pub deposit_txid: Txid,
+    #[schema(value_type = String)]
+    pub spend_txid: Txid,
+    /// Hex encoded x-only ephemeral key the deposit is locked to, deleted after signing.
+    pub ephemeral_pubkey: String,
+    #[schema(value_type = String, example = "bitcoin")]
+    pub network: Network,
+    pub deposit_sat: u64,
+    pub spend_fee_sat: u64,
+    /// Quote whose terms the request was signed on, if any.
+    #[serde(default)]
+    pub quote_id: Option<String>,
+    /// Unix time of signing.
+    pub timestamp: u64,
+    /// Hex encoded x-only identity key of the operator.
+    pub operator_key: String,
+    /// Each signer's enclave attestation that it deleted its key of the session, in key
+    /// aggregation order, if all signers run in enclaves.
+    #[serde(default)]
+    pub enclave_attestations: Vec<EnclaveAttestation>,
+    /// Hex encoded BIP-340 signature with the operator key on the receipt's message.
+    pub signature: String,
+}
+
+impl Receipt {
+    /// The tagged hash the signature is on, of one `name=value` line per field in order. The
+    /// enclave attestations are committed to by the hash of a line for each, and left out if
+    /// there are none, as before they were added.
+    pub fn message(&self) -> [u8; 32] {
+        let mut data = format!(
+            "deposit_txid={}\nspend_txid={}\nephemeral_pubkey={}\nnetwork={}\ndeposit_sat={}\n\
+             spend_fee_sat={}\nquote_id={}\ntimestamp={}\noperator_key={}\n",
+            self.deposit_txid,
+            self.spend_txid,
+            self.ephemeral_pubkey,
+            self.network,
+            self.deposit_sat,
+            self.spend_fee_sat,
+            self.quote_id.as_deref().unwrap_or(""),
+            self.timestamp,
+            self.operator_key,
+        );
+        if !self.enclave_attestations.is_empty() {
+            let mut hasher = Sha256::new();
+            for a in &self.enclave_attestations {
+                hasher.update(format!(
+                    "{}:{}:{}:{}\n",
+                    a.platform, a.session_id, a.pubkey, a.document
+                ));
+            }
+            let hash = hasher.finalize();
+            data.push_str(&format!("enclave_attestations={}\n", hex::encode(hash)));
+        }
+        tagged_hash(RECEIPT_TAG, &[data.as_bytes()])
+    }
+
+    /// Sets the operator key to that of `seckey` and signs the receipt with it.
+    pub fn sign(&mut self, seckey: Scalar, aux: &[u8; 32]) {
+        let sign = |message: [u8; 32]| {
+            let signature: LiftedSignature = musig2::sign_solo(seckey, message, *aux);
+            Ok::<_, Infallible>(signature.serialize())
+        };
+        let Ok(()) = self.sign_with(seckey.base_point_mul(), sign);
+    }
+
+    /// Sets the operator key to `operator_key` and signs the receipt with `sign`, making BIP-340
+    /// signatures with the key that is kept elsewhere, such as in an HSM.
+    pub fn sign_with<E>(
+        &mut self,
+        operator_key: Point,
+        sign: impl FnOnce([u8; 32]) -> Result<[u8; 64], E>,
+    ) -> Result<(), E> {
+        self.operator_key = hex::encode(operator_key.serialize_xonly());
+        self.signature = hex::encode(sign(self.message())?);
+        Ok(())
+    }
+
+    /// Verifies the signature, returning the operator key it is by.
+    pub fn verify(&self) -> Result<Point, Box<dyn std::error::Error>> {
+        let operator_key = Point::lift_x_hex(&self.operator_key)?;
+        let signature: [u8; 64] = hex::decode(&self.signature)?
+            .try_into()
+            .map_err(|_| "invalid receipt signature length")?;
+        musig2::verify_single(operator_key, signature, self.message())
+            .map_err(|_| "invalid receipt signature")?;
+        Ok(operator_key)
+    }
+
+    /// Checks the receipt is for the transactions of `resp` and verifies its signature,
+    /// returning the operator key it is by.
+    pub fn verify_for(&self, resp: &SignPsbtResp) -> Result<Point, Box<dyn std::error::Error>> {
+        let deposit_tx = &resp.deposit_psbt.unsigned_tx;
+        if self.deposit_txid != deposit_tx.compute_txid() {
+            return Err("receipt is for another deposit".into());
+        }
+        if self.spend_txid != resp.spend_psbt.unsigned_tx.compute_txid() {
+            return Err("receipt is for another spend".into());
+        }
+        if self.network != resp.network {
+            return Err(format!("receipt is for {}", self.network).into());
+        }
+        if deposit_tx.output.first().map(|o| o.value.to_sat()) != Some(self.deposit_sat) {
+            return Err("receipt is for another deposit amount".into());
+        }
+        if let Some(descriptor) = &resp.descriptor {
+            if descriptor.internal_key != self.ephemeral_pubkey {
+                return Err("receipt is for another ephemeral key".into());
+            }
+        }
+        if let Some(fee) = &resp.spend_fee {
+            if fee.fee_sat != self.spend_fee_sat {
+                return Err("receipt is for another spend fee".into());
+            }
+        }
+        self.verify()
+    }
+}
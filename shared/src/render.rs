@@ -0,0 +1,288 @@
+//! Renders transactions and PSBTs as aligned tables for review on the terminal: inputs and their
+//! values, outputs decoded to addresses, the fee and the locktimes.
+
+use std::io::IsTerminal;
+
+use bitcoin::script::ScriptExt;
+use bitcoin::{Address, Amount, Network, Psbt, Script, Sequence, Transaction, TxOut};
+
+/// Locktimes from this value on are Unix times rather than block heights.
+const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// Relative locktimes with this flag set are in units of 512 seconds rather than blocks.
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+
+const BOLD: &str = "1";
+const DIM: &str = "2";
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+const CYAN: &str = "36";
+
+/// Renders `tx` under `title`. `prevouts` are the outputs its inputs spend, in order, as far as
+/// they are known; the fee is only shown if all are.
+pub fn transaction(
+    title: &str,
+    tx: &Transaction,
+    prevouts: &[Option<TxOut>],
+    network: Network,
+) -> String {
+    let details = format!(
+        "version {}, {} vB, locktime {}",
+        tx.version,
+        tx.vsize(),
+        lock_time(tx.lock_time.to_consensus_u32())
+    );
+    let fee = fee(tx, prevouts).map(|fee| {
+        let feerate = fee.to_sat() as f64 / tx.vsize() as f64;
+        format!("{} sat, {:.1} sat/vB", fee.to_sat(), feerate)
+    });
+    render(title, &details, tx, prevouts, None, fee, network)
+}
+
+/// Renders the unsigned transaction of `psbt` under `title`, with the outputs its inputs spend
+/// and whether each input is signed yet. The size of the transaction is not known until all are,
+/// so only the fee is shown.
+pub fn psbt(title: &str, psbt: &Psbt, network: Network) -> String {
+    let tx = &psbt.unsigned_tx;
+    let prevouts: Vec<Option<TxOut>> = psbt
+        .inputs
+        .iter()
+        .zip(&tx.input)
+        .map(|(input, txin)| {
+            input.witness_utxo.clone().or_else(|| {
+                let prev_tx = input.non_witness_utxo.as_ref()?;
+                prev_tx
+                    .output
+                    .get(txin.previous_output.vout as usize)
+                    .cloned()
+            })
+        })
+        .collect();
+    let signed: Vec<bool> = psbt
+        .inputs
+        .iter()
+        .map(|input| {
+            input.final_script_witness.is_some()
+                || input.tap_key_sig.is_some()
+                || !input.tap_script_sigs.is_empty()
+                || !input.partial_sigs.is_empty()
+        })
+        .collect();
+    let details = format!(
+        "version {}, locktime {}",
+        tx.version,
+        lock_time(tx.lock_time.to_consensus_u32())
+    );
+    let fee = fee(tx, &prevouts).map(|fee| format!("{} sat", fee.to_sat()));
+    render(title, &details, tx, &prevouts, Some(&signed), fee, network)
+}
+
+fn render(
+    title: &str,
+    details: &str,
+    tx: &Transaction,
+    prevouts: &[Option<TxOut>],
+    signed: Option<&[bool]>,
+    fee: Option<String>,
+    network: Network,
+) -> String {
+    let mut out = format!(
+        "{} {}\n  {}\n",
+        paint(title, BOLD),
+        paint(&tx.compute_txid().to_string(), CYAN),
+        paint(details, DIM)
+    );
+
+    let mut inputs = Table::new(&["in", "outpoint", "value", "sequence", "spends"]);
+    for (i, txin) in tx.input.iter().enumerate() {
+        let prevout = prevouts.get(i).cloned().flatten();
+        let mut spends = prevout.as_ref().map_or("unknown".to_string(), |p| {
+            describe(&p.script_pubkey, network)
+        });
+        if let Some(signed) = signed.and_then(|s| s.get(i)) {
+            spends.push_str(if *signed {
+                "  [signed]"
+            } else {
+                "  [unsigned]"
+            });
+        }
+        inputs.row(vec![
+            Cell::plain(i.to_string()),
+            Cell::new(txin.previous_output.to_string(), CYAN),
+            value(prevout.map(|p| p.value)),
+            Cell::plain(sequence(txin.sequence)),
+            Cell::plain(spends),
+        ]);
+    }
+    out.push_str(&inputs.render());
+
+    let mut outputs = Table::new(&["out", "value", "pays"]);
+    for (i, output) in tx.output.iter().enumerate() {
+        outputs.row(vec![
+            Cell::plain(i.to_string()),
+            value(Some(output.value)),
+            Cell::plain(describe(&output.script_pubkey, network)),
+        ]);
+    }
+    out.push_str(&outputs.render());
+
+    let fee = match fee {
+        Some(fee) => paint(&fee, GREEN),
+        None => paint("unknown", RED),
+    };
+    out.push_str(&format!("  {} {}\n", paint("fee", BOLD), fee));
+    out
+}
+
+/// The address `script` pays, or the script itself if it has none.
+pub fn describe(script: &Script, network: Network) -> String {
+    if script.is_op_return() {
+        return format!("OP_RETURN {}", script.to_hex_string());
+    }
+    match Address::from_script(script, network) {
+        Ok(addr) => addr.to_string(),
+        Err(_) => format!("script {}", script.to_hex_string()),
+    }
+}
+
+fn fee(tx: &Transaction, prevouts: &[Option<TxOut>]) -> Option<Amount> {
+    let input_value = (0..tx.input.len()).try_fold(Amount::ZERO, |sum, i| {
+        sum.checked_add(prevouts.get(i)?.as_ref()?.value)
+    })?;
+    let output_value = tx
+        .output
+        .iter()
+        .try_fold(Amount::ZERO, |sum, o| sum.checked_add(o.value))?;
+    input_value.checked_sub(output_value)
+}
+
+fn lock_time(lock_time: u32) -> String {
+    match lock_time {
+        0 => "none".to_string(),
+        height if height < LOCK_TIME_THRESHOLD => format!("from block {}", height),
+        time => format!("from Unix time {}", time),
+    }
+}
+
+fn sequence(sequence: Sequence) -> String {
+    let n = sequence.to_consensus_u32();
+    if sequence == Sequence::MAX {
+        return "final".to_string();
+    }
+    if !sequence.is_relative_lock_time() {
+        return match sequence.is_rbf() {
+            true => "rbf".to_string(),
+            false => "no rbf".to_string(),
+        };
+    }
+    match n & SEQUENCE_TYPE_FLAG != 0 {
+        true => format!("rbf, after {} s", (n & 0xffff) * 512),
+        false => format!("rbf, after {} blocks", n & 0xffff),
+    }
+}
+
+fn value(value: Option<Amount>) -> Cell {
+    match value {
+        Some(value) => Cell::new(format!("{} sat", value.to_sat()), YELLOW).right(),
+        None => Cell::new("unknown".to_string(), RED).right(),
+    }
+}
+
+/// Whether to color the output: only on a terminal, and not if NO_COLOR is set.
+fn color() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+fn paint(text: &str, code: &str) -> String {
+    match color() {
+        true => format!("\x1b[{}m{}\x1b[0m", code, text),
+        false => text.to_string(),
+    }
+}
+
+struct Cell {
+    text: String,
+    color: Option<&'static str>,
+    right: bool,
+}
+
+impl Cell {
+    fn new(text: String, color: &'static str) -> Self {
+        Cell {
+            text,
+            color: Some(color),
+            right: false,
+        }
+    }
+
+    fn plain(text: String) -> Self {
+        Cell {
+            text,
+            color: None,
+            right: false,
+        }
+    }
+
+    fn right(self) -> Self {
+        Cell {
+            right: true,
+            ..self
+        }
+    }
+}
+
+/// Rows of cells aligned in columns under a header. Cells are padded before they are colored,
+/// so the escape codes do not count towards the width.
+struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    fn new(header: &[&str]) -> Self {
+        Table {
+            header: header.iter().map(|h| h.to_string()).collect(),
+            rows: vec![],
+        }
+    }
+
+    fn row(&mut self, row: Vec<Cell>) {
+        self.rows.push(row);
+    }
+
+    fn render(&self) -> String {
+        let mut widths: Vec<usize> = self.header.iter().map(|h| h.chars().count()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.text.chars().count());
+            }
+        }
+
+        let header: Vec<String> = self
+            .header
+            .iter()
+            .zip(&widths)
+            .map(|(h, w)| format!("{:<w$}", h, w = w))
+            .collect();
+        let mut out = format!("  {}\n", paint(header.join("  ").trim_end(), BOLD));
+        for row in &self.rows {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, w)| {
+                    let padded = match cell.right {
+                        true => format!("{:>w$}", cell.text, w = w),
+                        false => format!("{:<w$}", cell.text, w = w),
+                    };
+                    match cell.color {
+                        Some(color) => paint(&padded, color),
+                        None => padded,
+                    }
+                })
+                .collect();
+            out.push_str(&format!("  {}\n", cells.join("  ").trim_end()));
+        }
+        out
+    }
+}
@@ -0,0 +1,137 @@
+use bitcoin::opcodes::Opcode;
+use bitcoin::opcodes::all::{
+    OP_CHECKSIG, OP_CHECKSIGADD, OP_CLTV, OP_CSV, OP_DROP, OP_NUMEQUAL, OP_PUSHNUM_1, OP_RETURN,
+};
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::taproot::TaprootSpendInfo;
+use bitcoin::{ScriptBuf, Sequence, XOnlyPublicKey, absolute};
+use std::str::FromStr;
+
+/// Script letting `key` spend the deposit once it has been confirmed for `delay` blocks.
+pub fn recovery_script(key: XOnlyPublicKey, delay: u16) -> ScriptBuf {
+    Builder::new()
+        .push_sequence(Sequence::from_height(delay))
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_DROP)
+        .push_x_only_key(&key)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Script letting `key` spend the unvault output of a vault once it has been confirmed for `delay`
+/// blocks, through which the final spend is signed.
+pub fn unvault_script(key: XOnlyPublicKey, delay: u16) -> ScriptBuf {
+    recovery_script(key, delay)
+}
+
+/// Script letting `key` spend the deposit from block height `height` on.
+pub fn expiry_script(key: XOnlyPublicKey, height: absolute::LockTime) -> ScriptBuf {
+    Builder::new()
+        .push_lock_time(height)
+        .push_opcode(OP_CLTV)
+        .push_opcode(OP_DROP)
+        .push_x_only_key(&key)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Script letting `key` alone spend the output. Each presigned transaction signed through a
+/// script path gets a key and leaf of its own, so no key ever signs more than one message.
+pub fn checksig_script(key: XOnlyPublicKey) -> ScriptBuf {
+    Builder::new()
+        .push_x_only_key(&key)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Pay-to-anchor script, which anyone can spend with an empty witness, for bumping the fee of
+/// the transaction paying it by CPFP.
+pub fn anchor_script() -> ScriptBuf {
+    Builder::new()
+        .push_opcode(OP_PUSHNUM_1)
+        .push_slice([0x4e, 0x73])
+        .into_script()
+}
+
+/// Most data an OP_RETURN output relays with by default.
+pub const MAX_OP_RETURN_DATA: usize = 80;
+
+/// Unspendable script carrying `data`, for binding it to a transaction on-chain.
+pub fn op_return_script(data: &[u8]) -> Result<ScriptBuf, Box<dyn std::error::Error>> {
+    if data.len() > MAX_OP_RETURN_DATA {
+        return Err(format!(
+            "OP_RETURN data of {} bytes exceeds the maximum of {}",
+            data.len(),
+            MAX_OP_RETURN_DATA
+        )
+        .into());
+    }
+    let data = PushBytesBuf::try_from(data.to_vec())?;
+    Ok(Builder::new()
+        .push_opcode(OP_RETURN)
+        .push_slice(data)
+        .into_script())
+}
+
+/// BIP-341 NUMS point, used as internal key for outputs that can only be spent by script path.
+const UNSPENDABLE_KEY: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+/// Script requiring signatures for `threshold` of `keys`, optionally only once the output has
+/// been confirmed for `delay` blocks. At most 16 keys are supported.
+pub fn multisig_script(keys: &[XOnlyPublicKey], threshold: usize, delay: Option<u16>) -> ScriptBuf {
+    let mut builder = Builder::new();
+    if let Some(delay) = delay {
+        builder = builder
+            .push_sequence(Sequence::from_height(delay))
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP);
+    }
+
+    for (i, key) in keys.iter().enumerate() {
+        builder = builder.push_x_only_key(key);
+        builder = match i {
+            0 => builder.push_opcode(OP_CHECKSIG),
+            _ => builder.push_opcode(OP_CHECKSIGADD),
+        };
+    }
+
+    builder
+        .push_opcode(Opcode::from(OP_PUSHNUM_1.to_u8() + threshold as u8 - 1))
+        .push_opcode(OP_NUMEQUAL)
+        .into_script()
+}
+
+/// Builds the taproot tree of an output spendable by all of `keys`, with the threshold decreasing
+/// by one after each of `delays`. The internal key is unspendable.
+pub fn decaying_multisig_spend_info<C: Verification>(
+    secp: &Secp256k1<C>,
+    keys: &[XOnlyPublicKey],
+    delays: &[u16],
+) -> TaprootSpendInfo {
+    let internal_key = XOnlyPublicKey::from_str(UNSPENDABLE_KEY).unwrap();
+
+    let mut leaves = vec![multisig_script(keys, keys.len(), None)];
+    for (i, delay) in delays.iter().enumerate() {
+        leaves.push(multisig_script(keys, keys.len() - 1 - i, Some(*delay)));
+    }
+
+    TaprootSpendInfo::with_huffman_tree(secp, internal_key, leaves.into_iter().map(|l| (1, l)))
+        .expect("non-empty tree")
+}
+
+/// Builds the taproot tree of the deposit output, with the ephemeral key as internal key and a
+/// script path for each of `leaves`. Both sides must build the tree from the leaves in the same
+/// order to arrive at the same output key.
+pub fn deposit_spend_info<C: Verification>(
+    secp: &Secp256k1<C>,
+    internal_key: XOnlyPublicKey,
+    leaves: Vec<ScriptBuf>,
+) -> TaprootSpendInfo {
+    if leaves.is_empty() {
+        return TaprootSpendInfo::new_key_spend(secp, internal_key, None);
+    }
+
+    TaprootSpendInfo::with_huffman_tree(secp, internal_key, leaves.into_iter().map(|l| (1, l)))
+        .expect("non-empty tree")
+}
@@ -0,0 +1,679 @@
+use bech32::primitives::decode::CheckedHrpstring;
+use bech32::{Bech32m, Hrp};
+use bitcoin::key::TweakedPublicKey;
+use bitcoin::{Network, OutPoint, ScriptBuf, XOnlyPublicKey, consensus};
+use musig2::KeyAggContext;
+use musig2::secp::{G, MaybePoint, MaybeScalar, Point, Scalar};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+/// A BIP-352 silent payment address.
+#[derive(Clone, Debug)]
+pub struct SilentPaymentAddress {
+    pub scan_key: Point,
+    pub spend_key: Point,
+}
+
+impl SilentPaymentAddress {
+    /// Parses a version 0 silent payment address for the given network.
+    pub fn parse(addr: &str, network: Network) -> Result<Self, Box<dyn std::error::Error>> {
+        let hrp = match network {
+            Network::Bitcoin => Hrp::parse("sp")?,
+            _ => Hrp::parse("tsp")?,
+        };
+
+        let mut checked = CheckedHrpstring::new::<Bech32m>(addr)?;
+        if checked.hrp() != hrp {
+            return Err("silent payment address for wrong network".into());
+        }
+
+        match checked.remove_witness_version().map(|v| v.to_u8()) {
+            Some(0) => {}
+            _ => return Err("unsupported silent payment address version".into()),
+        }
+
+        // The rest of the data is the scan key followed by the spend key.
+        let bytes: Vec<u8> = checked.byte_iter().collect();
+        if bytes.len() != 66 {
+            return Err("invalid silent payment address length".into());
+        }
+
+        Ok(SilentPaymentAddress {
+            scan_key: Point::from_slice(&bytes[..33])?,
+            spend_key: Point::from_slice(&bytes[33..])?,
+        })
+    }
+
+    /// Returns true if the address looks like a silent payment address rather than a regular one.
+    pub fn is_silent_payment(addr: &str) -> bool {
+        let addr = addr.to_lowercase();
+        addr.starts_with("sp1") || addr.starts_with("tsp1")
+    }
+}
+
+/// A signer's share of the ECDH with the scan key of a silent payment address, proven to use the
+/// same secret as its public key.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct EcdhShare {
+    /// Hex encoded (compressed) public key of the signer.
+    pub pubkey: String,
+    /// Hex encoded (compressed) point of the signer's secret key times the scan key.
+    pub share: String,
+    /// Hex encoded BIP-374 DLEQ proof of the share.
+    pub proof: String,
+}
+
+impl EcdhShare {
+    pub fn new(seckey: Scalar, scan_key: Point, aux: &[u8; 32]) -> Self {
+        let share = seckey * scan_key;
+        EcdhShare {
+            pubkey: hex::encode(seckey.base_point_mul().serialize()),
+            share: hex::encode(share.serialize()),
+            proof: hex::encode(dleq_prove(generator(), seckey, scan_key, aux, None)),
+        }
+    }
+
+    /// Verifies the DLEQ proof, returning the signer's public key and share.
+    fn verify(&self, scan_key: Point) -> Result<(Point, Point), Box<dyn std::error::Error>> {
+        let pubkey = Point::from_hex(&self.pubkey)?;
+        let share = Point::from_hex(&self.share)?;
+        let proof: [u8; 64] = hex::decode(&self.proof)?
+            .try_into()
+            .map_err(|_| "invalid DLEQ proof length")?;
+
+        if !dleq_verify(generator(), pubkey, scan_key, share, &proof, None) {
+            return Err("invalid DLEQ proof for ECDH share".into());
+        }
+
+        Ok((pubkey, share))
+    }
+}
+
+/// Derives the first BIP-352 output to `addr` from a transaction with `outpoint` as its only
+/// input, a P2TR output of the aggregate key of `key_agg_ctx`, tweaked as for signing the input.
+/// There must be one ECDH share for each key in the context, each of which is verified.
+pub fn output_script(
+    addr: &SilentPaymentAddress,
+    key_agg_ctx: &KeyAggContext,
+    shares: &[EcdhShare],
+    outpoint: OutPoint,
+) -> Result<ScriptBuf, Box<dyn std::error::Error>> {
+    let ecdh = aggregate_ecdh(key_agg_ctx, shares, addr.scan_key)?;
+
+    // Taproot inputs are included with their even y public key.
+    let output_key: Point = key_agg_ctx.aggregated_pubkey();
+    let output = derive_output(addr, outpoint, output_key.to_even_y(), ecdh)?;
+    Ok(ScriptBuf::new_p2tr_tweaked(
+        TweakedPublicKey::dangerous_assume_tweaked(output),
+    ))
+}
+
+/// Aggregates the signers' ECDH shares with `scan_key` into the ECDH of the secret key of the
+/// P2TR output of `key_agg_ctx`, negated as BIP-352 does for an odd output key. Each share is
+/// verified.
+fn aggregate_ecdh(
+    key_agg_ctx: &KeyAggContext,
+    shares: &[EcdhShare],
+    scan_key: Point,
+) -> Result<MaybePoint, Box<dyn std::error::Error>> {
+    let pubkeys: &[Point] = key_agg_ctx.pubkeys();
+    if shares.len() != pubkeys.len() {
+        return Err("need one ECDH share per signer".into());
+    }
+
+    // The input's secret key is the aggregate of the signer keys, tweaked and negated the same
+    // way as when signing, so we aggregate the shares the same way.
+    let output_key: Point = key_agg_ctx.aggregated_pubkey();
+    let challenge_parity = output_key.parity() ^ key_agg_ctx.parity_acc();
+    let tweak: MaybeScalar = key_agg_ctx.tweak_sum().unwrap_or(MaybeScalar::Zero);
+
+    let mut ecdh = MaybePoint::Infinity;
+    for share in shares {
+        let (pubkey, share) = share.verify(scan_key)?;
+        let coeff = key_agg_ctx
+            .key_coefficient(pubkey)
+            .ok_or("ECDH share for unknown signer")?;
+        ecdh = ecdh + coeff * share;
+    }
+    Ok(ecdh.negate_if(challenge_parity) + (tweak * scan_key).negate_if(output_key.parity()))
+}
+
+/// Derives the first BIP-352 output key to `addr` of a transaction whose smallest outpoint is
+/// `outpoint`, from the sum `input_key` of its input keys and their `ecdh` with the scan key.
+fn derive_output(
+    addr: &SilentPaymentAddress,
+    outpoint: OutPoint,
+    input_key: Point,
+    ecdh: MaybePoint,
+) -> Result<XOnlyPublicKey, Box<dyn std::error::Error>> {
+    let input_hash = tagged_hash(
+        "BIP0352/Inputs",
+        &[
+            &consensus::encode::serialize(&outpoint),
+            &input_key.serialize(),
+        ],
+    );
+    let shared_secret = MaybeScalar::reduce_from(&input_hash) * ecdh;
+    let shared_secret = match shared_secret {
+        MaybePoint::Valid(p) => p,
+        MaybePoint::Infinity => return Err("invalid ECDH shared secret".into()),
+    };
+
+    let t = tagged_hash(
+        "BIP0352/SharedSecret",
+        &[&shared_secret.serialize(), &0u32.to_be_bytes()],
+    );
+    let output = match addr.spend_key + MaybeScalar::reduce_from(&t) * G {
+        MaybePoint::Valid(p) => p,
+        MaybePoint::Infinity => return Err("invalid silent payment output".into()),
+    };
+
+    Ok(XOnlyPublicKey::from_slice(&output.serialize_xonly())?)
+}
+
+pub(crate) fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    for d in data {
+        hasher.update(d);
+    }
+    hasher.finalize().into()
+}
+
+/// The secp256k1 generator, which the ECDH shares are proven against.
+fn generator() -> Point {
+    Scalar::one() * G
+}
+
+fn dleq_challenge(
+    g: Point,
+    a: Point,
+    b: Point,
+    c: Point,
+    r1: Point,
+    r2: Point,
+    m: Option<&[u8; 32]>,
+) -> MaybeScalar {
+    let e = tagged_hash(
+        "BIP0374/challenge",
+        &[
+            &a.serialize(),
+            &b.serialize(),
+            &c.serialize(),
+            &g.serialize(),
+            &r1.serialize(),
+            &r2.serialize(),
+            m.map_or(&[][..], |m| &m[..]),
+        ],
+    );
+    MaybeScalar::reduce_from(&e)
+}
+
+/// Creates a BIP-374 proof that `a*g` and `a*b` share the same discrete log `a`, optionally
+/// bound to the message `m`.
+fn dleq_prove(g: Point, a: Scalar, b: Point, aux: &[u8; 32], m: Option<&[u8; 32]>) -> [u8; 64] {
+    let big_a = a * g;
+    let c = a * b;
+
+    let aux_hash = tagged_hash("BIP0374/aux", &[aux]);
+    let t: Vec<u8> = a
+        .serialize()
+        .iter()
+        .zip(aux_hash.iter())
+        .map(|(x, y)| x ^ y)
+        .collect();
+    let rand = tagged_hash(
+        "BIP0374/nonce",
+        &[
+            &t,
+            &big_a.serialize(),
+            &c.serialize(),
+            m.map_or(&[][..], |m| &m[..]),
+        ],
+    );
+    let k = MaybeScalar::reduce_from(&rand)
+        .not_zero()
+        .expect("nonzero nonce");
+
+    let e = dleq_challenge(g, big_a, b, c, k * g, k * b, m);
+    let s = k + e * a;
+
+    let mut proof = [0u8; 64];
+    proof[..32].copy_from_slice(&e.serialize());
+    proof[32..].copy_from_slice(&s.serialize());
+    proof
+}
+
+/// Verifies a BIP-374 proof created by [`dleq_prove`].
+fn dleq_verify(
+    g: Point,
+    a: Point,
+    b: Point,
+    c: Point,
+    proof: &[u8; 64],
+    m: Option<&[u8; 32]>,
+) -> bool {
+    let (e, s) = match (
+        MaybeScalar::from_slice(&proof[..32]),
+        MaybeScalar::from_slice(&proof[32..]),
+    ) {
+        (Ok(e), Ok(s)) => (e, s),
+        _ => return false,
+    };
+
+    let r1 = s * g - e * a;
+    let r2 = s * b - e * c;
+    match (r1, r2) {
+        (MaybePoint::Valid(r1), MaybePoint::Valid(r2)) => {
+            e == dleq_challenge(g, a, b, c, r1, r2, m)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Txid;
+    use std::str::FromStr;
+
+    /// BIP-374 proof generation vectors, by index: generator, a, B, auxiliary randomness,
+    /// message and proof.
+    const GENERATE_VECTORS: &[(&str, &str, &str, &str, &str, &str)] = &[
+        // 0: Success case 1
+        (
+            "02cef38f55e78b321a1f785cb1c6e33dfcef9784c18bdc4e279801c449ccdfb88e",
+            "07ff93d43f1012a5d4a44aba55240212ed39c87b3344e46757d99f24177fc576",
+            "02dad4b35c2379ba8334c9a5dda8f6e6d5cd575a7cc9d3ca4faaac51839daaa30f",
+            "cb979b0fc8ccc7f237751e719d992fcc324b6500af33999cd54a3e5c05fb1ea4",
+            "efb07d4b382d3da1079fbf24df623ba6c2e4c764993bbfa6dd7a4fe4aaf33859",
+            concat!(
+                "7e7e934169e0bf4706e6b29e5a621c7fe199a524744a25af80071e111c0e2e94",
+                "118e730d8add118dd2ee4f7d1cc183e1b87168362d1a6f85c16d8671a3fc7a8a"
+            ),
+        ),
+        // 1: Success case 2
+        (
+            "02464e351831efedb755223cabbf664f10564b4742c725c023034bc928ed339e0e",
+            "f4e9172285393c6ada994c811b3e50fc47e96421ea7e54f4a4e459528d4cf562",
+            "03fe589b0fa23f060f6d4d1e76b9b19d5bb3db0e56d39a4303913de0e706463008",
+            "75f12482b9209dae12230ea1f8bf69723a1b447d361db8f510dd9ab33556fd4c",
+            "76184ce9eea5b339ebf5304b57452c1ada1466610f0a58574d6c496798cee04b",
+            concat!(
+                "6b4521a8363a7ebc5d95ac6ec6b64db81fcf21795187d7c4600c42b73fb4fb98",
+                "70ab8d106c0fd2d292c1710e10437b20575ddb3cb32eb77a5618d94ddba600f2"
+            ),
+        ),
+        // 5: Success case 6
+        (
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "c08ca8e0bb59769fc6a4e078456284e00ea34f65add988c246e1bba85824ccdc",
+            "034bccb1c570ac1f3bc42d61fe35de605b99626501ccb20297e1acbbf2d7152aa1",
+            "c8d7056abd4726eb5a0f198740af14d6c1f0c16e5d7a37eaec621b661e669ac4",
+            "",
+            concat!(
+                "503562d36910cd2d61a4d07c8ff680265c713e63dde0dcb88e6ea3c58597bdc0",
+                "5b86db9af95eccc475ce2177f941c118fefed20227d4ce8ce9557cb008758de6"
+            ),
+        ),
+        // 6: Success case 7
+        (
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "8e641ba6bf7f64eec76005a29585a5035376375f33e331215aedfe03b8e80e7a",
+            "0231c64e3efa506fdad6aad0f6084d5f6739de7f448d7e66f9d22f842638f41d60",
+            "02a7b2e2f5a5e9b1078dbb160502a32491fe80a091e91dd92cf77b0b7d90970f",
+            "35841ca532846e1cdd23a3d107824343584f88eff580929469865eae8355ee3c",
+            concat!(
+                "5c7b27a33210750e9de8679d9f43497cf9f12ac642cde0a1fc26443aa2fc89bf",
+                "71aabf7bac89f5d8a96cbe86daba155fa74d6f3e111136179e53b04eb6d7807f"
+            ),
+        ),
+        // 7: Success case 8
+        (
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "cfb9a7ecc49bea4f2e2ee34c38a6f48b5cd5bd06f4e4d4ffb45905b3d26db842",
+            "021cb81121a00f89769903305a367ad3cc02d5b402b12c026e06ac94bde28cd608",
+            "d38466b77484154a3fcb3151094c1c8a845c73a3c036b3a8ebffd8ef62c9047f",
+            "22616bb5fb2d7c68270f305122f2a09e833239c4b1c9a04e285119fb606ac794",
+            concat!(
+                "78a5544afa75bf152653fe55fb76926f2f65131bf090972a0b0b37d310c28a6b",
+                "de0e7bfacc10ac12d36f55316ba134b6ba0b844a65ae05cad53c0b296c6639bb"
+            ),
+        ),
+    ];
+
+    /// BIP-374 proof verification vectors, by index: generator, A, B, C, proof, message and
+    /// whether the proof is valid.
+    const VERIFY_VECTORS: &[(&str, &str, &str, &str, &str, &str, bool)] = &[
+        // 0: Success case 1
+        (
+            "02cef38f55e78b321a1f785cb1c6e33dfcef9784c18bdc4e279801c449ccdfb88e",
+            "02b540b22c2c5ef0dc886abdaad27498453d893265560bc08a187319af6f845f58",
+            "02dad4b35c2379ba8334c9a5dda8f6e6d5cd575a7cc9d3ca4faaac51839daaa30f",
+            "03fefe00951dcd0ef10b12523393c2b8113119de4fdeeab320694e96bdccd2775b",
+            concat!(
+                "7e7e934169e0bf4706e6b29e5a621c7fe199a524744a25af80071e111c0e2e94",
+                "118e730d8add118dd2ee4f7d1cc183e1b87168362d1a6f85c16d8671a3fc7a8a"
+            ),
+            "efb07d4b382d3da1079fbf24df623ba6c2e4c764993bbfa6dd7a4fe4aaf33859",
+            true,
+        ),
+        // 5: Success case 6
+        (
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "02637b2c3ea8ca80b9caecc50f4134c86ae9cf7a269133e7afc71f30e3a3cda60c",
+            "034bccb1c570ac1f3bc42d61fe35de605b99626501ccb20297e1acbbf2d7152aa1",
+            "0285b826c8dd175805901906b6c9b4140a30cbcc94c6e7dcf36476038bf90d4718",
+            concat!(
+                "503562d36910cd2d61a4d07c8ff680265c713e63dde0dcb88e6ea3c58597bdc0",
+                "5b86db9af95eccc475ce2177f941c118fefed20227d4ce8ce9557cb008758de6"
+            ),
+            "",
+            true,
+        ),
+        // 7: Success case 8
+        (
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "03611410561c35dae13135e4ad8094baac9bbcf2f4e18498181a8ff8a6d43be9d9",
+            "021cb81121a00f89769903305a367ad3cc02d5b402b12c026e06ac94bde28cd608",
+            "03d9a98624c0c74fc7eebd39ed84175f80d03c774908e75ca737a0745d1c64e20a",
+            concat!(
+                "78a5544afa75bf152653fe55fb76926f2f65131bf090972a0b0b37d310c28a6b",
+                "de0e7bfacc10ac12d36f55316ba134b6ba0b844a65ae05cad53c0b296c6639bb"
+            ),
+            "22616bb5fb2d7c68270f305122f2a09e833239c4b1c9a04e285119fb606ac794",
+            true,
+        ),
+        // 8: Swapped points case 1
+        (
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "03611410561c35dae13135e4ad8094baac9bbcf2f4e18498181a8ff8a6d43be9d9",
+            "03d9a98624c0c74fc7eebd39ed84175f80d03c774908e75ca737a0745d1c64e20a",
+            "021cb81121a00f89769903305a367ad3cc02d5b402b12c026e06ac94bde28cd608",
+            concat!(
+                "78a5544afa75bf152653fe55fb76926f2f65131bf090972a0b0b37d310c28a6b",
+                "de0e7bfacc10ac12d36f55316ba134b6ba0b844a65ae05cad53c0b296c6639bb"
+            ),
+            "22616bb5fb2d7c68270f305122f2a09e833239c4b1c9a04e285119fb606ac794",
+            false,
+        ),
+        // 9: Swapped points case 2
+        (
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "021cb81121a00f89769903305a367ad3cc02d5b402b12c026e06ac94bde28cd608",
+            "03611410561c35dae13135e4ad8094baac9bbcf2f4e18498181a8ff8a6d43be9d9",
+            "03d9a98624c0c74fc7eebd39ed84175f80d03c774908e75ca737a0745d1c64e20a",
+            concat!(
+                "78a5544afa75bf152653fe55fb76926f2f65131bf090972a0b0b37d310c28a6b",
+                "de0e7bfacc10ac12d36f55316ba134b6ba0b844a65ae05cad53c0b296c6639bb"
+            ),
+            "22616bb5fb2d7c68270f305122f2a09e833239c4b1c9a04e285119fb606ac794",
+            false,
+        ),
+        // 13: Tampered proof (random bit-flip)
+        (
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "03611410561c35dae13135e4ad8094baac9bbcf2f4e18498181a8ff8a6d43be9d9",
+            "021cb81121a00f89769903305a367ad3cc02d5b402b12c026e06ac94bde28cd608",
+            "03d9a98624c0c74fc7eebd39ed84175f80d03c774908e75ca737a0745d1c64e20a",
+            concat!(
+                "78a5544afa75bf152653fe55fb76926f2f65131ff090972a0b0b37d310c28a6b",
+                "de0e7bfacc10ac12d36f55316ba134b6ba0b844a65ae05cad53c0b296c6639bb"
+            ),
+            "22616bb5fb2d7c68270f305122f2a09e833239c4b1c9a04e285119fb606ac794",
+            false,
+        ),
+        // 14: Tampered message (random bit-flip)
+        (
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "03611410561c35dae13135e4ad8094baac9bbcf2f4e18498181a8ff8a6d43be9d9",
+            "021cb81121a00f89769903305a367ad3cc02d5b402b12c026e06ac94bde28cd608",
+            "03d9a98624c0c74fc7eebd39ed84175f80d03c774908e75ca737a0745d1c64e20a",
+            concat!(
+                "78a5544afa75bf152653fe55fb76926f2f65131bf090972a0b0b37d310c28a6b",
+                "de0e7bfacc10ac12d36f55316ba134b6ba0b844a65ae05cad53c0b296c6639bb"
+            ),
+            "22616bb5fb6d7c68270f305122f2a09e833239c4b1c9a04e285119fb606ac794",
+            false,
+        ),
+    ];
+
+    /// BIP-352 sending vectors with a single recipient: the inputs as txid, vout, private key and
+    /// whether they are taproot, the recipient address and its output key.
+    const BIP352_VECTORS: &[(&[(&str, u32, &str, bool)], &str, &str)] = &[
+        // Simple send: two inputs
+        (
+            &[
+                (
+                    "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                    0,
+                    "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                    false,
+                ),
+                (
+                    "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+                    0,
+                    "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+                    false,
+                ),
+            ],
+            concat!(
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgq",
+                "juexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv"
+            ),
+            "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1",
+        ),
+        // Outpoint ordering byte-lexicographically vs. vout-integer
+        (
+            &[
+                (
+                    "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                    1,
+                    "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                    false,
+                ),
+                (
+                    "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                    256,
+                    "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+                    false,
+                ),
+            ],
+            concat!(
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgq",
+                "juexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv"
+            ),
+            "a85ef8701394b517a4b35217c4bd37ac01ebeed4b008f8d0879f9e09ba95319c",
+        ),
+        // Single recipient: taproot only inputs with even y-values
+        (
+            &[
+                (
+                    "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                    0,
+                    "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                    true,
+                ),
+                (
+                    "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+                    0,
+                    "fc8716a97a48ba9a05a98ae47b5cd201a25a7fd5d8b73c203c5f7b6b6b3b6ad7",
+                    true,
+                ),
+            ],
+            concat!(
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgq",
+                "juexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv"
+            ),
+            "de88bea8e7ffc9ce1af30d1132f910323c505185aec8eae361670421e749a1fb",
+        ),
+        // Single recipient: taproot only with mixed even/odd y-values
+        (
+            &[
+                (
+                    "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                    0,
+                    "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                    true,
+                ),
+                (
+                    "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+                    0,
+                    "1d37787c2b7116ee983e9f9c13269df29091b391c04db94239e0d2bc2182c3bf",
+                    true,
+                ),
+            ],
+            concat!(
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgq",
+                "juexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv"
+            ),
+            "77cab7dd12b10259ee82c6ea4b509774e33e7078e7138f568092241bf26b99f1",
+        ),
+        // Single recipient: taproot input with odd y-value and non-taproot input
+        (
+            &[
+                (
+                    "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                    0,
+                    "1d37787c2b7116ee983e9f9c13269df29091b391c04db94239e0d2bc2182c3bf",
+                    true,
+                ),
+                (
+                    "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+                    0,
+                    "8d4751f6e8a3586880fb66c19ae277969bd5aa06f61c4ee2f1e2486efdf666d3",
+                    false,
+                ),
+            ],
+            concat!(
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgq",
+                "juexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv"
+            ),
+            "359358f59ee9e9eec3f00bdf4882570fd5c182e451aa2650b788544aff012a3a",
+        ),
+        // Receiving with labels: label with even parity
+        (
+            &[
+                (
+                    "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                    0,
+                    "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                    false,
+                ),
+                (
+                    "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+                    0,
+                    "0378e95685b74565fa56751b84a32dfd18545d10d691641b8372e32164fad66a",
+                    false,
+                ),
+            ],
+            concat!(
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgq",
+                "jex54dmqmmv6rw353tsuqhs99ydvadxzrsy9nuvk74epvee55drs734pqq"
+            ),
+            "d014d4860f67d607d60b1af70e0ee236b99658b61bb769832acbbe87c374439a",
+        ),
+    ];
+
+    fn message(hex: &str) -> Option<[u8; 32]> {
+        (!hex.is_empty()).then(|| hex::decode(hex).unwrap().try_into().unwrap())
+    }
+
+    #[test]
+    fn dleq_generate_vectors() {
+        for (g, a, b, aux, m, proof) in GENERATE_VECTORS {
+            let g = Point::from_hex(g).unwrap();
+            let a = Scalar::from_hex(a).unwrap();
+            let b = Point::from_hex(b).unwrap();
+            let aux: [u8; 32] = hex::decode(aux).unwrap().try_into().unwrap();
+            let m = message(m);
+
+            let got = dleq_prove(g, a, b, &aux, m.as_ref());
+            assert_eq!(hex::encode(got), *proof);
+            assert!(dleq_verify(g, a * g, b, a * b, &got, m.as_ref()));
+        }
+    }
+
+    #[test]
+    fn dleq_verify_vectors() {
+        for (g, a, b, c, proof, m, valid) in VERIFY_VECTORS {
+            let [g, a, b, c] = [g, a, b, c].map(|p| Point::from_hex(p).unwrap());
+            let proof: [u8; 64] = hex::decode(proof).unwrap().try_into().unwrap();
+            let m = message(m);
+            assert_eq!(dleq_verify(g, a, b, c, &proof, m.as_ref()), *valid);
+        }
+    }
+
+    #[test]
+    fn bip352_vectors() {
+        for (inputs, addr, expected) in BIP352_VECTORS {
+            let addr = SilentPaymentAddress::parse(addr, Network::Bitcoin).unwrap();
+
+            // Taproot inputs are summed with the secret key of their even y public key.
+            let mut a = MaybeScalar::Zero;
+            for (_, _, key, taproot) in inputs.iter() {
+                let key = Scalar::from_hex(key).unwrap();
+                a = match *taproot && key.base_point_mul().has_odd_y() {
+                    true => a - key,
+                    false => a + key,
+                };
+            }
+            let a = a.not_zero().unwrap();
+
+            let outpoint = inputs
+                .iter()
+                .map(|(txid, vout, _, _)| OutPoint {
+                    txid: Txid::from_str(txid).unwrap(),
+                    vout: *vout,
+                })
+                .min_by_key(consensus::encode::serialize)
+                .unwrap();
+            let ecdh = MaybePoint::from(a * addr.scan_key);
+            let output = derive_output(&addr, outpoint, a.base_point_mul(), ecdh).unwrap();
+            assert_eq!(output.to_string(), *expected);
+        }
+    }
+
+    /// The ECDH aggregated from the signers' shares must be that of the secret key of the
+    /// tweaked 2-of-2 MuSig output, as a single signer holding it would compute.
+    #[test]
+    fn aggregated_ecdh_is_of_the_output_key() {
+        let seckeys: Vec<Scalar> = [[1u8; 32], [2; 32]]
+            .iter()
+            .map(|k| Scalar::from_slice(k).unwrap())
+            .collect();
+        let pubkeys: Vec<Point> = seckeys.iter().map(|k| k.base_point_mul()).collect();
+        let scan_key = Scalar::from_slice(&[3; 32]).unwrap().base_point_mul();
+        let shares: Vec<EcdhShare> = seckeys
+            .iter()
+            .map(|k| EcdhShare::new(*k, scan_key, &[0; 32]))
+            .collect();
+
+        // Different merkle roots give output keys of either parity.
+        let (mut even, mut odd) = (false, false);
+        for root in 0..16u8 {
+            let key_agg_ctx = KeyAggContext::new(pubkeys.clone())
+                .unwrap()
+                .with_taproot_tweak(&[root; 32])
+                .unwrap();
+            let output_key: Point = key_agg_ctx.aggregated_pubkey();
+            let a: Scalar = key_agg_ctx.aggregated_seckey(seckeys.clone()).unwrap();
+            let a = match output_key.has_odd_y() {
+                true => -a,
+                false => a,
+            };
+
+            let ecdh = aggregate_ecdh(&key_agg_ctx, &shares, scan_key).unwrap();
+            assert_eq!(ecdh, MaybePoint::from(a * scan_key));
+
+            match output_key.has_odd_y() {
+                true => odd = true,
+                false => even = true,
+            }
+        }
+        assert!(even && odd);
+    }
+}
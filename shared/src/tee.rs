@@ -0,0 +1,167 @@
+use bitcoin::base64::Engine;
+use bitcoin::base64::engine::general_purpose::STANDARD as BASE64;
+use ciborium::Value;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::silent_payment::tagged_hash;
+
+const DELETION_TAG: &str = "ephemeral-sign/deletion";
+
+/// Offset of the report body in an SGX ECDSA quote, after its header.
+const SGX_REPORT_BODY: usize = 48;
+const SGX_MRENCLAVE: usize = SGX_REPORT_BODY + 64;
+const SGX_REPORT_DATA: usize = SGX_REPORT_BODY + 320;
+
+/// Trusted execution environment a signer runs in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TeePlatform {
+    /// Intel SGX, with a DCAP quote.
+    Sgx,
+    /// AWS Nitro Enclaves, with an NSM attestation document.
+    Nitro,
+}
+
+impl std::fmt::Display for TeePlatform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TeePlatform::Sgx => write!(f, "sgx"),
+            TeePlatform::Nitro => write!(f, "nitro"),
+        }
+    }
+}
+
+impl std::str::FromStr for TeePlatform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sgx" => Ok(TeePlatform::Sgx),
+            "nitro" => Ok(TeePlatform::Nitro),
+            _ => Err(format!("unknown TEE platform {}, expected sgx or nitro", s)),
+        }
+    }
+}
+
+/// Remote attestation by a signer's enclave that it deleted the ephemeral key of a session after
+/// signing with it. The enclave's measurement identifies the code that generated, held and then
+/// erased the key.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct EnclaveAttestation {
+    pub platform: TeePlatform,
+    pub session_id: String,
+    /// Hex encoded public key of the signer's session.
+    pub pubkey: String,
+    /// Base64 encoded SGX quote or Nitro attestation document, with the session's
+    /// `deletion_report_data` as report data.
+    pub document: String,
+}
+
+/// What an attestation document says about the enclave, before its signature is checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evidence {
+    /// Hex encoded MRENCLAVE of an SGX enclave, or PCR0 of a Nitro enclave.
+    pub measurement: String,
+    pub report_data: Vec<u8>,
+}
+
+/// The report data an enclave attests with that it deleted the key `pubkey` of session
+/// `session_id`.
+pub fn deletion_report_data(session_id: &str, pubkey: &str) -> [u8; 32] {
+    tagged_hash(DELETION_TAG, &[session_id.as_bytes(), pubkey.as_bytes()])
+}
+
+impl EnclaveAttestation {
+    pub fn new(platform: TeePlatform, session_id: &str, pubkey: &str, document: &[u8]) -> Self {
+        EnclaveAttestation {
+            platform,
+            session_id: session_id.to_string(),
+            pubkey: pubkey.to_string(),
+            document: BASE64.encode(document),
+        }
+    }
+
+    pub fn document_bytes(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(BASE64.decode(&self.document)?)
+    }
+
+    /// Extracts the measurement and report data from the document.
+    pub fn evidence(&self) -> Result<Evidence, Box<dyn std::error::Error>> {
+        let document = self.document_bytes()?;
+        match self.platform {
+            TeePlatform::Sgx => sgx_evidence(&document),
+            TeePlatform::Nitro => nitro_evidence(&document),
+        }
+    }
+
+    /// Checks the document attests the deletion of the session's key, returning the enclave's
+    /// measurement. The document's signature by the platform is left to the caller.
+    pub fn verify_binding(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let evidence = self.evidence()?;
+        let expected = deletion_report_data(&self.session_id, &self.pubkey);
+        // SGX pads the report data to 64 bytes.
+        if evidence.report_data.get(..32) != Some(&expected[..])
+            || evidence.report_data[32..].iter().any(|b| *b != 0)
+        {
+            return Err(format!(
+                "attestation of session {} does not attest its key deletion",
+                self.session_id
+            )
+            .into());
+        }
+        Ok(evidence.measurement)
+    }
+}
+
+fn sgx_evidence(quote: &[u8]) -> Result<Evidence, Box<dyn std::error::Error>> {
+    if quote.len() < SGX_REPORT_DATA + 64 {
+        return Err("SGX quote too short".into());
+    }
+    Ok(Evidence {
+        measurement: hex::encode(&quote[SGX_MRENCLAVE..SGX_MRENCLAVE + 32]),
+        report_data: quote[SGX_REPORT_DATA..SGX_REPORT_DATA + 64].to_vec(),
+    })
+}
+
+/// Reads the payload of the COSE_Sign1 structure of a Nitro attestation document.
+fn nitro_evidence(document: &[u8]) -> Result<Evidence, Box<dyn std::error::Error>> {
+    let cose: Value = ciborium::from_reader(document)?;
+    // The document may carry the COSE_Sign1 tag.
+    let cose = match cose {
+        Value::Tag(_, inner) => *inner,
+        v => v,
+    };
+    let payload = match cose {
+        Value::Array(mut items) if items.len() == 4 => match items.swap_remove(2) {
+            Value::Bytes(b) => b,
+            _ => return Err("Nitro attestation payload is not bytes".into()),
+        },
+        _ => return Err("Nitro attestation is not a COSE_Sign1 structure".into()),
+    };
+    let payload: Value = ciborium::from_reader(&payload[..])?;
+    let map = payload
+        .as_map()
+        .ok_or("Nitro attestation payload is not a map")?;
+    let field = |name: &str| {
+        map.iter()
+            .find(|(k, _)| k.as_text() == Some(name))
+            .map(|(_, v)| v)
+    };
+
+    let pcr0 = field("pcrs")
+        .and_then(Value::as_map)
+        .and_then(|pcrs| {
+            pcrs.iter()
+                .find(|(k, _)| k.as_integer() == Some(0.into()))
+                .and_then(|(_, v)| v.as_bytes())
+        })
+        .ok_or("Nitro attestation has no PCR0")?;
+    let user_data = field("user_data")
+        .and_then(Value::as_bytes)
+        .ok_or("Nitro attestation has no user data")?;
+    Ok(Evidence {
+        measurement: hex::encode(pcr0),
+        report_data: user_data.clone(),
+    })
+}
@@ -1,30 +1,49 @@
+use actix_web::dev::ServerHandle;
 use actix_web::error::UrlGenerationError::ResourceNotFound;
-use actix_web::error::{ErrorInternalServerError, JsonPayloadError, PayloadError, UrlencodedError};
-use actix_web::{App, HttpServer, Responder, Result, get, post, web};
+use actix_web::error::{
+    ErrorBadRequest, ErrorInternalServerError, JsonPayloadError, PayloadError, UrlencodedError,
+};
+use actix_web::error::{ErrorConflict, ErrorNotFound, ErrorServiceUnavailable, InternalError};
+use actix_web::rt::signal::unix::{SignalKind, signal};
+use actix_web::{App, HttpResponse, HttpServer, Responder, Result, delete, get, post, web};
 use clap::Parser;
 use hex::ToHex;
 use musig2::SecNonce;
-use musig2::secp::MaybeScalar;
+use musig2::secp::{MaybeScalar, Point};
 use secp256k1::{Secp256k1, SecretKey, rand};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sha2::Digest;
-use shared::{InitResp, SignReq, SignResp};
-use std::collections::HashMap;
+use shared::tee::TeePlatform;
+use shared::{InitResp, SessionExpired, SignChallenge, SignReq, SignResp};
 use std::fmt::Debug;
 use std::net::SocketAddr;
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use replica::{ReplicaReq, Replication};
+use session::{EphemeralKey, Session, SessionError, SessionTable};
+use stateless::{SessionState, StateError, StateKey};
+use tee::Enclave;
+
+mod replica;
+mod secmem;
+mod session;
+mod stateless;
+mod tee;
 
 // This struct represents state
 struct AppState {
-    sessions: Mutex<HashMap<String, SessionData>>,
-}
-
-#[derive(Clone, Debug)]
-struct SessionData {
-    session_id: String,
-    init_resp: InitResp,
-    secret_key: SecretKey,
-    secret_nonce: SecNonce,
+    sessions: SessionTable,
+    /// Set once shutting down, after which no new sessions are started.
+    draining: AtomicBool,
+    /// Set to keep no sessions, sealing their state for the client instead.
+    state_key: Option<StateKey>,
+    /// Set to replicate the sessions to peers, and take over theirs.
+    replication: Option<Replication>,
+    /// Set when running in an enclave, to attest the deletion of each session's key.
+    enclave: Option<Enclave>,
 }
 
 #[derive(Debug, Parser)]
@@ -32,33 +51,187 @@ struct SessionData {
 struct Args {
     #[arg(long)]
     listen: SocketAddr,
+
+    /// Seconds to wait on SIGTERM for the open sessions to be signed, before erasing their keys
+    /// and exiting anyway.
+    #[arg(long, default_value_t = 60)]
+    shutdown_timeout: u64,
+
+    /// Seconds a session's key lives. A session not signed within it is wiped, and sign requests
+    /// for it fail as expired.
+    #[arg(long, default_value_t = 300)]
+    session_ttl: u64,
+
+    /// Keep no sessions, but hand their state to the client sealed with the hex encoded 32 byte
+    /// key in this file. The ephemeral key then outlives the session in sealed form, so its
+    /// deletion rests on this key staying secret.
+    #[arg(long)]
+    state_key_file: Option<PathBuf>,
+
+    /// Directory the signed sessions are recorded in when keeping no sessions or replicating
+    /// them, to never sign one twice.
+    #[arg(long, default_value = "spent-sessions")]
+    spent_sessions_dir: PathBuf,
+
+    /// Replicate the sessions sealed with the hex encoded 32 byte key in this file, and sign the
+    /// sessions peers replicated sealed with it. The peers must share both the key and the
+    /// spent sessions directory, and only they should be able to reach `/replica`.
+    #[arg(long, conflicts_with = "state_key_file")]
+    replication_key_file: Option<PathBuf>,
+
+    /// Address of a peer signer to replicate the open sessions to, so it can sign them should
+    /// this signer go away. Can be given multiple times.
+    #[arg(long, requires = "replication_key_file")]
+    replicate_to: Vec<String>,
+
+    /// Trusted execution environment the signer runs in, sgx (under Gramine) or nitro. The
+    /// signer then returns a remote attestation that it deleted the key with the signatures of
+    /// each session. Sealed sessions outlive the enclave, so they cannot be attested.
+    #[arg(long, conflicts_with_all = ["state_key_file", "replication_key_file"])]
+    tee: Option<TeePlatform>,
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let args = Args::parse();
+    // Keys must never end up on disk, the ephemeral ones are kept in locked memory for this too.
+    secmem::disable_core_dumps().expect("able to disable core dumps");
 
     let bind = args.listen;
     println!("listening on {}", bind);
 
     let app_state = web::Data::new(AppState {
-        sessions: Mutex::new(HashMap::new()),
+        sessions: SessionTable::new(Duration::from_secs(args.session_ttl)),
+        draining: AtomicBool::new(false),
+        state_key: args.state_key_file.as_ref().map(|key_file| {
+            StateKey::load(key_file, &args.spent_sessions_dir).expect("valid state key")
+        }),
+        replication: args.replication_key_file.as_ref().map(|key_file| {
+            let key =
+                StateKey::load(key_file, &args.spent_sessions_dir).expect("valid replication key");
+            Replication::new(key, args.replicate_to.clone())
+        }),
+        enclave: args.tee.map(Enclave::new),
     });
-    HttpServer::new(move || {
+    let data = app_state.clone();
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(app_state.clone())
             .service(session_init)
             .service(session_sign)
+            .service(replica_store)
+            .service(replica_drop)
     })
+    // Signals are handled below, so open sessions can still be signed while shutting down.
+    .disable_signals()
     .bind(bind)?
-    .run()
-    .await
+    .run();
+
+    actix_web::rt::spawn(expire_sessions(data.clone()));
+    actix_web::rt::spawn(drain_on_signal(
+        data.clone(),
+        server.handle(),
+        Duration::from_secs(args.shutdown_timeout),
+    ));
+    let res = server.await;
+
+    println!("erased {} unsigned sessions", data.sessions.clear());
+    res
+}
+
+/// Wipes the sessions and replicas that outlived the TTL, once a second, and forgets the signed
+/// sessions that expired once a minute.
+async fn expire_sessions(data: web::Data<AppState>) {
+    for i in 1u64.. {
+        actix_web::rt::time::sleep(Duration::from_secs(1)).await;
+        data.sessions.expire();
+        if let Some(replication) = &data.replication {
+            replication.expire(data.sessions.ttl());
+        }
+        if i % 60 == 0 {
+            let keys = [
+                data.state_key.as_ref(),
+                data.replication.as_ref().map(|r| &r.key),
+            ];
+            keys.into_iter().flatten().for_each(StateKey::prune);
+        }
+    }
+}
+
+/// Error response for a request for session `session_id` that failed with `e`.
+fn session_error(data: &AppState, session_id: String, e: SessionError) -> actix_web::Error {
+    match e {
+        SessionError::NotFound => ResourceNotFound.into(),
+        SessionError::Expired => {
+            let body = SessionExpired {
+                session_id,
+                ttl_secs: data.sessions.ttl().as_secs(),
+            };
+            InternalError::from_response(body.to_string(), HttpResponse::Gone().json(&body)).into()
+        }
+        SessionError::AlreadyExists => {
+            ErrorConflict(format!("session {} already exists", session_id))
+        }
+        SessionError::WrongState(state) => {
+            ErrorConflict(format!("session {} is {:?}", session_id, state))
+        }
+    }
 }
 
+/// Waits for SIGTERM or SIGINT, then stops starting sessions and waits up to `timeout` for the
+/// open ones to be signed before stopping `server`.
+async fn drain_on_signal(data: web::Data<AppState>, server: ServerHandle, timeout: Duration) {
+    let mut term = signal(SignalKind::terminate()).expect("able to listen for SIGTERM");
+    let mut int = signal(SignalKind::interrupt()).expect("able to listen for SIGINT");
+    std::future::poll_fn(|cx| match (term.poll_recv(cx), int.poll_recv(cx)) {
+        (Poll::Pending, Poll::Pending) => Poll::Pending,
+        _ => Poll::Ready(()),
+    })
+    .await;
+
+    println!("shutting down, no longer starting sessions");
+    data.draining.store(true, Ordering::SeqCst);
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let open = data.sessions.len();
+        if open == 0 {
+            break;
+        }
+        if Instant::now() >= deadline {
+            println!("{} sessions still open, stopping anyway", open);
+            break;
+        }
+        println!("waiting for {} open sessions", open);
+        actix_web::rt::time::sleep(Duration::from_millis(500)).await;
+    }
+    server.stop(true).await;
+}
+
+#[derive(Debug, Deserialize)]
+struct InitQuery {
+    /// Hex encoded scan key of a silent payment address to compute an ECDH share with.
+    scan_key: Option<String>,
+}
+
+/// Starts a session with a fresh key and a single nonce, so it signs a single message. Signing
+/// several blinded challenges with one key opens it to the ROS attacks, where the requester picks
+/// the challenges so the signatures combine into one on a message the signer never saw. These
+/// need many open challenges under the same key. With a single one, the requester gets a single
+/// signature from the key and nothing to combine it with, and the key is deleted before it could
+/// sign another.
 #[get("/init/{id}")]
-async fn session_init(data: web::Data<AppState>, id: web::Path<String>) -> Result<impl Responder> {
+async fn session_init(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<InitQuery>,
+) -> Result<impl Responder> {
     let session_id = id.to_string();
 
+    if data.draining.load(Ordering::SeqCst) {
+        return Err(ErrorServiceUnavailable("signer is shutting down"));
+    }
+
     // Make sure session id is valid hex encoding of 32 bytes.
     match hex::decode(session_id.clone()) {
         Ok(h) => {
@@ -72,31 +245,56 @@ async fn session_init(data: web::Data<AppState>, id: web::Path<String>) -> Resul
     println!("session_id: {}", session_id);
 
     let secp = Secp256k1::new();
-    let secret_key = SecretKey::new(&mut rand::thread_rng());
+    let mut secret_key = SecretKey::new(&mut rand::thread_rng());
     let pubkey = secret_key.public_key(&secp);
     let secnonce = musig2::SecNonceBuilder::new(&mut rand::rngs::OsRng)
         .with_message(&session_id)
         .build();
 
     let pubnonce = secnonce.public_nonce();
+    let key = EphemeralKey::new(&mut secret_key, secnonce).map_err(locked_memory_error)?;
+
+    let ecdh_share = match &query.scan_key {
+        None => None,
+        Some(scan_key) => {
+            let scan_key = Point::from_hex(scan_key).map_err(ErrorBadRequest)?;
+            let aux: [u8; 32] = rand::random();
+            Some(key.ecdh_share(scan_key, &aux))
+        }
+    };
 
-    let resp = InitResp {
+    let mut resp = InitResp {
         session_id: session_id.clone(),
         pubkey: hex::encode(pubkey.serialize()),
         pubnonce: hex::encode(pubnonce.serialize()),
+        ecdh_share,
+        state: None,
     };
 
-    let session_data = SessionData {
-        session_id: session_id.clone(),
-        init_resp: resp.clone(),
-        secret_key: secret_key.clone(),
-        secret_nonce: secnonce.clone(),
+    let state = SessionState {
+        key,
+        created_at: now(),
     };
+    if let Some(state_key) = &data.state_key {
+        resp.state = Some(state_key.seal(&session_id, &state));
+        return Ok(web::Json(resp));
+    }
+
+    let replica = data
+        .replication
+        .as_ref()
+        .map(|replication| replication.key.seal(&session_id, &state));
+    let session = Session::new(session_id.clone(), resp.clone(), state.key);
 
     data.sessions
-        .lock()
-        .unwrap()
-        .insert(session_id, session_data);
+        .insert(session)
+        .map_err(|e| session_error(&data, session_id.clone(), e))?;
+
+    // Replicate before handing out the nonce, so the session can be signed even if this signer
+    // goes away right after.
+    if let (Some(replication), Some(replica)) = (&data.replication, replica) {
+        replication.replicate(&session_id, replica).await;
+    }
     Ok(web::Json(resp))
 }
 
@@ -109,16 +307,185 @@ async fn session_sign(
     println!("req: {:?}", req);
     let session_id = id.to_string();
 
-    // Delete all data about this session, ensuring we will never sign twice with same key.
-    let session = match data.sessions.lock().unwrap().remove(&session_id) {
-        None => return Err(ResourceNotFound.into()),
-        Some(s) => s,
+    // The key signs a single challenge, see session_init.
+    let challenge = &req.challenge;
+
+    if let Some(blob) = &req.state {
+        let state_key = data.state_key.as_ref().ok_or(ErrorBadRequest(
+            "signer keeps its sessions, no state expected",
+        ))?;
+        let sig = sign_sealed(&data, state_key, &session_id, blob, challenge)?;
+        return Ok(web::Json(SignResp {
+            session_id,
+            sig,
+            attestation: None,
+        }));
+    }
+
+    let session = match data.sessions.get(&session_id) {
+        Ok(session) => session,
+        Err(SessionError::NotFound) => {
+            // The session may have been started with a peer that went away, and replicated here.
+            let replica = data.replication.as_ref().and_then(|replication| {
+                let blob = replication.get(&session_id)?;
+                Some((replication, blob))
+            });
+            let Some((replication, blob)) = replica else {
+                return Err(session_error(&data, session_id, SessionError::NotFound));
+            };
+            println!("signing session {} replicated by a peer", session_id);
+            let sig = sign_sealed(&data, &replication.key, &session_id, &blob, challenge)?;
+            replication.remove(&session_id);
+            return Ok(web::Json(SignResp {
+                session_id,
+                sig,
+                attestation: None,
+            }));
+        }
+        Err(e) => return Err(session_error(&data, session_id, e)),
+    };
+    let mut session = session.lock().unwrap();
+
+    // Once signing starts the session can never be signed again, even if this request fails,
+    // ensuring we will never sign twice with the same key.
+    let key = session
+        .start_signing()
+        .map_err(|e| session_error(&data, session_id.clone(), e))?;
+
+    // A replicated session is recorded as signed first, so a peer can never sign it as well.
+    let spent = match &data.replication {
+        Some(replication) => replication
+            .key
+            .spend(&session_id, now() + data.sessions.ttl().as_secs())
+            .map_err(|e| state_error(&data, &session_id, e)),
+        None => Ok(()),
     };
+    let sig = spent.and_then(|_| sign_with_key(key, challenge));
+    session.finish();
+    let pubkey = session.init_resp.pubkey.clone();
+    drop(session);
+    data.sessions.remove(&session_id);
 
-    let seckey = session.secret_key;
-    let secnonce = session.secret_nonce;
-    
+    // The key is erased, so the enclave can attest to it. The signature is handed out
+    // regardless, as the key is gone and the session cannot be signed again.
+    let attestation = match &data.enclave {
+        Some(enclave) if sig.is_ok() => match enclave.attest_deletion(&session_id, &pubkey) {
+            Ok(attestation) => Some(attestation),
+            Err(e) => {
+                println!(
+                    "unable to attest key deletion of session {}: {}",
+                    session_id, e
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+
+    if data.replication.is_some() {
+        let data = data.clone();
+        let session_id = session_id.clone();
+        actix_web::rt::spawn(async move {
+            if let Some(replication) = &data.replication {
+                replication.forget(&session_id).await;
+            }
+        });
+    }
 
+    let resp = SignResp {
+        session_id,
+        sig: sig?,
+        attestation,
+    };
+    Ok(web::Json(resp))
+}
+
+/// Error response for a session whose key could not be put in locked memory, such as when the
+/// signer's limit of locked memory is used up.
+fn locked_memory_error(e: std::io::Error) -> actix_web::Error {
+    println!("unable to lock memory for a session key: {}", e);
+    ErrorServiceUnavailable("no locked memory for the session key")
+}
+
+/// Signs `challenge` with the sealed state `blob` of session `session_id`, recording the
+/// session as signed.
+fn sign_sealed(
+    data: &AppState,
+    key: &StateKey,
+    session_id: &str,
+    blob: &str,
+    challenge: &SignChallenge,
+) -> Result<String> {
+    let state = key
+        .open(session_id, blob, data.sessions.ttl())
+        .map_err(|e| state_error(data, session_id, e))?;
+
+    sign_with_key(state.key, challenge)
+}
+
+/// Error response for a request to sign sealed session `session_id` that failed with `e`.
+fn state_error(data: &AppState, session_id: &str, e: StateError) -> actix_web::Error {
+    match e {
+        StateError::Invalid => ErrorBadRequest("invalid session state"),
+        StateError::Expired => session_error(data, session_id.to_string(), SessionError::Expired),
+        StateError::Spent => ErrorConflict(format!("session {} was signed", session_id)),
+        StateError::Io => ErrorInternalServerError("unable to record signed session"),
+        StateError::LockedMemory => ErrorServiceUnavailable("no locked memory for the session key"),
+    }
+}
+
+/// Keeps a session a peer replicated, to sign it should the peer go away.
+#[post("/replica/{id}")]
+async fn replica_store(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    req: web::Json<ReplicaReq>,
+) -> Result<impl Responder> {
+    let replication = data
+        .replication
+        .as_ref()
+        .ok_or(ErrorNotFound("signer does not replicate sessions"))?;
+    let session_id = id.into_inner();
+    if !replication.key.verify(&session_id, &req.state) {
+        return Err(ErrorBadRequest("invalid session state"));
+    }
+    replication.insert(session_id, req.into_inner().state);
+    Ok(HttpResponse::Ok())
+}
+
+/// Drops the replica of a session the peer signed.
+#[delete("/replica/{id}")]
+async fn replica_drop(data: web::Data<AppState>, id: web::Path<String>) -> Result<impl Responder> {
+    let replication = data
+        .replication
+        .as_ref()
+        .ok_or(ErrorNotFound("signer does not replicate sessions"))?;
+    replication.remove(&id);
+    Ok(HttpResponse::Ok())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Signs `challenge` with `key`, which is erased as soon as the signature is made, before it is
+/// handed out.
+fn sign_with_key(key: EphemeralKey, challenge: &SignChallenge) -> Result<String> {
+    let mut seckey = key.secret_key();
+    let sig = sign_challenge(seckey, key.secret_nonce(), challenge);
+    seckey.non_secure_erase();
+    drop(key);
+    Ok(sig?.encode_hex())
+}
+
+fn sign_challenge(
+    seckey: SecretKey,
+    secnonce: SecNonce,
+    req: &SignChallenge,
+) -> Result<MaybeScalar> {
     let key_coeff = match MaybeScalar::from_hex(&req.key_coeff) {
         Ok(k) => k,
         Err(e) => return Err(JsonPayloadError::Payload(PayloadError::EncodingCorrupted).into()),
@@ -133,7 +500,7 @@ async fn session_sign(
         Err(e) => return Err(JsonPayloadError::Payload(PayloadError::EncodingCorrupted).into()),
     };
 
-    let sig: MaybeScalar = match musig2::sign_partial_challenge(
+    match musig2::sign_partial_challenge(
         b,
         key_coeff,
         req.challenge_parity.into(),
@@ -142,16 +509,10 @@ async fn session_sign(
         req.nonce_parity.into(),
         ep,
     ) {
-        Ok(s) => s,
+        Ok(s) => Ok(s),
         Err(e) => {
             println!("sign partial challenge error: {:?}", e);
-            return Err(ErrorInternalServerError(e))
-        },
-    };
-
-    let resp = SignResp {
-        session_id,
-        sig: sig.encode_hex(),
-    };
-    Ok(web::Json(resp))
+            Err(ErrorInternalServerError(e))
+        }
+    }
 }
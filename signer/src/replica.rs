@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stateless::StateKey;
+
+/// Body of `POST /replica/{id}`, a session a peer replicates to this signer.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReplicaReq {
+    /// The session's state, sealed with the replication key.
+    pub state: String,
+}
+
+/// Replication of the open sessions between redundant signers, so a session started with one of
+/// them can still be signed by another if it crashes.
+///
+/// Sessions are replicated sealed with a key the signers share, and the signers share the
+/// directory the key records signed sessions in, so a session is never signed by two of them.
+pub struct Replication {
+    pub key: StateKey,
+    /// Addresses of the peers this signer replicates its sessions to.
+    peers: Vec<String>,
+    /// Sealed sessions replicated to this signer, with the time they arrived.
+    replicas: Mutex<HashMap<String, (String, Instant)>>,
+    client: reqwest::Client,
+}
+
+impl Replication {
+    pub fn new(key: StateKey, peers: Vec<String>) -> Self {
+        Replication {
+            key,
+            peers,
+            replicas: Mutex::new(HashMap::new()),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap(),
+        }
+    }
+
+    /// Sends sealed session `session_id` to the peers. A peer that cannot be reached does not
+    /// fail the session, it just cannot take it over.
+    pub async fn replicate(&self, session_id: &str, state: String) {
+        let body = ReplicaReq { state };
+        for peer in &self.peers {
+            let url = format!("http://{peer}/replica/{session_id}");
+            let res = self.client.post(url).json(&body).send().await;
+            if let Err(e) = res.and_then(|r| r.error_for_status()) {
+                println!(
+                    "unable to replicate session {} to {}: {}",
+                    session_id, peer, e
+                );
+            }
+        }
+    }
+
+    /// Tells the peers session `session_id` is done, so they drop their replicas of it.
+    pub async fn forget(&self, session_id: &str) {
+        for peer in &self.peers {
+            let url = format!("http://{peer}/replica/{session_id}");
+            if let Err(e) = self.client.delete(url).send().await {
+                println!(
+                    "unable to drop replica of {} at {}: {}",
+                    session_id, peer, e
+                );
+            }
+        }
+    }
+
+    /// Keeps sealed session `session_id` replicated by a peer.
+    pub fn insert(&self, session_id: String, state: String) {
+        self.replicas
+            .lock()
+            .unwrap()
+            .insert(session_id, (state, Instant::now()));
+    }
+
+    /// The replica of session `session_id`, if a peer replicated it.
+    pub fn get(&self, session_id: &str) -> Option<String> {
+        self.replicas
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .map(|(state, _)| state.clone())
+    }
+
+    pub fn remove(&self, session_id: &str) {
+        self.replicas.lock().unwrap().remove(session_id);
+    }
+
+    /// Drops the replicas older than `ttl`, whose sessions can no longer be signed.
+    pub fn expire(&self, ttl: Duration) {
+        self.replicas
+            .lock()
+            .unwrap()
+            .retain(|_, (_, at)| at.elapsed() < ttl);
+    }
+}
@@ -0,0 +1,90 @@
+use std::io;
+use std::ptr;
+
+use zeroize::Zeroize;
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// A page of memory outside the heap for holding a secret. It is locked into RAM so it is never
+/// swapped out, left out of core dumps, and fenced by inaccessible guard pages so an overrun of
+/// the memory next to it faults rather than reads it. It is wiped and unmapped when dropped.
+pub struct LockedPage {
+    /// Start of the mapping, the guard page before the data.
+    base: *mut libc::c_void,
+    page: usize,
+}
+
+// The page is only reachable through the handle, so it moves between threads with it.
+unsafe impl Send for LockedPage {}
+unsafe impl Sync for LockedPage {}
+
+impl LockedPage {
+    pub fn new() -> io::Result<Self> {
+        let page = page_size();
+        unsafe {
+            let base = libc::mmap(
+                ptr::null_mut(),
+                3 * page,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            let data = base.add(page);
+            let locked = libc::mprotect(data, page, libc::PROT_READ | libc::PROT_WRITE) == 0
+                && libc::mlock(data, page) == 0;
+            #[cfg(target_os = "linux")]
+            let locked = locked && libc::madvise(data, page, libc::MADV_DONTDUMP) == 0;
+            if !locked {
+                let e = io::Error::last_os_error();
+                libc::munmap(base, 3 * page);
+                return Err(e);
+            }
+            Ok(LockedPage { base, page })
+        }
+    }
+
+    fn data_ptr(&self) -> *mut libc::c_void {
+        unsafe { self.base.add(self.page) }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data_ptr() as *const u8, self.page) }
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.data_ptr() as *mut u8, self.page) }
+    }
+}
+
+impl Drop for LockedPage {
+    fn drop(&mut self) {
+        self.bytes_mut().zeroize();
+        unsafe {
+            libc::munlock(self.data_ptr(), self.page);
+            libc::munmap(self.base, 3 * self.page);
+        }
+    }
+}
+
+/// Keeps the process from dumping core, and on Linux from being attached to by debuggers of the
+/// same user, so the keys in its memory never end up on disk or in another process.
+pub fn disable_core_dumps() -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[cfg(target_os = "linux")]
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
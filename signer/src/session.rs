@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use musig2::SecNonce;
+use musig2::secp::{Point, Scalar};
+use secp256k1::SecretKey;
+use shared::InitResp;
+use shared::silent_payment::EcdhShare;
+
+use crate::secmem::LockedPage;
+
+/// Where the key and the secret nonce are kept in the locked page.
+const KEY: Range<usize> = 0..32;
+const NONCE: Range<usize> = 32..96;
+
+/// Handle to the ephemeral key of a session and the single nonce it signs with, kept in a locked
+/// page of their own. Both are erased when the handle is dropped.
+pub struct EphemeralKey(LockedPage);
+
+impl EphemeralKey {
+    /// Moves `secret_key` and `secret_nonce` into locked memory, erasing the given key. musig2
+    /// gives no way to wipe the given nonce, so it must not be kept.
+    pub fn new(secret_key: &mut SecretKey, secret_nonce: SecNonce) -> io::Result<Self> {
+        let mut page = LockedPage::new()?;
+        page.bytes_mut()[KEY].copy_from_slice(&secret_key.secret_bytes());
+        page.bytes_mut()[NONCE].copy_from_slice(&secret_nonce.to_bytes());
+        secret_key.non_secure_erase();
+        Ok(EphemeralKey(page))
+    }
+
+    /// Moves the key and secret nonce in `bytes`, as given by `to_bytes`, into locked memory.
+    /// Fails with `InvalidData` if they are not valid.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let valid = bytes.len() == NONCE.end
+            && SecretKey::from_slice(&bytes[KEY])
+                .map(|mut key| key.non_secure_erase())
+                .is_ok()
+            && SecNonce::from_bytes(&bytes[NONCE]).is_ok();
+        if !valid {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let mut page = LockedPage::new()?;
+        page.bytes_mut()[..NONCE.end].copy_from_slice(bytes);
+        Ok(EphemeralKey(page))
+    }
+
+    /// The key followed by the secret nonce, for sealing them without a copy outside locked
+    /// memory.
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.0.bytes()[..NONCE.end]
+    }
+
+    /// A copy of the key for signing with, to be erased right after.
+    pub fn secret_key(&self) -> SecretKey {
+        SecretKey::from_slice(&self.0.bytes()[KEY]).expect("valid key")
+    }
+
+    /// A copy of the nonce for signing with, consumed by signing.
+    pub fn secret_nonce(&self) -> SecNonce {
+        SecNonce::from_bytes(&self.0.bytes()[NONCE]).expect("valid nonce")
+    }
+
+    /// The key's ECDH share with `scan_key`, computed from the key in locked memory.
+    pub fn ecdh_share(&self, scan_key: Point, aux: &[u8; 32]) -> EcdhShare {
+        let seckey = Scalar::from_slice(&self.0.bytes()[KEY]).expect("valid key");
+        EcdhShare::new(seckey, scan_key, aux)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    /// The nonce was handed out, and the session waits for the challenge to sign.
+    Initialized,
+    /// Signing the challenge.
+    Signing,
+    /// Signed, the key is erased.
+    Signed,
+}
+
+pub struct Session {
+    pub id: String,
+    pub init_resp: InitResp,
+    pub state: State,
+    /// The key and the single nonce it signs with, None once signing started.
+    key: Option<EphemeralKey>,
+    created: Instant,
+}
+
+impl Session {
+    pub fn new(id: String, init_resp: InitResp, key: EphemeralKey) -> Self {
+        Session {
+            id,
+            init_resp,
+            state: State::Initialized,
+            key: Some(key),
+            created: Instant::now(),
+        }
+    }
+
+    /// Moves the session to signing, handing out its key and nonce. Only an initialized session
+    /// can be signed, so the key never signs twice, and the session keeps no key of its own from
+    /// here on.
+    pub fn start_signing(&mut self) -> Result<EphemeralKey, SessionError> {
+        if self.state != State::Initialized {
+            return Err(SessionError::WrongState(self.state));
+        }
+        self.state = State::Signing;
+        Ok(self.key.take().unwrap())
+    }
+
+    /// Marks the session signed.
+    pub fn finish(&mut self) {
+        self.state = State::Signed;
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    NotFound,
+    /// The session outlived the TTL and was wiped.
+    Expired,
+    AlreadyExists,
+    WrongState(State),
+}
+
+/// The open sessions, each behind its own lock so requests for different sessions never wait on
+/// each other.
+pub struct SessionTable {
+    sessions: Mutex<HashMap<String, Arc<Mutex<Session>>>>,
+    /// Sessions wiped for outliving the TTL, with the time they expired. Kept for another TTL so
+    /// late requests for them get a distinct error.
+    expired: Mutex<HashMap<String, Instant>>,
+    ttl: Duration,
+}
+
+impl SessionTable {
+    pub fn new(ttl: Duration) -> Self {
+        SessionTable {
+            sessions: Mutex::new(HashMap::new()),
+            expired: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn insert(&self, session: Session) -> Result<(), SessionError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.contains_key(&session.id) {
+            return Err(SessionError::AlreadyExists);
+        }
+        sessions.insert(session.id.clone(), Arc::new(Mutex::new(session)));
+        Ok(())
+    }
+
+    /// Looks up session `id`, failing if it expired, including since the last sweep.
+    pub fn get(&self, id: &str) -> Result<Arc<Mutex<Session>>, SessionError> {
+        let session = self.sessions.lock().unwrap().get(id).cloned();
+        match session {
+            Some(s) if s.lock().unwrap().created.elapsed() < self.ttl => Ok(s),
+            Some(_) => Err(SessionError::Expired),
+            None if self.expired.lock().unwrap().contains_key(id) => Err(SessionError::Expired),
+            None => Err(SessionError::NotFound),
+        }
+    }
+
+    /// Removes session `id` from the table, once it is done.
+    pub fn remove(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+
+    /// Wipes the sessions that outlived the TTL. Sessions being signed are left alone.
+    pub fn expire(&self) {
+        let now = Instant::now();
+        let mut expired = self.expired.lock().unwrap();
+        expired.retain(|_, at| now.duration_since(*at) < self.ttl);
+
+        self.sessions.lock().unwrap().retain(|id, session| {
+            let Ok(session) = session.try_lock() else {
+                return true;
+            };
+            if now.duration_since(session.created) < self.ttl {
+                return true;
+            }
+            println!("session {} expired", id);
+            expired.insert(id.clone(), now);
+            false
+        });
+    }
+
+    /// Drops all sessions, erasing their keys.
+    pub fn clear(&self) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let n = sessions.len();
+        sessions.clear();
+        n
+    }
+}
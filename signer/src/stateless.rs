@@ -0,0 +1,181 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{Key, KeyInit, XChaCha20Poly1305, XNonce};
+use zeroize::Zeroizing;
+
+use crate::session::EphemeralKey;
+
+/// Version byte prefixed to every state blob.
+const BLOB_VERSION: u8 = 2;
+
+/// Length of an unsealed blob: the creation time, the key and the secret nonce.
+const PLAIN_LEN: usize = 8 + 32 + 64;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// A session's state as handed to the client between rounds, sealed so only this signer can open
+/// it and any change to it is detected.
+pub struct SessionState {
+    /// The key along with the nonce it signs with.
+    pub key: EphemeralKey,
+    /// Unix time the session was initialized.
+    pub created_at: u64,
+}
+
+/// Seals and opens session state blobs, so the signer keeps no sessions itself, or can hand them
+/// to its replicas.
+///
+/// To never sign twice with the same key, the signer still records which sessions it signed,
+/// until their blobs expire. Each is a file in `spent_dir`, created atomically, so restarting
+/// does not forget them and instances sharing the state key can share the directory.
+pub struct StateKey {
+    cipher: XChaCha20Poly1305,
+    spent_dir: PathBuf,
+}
+
+impl StateKey {
+    /// Loads the hex encoded 32 byte key in `key_file`, recording spent sessions in `spent_dir`.
+    pub fn load(key_file: &Path, spent_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let key = Zeroizing::new(hex::decode(fs::read_to_string(key_file)?.trim())?);
+        if key.len() != 32 {
+            return Err("state key must be 32 bytes".into());
+        }
+        fs::create_dir_all(spent_dir)?;
+        Ok(StateKey {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(&key)),
+            spent_dir: spent_dir.to_path_buf(),
+        })
+    }
+
+    /// Seals `state` of session `session_id` into a hex encoded blob.
+    pub fn seal(&self, session_id: &str, state: &SessionState) -> String {
+        let mut plain = Zeroizing::new(vec![]);
+        plain.extend_from_slice(&state.created_at.to_be_bytes());
+        plain.extend_from_slice(state.key.to_bytes());
+
+        let nonce: [u8; 24] = secp256k1::rand::random();
+        let sealed = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plain,
+                    aad: session_id.as_bytes(),
+                },
+            )
+            .expect("encryption does not fail");
+
+        let mut blob = vec![BLOB_VERSION];
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&sealed);
+        hex::encode(blob)
+    }
+
+    /// Opens the blob of session `session_id` for signing, unless it is expired after `ttl` or
+    /// the session was signed already. The session counts as signed from here on.
+    pub fn open(
+        &self,
+        session_id: &str,
+        blob: &str,
+        ttl: Duration,
+    ) -> Result<SessionState, StateError> {
+        let plain = self.unseal(session_id, blob)?;
+        let created_at = u64::from_be_bytes(plain[..8].try_into().unwrap());
+        let expires_at = created_at + ttl.as_secs();
+        if now() >= expires_at {
+            return Err(StateError::Expired);
+        }
+        // The key is locked away before the session is spent, so it is not lost if it cannot be.
+        let key = EphemeralKey::from_bytes(&plain[8..]).map_err(|e| match e.kind() {
+            ErrorKind::InvalidData => StateError::Invalid,
+            _ => StateError::LockedMemory,
+        })?;
+        self.spend(session_id, expires_at)?;
+
+        Ok(SessionState { key, created_at })
+    }
+
+    /// Whether `blob` was sealed with this key for session `session_id`.
+    pub fn verify(&self, session_id: &str, blob: &str) -> bool {
+        self.unseal(session_id, blob).is_ok()
+    }
+
+    fn unseal(&self, session_id: &str, blob: &str) -> Result<Zeroizing<Vec<u8>>, StateError> {
+        let blob = hex::decode(blob).map_err(|_| StateError::Invalid)?;
+        if blob.len() < 25 || blob[0] != BLOB_VERSION {
+            return Err(StateError::Invalid);
+        }
+        let plain = Zeroizing::new(
+            self.cipher
+                .decrypt(
+                    XNonce::from_slice(&blob[1..25]),
+                    Payload {
+                        msg: &blob[25..],
+                        aad: session_id.as_bytes(),
+                    },
+                )
+                .map_err(|_| StateError::Invalid)?,
+        );
+        if plain.len() != PLAIN_LEN {
+            return Err(StateError::Invalid);
+        }
+        Ok(plain)
+    }
+
+    /// Records session `session_id`, which can be signed until `expires_at`, as signed, failing
+    /// if it already was.
+    pub fn spend(&self, session_id: &str, expires_at: u64) -> Result<(), StateError> {
+        // The id names the file, so it must not be able to point outside the directory.
+        if session_id.len() != 64 || !session_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(StateError::Invalid);
+        }
+        let path = self.spent_dir.join(session_id);
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(StateError::Spent),
+            Err(_) => return Err(StateError::Io),
+        };
+        writeln!(file, "{}", expires_at)
+            .and_then(|_| file.sync_all())
+            .map_err(|_| StateError::Io)
+    }
+
+    /// Removes the records of spent sessions whose blobs expired.
+    pub fn prune(&self) {
+        let Ok(entries) = fs::read_dir(&self.spent_dir) else {
+            return;
+        };
+        let now = now();
+        for entry in entries.flatten() {
+            let expired = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|data| data.trim().parse::<u64>().ok())
+                .is_some_and(|expires_at| expires_at <= now);
+            if expired {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    /// The blob was not sealed by this signer for the session, or was tampered with.
+    Invalid,
+    Expired,
+    /// The session was signed already.
+    Spent,
+    /// The spent session could not be recorded, so it is not signed.
+    Io,
+    /// No locked memory was left for the session's key.
+    LockedMemory,
+}
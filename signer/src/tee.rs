@@ -0,0 +1,75 @@
+use std::sync::Mutex;
+
+use aws_nitro_enclaves_nsm_api::api::{Request, Response};
+use aws_nitro_enclaves_nsm_api::driver;
+use serde_bytes::ByteBuf;
+use shared::tee::{EnclaveAttestation, TeePlatform, deletion_report_data};
+
+/// Pseudo-files Gramine gets an SGX quote over the report data with.
+const SGX_USER_REPORT_DATA: &str = "/dev/attestation/user_report_data";
+const SGX_QUOTE: &str = "/dev/attestation/quote";
+
+/// The enclave the signer runs in, attesting that it deleted the keys of the sessions it signed.
+pub struct Enclave {
+    platform: TeePlatform,
+    /// The SGX pseudo-files are shared by all requests, so a quote is taken one at a time.
+    quoting: Mutex<()>,
+}
+
+impl Enclave {
+    pub fn new(platform: TeePlatform) -> Self {
+        Enclave {
+            platform,
+            quoting: Mutex::new(()),
+        }
+    }
+
+    /// Attests that the key `pubkey` of session `session_id` was deleted. Must only be called
+    /// once the key is erased.
+    pub fn attest_deletion(
+        &self,
+        session_id: &str,
+        pubkey: &str,
+    ) -> Result<EnclaveAttestation, Box<dyn std::error::Error>> {
+        let report_data = deletion_report_data(session_id, pubkey);
+        let document = match self.platform {
+            TeePlatform::Sgx => self.sgx_quote(&report_data)?,
+            TeePlatform::Nitro => nitro_attestation(&report_data)?,
+        };
+        Ok(EnclaveAttestation::new(
+            self.platform,
+            session_id,
+            pubkey,
+            &document,
+        ))
+    }
+
+    fn sgx_quote(&self, report_data: &[u8; 32]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut user_report_data = [0u8; 64];
+        user_report_data[..32].copy_from_slice(report_data);
+        let _quoting = self.quoting.lock().unwrap();
+        std::fs::write(SGX_USER_REPORT_DATA, user_report_data)?;
+        Ok(std::fs::read(SGX_QUOTE)?)
+    }
+}
+
+fn nitro_attestation(report_data: &[u8; 32]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let fd = driver::nsm_init();
+    if fd < 0 {
+        return Err("unable to open the Nitro Secure Module".into());
+    }
+    let resp = driver::nsm_process_request(
+        fd,
+        Request::Attestation {
+            user_data: Some(ByteBuf::from(report_data.to_vec())),
+            nonce: None,
+            public_key: None,
+        },
+    );
+    driver::nsm_exit(fd);
+    match resp {
+        Response::Attestation { document } => Ok(document),
+        Response::Error(e) => Err(format!("Nitro Secure Module error: {:?}", e).into()),
+        _ => Err("unexpected response from the Nitro Secure Module".into()),
+    }
+}